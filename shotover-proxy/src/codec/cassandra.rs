@@ -1,24 +1,57 @@
+use crate::codec::CodecBuilder;
 use crate::frame::cassandra::{CassandraMetadata, CassandraOperation};
 use crate::frame::{CassandraFrame, Frame, MessageType};
 use crate::message::{Encodable, Message, Messages, Metadata};
 use crate::server::CodecReadError;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use bytes::{Buf, BufMut, BytesMut};
 use cassandra_protocol::compression::Compression;
 use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use cassandra_protocol::frame::message_request::RequestBody;
 use cassandra_protocol::frame::{
-    CheckEnvelopeSizeError, Envelope as RawCassandraFrame, Opcode, Version,
+    CheckEnvelopeSizeError, Envelope as RawCassandraFrame, Opcode, Serialize, Version,
 };
 use cql3_parser::cassandra_statement::CassandraStatement;
 use cql3_parser::common::Identifier;
+use std::sync::{Arc, Mutex, MutexGuard};
 use tokio_util::codec::{Decoder, Encoder};
 use tracing::info;
 
+/// Length of the envelope header: version, flags, stream id, opcode and body length
+const ENVELOPE_HEADER_LEN: usize = 9;
+/// The envelope flag indicating that a v3/v4 envelope body is compressed
+const ENVELOPE_COMPRESSION_FLAG: u8 = 0x01;
+/// Length of an uncompressed v5 segment header:
+/// 17 bits of payload length, a self contained flag bit and 6 bits of padding followed by a CRC24 of those 3 bytes
+const SEGMENT_HEADER_LEN: usize = 6;
+/// Length of a compressed v5 segment header:
+/// 17 bits of compressed length, 17 bits of uncompressed length, a self contained flag bit and 5 bits of padding followed by a CRC24 of those 5 bytes
+const COMPRESSED_SEGMENT_HEADER_LEN: usize = 8;
+/// Length of the CRC32 of the payload that trails every v5 segment
+const SEGMENT_TRAILER_LEN: usize = 4;
+/// Envelopes larger than this are split across multiple segments that are not self contained
+const SEGMENT_MAX_PAYLOAD_LEN: usize = (1 << 17) - 1;
+
+/// Connection level state that must be shared between the decoder and encoder of a connection,
+/// as only one of them will observe the messages that change it.
+#[derive(Debug)]
+struct ConnectionState {
+    /// Set once a protocol v5 handshake completes, from then on every envelope is wrapped in a segment.
+    segment_framing: bool,
+    /// The compression requested by the STARTUP message.
+    /// For v3/v4 it is applied to envelope bodies while for v5 it is applied to segments.
+    compression: Compression,
+}
+
 #[derive(Debug, Clone)]
 pub struct CassandraCodec {
-    compressor: Compression,
     messages: Vec<Message>,
     current_use_keyspace: Option<Identifier>,
+    state: Arc<Mutex<ConnectionState>>,
+    /// When set, the COMPRESSION option of outgoing STARTUP messages is replaced with this compression
+    startup_compression: Option<Compression>,
+    /// Payloads of segments that are not self contained, accumulated until they form a complete envelope
+    partial_envelope: BytesMut,
 }
 
 impl Default for CassandraCodec {
@@ -30,84 +63,459 @@ impl Default for CassandraCodec {
 impl CassandraCodec {
     pub fn new() -> CassandraCodec {
         CassandraCodec {
-            compressor: Compression::None,
             messages: vec![],
             current_use_keyspace: None,
+            state: Arc::new(Mutex::new(ConnectionState {
+                segment_framing: false,
+                compression: Compression::None,
+            })),
+            startup_compression: None,
+            partial_envelope: BytesMut::new(),
+        }
+    }
+
+    /// Create a codec for a connection to a cassandra node that requests the provided compression regardless of the compression requested by the client.
+    /// This allows the connection between shotover and cassandra to be compressed independently of the connection between the client and shotover.
+    pub fn new_with_compression(compression: Compression) -> CassandraCodec {
+        CassandraCodec {
+            startup_compression: Some(compression),
+            ..CassandraCodec::new()
         }
     }
 }
 
+#[derive(Clone, Default)]
+pub struct CassandraCodecBuilder {}
+
+impl CassandraCodecBuilder {
+    pub fn new() -> Self {
+        CassandraCodecBuilder {}
+    }
+}
+
+impl CodecBuilder for CassandraCodecBuilder {
+    type Decoder = CassandraCodec;
+    type Encoder = CassandraCodec;
+    fn build(&self) -> (CassandraCodec, CassandraCodec) {
+        let codec = CassandraCodec::new();
+        (codec.clone(), codec)
+    }
+}
+
 impl CassandraCodec {
     fn encode_raw(&mut self, item: CassandraFrame, dst: &mut BytesMut) {
-        let buffer = item.encode().encode_with(self.compressor).unwrap();
+        // compression is applied after the envelope is encoded, see `CassandraCodec::encode`
+        let buffer = item.encode().encode_with(Compression::None).unwrap();
         if buffer.is_empty() {
             info!("trying to send 0 length frame");
         }
         dst.put(buffer.as_slice());
     }
-}
 
-impl Decoder for CassandraCodec {
-    type Item = Messages;
-    type Error = CodecReadError;
+    fn state(&self) -> MutexGuard<ConnectionState> {
+        self.state.lock().unwrap()
+    }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, CodecReadError> {
-        loop {
-            match RawCassandraFrame::check_envelope_size(src) {
-                Ok(frame_len) => {
-                    // Clear the read bytes from the FramedReader
-                    let bytes = src.split_to(frame_len);
-                    tracing::debug!(
-                        "incoming cassandra message:\n{}",
-                        pretty_hex::pretty_hex(&bytes)
-                    );
-
-                    let version = Version::try_from(bytes[0])
-                        .expect("Gauranteed because check_envelope_size only returns Ok if the Version will parse");
-                    if let Version::V3 | Version::V4 = version {
-                        // Accept these protocols
-                    } else {
-                        // Reject protocols that cassandra-protocol supports but shotover does not yet support
-                        return Err(reject_protocol_version(version.into()));
-                    }
+    fn take_messages(&mut self, src: &BytesMut) -> Option<Messages> {
+        if self.messages.is_empty() || src.remaining() != 0 {
+            None
+        } else {
+            Some(std::mem::take(&mut self.messages))
+        }
+    }
 
-                    let mut message = Message::from_bytes(bytes.freeze(), MessageType::Cassandra);
+    fn process_envelope(&mut self, bytes: BytesMut) -> Result<(), CodecReadError> {
+        let version = Version::try_from(bytes[0]).expect(
+            "Gauranteed because check_envelope_size only returns Ok if the Version will parse",
+        );
+        let bytes = if version == Version::V5 {
+            // v5 compresses segments instead of envelopes
+            bytes
+        } else {
+            let compression = self.state().compression;
+            decompress_envelope(bytes, compression).map_err(CodecReadError::Parser)?
+        };
 
-                    if let Ok(Metadata::Cassandra(CassandraMetadata {
-                        opcode: Opcode::Query | Opcode::Batch,
-                        ..
-                    })) = message.metadata()
-                    {
-                        if let Some(keyspace) = get_use_keyspace(&mut message) {
-                            self.current_use_keyspace = Some(keyspace);
-                        }
-
-                        if let Some(keyspace) = &self.current_use_keyspace {
-                            set_default_keyspace(&mut message, keyspace);
-                        }
-                    }
+        tracing::debug!(
+            "incoming cassandra message:\n{}",
+            pretty_hex::pretty_hex(&bytes)
+        );
 
-                    self.messages.push(message);
+        if let Ok(Opcode::Startup) = Opcode::try_from(bytes[4]) {
+            // We are the server side of the connection and the client has just requested its compression
+            match get_startup_compression(&bytes) {
+                Ok(Compression::Snappy) if version == Version::V5 => {
+                    return Err(reject_startup(
+                        &bytes,
+                        version,
+                        "protocol v5 does not support snappy compression".into(),
+                    ));
                 }
-                Err(CheckEnvelopeSizeError::NotEnoughBytes) => {
-                    if self.messages.is_empty() || src.remaining() != 0 {
-                        return Ok(None);
-                    } else {
-                        return Ok(Some(std::mem::take(&mut self.messages)));
+                Ok(compression) => self.state().compression = compression,
+                Err(err) => return Err(reject_startup(&bytes, version, format!("{err}"))),
+            }
+        }
+
+        let mut message = Message::from_bytes(bytes.freeze(), MessageType::Cassandra);
+
+        match message.metadata() {
+            Ok(Metadata::Cassandra(CassandraMetadata {
+                opcode: Opcode::Query | Opcode::Batch,
+                ..
+            })) => {
+                if let Some(keyspace) = get_use_keyspace(&mut message) {
+                    self.current_use_keyspace = Some(keyspace);
+                }
+
+                // A keyspace provided via the v5 keyspace flag takes priority over the keyspace set by USE
+                if let Some(keyspace) =
+                    get_keyspace_flag(&mut message).or_else(|| self.current_use_keyspace.clone())
+                {
+                    set_default_keyspace(&mut message, &keyspace);
+                }
+            }
+            Ok(Metadata::Cassandra(CassandraMetadata {
+                opcode: Opcode::Ready | Opcode::Authenticate,
+                version: Version::V5,
+                ..
+            })) => {
+                // We are the client side of the connection and the server has just completed the v5 handshake,
+                // everything received or sent after this point is wrapped in segments.
+                self.state().segment_framing = true;
+            }
+            _ => {}
+        }
+
+        self.messages.push(message);
+        Ok(())
+    }
+
+    fn process_segment(&mut self, segment: Segment) -> Result<(), CodecReadError> {
+        let mut payload = segment.payload;
+        if segment.self_contained {
+            // A self contained segment holds one or more complete envelopes
+            while !payload.is_empty() {
+                match RawCassandraFrame::check_envelope_size(&payload) {
+                    Ok(envelope_len) => {
+                        let envelope = payload.split_to(envelope_len);
+                        self.process_envelope(envelope)?;
+                    }
+                    Err(CheckEnvelopeSizeError::UnsupportedVersion(version)) => {
+                        return Err(reject_protocol_version(version));
+                    }
+                    err => {
+                        return Err(CodecReadError::Parser(anyhow!(
+                            "Self contained segment did not contain complete envelopes {:?}",
+                            err
+                        )))
                     }
                 }
-                Err(CheckEnvelopeSizeError::UnsupportedVersion(version)) => {
-                    return Err(reject_protocol_version(version));
+            }
+        } else {
+            // Segments that are not self contained each hold a piece of a single large envelope
+            self.partial_envelope.extend_from_slice(&payload);
+            match RawCassandraFrame::check_envelope_size(&self.partial_envelope) {
+                Ok(envelope_len) if envelope_len == self.partial_envelope.len() => {
+                    let envelope = std::mem::take(&mut self.partial_envelope);
+                    self.process_envelope(envelope)?;
                 }
+                Err(CheckEnvelopeSizeError::NotEnoughBytes) => {}
                 err => {
                     return Err(CodecReadError::Parser(anyhow!(
-                        "Failed to parse frame {:?}",
+                        "Failed to reassemble envelope from segments {:?}",
                         err
                     )))
                 }
             }
         }
+        Ok(())
+    }
+}
+
+impl Decoder for CassandraCodec {
+    type Item = Messages;
+    type Error = CodecReadError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, CodecReadError> {
+        loop {
+            // The v5 handshake can complete partway through src, so this needs to be checked before every read
+            let (segment_framing, compression) = {
+                let state = self.state();
+                (state.segment_framing, state.compression)
+            };
+            if segment_framing {
+                match decode_segment(src, compression).map_err(CodecReadError::Parser)? {
+                    Some(segment) => self.process_segment(segment)?,
+                    None => return Ok(self.take_messages(src)),
+                }
+            } else {
+                match RawCassandraFrame::check_envelope_size(src) {
+                    Ok(frame_len) => {
+                        // Clear the read bytes from the FramedReader
+                        let bytes = src.split_to(frame_len);
+                        self.process_envelope(bytes)?;
+                    }
+                    Err(CheckEnvelopeSizeError::NotEnoughBytes) => {
+                        return Ok(self.take_messages(src));
+                    }
+                    Err(CheckEnvelopeSizeError::UnsupportedVersion(version)) => {
+                        return Err(reject_protocol_version(version));
+                    }
+                    err => {
+                        return Err(CodecReadError::Parser(anyhow!(
+                            "Failed to parse frame {:?}",
+                            err
+                        )))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the compression requested by the COMPRESSION option of a STARTUP envelope
+fn get_startup_compression(envelope: &[u8]) -> Result<Compression> {
+    let envelope = RawCassandraFrame::from_buffer(envelope, Compression::None)
+        .map_err(|e| anyhow!("{e:?}"))?
+        .envelope;
+    if let RequestBody::Startup(startup) = envelope.request_body()? {
+        match startup.map.get("COMPRESSION").map(|x| x.as_str()) {
+            Some("lz4") => Ok(Compression::Lz4),
+            Some("snappy") => Ok(Compression::Snappy),
+            Some(compression) => Err(anyhow!(
+                "STARTUP requested unsupported compression {compression}"
+            )),
+            None => Ok(Compression::None),
+        }
+    } else {
+        Err(anyhow!("Expected a STARTUP envelope"))
+    }
+}
+
+/// Replaces the COMPRESSION option of a STARTUP envelope
+fn rewrite_startup_compression(envelope: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    let mut envelope = RawCassandraFrame::from_buffer(envelope, Compression::None)
+        .map_err(|e| anyhow!("{e:?}"))?
+        .envelope;
+    if let RequestBody::Startup(mut startup) = envelope.request_body()? {
+        match compression {
+            Compression::Lz4 => startup.map.insert("COMPRESSION".into(), "lz4".into()),
+            Compression::Snappy => startup.map.insert("COMPRESSION".into(), "snappy".into()),
+            Compression::None => startup.map.remove("COMPRESSION"),
+        };
+        envelope.body = startup.serialize_to_vec(envelope.version);
+        envelope
+            .encode_with(Compression::None)
+            .map_err(|e| anyhow!("{e:?}"))
+    } else {
+        Err(anyhow!("Expected a STARTUP envelope"))
+    }
+}
+
+/// Decompresses the body of a v3/v4 envelope if it has the compression flag set
+fn decompress_envelope(envelope: BytesMut, compression: Compression) -> Result<BytesMut> {
+    if envelope[1] & ENVELOPE_COMPRESSION_FLAG == 0 {
+        return Ok(envelope);
+    }
+
+    let compressed = &envelope[ENVELOPE_HEADER_LEN..];
+    let body = match compression {
+        Compression::Lz4 => {
+            // lz4 compressed bodies are prefixed with their uncompressed length
+            if compressed.len() < 4 {
+                bail!("lz4 compressed envelope body is missing its uncompressed length");
+            }
+            let uncompressed_len = u32::from_be_bytes(compressed[..4].try_into()?) as usize;
+            lz4_flex::block::decompress(&compressed[4..], uncompressed_len)?
+        }
+        Compression::Snappy => snap::raw::Decoder::new().decompress_vec(compressed)?,
+        Compression::None => {
+            bail!("Received a compressed envelope but the STARTUP message did not request compression")
+        }
+    };
+
+    let mut result = BytesMut::with_capacity(ENVELOPE_HEADER_LEN + body.len());
+    result.put_u8(envelope[0]);
+    result.put_u8(envelope[1] & !ENVELOPE_COMPRESSION_FLAG);
+    result.put_slice(&envelope[2..5]);
+    result.put_u32(body.len() as u32);
+    result.put_slice(&body);
+    Ok(result)
+}
+
+/// Writes the v3/v4 envelope to dst, compressing its body if compression was requested
+fn compress_envelope(dst: &mut BytesMut, envelope: &[u8], compression: Compression) -> Result<()> {
+    let body = &envelope[ENVELOPE_HEADER_LEN..];
+    let compressed = match compression {
+        // Empty bodies are left uncompressed as there is nothing to gain
+        _ if body.is_empty() => None,
+        Compression::Lz4 => {
+            let mut compressed = (body.len() as u32).to_be_bytes().to_vec();
+            compressed.extend(lz4_flex::block::compress(body));
+            Some(compressed)
+        }
+        Compression::Snappy => Some(snap::raw::Encoder::new().compress_vec(body)?),
+        Compression::None => None,
+    };
+
+    match compressed {
+        Some(compressed) => {
+            dst.put_u8(envelope[0]);
+            dst.put_u8(envelope[1] | ENVELOPE_COMPRESSION_FLAG);
+            dst.put_slice(&envelope[2..5]);
+            dst.put_u32(compressed.len() as u32);
+            dst.put_slice(&compressed);
+        }
+        None => dst.put_slice(envelope),
+    }
+    Ok(())
+}
+
+/// A v5 segment with its header and trailer removed and its payload decompressed
+struct Segment {
+    payload: BytesMut,
+    self_contained: bool,
+}
+
+fn decode_segment(src: &mut BytesMut, compression: Compression) -> Result<Option<Segment>> {
+    let (header_len, header_bytes) = match compression {
+        Compression::None => (SEGMENT_HEADER_LEN, 3),
+        Compression::Lz4 => (COMPRESSED_SEGMENT_HEADER_LEN, 5),
+        Compression::Snappy => bail!("protocol v5 does not support snappy compression"),
+    };
+    if src.len() < header_len {
+        return Ok(None);
+    }
+
+    let mut header = 0;
+    for (i, byte) in src[..header_bytes].iter().enumerate() {
+        header |= u64::from(*byte) << (i * 8);
+    }
+    let header_crc = u32::from_le_bytes([
+        src[header_bytes],
+        src[header_bytes + 1],
+        src[header_bytes + 2],
+        0,
+    ]);
+    if crc24(header, header_bytes) != header_crc {
+        bail!("v5 segment header failed CRC24 check");
+    }
+
+    let payload_len = (header & SEGMENT_MAX_PAYLOAD_LEN as u64) as usize;
+    let (uncompressed_len, self_contained) = match compression {
+        Compression::None => (0, header & (1 << 17) != 0),
+        _ => (
+            ((header >> 17) & SEGMENT_MAX_PAYLOAD_LEN as u64) as usize,
+            header & (1 << 34) != 0,
+        ),
+    };
+
+    let segment_len = header_len + payload_len + SEGMENT_TRAILER_LEN;
+    if src.len() < segment_len {
+        return Ok(None);
+    }
+
+    let mut segment = src.split_to(segment_len);
+    segment.advance(header_len);
+    let payload = segment.split_to(payload_len);
+    if crc32(&payload) != segment.get_u32_le() {
+        bail!("v5 segment payload failed CRC32 check");
+    }
+
+    // An uncompressed length of 0 indicates that the payload was not worth compressing and was sent as is
+    let payload = if uncompressed_len == 0 {
+        payload
+    } else {
+        lz4_flex::block::decompress(&payload, uncompressed_len)?
+            .as_slice()
+            .into()
+    };
+
+    Ok(Some(Segment {
+        payload,
+        self_contained,
+    }))
+}
+
+fn encode_segment(
+    dst: &mut BytesMut,
+    payload: &[u8],
+    self_contained: bool,
+    compression: Compression,
+) -> Result<()> {
+    match compression {
+        Compression::None => {
+            let mut header = payload.len() as u64;
+            if self_contained {
+                header |= 1 << 17;
+            }
+            dst.put_slice(&header.to_le_bytes()[..3]);
+            dst.put_slice(&crc24(header, 3).to_le_bytes()[..3]);
+            dst.put_slice(payload);
+            dst.put_u32_le(crc32(payload));
+        }
+        Compression::Lz4 => {
+            let compressed = lz4_flex::block::compress(payload);
+            let (payload, uncompressed_len) = if compressed.len() < payload.len() {
+                (compressed.as_slice(), payload.len())
+            } else {
+                (payload, 0)
+            };
+
+            let mut header = payload.len() as u64 | (uncompressed_len as u64) << 17;
+            if self_contained {
+                header |= 1 << 34;
+            }
+            dst.put_slice(&header.to_le_bytes()[..5]);
+            dst.put_slice(&crc24(header, 5).to_le_bytes()[..3]);
+            dst.put_slice(payload);
+            dst.put_u32_le(crc32(payload));
+        }
+        Compression::Snappy => bail!("protocol v5 does not support snappy compression"),
+    }
+    Ok(())
+}
+
+/// The CRC24 used to protect v5 segment headers, `len` is the number of bytes of `bytes` to include starting from the least significant byte.
+fn crc24(mut bytes: u64, len: usize) -> u32 {
+    let mut crc = 0x875060;
+    for _ in 0..len {
+        crc ^= ((bytes & 0xff) as u32) << 16;
+        bytes >>= 8;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1974F0B;
+            }
+        }
     }
+    crc
+}
+
+/// The CRC32 used to protect v5 segment payloads, it is a regular CRC32 seeded with 4 fixed bytes.
+fn crc32(payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[0xFA, 0x2D, 0x55, 0xCA]);
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+/// Returns true if the envelope is the server's response to a v5 STARTUP, after which both sides switch to segment framing
+fn is_v5_handshake_response(envelope: &[u8]) -> bool {
+    matches!(Version::try_from(envelope[0]), Ok(Version::V5))
+        && matches!(
+            Opcode::try_from(envelope[4]),
+            Ok(Opcode::Ready | Opcode::Authenticate)
+        )
+}
+
+/// Compression is only applied to v3/v4 envelopes once the handshake has started
+fn is_compressible_envelope(envelope: &[u8]) -> bool {
+    !matches!(Version::try_from(envelope[0]), Ok(Version::V5))
+        && !matches!(
+            Opcode::try_from(envelope[4]),
+            Ok(Opcode::Startup | Opcode::Options | Opcode::Supported)
+        )
 }
 
 fn get_use_keyspace(message: &mut Message) -> Option<Identifier> {
@@ -121,6 +529,17 @@ fn get_use_keyspace(message: &mut Message) -> Option<Identifier> {
     None
 }
 
+fn get_keyspace_flag(message: &mut Message) -> Option<Identifier> {
+    if let Some(Frame::Cassandra(frame)) = message.frame() {
+        // The keyspace flag holds the keyspace name as is, so it needs to be quoted to retain its case
+        return frame
+            .operation
+            .keyspace_flag()
+            .map(|keyspace| Identifier::Quoted(keyspace.to_owned()));
+    }
+    None
+}
+
 fn set_default_keyspace(message: &mut Message, keyspace: &Identifier) {
     // TODO: rewrite Operation::Prepared in the same way
     if let Some(Frame::Cassandra(frame)) = message.frame() {
@@ -196,6 +615,25 @@ fn reject_protocol_version(version: u8) -> CodecReadError {
     ))])
 }
 
+/// Rejects a STARTUP requesting a compression that can not be used, the client can then retry with another compression on the same connection.
+fn reject_startup(envelope: &[u8], version: Version, message: String) -> CodecReadError {
+    info!("Rejecting STARTUP: {message}");
+
+    let stream_id = i16::from_be_bytes([envelope[2], envelope[3]]);
+    CodecReadError::Respond(vec![Message::from_frame(Frame::Cassandra(
+        CassandraFrame {
+            version,
+            stream_id,
+            operation: CassandraOperation::Error(ErrorBody {
+                message,
+                ty: ErrorType::Protocol,
+            }),
+            tracing_id: None,
+            warnings: vec![],
+        },
+    ))])
+}
+
 impl Encoder<Messages> for CassandraCodec {
     type Error = anyhow::Error;
 
@@ -204,17 +642,69 @@ impl Encoder<Messages> for CassandraCodec {
         item: Messages,
         dst: &mut BytesMut,
     ) -> std::result::Result<(), Self::Error> {
+        // Envelopes waiting to be written out together in a single self contained segment
+        let mut segment_payload = BytesMut::new();
+
         for m in item {
-            let start = dst.len();
+            let mut envelope = BytesMut::new();
             // TODO: always check if cassandra message
             match m.into_encodable(MessageType::Cassandra)? {
-                Encodable::Bytes(bytes) => dst.extend_from_slice(&bytes),
-                Encodable::Frame(frame) => self.encode_raw(frame.into_cassandra().unwrap(), dst),
+                Encodable::Bytes(bytes) => envelope.extend_from_slice(&bytes),
+                Encodable::Frame(frame) => {
+                    self.encode_raw(frame.into_cassandra().unwrap(), &mut envelope)
+                }
             }
+
+            if let Ok(Opcode::Startup) = Opcode::try_from(envelope[4]) {
+                // We are the client side of the connection and are requesting compression from the server
+                if let Some(compression) = self.startup_compression {
+                    envelope = rewrite_startup_compression(&envelope, compression)?
+                        .as_slice()
+                        .into();
+                }
+                self.state().compression = get_startup_compression(&envelope)?;
+            }
+
             tracing::debug!(
                 "outgoing cassandra message:\n{}",
-                pretty_hex::pretty_hex(&&dst[start..])
+                pretty_hex::pretty_hex(&envelope)
             );
+
+            let mut state = self.state();
+            if state.segment_framing {
+                if envelope.len() > SEGMENT_MAX_PAYLOAD_LEN {
+                    if !segment_payload.is_empty() {
+                        encode_segment(dst, &segment_payload, true, state.compression)?;
+                        segment_payload.clear();
+                    }
+                    for chunk in envelope.chunks(SEGMENT_MAX_PAYLOAD_LEN) {
+                        encode_segment(dst, chunk, false, state.compression)?;
+                    }
+                } else {
+                    if segment_payload.len() + envelope.len() > SEGMENT_MAX_PAYLOAD_LEN {
+                        encode_segment(dst, &segment_payload, true, state.compression)?;
+                        segment_payload.clear();
+                    }
+                    segment_payload.extend_from_slice(&envelope);
+                }
+            } else {
+                if is_compressible_envelope(&envelope) {
+                    compress_envelope(dst, &envelope, state.compression)?;
+                } else {
+                    dst.extend_from_slice(&envelope);
+                }
+
+                if is_v5_handshake_response(&envelope) {
+                    // We are the server side of the connection and have just completed the v5 handshake,
+                    // everything received or sent after this point is wrapped in segments.
+                    state.segment_framing = true;
+                }
+            }
+        }
+
+        if !segment_payload.is_empty() {
+            let compression = self.state().compression;
+            encode_segment(dst, &segment_payload, true, compression)?;
         }
         Ok(())
     }
@@ -222,14 +712,19 @@ impl Encoder<Messages> for CassandraCodec {
 
 #[cfg(test)]
 mod cassandra_protocol_tests {
-    use crate::codec::cassandra::CassandraCodec;
+    use crate::codec::cassandra::{
+        get_startup_compression, rewrite_startup_compression, CassandraCodec,
+    };
     use crate::frame::cassandra::{
         parse_statement_single, CassandraFrame, CassandraOperation, CassandraResult,
     };
     use crate::frame::Frame;
     use crate::message::Message;
+    use crate::server::CodecReadError;
     use bytes::BytesMut;
+    use cassandra_protocol::compression::Compression;
     use cassandra_protocol::events::SimpleServerEvent;
+    use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
     use cassandra_protocol::frame::message_register::BodyReqRegister;
     use cassandra_protocol::frame::message_result::{
         ColSpec, ColType, ColTypeOption, ColTypeOptionValue, RowsMetadata, RowsMetadataFlags,
@@ -495,4 +990,173 @@ mod cassandra_protocol_tests {
         }))];
         test_frame_codec_roundtrip(&mut codec, &bytes, messages);
     }
+
+    #[test]
+    fn test_codec_lz4_compression() {
+        let mut codec = CassandraCodec::new();
+
+        // receiving a STARTUP requesting lz4 enables compression for all following envelopes
+        codec
+            .decode(&mut BytesMut::from(
+                hex!(
+                    "0400000001000000280002000b43514c5f56455253494f4e0005332e302e30
+                    000b434f4d5052455353494f4e00036c7a34"
+                )
+                .as_slice(),
+            ))
+            .unwrap()
+            .unwrap();
+
+        let bytes = hex!("840100001000000009 00000004 40ffffffff");
+        let messages = vec![Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            operation: CassandraOperation::AuthSuccess(vec![255, 255, 255, 255]),
+            stream_id: 0,
+            tracing_id: None,
+            warnings: vec![],
+        }))];
+        test_frame_codec_roundtrip(&mut codec, &bytes, messages);
+    }
+
+    #[test]
+    fn test_codec_v5_segment() {
+        let mut codec = CassandraCodec::new();
+
+        // receiving a v5 READY completes the handshake, all following envelopes are wrapped in segments
+        codec
+            .decode(&mut BytesMut::from(hex!("850000000200000000").as_slice()))
+            .unwrap()
+            .unwrap();
+
+        let bytes = hex!("090002a4c8c1 050000000500000000 1086284d");
+        let messages = vec![Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V5,
+            operation: CassandraOperation::Options(vec![]),
+            stream_id: 0,
+            tracing_id: None,
+            warnings: vec![],
+        }))];
+        test_frame_codec_roundtrip(&mut codec, &bytes, messages);
+    }
+
+    const STARTUP_SNAPPY: [u8; 52] = hex!(
+        "04000000010000002b0002000b43514c5f56455253494f4e0005332e302e30
+        000b434f4d5052455353494f4e0006736e61707079"
+    );
+
+    #[test]
+    fn test_codec_snappy_compression() {
+        let mut codec = CassandraCodec::new();
+
+        // receiving a STARTUP requesting snappy enables compression for all following envelopes
+        codec
+            .decode(&mut BytesMut::from(STARTUP_SNAPPY.as_slice()))
+            .unwrap()
+            .unwrap();
+
+        let messages = vec![Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            operation: CassandraOperation::AuthSuccess(vec![255, 255, 255, 255]),
+            stream_id: 0,
+            tracing_id: None,
+            warnings: vec![],
+        }))];
+        let mut bytes = BytesMut::new();
+        codec.encode(messages.clone(), &mut bytes).unwrap();
+        // the compression flag is set
+        assert_eq!(bytes[1] & 0x01, 0x01);
+        test_frame_codec_roundtrip(&mut codec, &bytes, messages);
+    }
+
+    fn assert_startup_rejected(codec: &mut CassandraCodec, startup: &[u8], expected: &str) {
+        match codec.decode(&mut BytesMut::from(startup)) {
+            Err(CodecReadError::Respond(mut messages)) => {
+                assert_eq!(messages.len(), 1);
+                assert!(matches!(
+                    messages[0].frame(),
+                    Some(Frame::Cassandra(CassandraFrame {
+                        stream_id: 0,
+                        operation: CassandraOperation::Error(ErrorBody {
+                            ty: ErrorType::Protocol,
+                            message,
+                        }),
+                        ..
+                    })) if message.contains(expected)
+                ));
+            }
+            result => panic!("expected the STARTUP to be rejected but was {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_codec_unsupported_compression() {
+        let mut codec = CassandraCodec::new();
+
+        assert_startup_rejected(
+            &mut codec,
+            &hex!(
+                "0400000001000000290002000b43514c5f56455253494f4e0005332e302e30
+                000b434f4d5052455353494f4e00047a737464"
+            ),
+            "unsupported compression zstd",
+        );
+
+        // the client can retry on the same connection without compression
+        let mut messages = codec
+            .decode(&mut BytesMut::from(
+                hex!("0400000001000000160001000b43514c5f56455253494f4e0005332e302e30").as_slice(),
+            ))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            messages[0].frame(),
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Startup(_),
+                ..
+            }))
+        ));
+        assert_eq!(codec.state().compression, Compression::None);
+    }
+
+    #[test]
+    fn test_codec_v5_snappy_compression() {
+        let mut codec = CassandraCodec::new();
+
+        let mut startup = STARTUP_SNAPPY;
+        startup[0] = 0x05;
+        assert_startup_rejected(
+            &mut codec,
+            &startup,
+            "protocol v5 does not support snappy compression",
+        );
+        assert_eq!(codec.state().compression, Compression::None);
+    }
+
+    #[test]
+    fn test_rewrite_startup_compression() {
+        let rewritten = rewrite_startup_compression(&STARTUP_SNAPPY, Compression::Lz4).unwrap();
+        assert_eq!(
+            get_startup_compression(&rewritten).unwrap(),
+            Compression::Lz4
+        );
+        // the envelope length is updated to match the rewritten body
+        assert_eq!(
+            u32::from_be_bytes(rewritten[5..9].try_into().unwrap()) as usize,
+            rewritten.len() - 9
+        );
+
+        let rewritten = rewrite_startup_compression(&rewritten, Compression::None).unwrap();
+        assert_eq!(
+            get_startup_compression(&rewritten).unwrap(),
+            Compression::None
+        );
+        assert_eq!(
+            rewritten,
+            hex!("0400000001000000160001000b43514c5f56455253494f4e0005332e302e30")
+        );
+
+        assert!(
+            rewrite_startup_compression(&hex!("040000000500000000"), Compression::Lz4).is_err()
+        );
+    }
 }
@@ -0,0 +1,247 @@
+use crate::codec::CodecBuilder;
+use crate::frame::kafka::{KafkaFrame, KafkaResponse, KafkaResponseBody, SIZE_PREFIX_LEN};
+use crate::frame::{Frame, MessageType};
+use crate::message::{Encodable, Message, Messages};
+use crate::server::CodecReadError;
+use anyhow::{anyhow, Result};
+use bytes::{Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Decodes requests and encodes responses when facing a client, decodes responses and encodes requests when facing kafka.
+#[derive(Debug, Clone)]
+pub struct KafkaCodec {
+    messages: Messages,
+    /// Only present on codecs facing kafka.
+    /// Kafka responses do not contain the api key or version they were sent in response to, which is needed to parse them.
+    /// So the api key and version of every request that will receive a response is recorded here by the encoder.
+    /// Kafka always responds in the order requests were sent on a connection.
+    pending_requests: Option<Arc<Mutex<VecDeque<(i16, i16)>>>>,
+}
+
+impl Default for KafkaCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KafkaCodec {
+    /// Create a codec for a single connection to kafka
+    pub fn new() -> KafkaCodec {
+        KafkaCodec {
+            messages: vec![],
+            pending_requests: Some(Arc::new(Mutex::new(VecDeque::new()))),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct KafkaCodecBuilder {}
+
+impl KafkaCodecBuilder {
+    pub fn new() -> Self {
+        KafkaCodecBuilder {}
+    }
+}
+
+impl CodecBuilder for KafkaCodecBuilder {
+    type Decoder = KafkaCodec;
+    type Encoder = KafkaCodec;
+    fn build(&self) -> (KafkaCodec, KafkaCodec) {
+        let codec = KafkaCodec {
+            messages: vec![],
+            pending_requests: None,
+        };
+        (codec.clone(), codec)
+    }
+}
+
+impl KafkaCodec {
+    fn decode_message(&self, bytes: Bytes) -> Result<Message> {
+        match &self.pending_requests {
+            Some(pending_requests) => {
+                let (api_key, api_version) = pending_requests
+                    .lock()
+                    .unwrap()
+                    .pop_front()
+                    .ok_or_else(|| anyhow!("Received a kafka response without a request"))?;
+                let response = KafkaResponse::from_bytes(bytes.clone(), api_key, api_version)?;
+                Ok(Message::from_bytes_and_frame(
+                    bytes,
+                    Frame::Kafka(KafkaFrame::Response(response)),
+                ))
+            }
+            // Requests can be lazily parsed since they contain their own api key and version
+            None => Ok(Message::from_bytes(bytes, MessageType::Kafka)),
+        }
+    }
+}
+
+impl Decoder for KafkaCodec {
+    type Item = Messages;
+    type Error = CodecReadError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, CodecReadError> {
+        loop {
+            if src.len() < SIZE_PREFIX_LEN {
+                break;
+            }
+            let size = i32::from_be_bytes(src[..SIZE_PREFIX_LEN].try_into().unwrap());
+            if size < 0 {
+                return Err(CodecReadError::Parser(anyhow!(
+                    "Received kafka message with negative size {size}"
+                )));
+            }
+            let len = SIZE_PREFIX_LEN + size as usize;
+            if src.len() < len {
+                src.reserve(len - src.len());
+                break;
+            }
+
+            let bytes = src.split_to(len).freeze();
+            tracing::debug!(
+                "incoming kafka message:\n{}",
+                pretty_hex::pretty_hex(&bytes)
+            );
+            let message = self
+                .decode_message(bytes)
+                .map_err(|e| CodecReadError::Parser(e.context("Error decoding kafka message")))?;
+            self.messages.push(message);
+        }
+
+        if self.messages.is_empty() || !src.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(std::mem::take(&mut self.messages)))
+        }
+    }
+}
+
+impl Encoder<Messages> for KafkaCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, item: Messages, dst: &mut BytesMut) -> Result<()> {
+        item.into_iter().try_for_each(|mut m| {
+            match (m.frame(), &self.pending_requests) {
+                (Some(Frame::Kafka(KafkaFrame::Request(request))), Some(pending_requests)) => {
+                    if request.expects_response() {
+                        pending_requests
+                            .lock()
+                            .unwrap()
+                            .push_back((request.header.api_key, request.header.api_version));
+                    }
+                }
+                (
+                    Some(Frame::Kafka(KafkaFrame::Response(KafkaResponse {
+                        body: KafkaResponseBody::NoResponse,
+                        ..
+                    }))),
+                    None,
+                ) => return Ok(()),
+                _ => {}
+            }
+
+            let start = dst.len();
+            match m.into_encodable(MessageType::Kafka)? {
+                Encodable::Bytes(bytes) => dst.extend_from_slice(&bytes),
+                Encodable::Frame(frame) => frame.into_kafka()?.encode(dst)?,
+            }
+            tracing::debug!(
+                "outgoing kafka message:\n{}",
+                pretty_hex::pretty_hex(&&dst[start..])
+            );
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod kafka_tests {
+    use crate::codec::kafka::{KafkaCodec, KafkaCodecBuilder};
+    use crate::codec::CodecBuilder;
+    use crate::frame::kafka::{api_key, KafkaFrame, KafkaResponseBody, MetadataBroker};
+    use crate::frame::Frame;
+    use bytes::{Bytes, BytesMut};
+    use hex_literal::hex;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    /// Metadata v1 request for all topics from client id "test"
+    const METADATA_REQUEST: [u8; 22] = hex!("00000012 0003 0001 00000007 0004 74657374 ffffffff");
+
+    /// Metadata v1 response with a single broker 1 at localhost:9092 in rack null, controller 1 and no topics
+    const METADATA_RESPONSE: [u8; 41] = hex!(
+        "00000025 00000007 00000001 00000001 0009 6c6f63616c686f7374 00002384 ffff 00000001 00000000"
+    );
+
+    #[test]
+    fn test_metadata_rewrite() {
+        let (mut source_decoder, mut source_encoder) = KafkaCodecBuilder::new().build();
+        let mut sink_codec = KafkaCodec::new();
+
+        // client -> shotover
+        let mut requests = source_decoder
+            .decode(&mut BytesMut::from(METADATA_REQUEST.as_slice()))
+            .unwrap()
+            .unwrap();
+        match requests[0].frame().unwrap() {
+            Frame::Kafka(KafkaFrame::Request(request)) => {
+                assert_eq!(request.header.api_key, api_key::METADATA);
+                assert_eq!(request.header.correlation_id, 7);
+                assert_eq!(request.header.client_id, Some(Bytes::from("test")));
+            }
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+
+        // shotover -> kafka
+        let mut dst = BytesMut::new();
+        sink_codec.encode(requests, &mut dst).unwrap();
+        assert_eq!(&dst, METADATA_REQUEST.as_slice());
+
+        // kafka -> shotover
+        let mut responses = sink_codec
+            .decode(&mut BytesMut::from(METADATA_RESPONSE.as_slice()))
+            .unwrap()
+            .unwrap();
+        match responses[0].frame().unwrap() {
+            Frame::Kafka(KafkaFrame::Response(response)) => {
+                match &response.body {
+                    KafkaResponseBody::Metadata(metadata) => assert_eq!(
+                        metadata.brokers,
+                        vec![MetadataBroker {
+                            node_id: 1,
+                            host: Bytes::from("localhost"),
+                            port: 9092,
+                            rack: None,
+                            tagged_fields: Bytes::new(),
+                        }]
+                    ),
+                    body => panic!("unexpected body {body:?}"),
+                }
+                response.rewrite_broker_addresses(|host, port| {
+                    *host = Bytes::from("shotover");
+                    *port = 9192;
+                });
+            }
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+        responses[0].invalidate_cache();
+
+        // shotover -> client
+        let mut dst = BytesMut::new();
+        source_encoder.encode(responses, &mut dst).unwrap();
+        assert_eq!(
+            &dst,
+            hex!("00000024 00000007 00000001 00000001 0008 73686f746f766572 000023e8 ffff 00000001 00000000")
+                .as_slice()
+        );
+    }
+
+    #[test]
+    fn test_incomplete_message() {
+        let (mut decoder, _) = KafkaCodecBuilder::new().build();
+        let mut src = BytesMut::from(&METADATA_REQUEST[..10]);
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+        assert_eq!(src.len(), 10);
+    }
+}
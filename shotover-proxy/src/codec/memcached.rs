@@ -0,0 +1,359 @@
+use crate::codec::CodecBuilder;
+use crate::frame::memcached::{
+    MemcachedCommand, MemcachedFrame, MemcachedProtocol, MemcachedResponse, MemcachedStatus,
+};
+use crate::frame::{Frame, MessageType};
+use crate::message::{Encodable, Message, Messages};
+use crate::server::CodecReadError;
+use anyhow::{anyhow, Result};
+use bytes::BytesMut;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Decodes requests and encodes responses when facing a client, decodes responses and encodes requests when facing memcached.
+#[derive(Debug, Clone)]
+pub struct MemcachedCodec {
+    messages: Messages,
+    /// Only present on codecs facing a client, where shotover is acting as the memcached server.
+    client_state: Option<Arc<Mutex<ClientState>>>,
+    /// Set once the client has sent a quit, holds the protocol and opaque of the quit request.
+    /// Any further bytes on the connection are ignored.
+    quit: Option<(MemcachedProtocol, u32)>,
+}
+
+impl Default for MemcachedCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemcachedCodec {
+    pub fn new() -> MemcachedCodec {
+        MemcachedCodec {
+            messages: vec![],
+            client_state: None,
+            quit: None,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MemcachedCodecBuilder {}
+
+impl MemcachedCodecBuilder {
+    pub fn new() -> Self {
+        MemcachedCodecBuilder {}
+    }
+}
+
+impl CodecBuilder for MemcachedCodecBuilder {
+    type Decoder = MemcachedCodec;
+    type Encoder = MemcachedCodec;
+    fn build(&self) -> (MemcachedCodec, MemcachedCodec) {
+        let codec = MemcachedCodec {
+            messages: vec![],
+            client_state: Some(Arc::new(Mutex::new(ClientState::default()))),
+            quit: None,
+        };
+        (codec.clone(), codec)
+    }
+}
+
+/// The `noreply` option and quiet opcodes are stripped from requests before they are sent to memcached,
+/// so that every request receives exactly one response and the transforms can rely on responses lining up with requests.
+/// The codec then drops the responses that the client did not ask for.
+#[derive(Debug, Default)]
+struct ClientState {
+    /// Whether each in flight request asked for its response to be suppressed
+    pending_noreply: VecDeque<bool>,
+}
+
+/// Returns true if a client that sent a request with `noreply` set still expects this response.
+/// The text protocol never responds to such a request, while the binary protocol still reports misses on gets and failures on everything else.
+fn quiet_response_is_sent(response: &MemcachedResponse) -> bool {
+    match response.protocol {
+        MemcachedProtocol::Text => false,
+        MemcachedProtocol::Binary => match &response.status {
+            MemcachedStatus::Values(values) => !values.is_empty(),
+            MemcachedStatus::NotFound => !response.command.map_or(false, |x| x.is_retrieval()),
+            MemcachedStatus::NotStored | MemcachedStatus::Exists | MemcachedStatus::Error(_) => {
+                true
+            }
+            _ => false,
+        },
+    }
+}
+
+impl Decoder for MemcachedCodec {
+    type Item = Messages;
+    type Error = CodecReadError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, CodecReadError> {
+        if let Some((protocol, opaque)) = self.quit {
+            src.clear();
+            return Err(quit_response(protocol, opaque));
+        }
+
+        loop {
+            let decoded = match &self.client_state {
+                Some(_) => MemcachedFrame::decode_request(src),
+                None => MemcachedFrame::decode_response(src),
+            }
+            .map_err(|e| CodecReadError::Parser(e.context("Error decoding memcached frame")))?;
+
+            match decoded {
+                Some((mut frame, bytes)) => {
+                    tracing::debug!(
+                        "incoming memcached message:\n{}",
+                        pretty_hex::pretty_hex(&bytes)
+                    );
+                    let message = match (&self.client_state, &mut frame) {
+                        (Some(state), MemcachedFrame::Request(request)) => {
+                            if request.command == MemcachedCommand::Quit {
+                                // Any requests received before the quit still need to be handled, so close the connection on the next call.
+                                if self.messages.is_empty() {
+                                    return Err(quit_response(request.protocol, request.opaque));
+                                }
+                                self.quit = Some((request.protocol, request.opaque));
+                                return Ok(Some(std::mem::take(&mut self.messages)));
+                            }
+
+                            let noreply = request.noreply;
+                            state.lock().unwrap().pending_noreply.push_back(noreply);
+                            if noreply {
+                                request.noreply = false;
+                                Message::from_frame(Frame::Memcached(frame))
+                            } else {
+                                Message::from_bytes_and_frame(bytes, Frame::Memcached(frame))
+                            }
+                        }
+                        _ => Message::from_bytes_and_frame(bytes, Frame::Memcached(frame)),
+                    };
+                    self.messages.push(message);
+                }
+                None => {
+                    if self.messages.is_empty() || !src.is_empty() {
+                        return Ok(None);
+                    } else {
+                        return Ok(Some(std::mem::take(&mut self.messages)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The binary protocol acknowledges a quit before closing the connection, the text protocol just closes the connection.
+fn quit_response(protocol: MemcachedProtocol, opaque: u32) -> CodecReadError {
+    CodecReadError::RespondAndThenCloseConnection(match protocol {
+        MemcachedProtocol::Text => vec![],
+        MemcachedProtocol::Binary => vec![Message::from_frame(Frame::Memcached(
+            MemcachedFrame::Response(MemcachedResponse {
+                protocol,
+                command: Some(MemcachedCommand::Quit),
+                status: MemcachedStatus::Ok,
+                cas: 0,
+                opaque,
+            }),
+        ))],
+    })
+}
+
+fn encode_message(message: Message, dst: &mut BytesMut) -> Result<()> {
+    match message.into_encodable(MessageType::Memcached)? {
+        Encodable::Bytes(bytes) => {
+            dst.extend_from_slice(&bytes);
+            Ok(())
+        }
+        Encodable::Frame(frame) => frame.into_memcached()?.encode(dst),
+    }
+}
+
+/// Encodes a response to a client, dropping or marking it as quiet if the request asked for that
+fn encode_client_response(
+    state: &mut ClientState,
+    mut message: Message,
+    dst: &mut BytesMut,
+) -> Result<()> {
+    let response = match message.frame() {
+        Some(Frame::Memcached(MemcachedFrame::Response(response))) => response,
+        _ => {
+            state.pending_noreply.pop_front();
+            return encode_message(message, dst);
+        }
+    };
+    if response.command == Some(MemcachedCommand::Quit) {
+        // Generated by the codec itself and so does not correspond to any pending request
+        return encode_message(message, dst);
+    }
+
+    if !state.pending_noreply.pop_front().unwrap_or(false) {
+        return encode_message(message, dst);
+    }
+    if !quiet_response_is_sent(response) {
+        return Ok(());
+    }
+
+    let quiet_opcode = response
+        .command
+        .map(|command| command.binary_opcode(true))
+        .ok_or_else(|| anyhow!("binary protocol responses must specify their command"))?;
+    let start = dst.len();
+    encode_message(message, dst)?;
+    dst[start + 1] = quiet_opcode;
+    Ok(())
+}
+
+impl Encoder<Messages> for MemcachedCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, item: Messages, dst: &mut BytesMut) -> Result<()> {
+        item.into_iter().try_for_each(|m| {
+            let start = dst.len();
+            let result = match &self.client_state {
+                Some(state) => encode_client_response(&mut state.lock().unwrap(), m, dst),
+                None => encode_message(m, dst),
+            };
+            tracing::debug!(
+                "outgoing memcached message:\n{}",
+                pretty_hex::pretty_hex(&&dst[start..])
+            );
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod memcached_tests {
+    use crate::codec::memcached::{MemcachedCodec, MemcachedCodecBuilder};
+    use crate::codec::CodecBuilder;
+    use crate::frame::memcached::{
+        MemcachedCommand, MemcachedFrame, MemcachedProtocol, MemcachedRequest,
+    };
+    use crate::frame::Frame;
+    use crate::message::Message;
+    use bytes::{Bytes, BytesMut};
+    use hex_literal::hex;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn test_frame(codec: &mut MemcachedCodec, raw_frame: &[u8]) {
+        let mut messages = codec
+            .decode(&mut BytesMut::from(raw_frame))
+            .unwrap()
+            .unwrap();
+        for message in &mut messages {
+            message.frame().unwrap();
+        }
+
+        let mut dest = BytesMut::new();
+        codec.encode(messages, &mut dest).unwrap();
+        assert_eq!(raw_frame, &dest);
+    }
+
+    #[test]
+    fn test_text_response_codec() {
+        let mut codec = MemcachedCodec::new();
+        test_frame(&mut codec, b"STORED\r\n");
+        test_frame(
+            &mut codec,
+            b"VALUE foo 0 3\r\nbar\r\nVALUE baz 5 0 12\r\n\r\nEND\r\n",
+        );
+        test_frame(&mut codec, b"42\r\n");
+        test_frame(&mut codec, b"SERVER_ERROR out of memory\r\n");
+    }
+
+    #[test]
+    fn test_text_request_codec() {
+        let (mut decoder, _) = MemcachedCodecBuilder::new().build();
+        let mut messages = decoder
+            .decode(&mut BytesMut::from(
+                b"set foo 1 0 3\r\nbar\r\nget foo baz\r\n".as_slice(),
+            ))
+            .unwrap()
+            .unwrap();
+
+        let mut set = MemcachedRequest::new(MemcachedProtocol::Text, MemcachedCommand::Set);
+        set.keys = vec![Bytes::from("foo")];
+        set.flags = 1;
+        set.value = Bytes::from("bar");
+        let mut get = MemcachedRequest::new(MemcachedProtocol::Text, MemcachedCommand::Get);
+        get.keys = vec![Bytes::from("foo"), Bytes::from("baz")];
+        assert_eq!(
+            messages[0].frame().unwrap(),
+            &Frame::Memcached(MemcachedFrame::Request(set))
+        );
+        assert_eq!(
+            messages[1].frame().unwrap(),
+            &Frame::Memcached(MemcachedFrame::Request(get))
+        );
+    }
+
+    #[test]
+    fn test_incomplete_request() {
+        let (mut decoder, _) = MemcachedCodecBuilder::new().build();
+        let mut src = BytesMut::from(b"set foo 0 0 3\r\nba".as_slice());
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+        assert_eq!(src.len(), 17);
+    }
+
+    const BINARY_GET_REQUEST: [u8; 27] =
+        hex!("800000030000000000000003000000070000000000000000666f6f");
+
+    const BINARY_GET_RESPONSE: [u8; 31] =
+        hex!("8100000004000000000000070000000700000000000000010000000062617a");
+
+    #[test]
+    fn test_binary_codec() {
+        let (mut decoder, mut encoder) = MemcachedCodecBuilder::new().build();
+        let mut messages = decoder
+            .decode(&mut BytesMut::from(BINARY_GET_REQUEST.as_slice()))
+            .unwrap()
+            .unwrap();
+        let mut get = MemcachedRequest::new(MemcachedProtocol::Binary, MemcachedCommand::Get);
+        get.keys = vec![Bytes::from("foo")];
+        get.opaque = 7;
+        assert_eq!(
+            messages[0].frame().unwrap(),
+            &Frame::Memcached(MemcachedFrame::Request(get))
+        );
+
+        let mut response = Message::from_bytes(
+            Bytes::from_static(&BINARY_GET_RESPONSE),
+            crate::frame::MessageType::Memcached,
+        );
+        response.frame().unwrap();
+        let mut dest = BytesMut::new();
+        encoder.encode(vec![response], &mut dest).unwrap();
+        assert_eq!(&BINARY_GET_RESPONSE, &dest);
+    }
+
+    #[test]
+    fn test_noreply() {
+        let (mut decoder, mut encoder) = MemcachedCodecBuilder::new().build();
+        let mut messages = decoder
+            .decode(&mut BytesMut::from(
+                b"set foo 0 0 3 noreply\r\nbar\r\nset foo 0 0 3\r\nbar\r\n".as_slice(),
+            ))
+            .unwrap()
+            .unwrap();
+        match messages[0].frame().unwrap() {
+            Frame::Memcached(MemcachedFrame::Request(request)) => assert!(!request.noreply),
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+
+        let responses = vec![
+            Message::from_bytes(
+                Bytes::from_static(b"STORED\r\n"),
+                crate::frame::MessageType::Memcached,
+            ),
+            Message::from_bytes(
+                Bytes::from_static(b"STORED\r\n"),
+                crate::frame::MessageType::Memcached,
+            ),
+        ];
+        let mut dest = BytesMut::new();
+        encoder.encode(responses, &mut dest).unwrap();
+        assert_eq!(b"STORED\r\n", &dest);
+    }
+}
@@ -1,2 +1,18 @@
+use crate::server::{CodecReadHalf, CodecWriteHalf};
+
 pub mod cassandra;
+pub mod kafka;
+pub mod memcached;
+pub mod postgres;
 pub mod redis;
+
+/// Creates the decoder and encoder used for a single connection.
+///
+/// Some protocols need to track connection level state that must be shared between the read and write halves of a connection,
+/// e.g. whether the cassandra v5 handshake has completed and messages are now wrapped in segments.
+/// That state must never be shared between different connections, so sources hold onto a builder and call `build` once per connection.
+pub trait CodecBuilder: Clone + Send {
+    type Decoder: CodecReadHalf;
+    type Encoder: CodecWriteHalf;
+    fn build(&self) -> (Self::Decoder, Self::Encoder);
+}
@@ -0,0 +1,542 @@
+use crate::codec::CodecBuilder;
+use crate::frame::postgres::{
+    backend_tag, is_buffered_by_server, message_len, BackendMessage, FrontendMessage, PostgresFrame,
+};
+use crate::frame::{Frame, MessageType};
+use crate::message::{Encodable, Message, Messages};
+use crate::server::CodecReadError;
+use anyhow::{anyhow, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Decodes requests and encodes responses when facing a client, decodes responses and encodes requests when facing postgres.
+#[derive(Debug, Clone)]
+pub struct PostgresCodec {
+    messages: Messages,
+    /// Only present on codecs facing postgres.
+    /// Postgres responds to each request with any number of messages and does not mark which request they belong to.
+    /// So the encoder records what each request will be answered with, which the decoder uses to split the incoming messages into one response per request.
+    sink_state: Option<Arc<Mutex<SinkState>>>,
+}
+
+impl Default for PostgresCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostgresCodec {
+    /// Create a codec for a single connection to postgres
+    pub fn new() -> PostgresCodec {
+        PostgresCodec {
+            messages: vec![],
+            sink_state: Some(Arc::new(Mutex::new(SinkState::default()))),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct PostgresCodecBuilder {}
+
+impl PostgresCodecBuilder {
+    pub fn new() -> Self {
+        PostgresCodecBuilder {}
+    }
+}
+
+impl CodecBuilder for PostgresCodecBuilder {
+    type Decoder = PostgresCodec;
+    type Encoder = PostgresCodec;
+    fn build(&self) -> (PostgresCodec, PostgresCodec) {
+        let codec = PostgresCodec {
+            messages: vec![],
+            sink_state: None,
+        };
+        (codec.clone(), codec)
+    }
+}
+
+/// What postgres will send once it has processed a frontend message
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Awaiting {
+    /// Startup and password messages are answered once postgres asks for further authentication, becomes ready for queries or rejects the connection
+    Authentication,
+    /// Query and FunctionCall are answered once postgres becomes ready for the next query or starts a COPY FROM STDIN
+    ReadyForQuery,
+    Sync,
+    /// Parse, Bind, Describe, Execute or Close, each is answered by a single completion message
+    Extended(u8),
+    /// CopyDone or CopyFail
+    CopyEnd,
+    /// Postgres never answers Flush, CopyData or Terminate
+    Nothing,
+}
+
+impl Awaiting {
+    fn new(message: &FrontendMessage) -> Self {
+        match message {
+            FrontendMessage::Startup { .. } | FrontendMessage::Password(_) => {
+                Awaiting::Authentication
+            }
+            FrontendMessage::Query(_) | FrontendMessage::FunctionCall(_) => Awaiting::ReadyForQuery,
+            FrontendMessage::Sync => Awaiting::Sync,
+            FrontendMessage::Parse { .. } => Awaiting::Extended(b'P'),
+            FrontendMessage::Bind(_) => Awaiting::Extended(b'B'),
+            FrontendMessage::Describe(_) => Awaiting::Extended(b'D'),
+            FrontendMessage::Execute(_) => Awaiting::Extended(b'E'),
+            FrontendMessage::Close(_) => Awaiting::Extended(b'C'),
+            FrontendMessage::CopyDone | FrontendMessage::CopyFail(_) => Awaiting::CopyEnd,
+            FrontendMessage::Flush
+            | FrontendMessage::CopyData(_)
+            | FrontendMessage::Terminate
+            | FrontendMessage::SslRequest
+            | FrontendMessage::GssEncRequest
+            | FrontendMessage::CancelRequest { .. } => Awaiting::Nothing,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CopyIn {
+    /// Started by a Query, ended by postgres becoming ready for the next query
+    Simple,
+    /// Started by an Execute, ended by a CommandComplete
+    Extended,
+}
+
+#[derive(Debug, Default)]
+struct SinkState {
+    /// For each request that has not been completely answered, what each of its messages is awaiting
+    requests: VecDeque<VecDeque<Awaiting>>,
+    /// The messages received so far in response to the request at the front of `requests`
+    response: Vec<BackendMessage>,
+    response_bytes: BytesMut,
+    /// Postgres hit an error while processing an extended query and will ignore every message until the next Sync
+    skipping: bool,
+    copy_in: Option<CopyIn>,
+}
+
+impl SinkState {
+    /// Resolves frontend messages that postgres will not answer, and completes any requests that have been fully answered.
+    fn resolve_unanswered(&mut self, messages: &mut Messages) {
+        while let Some(request) = self.requests.front_mut() {
+            while let Some(awaiting) = request.front() {
+                let unanswered = match awaiting {
+                    Awaiting::Nothing => true,
+                    // Sync is ignored while a COPY FROM STDIN is in progress
+                    Awaiting::Sync => self.copy_in.is_some(),
+                    Awaiting::CopyEnd if self.copy_in.is_none() => true,
+                    _ => self.skipping,
+                };
+                if unanswered {
+                    request.pop_front();
+                } else {
+                    break;
+                }
+            }
+
+            if request.is_empty() {
+                self.requests.pop_front();
+                messages.push(Message::from_bytes_and_frame(
+                    self.response_bytes.split().freeze(),
+                    Frame::Postgres(PostgresFrame::Response(std::mem::take(&mut self.response))),
+                ));
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Adds the message to the current response and returns true when it answers the frontend message currently awaiting a response.
+    fn receive(&mut self, tag: u8, body: Bytes, bytes: &[u8]) -> bool {
+        self.response.push(BackendMessage::Tagged {
+            tag,
+            body: body.clone(),
+        });
+        self.response_bytes.extend_from_slice(bytes);
+
+        let awaiting = match self.requests.front().and_then(|x| x.front()) {
+            Some(awaiting) => *awaiting,
+            // Asynchronous messages such as notifications can arrive at any time, they are included in the next response
+            None => return false,
+        };
+
+        match (awaiting, tag) {
+            // Notices, parameter changes and notifications can arrive in the middle of any response
+            (
+                _,
+                backend_tag::NOTICE_RESPONSE
+                | backend_tag::PARAMETER_STATUS
+                | backend_tag::NOTIFICATION_RESPONSE,
+            ) => false,
+            // AuthenticationOk and AuthenticationSASLFinal are immediately followed by more messages from postgres,
+            // every other authentication message is a request for the client to respond.
+            (Awaiting::Authentication, backend_tag::AUTHENTICATION) => {
+                body.len() < 4 || !matches!((&body[..]).get_i32(), 0 | 12)
+            }
+            (
+                Awaiting::Authentication,
+                backend_tag::READY_FOR_QUERY | backend_tag::ERROR_RESPONSE,
+            ) => true,
+            (Awaiting::ReadyForQuery, backend_tag::READY_FOR_QUERY) => true,
+            (
+                Awaiting::ReadyForQuery,
+                backend_tag::COPY_IN_RESPONSE | backend_tag::COPY_BOTH_RESPONSE,
+            ) => {
+                self.copy_in = Some(CopyIn::Simple);
+                true
+            }
+            (Awaiting::Sync, backend_tag::READY_FOR_QUERY) => {
+                self.skipping = false;
+                true
+            }
+            (Awaiting::Extended(_), backend_tag::ERROR_RESPONSE) => {
+                self.skipping = true;
+                true
+            }
+            (Awaiting::Extended(b'P'), backend_tag::PARSE_COMPLETE) => true,
+            (Awaiting::Extended(b'B'), backend_tag::BIND_COMPLETE) => true,
+            (Awaiting::Extended(b'C'), backend_tag::CLOSE_COMPLETE) => true,
+            (Awaiting::Extended(b'D'), backend_tag::ROW_DESCRIPTION | backend_tag::NO_DATA) => true,
+            (
+                Awaiting::Extended(b'E'),
+                backend_tag::COMMAND_COMPLETE
+                | backend_tag::EMPTY_QUERY_RESPONSE
+                | backend_tag::PORTAL_SUSPENDED,
+            ) => true,
+            (
+                Awaiting::Extended(b'E'),
+                backend_tag::COPY_IN_RESPONSE | backend_tag::COPY_BOTH_RESPONSE,
+            ) => {
+                self.copy_in = Some(CopyIn::Extended);
+                true
+            }
+            (Awaiting::CopyEnd, tag) => match (self.copy_in, tag) {
+                (Some(CopyIn::Simple), backend_tag::READY_FOR_QUERY)
+                | (Some(CopyIn::Extended), backend_tag::COMMAND_COMPLETE) => {
+                    self.copy_in = None;
+                    true
+                }
+                (Some(CopyIn::Extended), backend_tag::ERROR_RESPONSE) => {
+                    self.copy_in = None;
+                    self.skipping = true;
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl PostgresCodec {
+    fn decode_requests(&mut self, src: &mut BytesMut) -> Result<()> {
+        // A request is only passed on once the client reaches a message that postgres will act on immediately,
+        // otherwise the sink could end up waiting on a response that postgres will not send until it receives the rest of the request.
+        let mut end = 0;
+        while let Some(len) = message_len(&src[end..])? {
+            if src.len() < end + len {
+                src.reserve(end + len - src.len());
+                break;
+            }
+            let buffered = is_buffered_by_server(&src[end..]);
+            end += len;
+            if !buffered {
+                let bytes = src.split_to(end).freeze();
+                tracing::debug!(
+                    "incoming postgres request:\n{}",
+                    pretty_hex::pretty_hex(&bytes)
+                );
+                self.messages
+                    .push(Message::from_bytes(bytes, MessageType::Postgres));
+                end = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_responses(&mut self, src: &mut BytesMut) -> Result<()> {
+        let mut state = self.sink_state.as_ref().unwrap().lock().unwrap();
+        loop {
+            state.resolve_unanswered(&mut self.messages);
+
+            let len = match message_len(src)? {
+                Some(len) if src.len() >= len => len,
+                Some(len) => {
+                    src.reserve(len - src.len());
+                    break;
+                }
+                None => break,
+            };
+            let bytes = src.split_to(len).freeze();
+            tracing::debug!(
+                "incoming postgres message:\n{}",
+                pretty_hex::pretty_hex(&bytes)
+            );
+            let (tag, body) = match BackendMessage::from_bytes(bytes.clone())? {
+                BackendMessage::Tagged { tag, body } => (tag, body),
+                BackendMessage::EncryptionNotSupported => unreachable!(),
+            };
+            if state.receive(tag, body, &bytes) {
+                state.requests.front_mut().unwrap().pop_front();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Decoder for PostgresCodec {
+    type Item = Messages;
+    type Error = CodecReadError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, CodecReadError> {
+        let result = if self.sink_state.is_some() {
+            self.decode_responses(src)
+        } else {
+            self.decode_requests(src)
+        };
+        result.map_err(|e| CodecReadError::Parser(e.context("Error decoding postgres message")))?;
+
+        if self.messages.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(std::mem::take(&mut self.messages)))
+        }
+    }
+}
+
+impl Encoder<Messages> for PostgresCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, item: Messages, dst: &mut BytesMut) -> Result<()> {
+        item.into_iter().try_for_each(|mut m| {
+            if let Some(sink_state) = &self.sink_state {
+                match m.frame() {
+                    Some(Frame::Postgres(frame)) => {
+                        let frame: &PostgresFrame = frame;
+                        if let PostgresFrame::Request(messages) = frame {
+                            if frame.expects_response() {
+                                let awaiting = messages.iter().map(Awaiting::new).collect();
+                                sink_state.lock().unwrap().requests.push_back(awaiting);
+                            }
+                        } else {
+                            return Err(anyhow!("Cannot send a postgres response to postgres"));
+                        }
+                    }
+                    frame => {
+                        return Err(anyhow!("Cannot send {frame:?} to postgres"));
+                    }
+                }
+            }
+
+            let start = dst.len();
+            match m.into_encodable(MessageType::Postgres)? {
+                Encodable::Bytes(bytes) => dst.extend_from_slice(&bytes),
+                Encodable::Frame(frame) => frame.into_postgres()?.encode(dst),
+            }
+            tracing::debug!(
+                "outgoing postgres message:\n{}",
+                pretty_hex::pretty_hex(&&dst[start..])
+            );
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod postgres_tests {
+    use crate::codec::postgres::{PostgresCodec, PostgresCodecBuilder};
+    use crate::codec::CodecBuilder;
+    use crate::frame::postgres::{backend_tag, BackendMessage, FrontendMessage};
+    use crate::frame::{Frame, PostgresFrame};
+    use crate::message::Message;
+    use bytes::{Bytes, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn request(messages: Vec<FrontendMessage>) -> Message {
+        Message::from_frame(Frame::Postgres(PostgresFrame::Request(messages)))
+    }
+
+    fn encode_request(messages: Vec<FrontendMessage>) -> BytesMut {
+        let mut dst = BytesMut::new();
+        PostgresFrame::Request(messages).encode(&mut dst);
+        dst
+    }
+
+    fn encode_response(tags: &[u8]) -> BytesMut {
+        let mut dst = BytesMut::new();
+        PostgresFrame::Response(
+            tags.iter()
+                .map(|tag| BackendMessage::Tagged {
+                    tag: *tag,
+                    body: Bytes::new(),
+                })
+                .collect(),
+        )
+        .encode(&mut dst);
+        dst
+    }
+
+    fn response_tags(mut message: Message) -> Vec<u8> {
+        match message.frame() {
+            Some(Frame::Postgres(PostgresFrame::Response(messages))) => {
+                messages.iter().map(|x| x.tag().unwrap()).collect()
+            }
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+    }
+
+    fn parse(query: &str) -> FrontendMessage {
+        FrontendMessage::Parse {
+            statement: Bytes::new(),
+            query: query.to_owned(),
+            parameter_types: vec![],
+        }
+    }
+
+    #[test]
+    fn test_request_waits_for_sync() {
+        let (mut decoder, _) = PostgresCodecBuilder::new().build();
+        let bind = FrontendMessage::Bind(Bytes::from_static(&[0; 8]));
+        let execute = FrontendMessage::Execute(Bytes::from_static(&[0; 5]));
+
+        let mut src = encode_request(vec![parse("SELECT 1"), bind.clone()]);
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(&encode_request(vec![
+            execute.clone(),
+            FrontendMessage::Sync,
+            FrontendMessage::Query("SELECT 2".to_owned()),
+        ]));
+        let mut messages = decoder.decode(&mut src).unwrap().unwrap();
+        assert!(src.is_empty());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].frame().unwrap(),
+            &mut Frame::Postgres(PostgresFrame::Request(vec![
+                parse("SELECT 1"),
+                bind,
+                execute,
+                FrontendMessage::Sync
+            ]))
+        );
+        assert_eq!(
+            messages[1].frame().unwrap(),
+            &mut Frame::Postgres(PostgresFrame::Request(vec![FrontendMessage::Query(
+                "SELECT 2".to_owned()
+            )]))
+        );
+    }
+
+    #[test]
+    fn test_responses_are_split_per_request() {
+        use backend_tag::*;
+
+        let mut codec = PostgresCodec::new();
+        codec
+            .encode(
+                vec![
+                    request(vec![FrontendMessage::Query("SELECT 1".to_owned())]),
+                    request(vec![
+                        parse("SELEC 1"),
+                        FrontendMessage::Bind(Bytes::from_static(&[0; 8])),
+                        FrontendMessage::Execute(Bytes::from_static(&[0; 5])),
+                        FrontendMessage::Sync,
+                    ]),
+                    request(vec![FrontendMessage::Flush]),
+                    request(vec![FrontendMessage::Query("SELECT 2".to_owned())]),
+                ],
+                &mut BytesMut::new(),
+            )
+            .unwrap();
+
+        let mut src = encode_response(&[
+            ROW_DESCRIPTION,
+            DATA_ROW,
+            COMMAND_COMPLETE,
+            READY_FOR_QUERY,
+            // The failed parse causes postgres to skip the bind and execute
+            ERROR_RESPONSE,
+            READY_FOR_QUERY,
+            NOTICE_RESPONSE,
+            ROW_DESCRIPTION,
+            DATA_ROW,
+            COMMAND_COMPLETE,
+        ]);
+        let responses: Vec<Vec<u8>> = codec
+            .decode(&mut src)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(response_tags)
+            .collect();
+        assert_eq!(
+            responses,
+            vec![
+                vec![ROW_DESCRIPTION, DATA_ROW, COMMAND_COMPLETE, READY_FOR_QUERY],
+                vec![ERROR_RESPONSE, READY_FOR_QUERY],
+            ]
+        );
+
+        // The final response is only complete once postgres is ready for the next query
+        let mut src = encode_response(&[READY_FOR_QUERY]);
+        let responses: Vec<Vec<u8>> = codec
+            .decode(&mut src)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(response_tags)
+            .collect();
+        assert_eq!(
+            responses,
+            vec![vec![
+                NOTICE_RESPONSE,
+                ROW_DESCRIPTION,
+                DATA_ROW,
+                COMMAND_COMPLETE,
+                READY_FOR_QUERY
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_copy_in() {
+        use backend_tag::*;
+
+        let mut codec = PostgresCodec::new();
+        codec
+            .encode(
+                vec![request(vec![FrontendMessage::Query(
+                    "COPY foo FROM STDIN".to_owned(),
+                )])],
+                &mut BytesMut::new(),
+            )
+            .unwrap();
+        let mut responses = codec
+            .decode(&mut encode_response(&[COPY_IN_RESPONSE]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(response_tags(responses.remove(0)), vec![COPY_IN_RESPONSE]);
+
+        codec
+            .encode(
+                vec![
+                    request(vec![FrontendMessage::CopyData(Bytes::from("1\n"))]),
+                    request(vec![FrontendMessage::CopyDone]),
+                ],
+                &mut BytesMut::new(),
+            )
+            .unwrap();
+        let mut responses = codec
+            .decode(&mut encode_response(&[COMMAND_COMPLETE, READY_FOR_QUERY]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            response_tags(responses.remove(0)),
+            vec![COMMAND_COMPLETE, READY_FOR_QUERY]
+        );
+    }
+}
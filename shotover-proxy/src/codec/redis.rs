@@ -1,16 +1,25 @@
-use crate::frame::RedisFrame;
+use crate::codec::CodecBuilder;
 use crate::frame::{Frame, MessageType};
+use crate::frame::{RedisFrame, Resp3Frame};
 use crate::message::{Encodable, Message, Messages, QueryType};
 use crate::server::CodecReadError;
 use anyhow::{anyhow, Result};
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
+use bytes_utils::Str;
+use itertools::Itertools;
 use redis_protocol::resp2::prelude::decode_mut;
 use redis_protocol::resp2::prelude::encode_bytes;
+use redis_protocol::resp3::encode::complete::encode_bytes as encode_resp3_bytes;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 use tokio_util::codec::{Decoder, Encoder};
 
 #[derive(Debug, Clone)]
 pub struct RedisCodec {
     messages: Messages,
+    /// Only present on codecs facing a client, where shotover is acting as the redis server.
+    /// Connections to redis always use RESP2 so this state is not needed there.
+    client_state: Option<Arc<Mutex<ClientState>>>,
 }
 
 #[inline]
@@ -38,7 +47,232 @@ impl Default for RedisCodec {
 
 impl RedisCodec {
     pub fn new() -> RedisCodec {
-        RedisCodec { messages: vec![] }
+        RedisCodec {
+            messages: vec![],
+            client_state: None,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct RedisCodecBuilder {}
+
+impl RedisCodecBuilder {
+    pub fn new() -> Self {
+        RedisCodecBuilder {}
+    }
+}
+
+impl CodecBuilder for RedisCodecBuilder {
+    type Decoder = RedisCodec;
+    type Encoder = RedisCodec;
+    fn build(&self) -> (RedisCodec, RedisCodec) {
+        let codec = RedisCodec {
+            messages: vec![],
+            client_state: Some(Arc::new(Mutex::new(ClientState::default()))),
+        };
+        (codec.clone(), codec)
+    }
+}
+
+/// The version of RESP used to communicate with a client, negotiated via the HELLO command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RespVersion {
+    Resp2,
+    Resp3,
+}
+
+/// Shotover always communicates with redis in RESP2, so when a client negotiates RESP3 its responses must be translated.
+/// RESP2 responses dont carry enough type information to do this on their own, so the type of the response is determined from the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseType {
+    /// Translated value by value, e.g. a null bulk string becomes a null and an integer becomes a number
+    Default,
+    /// An array of alternating keys and values that becomes a map
+    Map,
+    /// An array that becomes a set
+    Set,
+    /// A bulk string that becomes a double
+    Double,
+    /// An array of bulk strings that each become a double
+    DoubleArray,
+    /// A flat array of alternating members and values that becomes an array of [member, value] pairs.
+    /// When the values are scores they become doubles.
+    Pairs { scores: bool },
+    /// An array of arrays of alternating keys and values that becomes an array of maps
+    MapArray,
+    /// The response to a HELLO, the connection switches to the requested version once it succeeds.
+    /// A HELLO without a version just returns information about the connection.
+    Hello(Option<RespVersion>),
+    /// A (P|S)(UN)SUBSCRIBE request, which is responded to with the confirmation of its first channel.
+    /// The confirmations of any other channels are sent as pushes.
+    /// In RESP3 every confirmation is a push.
+    Subscription,
+    /// A PING, which has a different response when sent while subscribed
+    Ping,
+    /// A MONITOR, once it succeeds the output of MONITOR is sent alongside the responses to any further requests
+    Monitor,
+    /// A RESET, which switches the connection back to RESP2 and drops all subscriptions
+    Reset,
+}
+
+impl ResponseType {
+    fn for_request(frame: &RedisFrame) -> ResponseType {
+        let args = match frame {
+            RedisFrame::Array(args) => args,
+            _ => return ResponseType::Default,
+        };
+        let command = match args.first() {
+            Some(RedisFrame::BulkString(command)) => command.to_ascii_uppercase(),
+            _ => return ResponseType::Default,
+        };
+        match command.as_slice() {
+            b"HGETALL" => ResponseType::Map,
+            b"CONFIG" => match args.get(1) {
+                Some(RedisFrame::BulkString(sub_command))
+                    if sub_command.eq_ignore_ascii_case(b"GET") =>
+                {
+                    ResponseType::Map
+                }
+                _ => ResponseType::Default,
+            },
+            b"SMEMBERS" | b"SINTER" | b"SUNION" | b"SDIFF" => ResponseType::Set,
+            b"ZRANGE" | b"ZRANGEBYSCORE" | b"ZREVRANGE" | b"ZREVRANGEBYSCORE" | b"ZUNION"
+            | b"ZINTER" | b"ZDIFF" | b"ZRANDMEMBER"
+                if has_arg(args, b"WITHSCORES") =>
+            {
+                ResponseType::Pairs { scores: true }
+            }
+            b"HRANDFIELD" if has_arg(args, b"WITHVALUES") => ResponseType::Pairs { scores: false },
+            b"XINFO" => match args.get(1) {
+                Some(RedisFrame::BulkString(sub_command))
+                    if sub_command.eq_ignore_ascii_case(b"STREAM") =>
+                {
+                    ResponseType::Map
+                }
+                Some(RedisFrame::BulkString(sub_command))
+                    if sub_command.eq_ignore_ascii_case(b"GROUPS")
+                        || sub_command.eq_ignore_ascii_case(b"CONSUMERS") =>
+                {
+                    ResponseType::MapArray
+                }
+                _ => ResponseType::Default,
+            },
+            b"ZSCORE" | b"ZINCRBY" => ResponseType::Double,
+            b"ZMSCORE" => ResponseType::DoubleArray,
+            b"HELLO" => match args.get(1) {
+                None => ResponseType::Hello(None),
+                Some(RedisFrame::BulkString(version)) if version.as_ref() == b"2" => {
+                    ResponseType::Hello(Some(RespVersion::Resp2))
+                }
+                Some(RedisFrame::BulkString(version)) if version.as_ref() == b"3" => {
+                    ResponseType::Hello(Some(RespVersion::Resp3))
+                }
+                // redis will reject any other version with a NOPROTO error
+                _ => ResponseType::Default,
+            },
+            b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" | b"UNSUBSCRIBE" | b"PUNSUBSCRIBE"
+            | b"SUNSUBSCRIBE" => ResponseType::Subscription,
+            b"PING" => ResponseType::Ping,
+            b"MONITOR" => ResponseType::Monitor,
+            b"RESET" => ResponseType::Reset,
+            _ => ResponseType::Default,
+        }
+    }
+}
+
+/// Returns true if any argument after the command name matches `name`
+fn has_arg(args: &[RedisFrame], name: &[u8]) -> bool {
+    args.iter()
+        .skip(1)
+        .any(|arg| matches!(arg, RedisFrame::BulkString(arg) if arg.eq_ignore_ascii_case(name)))
+}
+
+/// Returns true if the frame is a line of MONITOR output, e.g. `+1339518083.107412 [0 127.0.0.1:60866] "keys" "*"`.
+/// The simple string responses to regular commands never start with a digit, so they can not be mistaken for MONITOR output.
+pub fn is_monitor_output(frame: &RedisFrame) -> bool {
+    matches!(frame, RedisFrame::SimpleString(line) if line.first().map_or(false, u8::is_ascii_digit))
+}
+
+/// Connection level state shared between the decoder and encoder of a connection from a client
+#[derive(Debug)]
+struct ClientState {
+    /// The version of RESP the client has negotiated, responses are translated to this version
+    version: RespVersion,
+    /// The type of the response to each request that has been received from the client but not yet responded to.
+    /// Every request receives exactly one response, in order, the same as redis does.
+    /// Messages that do not respond to any request are either pushes, such as pubsub messages, or MONITOR output, neither of which consume a pending response.
+    pending_responses: VecDeque<ResponseType>,
+    /// Set while the client is subscribed to at least one channel
+    subscribed: bool,
+    /// Set once a MONITOR request has succeeded
+    monitoring: bool,
+}
+
+impl Default for ClientState {
+    fn default() -> Self {
+        ClientState {
+            version: RespVersion::Resp2,
+            pending_responses: VecDeque::new(),
+            subscribed: false,
+            monitoring: false,
+        }
+    }
+}
+
+impl ClientState {
+    /// Consumes the pending request that a response responds to.
+    /// Returns the type of the response and the version of RESP it is sent in.
+    fn respond(&mut self, is_error: bool) -> (ResponseType, RespVersion) {
+        let response_type = self
+            .pending_responses
+            .pop_front()
+            .unwrap_or(ResponseType::Default);
+        let version = match response_type {
+            // The response to a HELLO uses the newly negotiated version
+            ResponseType::Hello(Some(version)) if !is_error => {
+                self.version = version;
+                version
+            }
+            ResponseType::Reset if !is_error => {
+                self.version = RespVersion::Resp2;
+                self.subscribed = false;
+                self.monitoring = false;
+                RespVersion::Resp2
+            }
+            ResponseType::Monitor if !is_error => {
+                self.monitoring = true;
+                self.version
+            }
+            _ => self.version,
+        };
+        (response_type, version)
+    }
+}
+
+/// Returns true if the first value of a pubsub array marks it as the confirmation of a (P|S)(UN)SUBSCRIBE.
+/// The last value of a confirmation is the number of subscriptions the connection has remaining.
+pub fn is_subscription_confirmation(kind: &[u8]) -> bool {
+    matches!(
+        kind,
+        b"subscribe"
+            | b"psubscribe"
+            | b"ssubscribe"
+            | b"unsubscribe"
+            | b"punsubscribe"
+            | b"sunsubscribe"
+    )
+}
+
+/// Returns the number of subscriptions remaining after a subscription confirmation sent as a push
+fn push_subscription_count(data: &[Resp3Frame]) -> Option<i64> {
+    match data {
+        [Resp3Frame::BlobString { data: kind, .. }, _, Resp3Frame::Number { data: count, .. }]
+            if is_subscription_confirmation(kind) =>
+        {
+            Some(*count)
+        }
+        _ => None,
     }
 }
 
@@ -51,13 +285,33 @@ impl Decoder for RedisCodec {
             match decode_mut(src).map_err(|e| {
                 CodecReadError::Parser(anyhow!(e).context("Error decoding redis frame"))
             })? {
-                Some((frame, _size, bytes)) => {
+                Some((mut frame, _size, bytes)) => {
                     tracing::debug!(
                         "incoming redis message:\n{}",
                         pretty_hex::pretty_hex(&bytes)
                     );
-                    self.messages
-                        .push(Message::from_bytes_and_frame(bytes, Frame::Redis(frame)));
+                    let message = match &self.client_state {
+                        Some(state) => {
+                            let response_type = ResponseType::for_request(&frame);
+                            state
+                                .lock()
+                                .unwrap()
+                                .pending_responses
+                                .push_back(response_type);
+
+                            if let ResponseType::Hello(Some(RespVersion::Resp3)) = response_type {
+                                // Redis is always spoken to in RESP2, shotover takes care of translating responses to RESP3
+                                if let RedisFrame::Array(args) = &mut frame {
+                                    args[1] = RedisFrame::BulkString(Bytes::from_static(b"2"));
+                                }
+                                Message::from_frame(Frame::Redis(frame))
+                            } else {
+                                Message::from_bytes_and_frame(bytes, Frame::Redis(frame))
+                            }
+                        }
+                        None => Message::from_bytes_and_frame(bytes, Frame::Redis(frame)),
+                    };
+                    self.messages.push(message);
                 }
                 None => {
                     if self.messages.is_empty() || src.remaining() != 0 {
@@ -71,23 +325,286 @@ impl Decoder for RedisCodec {
     }
 }
 
+fn encode_message(message: Message, dst: &mut BytesMut) -> Result<()> {
+    match message.into_encodable(MessageType::Redis)? {
+        Encodable::Bytes(bytes) => {
+            dst.extend_from_slice(&bytes);
+            Ok(())
+        }
+        Encodable::Frame(frame) => {
+            let item = frame.into_redis().unwrap();
+            encode_bytes(dst, &item)
+                .map(|_| ())
+                .map_err(|e| anyhow!("Redis encoding error: {} - {:#?}", e, item))
+        }
+    }
+}
+
+fn encode_resp3(frame: &Resp3Frame, dst: &mut BytesMut) -> Result<()> {
+    encode_resp3_bytes(dst, frame)
+        .map(|_| ())
+        .map_err(|e| anyhow!("Redis encoding error: {} - {:#?}", e, frame))
+}
+
+/// Encodes a message to a client in the version of RESP it has negotiated
+fn encode_client_response(
+    state: &mut ClientState,
+    mut message: Message,
+    dst: &mut BytesMut,
+) -> Result<()> {
+    match message.frame() {
+        Some(Frame::Redis(frame)) => {
+            // MONITOR output is a simple string in both RESP2 and RESP3
+            if state.monitoring && is_monitor_output(frame) {
+                return encode_message(message, dst);
+            }
+
+            let (response_type, version) = state.respond(matches!(frame, RedisFrame::Error(_)));
+            if let (ResponseType::Subscription, RedisFrame::Array(array)) = (response_type, &*frame)
+            {
+                if let [RedisFrame::BulkString(kind), _, RedisFrame::Integer(count)] =
+                    array.as_slice()
+                {
+                    if is_subscription_confirmation(kind) {
+                        state.subscribed = *count != 0;
+                    }
+                }
+            }
+            match version {
+                RespVersion::Resp2 => encode_message(message, dst),
+                RespVersion::Resp3 => {
+                    let frame = std::mem::replace(frame, RedisFrame::Null);
+                    encode_resp3(&resp2_to_resp3(frame, response_type, state.subscribed), dst)
+                }
+            }
+        }
+        // Pushes do not respond to any request
+        Some(Frame::Resp3(Resp3Frame::Push { data, attributes })) => {
+            if let Some(count) = push_subscription_count(data) {
+                state.subscribed = count != 0;
+            }
+            let is_invalidation = matches!(
+                data.first(),
+                Some(Resp3Frame::BlobString { data: kind, .. }) if kind.as_ref() == b"invalidate"
+            );
+            match state.version {
+                RespVersion::Resp3 => {
+                    let frame = Resp3Frame::Push {
+                        data: std::mem::take(data),
+                        attributes: attributes.take(),
+                    };
+                    encode_resp3(&frame, dst)
+                }
+                // Like redis, invalidations are not sent to RESP2 clients as they can only receive them on a connection that tracking was redirected to
+                RespVersion::Resp2 if is_invalidation => Ok(()),
+                RespVersion::Resp2 => encode_message(message, dst),
+            }
+        }
+        Some(Frame::Resp3(frame)) => {
+            let is_error = matches!(
+                frame,
+                Resp3Frame::SimpleError { .. } | Resp3Frame::BlobError { .. }
+            );
+            match state.respond(is_error).1 {
+                RespVersion::Resp3 => encode_resp3(frame, dst),
+                RespVersion::Resp2 => encode_message(message, dst),
+            }
+        }
+        _ => {
+            state.respond(false);
+            encode_message(message, dst)
+        }
+    }
+}
+
+/// Translates a RESP2 response from redis into the RESP3 response that redis would have sent
+fn resp2_to_resp3(frame: RedisFrame, response_type: ResponseType, subscribed: bool) -> Resp3Frame {
+    match (response_type, frame) {
+        (ResponseType::Map, RedisFrame::Array(array)) if array.len() % 2 == 0 => {
+            array_to_map(array, false)
+        }
+        (ResponseType::Hello(_), RedisFrame::Array(array)) if array.len() % 2 == 0 => {
+            array_to_map(array, true)
+        }
+        (ResponseType::MapArray, RedisFrame::Array(array))
+            if array
+                .iter()
+                .all(|map| matches!(map, RedisFrame::Array(map) if map.len() % 2 == 0)) =>
+        {
+            Resp3Frame::Array {
+                data: array
+                    .into_iter()
+                    .map(|map| match map {
+                        RedisFrame::Array(map) => array_to_map(map, false),
+                        value => resp2_to_resp3_value(value),
+                    })
+                    .collect(),
+                attributes: None,
+            }
+        }
+        (ResponseType::Pairs { scores }, RedisFrame::Array(array)) if array.len() % 2 == 0 => {
+            Resp3Frame::Array {
+                data: array
+                    .into_iter()
+                    .tuples()
+                    .map(|(member, value)| {
+                        let value = match value {
+                            RedisFrame::BulkString(bytes) if scores => bulk_string_to_double(bytes),
+                            value => resp2_to_resp3_value(value),
+                        };
+                        Resp3Frame::Array {
+                            data: vec![resp2_to_resp3_value(member), value],
+                            attributes: None,
+                        }
+                    })
+                    .collect(),
+                attributes: None,
+            }
+        }
+        (ResponseType::Set, RedisFrame::Array(array)) => Resp3Frame::Set {
+            data: array.into_iter().map(resp2_to_resp3_value).collect(),
+            attributes: None,
+        },
+        (ResponseType::Double, RedisFrame::BulkString(bytes)) => bulk_string_to_double(bytes),
+        (ResponseType::DoubleArray, RedisFrame::Array(array)) => Resp3Frame::Array {
+            data: array
+                .into_iter()
+                .map(|value| match value {
+                    RedisFrame::BulkString(bytes) => bulk_string_to_double(bytes),
+                    value => resp2_to_resp3_value(value),
+                })
+                .collect(),
+            attributes: None,
+        },
+        (ResponseType::Subscription, RedisFrame::Array(array)) => Resp3Frame::Push {
+            data: array.into_iter().map(resp2_to_resp3_value).collect(),
+            attributes: None,
+        },
+        // While subscribed redis responds to a RESP2 PING with an array of `pong` and the PING message,
+        // but in RESP3 the regular PING response is used.
+        (ResponseType::Ping, RedisFrame::Array(mut array)) if subscribed && array.len() == 2 => {
+            match array.pop() {
+                Some(RedisFrame::BulkString(message)) if !message.is_empty() => {
+                    Resp3Frame::BlobString {
+                        data: message,
+                        attributes: None,
+                    }
+                }
+                _ => Resp3Frame::SimpleString {
+                    data: Bytes::from_static(b"PONG"),
+                    attributes: None,
+                },
+            }
+        }
+        (_, frame) => resp2_to_resp3_value(frame),
+    }
+}
+
+/// Translates a RESP2 array of alternating keys and values into a RESP3 map
+fn array_to_map(array: Vec<RedisFrame>, is_hello: bool) -> Resp3Frame {
+    Resp3Frame::Map {
+        data: array
+            .into_iter()
+            .tuples()
+            .map(|(key, value)| {
+                let value = match (&key, value) {
+                    // redis reports the RESP2 connection we made to it, but the client is using RESP3
+                    (RedisFrame::BulkString(key), RedisFrame::Integer(_))
+                        if is_hello && key.as_ref() == b"proto" =>
+                    {
+                        RedisFrame::Integer(3)
+                    }
+                    (_, value) => value,
+                };
+                (resp2_to_resp3_value(key), resp2_to_resp3_value(value))
+            })
+            .collect(),
+        attributes: None,
+    }
+}
+
+/// Translates a RESP2 frame to its direct RESP3 equivalent
+pub fn resp2_to_resp3_value(frame: RedisFrame) -> Resp3Frame {
+    match frame {
+        RedisFrame::SimpleString(data) => Resp3Frame::SimpleString {
+            data,
+            attributes: None,
+        },
+        RedisFrame::Error(data) => Resp3Frame::SimpleError {
+            data,
+            attributes: None,
+        },
+        RedisFrame::Integer(data) => Resp3Frame::Number {
+            data,
+            attributes: None,
+        },
+        RedisFrame::BulkString(data) => Resp3Frame::BlobString {
+            data,
+            attributes: None,
+        },
+        RedisFrame::Array(array) => Resp3Frame::Array {
+            data: array.into_iter().map(resp2_to_resp3_value).collect(),
+            attributes: None,
+        },
+        RedisFrame::Null => Resp3Frame::Null,
+    }
+}
+
+/// Translates a RESP3 frame into the RESP2 frame that redis would have sent to a RESP2 connection
+pub fn resp3_to_resp2(frame: Resp3Frame) -> RedisFrame {
+    match frame {
+        Resp3Frame::BlobString { data, .. }
+        | Resp3Frame::VerbatimString { data, .. }
+        | Resp3Frame::BigNumber { data, .. }
+        | Resp3Frame::ChunkedString(data) => RedisFrame::BulkString(data),
+        Resp3Frame::SimpleString { data, .. } => RedisFrame::SimpleString(data),
+        Resp3Frame::SimpleError { data, .. } => RedisFrame::Error(data),
+        Resp3Frame::BlobError { data, .. } => RedisFrame::Error(
+            Str::from_inner(Bytes::from(String::from_utf8_lossy(&data).into_owned())).unwrap(),
+        ),
+        Resp3Frame::Boolean { data, .. } => RedisFrame::Integer(i64::from(data)),
+        Resp3Frame::Number { data, .. } => RedisFrame::Integer(data),
+        Resp3Frame::Double { data, .. } => RedisFrame::BulkString(Bytes::from(data.to_string())),
+        Resp3Frame::Array { data, .. } | Resp3Frame::Push { data, .. } => {
+            RedisFrame::Array(data.into_iter().map(resp3_to_resp2).collect())
+        }
+        Resp3Frame::Set { data, .. } => {
+            RedisFrame::Array(data.into_iter().map(resp3_to_resp2).collect())
+        }
+        Resp3Frame::Map { data, .. } => RedisFrame::Array(
+            data.into_iter()
+                .flat_map(|(key, value)| [resp3_to_resp2(key), resp3_to_resp2(value)])
+                .collect(),
+        ),
+        Resp3Frame::Null | Resp3Frame::Hello { .. } => RedisFrame::Null,
+    }
+}
+
+fn bulk_string_to_double(bytes: Bytes) -> Resp3Frame {
+    match std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|string| string.parse::<f64>().ok())
+    {
+        Some(data) => Resp3Frame::Double {
+            data,
+            attributes: None,
+        },
+        None => Resp3Frame::BlobString {
+            data: bytes,
+            attributes: None,
+        },
+    }
+}
+
 impl Encoder<Messages> for RedisCodec {
     type Error = anyhow::Error;
 
     fn encode(&mut self, item: Messages, dst: &mut BytesMut) -> Result<()> {
         item.into_iter().try_for_each(|m| {
             let start = dst.len();
-            let result = match m.into_encodable(MessageType::Redis)? {
-                Encodable::Bytes(bytes) => {
-                    dst.extend_from_slice(&bytes);
-                    Ok(())
-                }
-                Encodable::Frame(frame) => {
-                    let item = frame.into_redis().unwrap();
-                    encode_bytes(dst, &item)
-                        .map(|_| ())
-                        .map_err(|e| anyhow!("Redis encoding error: {} - {:#?}", e, item))
-                }
+            let result = match &self.client_state {
+                Some(state) => encode_client_response(&mut state.lock().unwrap(), m, dst),
+                None => encode_message(m, dst),
             };
             tracing::debug!(
                 "outgoing redis message:\n{}",
@@ -100,8 +617,11 @@ impl Encoder<Messages> for RedisCodec {
 
 #[cfg(test)]
 mod redis_tests {
-    use crate::codec::redis::RedisCodec;
-    use bytes::BytesMut;
+    use crate::codec::redis::{RedisCodec, RedisCodecBuilder};
+    use crate::codec::CodecBuilder;
+    use crate::frame::{Frame, RedisFrame, Resp3Frame};
+    use crate::message::Message;
+    use bytes::{Bytes, BytesMut};
     use hex_literal::hex;
     use tokio_util::codec::{Decoder, Encoder};
 
@@ -191,4 +711,387 @@ mod redis_tests {
         let mut codec = RedisCodec::new();
         test_frame(&mut codec, &HSET_MESSAGE);
     }
+
+    fn test_resp3_response(
+        decoder: &mut RedisCodec,
+        encoder: &mut RedisCodec,
+        request: &[u8],
+        response: RedisFrame,
+        expected: &[u8],
+    ) {
+        decoder
+            .decode(&mut BytesMut::from(request))
+            .unwrap()
+            .unwrap();
+
+        let mut dest = BytesMut::new();
+        encoder
+            .encode(vec![Message::from_frame(Frame::Redis(response))], &mut dest)
+            .unwrap();
+        assert_eq!(expected, &dest);
+    }
+
+    #[test]
+    fn test_resp3_negotiation() {
+        let (mut decoder, mut encoder) = RedisCodecBuilder::new().build();
+
+        // HELLO 3 is downgraded to HELLO 2 as shotover communicates with redis in RESP2
+        let mut hello = decoder
+            .decode(&mut BytesMut::from(
+                b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n".as_slice(),
+            ))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            hello[0].frame(),
+            Some(&mut Frame::Redis(RedisFrame::Array(vec![
+                RedisFrame::BulkString(Bytes::from_static(b"HELLO")),
+                RedisFrame::BulkString(Bytes::from_static(b"2")),
+            ])))
+        );
+
+        // The response is translated to a RESP3 map reporting the RESP3 connection
+        let mut dest = BytesMut::new();
+        encoder
+            .encode(
+                vec![Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                    RedisFrame::BulkString(Bytes::from_static(b"proto")),
+                    RedisFrame::Integer(2),
+                ])))],
+                &mut dest,
+            )
+            .unwrap();
+        assert_eq!(b"%1\r\n$5\r\nproto\r\n:3\r\n".as_slice(), &dest);
+
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*2\r\n$8\r\nSMEMBERS\r\n$5\r\nmyset\r\n",
+            RedisFrame::Array(vec![RedisFrame::BulkString(Bytes::from_static(b"foo"))]),
+            b"~1\r\n$3\r\nfoo\r\n",
+        );
+
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*3\r\n$6\r\nZSCORE\r\n$5\r\nmyset\r\n$3\r\nfoo\r\n",
+            RedisFrame::BulkString(Bytes::from_static(b"1.5")),
+            b",1.5\r\n",
+        );
+
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*2\r\n$3\r\nGET\r\n$5\r\nmykey\r\n",
+            RedisFrame::Null,
+            b"_\r\n",
+        );
+
+        // The subscription confirmation that responds to the request becomes a push
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*2\r\n$9\r\nSUBSCRIBE\r\n$4\r\nchan\r\n",
+            RedisFrame::Array(vec![
+                RedisFrame::BulkString(Bytes::from_static(b"subscribe")),
+                RedisFrame::BulkString(Bytes::from_static(b"chan")),
+                RedisFrame::Integer(1),
+            ]),
+            b">3\r\n$9\r\nsubscribe\r\n$4\r\nchan\r\n:1\r\n",
+        );
+        assert_eq!(
+            encode_push(&mut encoder, message_push()),
+            b">3\r\n$7\r\nmessage\r\n$4\r\nchan\r\n$2\r\nhi\r\n".as_slice()
+        );
+    }
+
+    fn blob_string(data: &'static str) -> Resp3Frame {
+        Resp3Frame::BlobString {
+            data: Bytes::from_static(data.as_bytes()),
+            attributes: None,
+        }
+    }
+
+    fn message_push() -> Resp3Frame {
+        Resp3Frame::Push {
+            data: vec![
+                blob_string("message"),
+                blob_string("chan"),
+                blob_string("hi"),
+            ],
+            attributes: None,
+        }
+    }
+
+    fn encode_push(encoder: &mut RedisCodec, push: Resp3Frame) -> BytesMut {
+        let mut dest = BytesMut::new();
+        encoder
+            .encode(vec![Message::from_frame(Frame::Resp3(push))], &mut dest)
+            .unwrap();
+        dest
+    }
+
+    #[test]
+    fn test_resp3_pushes_do_not_respond() {
+        let (mut decoder, mut encoder) = RedisCodecBuilder::new().build();
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n",
+            RedisFrame::Array(vec![]),
+            b"%0\r\n",
+        );
+
+        decoder
+            .decode(&mut BytesMut::from(
+                b"*3\r\n$9\r\nSUBSCRIBE\r\n$1\r\na\r\n$1\r\nb\r\n*1\r\n$4\r\nPING\r\n".as_slice(),
+            ))
+            .unwrap()
+            .unwrap();
+
+        // The first confirmation responds to the SUBSCRIBE, the rest are pushed
+        let mut dest = BytesMut::new();
+        encoder
+            .encode(
+                vec![Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                    RedisFrame::BulkString(Bytes::from_static(b"subscribe")),
+                    RedisFrame::BulkString(Bytes::from_static(b"a")),
+                    RedisFrame::Integer(1),
+                ])))],
+                &mut dest,
+            )
+            .unwrap();
+        assert_eq!(
+            b">3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n".as_slice(),
+            &dest
+        );
+        assert_eq!(
+            encode_push(
+                &mut encoder,
+                Resp3Frame::Push {
+                    data: vec![
+                        blob_string("subscribe"),
+                        blob_string("b"),
+                        Resp3Frame::Number {
+                            data: 2,
+                            attributes: None,
+                        },
+                    ],
+                    attributes: None,
+                },
+            ),
+            b">3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:2\r\n".as_slice()
+        );
+        assert_eq!(
+            encode_push(&mut encoder, message_push()),
+            b">3\r\n$7\r\nmessage\r\n$4\r\nchan\r\n$2\r\nhi\r\n".as_slice()
+        );
+
+        // The pushes did not consume the pending PING, so its subscribed RESP2 response is still translated
+        let mut dest = BytesMut::new();
+        encoder
+            .encode(
+                vec![Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                    RedisFrame::BulkString(Bytes::from_static(b"pong")),
+                    RedisFrame::BulkString(Bytes::new()),
+                ])))],
+                &mut dest,
+            )
+            .unwrap();
+        assert_eq!(b"+PONG\r\n".as_slice(), &dest);
+    }
+
+    #[test]
+    fn test_resp2_pushes() {
+        let (_, mut encoder) = RedisCodecBuilder::new().build();
+        assert_eq!(
+            encode_push(&mut encoder, message_push()),
+            b"*3\r\n$7\r\nmessage\r\n$4\r\nchan\r\n$2\r\nhi\r\n".as_slice()
+        );
+
+        // RESP2 clients can only receive invalidations on a connection that tracking was redirected to
+        assert_eq!(
+            encode_push(
+                &mut encoder,
+                Resp3Frame::Push {
+                    data: vec![
+                        blob_string("invalidate"),
+                        Resp3Frame::Array {
+                            data: vec![blob_string("foo")],
+                            attributes: None,
+                        },
+                    ],
+                    attributes: None,
+                },
+            ),
+            b"".as_slice()
+        );
+    }
+
+    #[test]
+    fn test_resp3_invalidation() {
+        let (mut decoder, mut encoder) = RedisCodecBuilder::new().build();
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n",
+            RedisFrame::Array(vec![]),
+            b"%0\r\n",
+        );
+        assert_eq!(
+            encode_push(
+                &mut encoder,
+                Resp3Frame::Push {
+                    data: vec![blob_string("invalidate"), Resp3Frame::Null],
+                    attributes: None,
+                },
+            ),
+            b">2\r\n$10\r\ninvalidate\r\n_\r\n".as_slice()
+        );
+    }
+
+    #[test]
+    fn test_monitor_output() {
+        let (mut decoder, mut encoder) = RedisCodecBuilder::new().build();
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n",
+            RedisFrame::Array(vec![]),
+            b"%0\r\n",
+        );
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*1\r\n$7\r\nMONITOR\r\n",
+            RedisFrame::SimpleString(Bytes::from_static(b"OK")),
+            b"+OK\r\n",
+        );
+
+        decoder
+            .decode(&mut BytesMut::from(
+                b"*2\r\n$3\r\nGET\r\n$5\r\nmykey\r\n".as_slice(),
+            ))
+            .unwrap()
+            .unwrap();
+        let mut dest = BytesMut::new();
+        encoder
+            .encode(
+                vec![
+                    Message::from_frame(Frame::Redis(RedisFrame::SimpleString(
+                        Bytes::from_static(
+                            b"1339518083.107412 [0 127.0.0.1:60866] \"get\" \"mykey\"",
+                        ),
+                    ))),
+                    Message::from_frame(Frame::Redis(RedisFrame::Null)),
+                ],
+                &mut dest,
+            )
+            .unwrap();
+        assert_eq!(
+            b"+1339518083.107412 [0 127.0.0.1:60866] \"get\" \"mykey\"\r\n_\r\n".as_slice(),
+            &dest
+        );
+    }
+
+    #[test]
+    fn test_resp3_frame_responses() {
+        let map = || {
+            Message::from_frame(Frame::Resp3(Resp3Frame::Map {
+                data: [(
+                    blob_string("foo"),
+                    Resp3Frame::Double {
+                        data: 1.5,
+                        attributes: None,
+                    },
+                )]
+                .into_iter()
+                .collect(),
+                attributes: None,
+            }))
+        };
+        let (mut decoder, mut encoder) = RedisCodecBuilder::new().build();
+
+        // RESP2 clients receive the equivalent RESP2 frame
+        decoder
+            .decode(&mut BytesMut::from(
+                b"*2\r\n$7\r\nHGETALL\r\n$6\r\nmyhash\r\n".as_slice(),
+            ))
+            .unwrap()
+            .unwrap();
+        let mut dest = BytesMut::new();
+        encoder.encode(vec![map()], &mut dest).unwrap();
+        assert_eq!(b"*2\r\n$3\r\nfoo\r\n$3\r\n1.5\r\n".as_slice(), &dest);
+
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n",
+            RedisFrame::Array(vec![]),
+            b"%0\r\n",
+        );
+        decoder
+            .decode(&mut BytesMut::from(
+                b"*2\r\n$7\r\nHGETALL\r\n$6\r\nmyhash\r\n".as_slice(),
+            ))
+            .unwrap()
+            .unwrap();
+        let mut dest = BytesMut::new();
+        encoder.encode(vec![map()], &mut dest).unwrap();
+        assert_eq!(b"%1\r\n$3\r\nfoo\r\n,1.5\r\n".as_slice(), &dest);
+    }
+
+    #[test]
+    fn test_resp3_reply_shapes() {
+        let (mut decoder, mut encoder) = RedisCodecBuilder::new().build();
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n",
+            RedisFrame::Array(vec![]),
+            b"%0\r\n",
+        );
+
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*5\r\n$6\r\nZRANGE\r\n$5\r\nmyset\r\n$1\r\n0\r\n$2\r\n-1\r\n$10\r\nwithscores\r\n",
+            RedisFrame::Array(vec![
+                RedisFrame::BulkString(Bytes::from_static(b"foo")),
+                RedisFrame::BulkString(Bytes::from_static(b"1.5")),
+            ]),
+            b"*1\r\n*2\r\n$3\r\nfoo\r\n,1.5\r\n",
+        );
+
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*4\r\n$10\r\nHRANDFIELD\r\n$6\r\nmyhash\r\n$1\r\n1\r\n$10\r\nWITHVALUES\r\n",
+            RedisFrame::Array(vec![
+                RedisFrame::BulkString(Bytes::from_static(b"field")),
+                RedisFrame::BulkString(Bytes::from_static(b"1.5")),
+            ]),
+            b"*1\r\n*2\r\n$5\r\nfield\r\n$3\r\n1.5\r\n",
+        );
+
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*3\r\n$5\r\nXINFO\r\n$6\r\nGROUPS\r\n$8\r\nmystream\r\n",
+            RedisFrame::Array(vec![RedisFrame::Array(vec![
+                RedisFrame::BulkString(Bytes::from_static(b"name")),
+                RedisFrame::BulkString(Bytes::from_static(b"group")),
+            ])]),
+            b"*1\r\n%1\r\n$4\r\nname\r\n$5\r\ngroup\r\n",
+        );
+
+        // A response that does not have the shape expected from the request is translated value by value
+        test_resp3_response(
+            &mut decoder,
+            &mut encoder,
+            b"*2\r\n$7\r\nHGETALL\r\n$6\r\nmyhash\r\n",
+            RedisFrame::Array(vec![RedisFrame::BulkString(Bytes::from_static(b"foo"))]),
+            b"*1\r\n$3\r\nfoo\r\n",
+        );
+    }
 }
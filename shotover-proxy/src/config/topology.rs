@@ -1,12 +1,18 @@
 use crate::sources::{Sources, SourcesConfig};
 use crate::transforms::chain::TransformChain;
 use crate::transforms::{build_chain_from_config, TransformsConfig};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use itertools::Itertools;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::watch;
-use tracing::info;
+use tokio::time::timeout;
+use tracing::{error, info, warn};
+
+/// How long a source is given to shutdown before it is aborted
+const SOURCE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Topology {
@@ -41,12 +47,8 @@ impl Topology {
         Ok(result)
     }
 
-    pub async fn run_chains(
-        &self,
-        trigger_shutdown_rx: watch::Receiver<bool>,
-    ) -> Result<Vec<Sources>> {
-        let mut sources_list: Vec<Sources> = Vec::new();
-
+    /// Build every chain and check that both the chains and the source to chain mapping are valid, without starting any sources.
+    async fn build_and_validate(&self) -> Result<HashMap<String, TransformChain>> {
         let chains = self.build_chains().await?;
         info!("Loaded chains {:?}", chains.keys());
 
@@ -65,33 +67,23 @@ impl Topology {
         }
 
         for (source_name, chain_name) in &self.source_to_chain_mapping {
-            if let Some(source_config) = self.sources.get(source_name.as_str()) {
-                if let Some(chain) = chains.get(chain_name.as_str()) {
-                    sources_list.append(
-                        &mut source_config
-                            .get_source(chain, trigger_shutdown_rx.clone())
-                            .await?,
-                    );
-                } else {
-                    return Err(anyhow!("Could not find the [{}] chain from \
-                    the source to chain mapping definition [{:?}] in list of configured chains [{:?}].",
-                                                        chain_name.as_str(),
-                                                        &self.source_to_chain_mapping.values().cloned().collect::<Vec<_>>(),
-                                                        chains.into_keys().collect::<Vec<_>>()));
-                }
-            } else {
+            if !self.sources.contains_key(source_name.as_str()) {
                 return Err(anyhow!("Could not find the [{}] source from \
                     the source to chain mapping definition [{:?}] in list of configured sources [{:?}].",
                                                     source_name.as_str(),
                                                     &self.source_to_chain_mapping.keys().cloned().collect::<Vec<_>>(),
                                                     self.sources.keys().cloned().collect::<Vec<_>>()));
             }
+            if !chains.contains_key(chain_name.as_str()) {
+                return Err(anyhow!("Could not find the [{}] chain from \
+                    the source to chain mapping definition [{:?}] in list of configured chains [{:?}].",
+                                                        chain_name.as_str(),
+                                                        &self.source_to_chain_mapping.values().cloned().collect::<Vec<_>>(),
+                                                        chains.into_keys().collect::<Vec<_>>()));
+            }
         }
-        info!(
-            "Loaded sources [{:?}] and linked to chains",
-            &self.source_to_chain_mapping.keys()
-        );
-        Ok(sources_list)
+
+        Ok(chains)
     }
 
     pub fn from_file(filepath: String) -> Result<Topology> {
@@ -117,13 +109,196 @@ impl Topology {
     }
 }
 
+/// The sources of a topology, each running until it is shutdown or removed by reloading a new topology.
+pub struct RunningTopology {
+    sources: HashMap<String, RunningSource>,
+}
+
+/// The sources created from a single entry in the topology's `sources`.
+struct RunningSource {
+    config: SourcesConfig,
+    /// The chain that new connections to the source are processed by.
+    chain: Arc<Mutex<TransformChain>>,
+    /// Shuts down only this source, shotover shutting down is propagated by [`RunningTopology::shutdown`].
+    trigger_shutdown_tx: watch::Sender<bool>,
+    sources: Vec<Sources>,
+}
+
+impl RunningSource {
+    async fn start(
+        source_name: &str,
+        config: &SourcesConfig,
+        chain: TransformChain,
+    ) -> Result<RunningSource> {
+        let (trigger_shutdown_tx, trigger_shutdown_rx) = watch::channel(false);
+        let chain = Arc::new(Mutex::new(chain));
+        let sources = config
+            .get_source(chain.clone(), trigger_shutdown_rx)
+            .await
+            .with_context(|| format!("Failed to start source {source_name}"))?;
+        Ok(RunningSource {
+            config: config.clone(),
+            chain,
+            trigger_shutdown_tx,
+            sources,
+        })
+    }
+
+    async fn shutdown(self) {
+        // The receivers are held by the sources so this can only fail if every source has already stopped.
+        self.trigger_shutdown_tx.send(true).ok();
+        let mut join_handles: Vec<_> = self
+            .sources
+            .into_iter()
+            .map(|x| x.into_join_handle())
+            .collect();
+        // Flushing the chain on shutdown can wait on an unresponsive destination, which must not block a reload forever
+        if timeout(
+            SOURCE_SHUTDOWN_TIMEOUT,
+            futures::future::join_all(join_handles.iter_mut()),
+        )
+        .await
+        .is_err()
+        {
+            warn!(
+                "Source did not shutdown within {:?}, aborting it",
+                SOURCE_SHUTDOWN_TIMEOUT
+            );
+            for join_handle in join_handles {
+                join_handle.abort();
+            }
+        }
+    }
+}
+
+impl RunningTopology {
+    pub async fn start(topology: &Topology) -> Result<RunningTopology> {
+        let chains = topology.build_and_validate().await?;
+
+        let mut sources = HashMap::new();
+        for (source_name, chain_name) in &topology.source_to_chain_mapping {
+            let source = RunningSource::start(
+                source_name,
+                &topology.sources[source_name],
+                chains[chain_name].clone(),
+            )
+            .await?;
+            sources.insert(source_name.clone(), source);
+        }
+        info!(
+            "Loaded sources [{:?}] and linked to chains",
+            &topology.source_to_chain_mapping.keys()
+        );
+
+        Ok(RunningTopology { sources })
+    }
+
+    /// Replace the running topology with `topology`.
+    ///
+    /// `topology` is validated in the same way as at startup before anything is changed, if it is invalid the running topology is left untouched.
+    /// Sources whose configuration is unchanged keep running: their new connections use the new chain while existing connections finish on the old chain.
+    /// Sources that were added are started first, if any of them fail to start the running topology is left untouched.
+    /// Sources that were removed or whose configuration changed are then shutdown, closing their connections, and the changed sources are started again.
+    /// A source can not be reconfigured in place, so changing any of its options closes its connections even when its address is unchanged.
+    /// If a changed source fails to start, the sources stopped by the reload are restarted with their previous configuration and chain.
+    pub async fn reload(&mut self, topology: &Topology) -> Result<()> {
+        let chains = topology.build_and_validate().await?;
+
+        let (added, changed): (Vec<&String>, Vec<&String>) = topology
+            .source_to_chain_mapping
+            .keys()
+            .filter(|source_name| match self.sources.get(*source_name) {
+                Some(source) => topology.sources.get(*source_name) != Some(&source.config),
+                None => true,
+            })
+            .partition(|source_name| !self.sources.contains_key(*source_name));
+        let removed: Vec<String> = self
+            .sources
+            .keys()
+            .filter(|source_name| !topology.source_to_chain_mapping.contains_key(*source_name))
+            .cloned()
+            .collect();
+
+        let mut started = vec![];
+        if let Err(err) = start_sources(&mut started, &added, topology, &chains).await {
+            shutdown_sources(started).await;
+            return Err(err);
+        }
+
+        // A changed source may listen on the same address as before, so it must be stopped before it can be started again.
+        let mut stopped = vec![];
+        for source_name in changed.iter().copied().chain(&removed) {
+            let source = self.sources.remove(source_name).unwrap();
+            let chain = source.chain.lock().unwrap().clone();
+            stopped.push((source_name.clone(), source.config.clone(), chain));
+            source.shutdown().await;
+            info!("Stopped source {source_name}");
+        }
+
+        if let Err(err) = start_sources(&mut started, &changed, topology, &chains).await {
+            shutdown_sources(started).await;
+            for (source_name, config, chain) in stopped {
+                match RunningSource::start(&source_name, &config, chain).await {
+                    Ok(source) => {
+                        self.sources.insert(source_name.clone(), source);
+                        info!("Restarted source {source_name} with its previous configuration");
+                    }
+                    Err(restart_err) => error!(
+                        "{:?}",
+                        restart_err.context("Failed to restart source after a failed reload")
+                    ),
+                }
+            }
+            return Err(err);
+        }
+
+        for (source_name, chain_name) in &topology.source_to_chain_mapping {
+            if let Some(source) = self.sources.get(source_name) {
+                *source.chain.lock().unwrap() = chains[chain_name].clone();
+                info!("New connections to source {source_name} will use chain {chain_name}");
+            }
+        }
+        for (source_name, source) in started {
+            info!(
+                "Started source {source_name} with chain {}",
+                topology.source_to_chain_mapping[&source_name]
+            );
+            self.sources.insert(source_name, source);
+        }
+
+        Ok(())
+    }
+
+    pub async fn shutdown(self) {
+        futures::future::join_all(self.sources.into_values().map(RunningSource::shutdown)).await;
+    }
+}
+
+/// Start each of `source_names`, adding them to `started` as soon as they are running so that they can be shutdown again if a later source fails to start.
+async fn start_sources(
+    started: &mut Vec<(String, RunningSource)>,
+    source_names: &[&String],
+    topology: &Topology,
+    chains: &HashMap<String, TransformChain>,
+) -> Result<()> {
+    for source_name in source_names {
+        let chain = chains[&topology.source_to_chain_mapping[*source_name]].clone();
+        let source =
+            RunningSource::start(source_name, &topology.sources[*source_name], chain).await?;
+        started.push(((*source_name).clone(), source));
+    }
+    Ok(())
+}
+
+async fn shutdown_sources(sources: Vec<(String, RunningSource)>) {
+    futures::future::join_all(sources.into_iter().map(|(_, source)| source.shutdown())).await;
+}
+
 #[cfg(test)]
 mod topology_tests {
-    use tokio::sync::watch;
-
     use crate::transforms::coalesce::CoalesceConfig;
     use crate::{
-        sources::{redis_source::RedisConfig, Sources, SourcesConfig},
+        sources::{redis_source::RedisConfig, SourcesConfig},
         transforms::{
             distributed::consistent_scatter::ConsistentScatterConfig,
             parallel_map::ParallelMapConfig, redis::cache::RedisConfig as RedisCacheConfig,
@@ -132,9 +307,11 @@ mod topology_tests {
     };
     use std::{collections::HashMap, fs};
 
-    use super::{Topology, TopologyConfig};
+    use super::{RunningTopology, Topology, TopologyConfig};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
 
-    async fn run_test_topology(chain: Vec<TransformsConfig>) -> anyhow::Result<Vec<Sources>> {
+    async fn run_test_topology(chain: Vec<TransformsConfig>) -> anyhow::Result<()> {
         let mut chain_config = HashMap::new();
         chain_config.insert("redis_chain".to_string(), chain);
 
@@ -143,6 +320,8 @@ mod topology_tests {
             connection_limit: None,
             hard_connection_limit: None,
             tls: None,
+            proxy_protocol: None,
+            chain_error_policy: None,
             timeout: None,
         });
 
@@ -155,10 +334,9 @@ mod topology_tests {
             source_to_chain_mapping: HashMap::new(), // Leave source to chain mapping empty so it doesn't build and run the transform chains
         };
 
-        let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
-
         let topology = Topology::topology_from_config(config);
-        topology.run_chains(trigger_shutdown_rx).await
+        RunningTopology::start(&topology).await?.shutdown().await;
+        Ok(())
     }
 
     #[tokio::test]
@@ -537,16 +715,14 @@ redis_chain:
 
     #[tokio::test]
     async fn test_validate_chain_multiple_subchains() {
-        let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
-
         let yaml_contents =
             fs::read_to_string("tests/test-configs/invalid_subchains.yaml").unwrap();
 
         let topology = Topology::new_from_yaml(yaml_contents);
-        let error = topology
-            .run_chains(trigger_shutdown_rx)
+        let error = RunningTopology::start(&topology)
             .await
-            .unwrap_err()
+            .err()
+            .unwrap()
             .to_string();
 
         let expected = r#"Topology errors
@@ -569,4 +745,110 @@ b_second_chain:
 
         assert_eq!(error, expected);
     }
+
+    fn reload_test_topology(sources: &[(&str, u16, &str)], returned: &str) -> Topology {
+        let mut yaml = "sources:\n".to_string();
+        for (source, port, _) in sources {
+            yaml.push_str(&format!(
+                "  {source}:\n    Redis:\n      listen_addr: \"127.0.0.1:{port}\"\n"
+            ));
+        }
+        yaml.push_str(&format!(
+            "chain_config:\n  chain:\n    - DebugReturner:\n        Redis: \"{returned}\"\n"
+        ));
+        yaml.push_str("source_to_chain_mapping:\n");
+        for (source, _, chain) in sources {
+            yaml.push_str(&format!("  {source}: {chain}\n"));
+        }
+        Topology::from_string(yaml).unwrap()
+    }
+
+    async fn send_get(connection: &mut TcpStream) -> String {
+        connection
+            .write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n")
+            .await
+            .unwrap();
+        let mut response = [0; 7];
+        connection.read_exact(&mut response).await.unwrap();
+        String::from_utf8(response.to_vec()).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reload() {
+        let mut running =
+            RunningTopology::start(&reload_test_topology(&[("redis_a", 6391, "chain")], "1"))
+                .await
+                .unwrap();
+        let mut old_connection = TcpStream::connect("127.0.0.1:6391").await.unwrap();
+        assert_eq!(send_get(&mut old_connection).await, "$1\r\n1\r\n");
+
+        // change the chain and add a source
+        running
+            .reload(&reload_test_topology(
+                &[("redis_a", 6391, "chain"), ("redis_b", 6392, "chain")],
+                "2",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(send_get(&mut old_connection).await, "$1\r\n1\r\n");
+        let mut connection = TcpStream::connect("127.0.0.1:6391").await.unwrap();
+        assert_eq!(send_get(&mut connection).await, "$1\r\n2\r\n");
+        let mut connection = TcpStream::connect("127.0.0.1:6392").await.unwrap();
+        assert_eq!(send_get(&mut connection).await, "$1\r\n2\r\n");
+
+        // an invalid topology is rejected without changing the running topology
+        let error = running
+            .reload(&reload_test_topology(
+                &[("redis_a", 6391, "missing_chain")],
+                "3",
+            ))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Could not find the [missing_chain] chain"));
+        let mut connection = TcpStream::connect("127.0.0.1:6392").await.unwrap();
+        assert_eq!(send_get(&mut connection).await, "$1\r\n2\r\n");
+
+        // remove a source
+        running
+            .reload(&reload_test_topology(&[("redis_a", 6391, "chain")], "4"))
+            .await
+            .unwrap();
+        assert!(TcpStream::connect("127.0.0.1:6392").await.is_err());
+        let mut connection = TcpStream::connect("127.0.0.1:6391").await.unwrap();
+        assert_eq!(send_get(&mut connection).await, "$1\r\n4\r\n");
+        assert_eq!(send_get(&mut old_connection).await, "$1\r\n1\r\n");
+
+        running.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reload_rollback() {
+        let mut running =
+            RunningTopology::start(&reload_test_topology(&[("redis_a", 6393, "chain")], "1"))
+                .await
+                .unwrap();
+        let _occupied = std::net::TcpListener::bind("127.0.0.1:6394").unwrap();
+
+        // an added source that fails to start leaves the running topology untouched
+        running
+            .reload(&reload_test_topology(
+                &[("redis_a", 6393, "chain"), ("redis_b", 6394, "chain")],
+                "2",
+            ))
+            .await
+            .unwrap_err();
+        let mut connection = TcpStream::connect("127.0.0.1:6393").await.unwrap();
+        assert_eq!(send_get(&mut connection).await, "$1\r\n1\r\n");
+
+        // a changed source that fails to start is restarted with its previous configuration and chain
+        running
+            .reload(&reload_test_topology(&[("redis_a", 6394, "chain")], "3"))
+            .await
+            .unwrap_err();
+        let mut connection = TcpStream::connect("127.0.0.1:6393").await.unwrap();
+        assert_eq!(send_get(&mut connection).await, "$1\r\n1\r\n");
+
+        running.shutdown().await;
+    }
 }
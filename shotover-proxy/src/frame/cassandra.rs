@@ -25,7 +25,7 @@ use cassandra_protocol::frame::{
 use cassandra_protocol::query::{QueryParams, QueryValues};
 use cassandra_protocol::types::blob::Blob;
 use cassandra_protocol::types::cassandra_type::CassandraType;
-use cassandra_protocol::types::{CBytesShort, CLong};
+use cassandra_protocol::types::{CBytesShort, CInt, CLong};
 use cql3_parser::begin_batch::{BatchType as ParserBatchType, BeginBatch};
 use cql3_parser::cassandra_ast::CassandraAST;
 use cql3_parser::cassandra_statement::CassandraStatement;
@@ -39,17 +39,24 @@ use std::str::FromStr;
 use uuid::Uuid;
 
 /// Functions for operations on an unparsed Cassandra frame
+///
+/// The codec hands messages over as a single envelope with any v3/v4 envelope compression or v5 segment framing already removed,
+/// so only the envelope header needs to be read, which is laid out the same across v3, v4 and v5.
 pub mod raw_frame {
-    use super::{CassandraMetadata, RawCassandraFrame};
+    use super::CassandraMetadata;
     use anyhow::{anyhow, bail, Result};
-    use cassandra_protocol::{compression::Compression, frame::Opcode};
+    use cassandra_protocol::frame::{Flags, Opcode, Version};
     use nonzero_ext::nonzero;
     use std::convert::TryInto;
     use std::num::NonZeroU32;
+    use uuid::Uuid;
+
+    const HEADER_LEN: usize = 9;
+    const RESPONSE_DIRECTION_BIT: u8 = 0x80;
 
     /// Extract the length of a BATCH statement (count of requests) from the body bytes
     fn get_batch_len(bytes: &[u8]) -> Result<NonZeroU32> {
-        if bytes.len() < 2 {
+        if bytes.len() < 3 {
             bail!("BATCH statement body is not long enough");
         }
 
@@ -61,30 +68,60 @@ pub mod raw_frame {
         Ok(NonZeroU32::new(short.into()).unwrap_or(nonzero!(1u32)))
     }
 
+    /// Returns the header and body of the envelope
+    fn split(bytes: &[u8]) -> Result<(&[u8], &[u8])> {
+        if bytes.len() < HEADER_LEN {
+            bail!("envelope is not long enough to contain a header");
+        }
+        let (header, body) = bytes.split_at(HEADER_LEN);
+        let body_len = u32::from_be_bytes(header[5..9].try_into()?) as usize;
+        if body.len() != body_len {
+            bail!(
+                "envelope header specifies a body of {body_len} bytes but the body is {} bytes",
+                body.len()
+            );
+        }
+        Ok((header, body))
+    }
+
     /// Parse metadata only from an unparsed Cassandra frame
     pub(crate) fn metadata(bytes: &[u8]) -> Result<CassandraMetadata> {
-        let frame = RawCassandraFrame::from_buffer(bytes, Compression::None)
-            .map_err(|e| anyhow!("{e:?}"))?
-            .envelope;
+        let (header, body) = split(bytes)?;
+        let version = Version::try_from(header[0]).map_err(|e| anyhow!("{e:?}"))?;
+        let flags = Flags::from_bits_truncate(header[1]);
+        let stream_id = i16::from_be_bytes(header[2..4].try_into()?);
+        let opcode = Opcode::try_from(header[4]).map_err(|e| anyhow!("{e:?}"))?;
+
+        // only responses carry the tracing id, as the first 16 bytes of the body
+        let is_response = header[0] & RESPONSE_DIRECTION_BIT != 0;
+        let tracing_id = match body.get(..16) {
+            Some(tracing_id) if is_response && flags.contains(Flags::TRACING) => {
+                Some(Uuid::from_slice(tracing_id)?)
+            }
+            None if is_response && flags.contains(Flags::TRACING) => {
+                bail!("envelope body is too short to contain its tracing id")
+            }
+            _ => None,
+        };
 
         Ok(CassandraMetadata {
-            version: frame.version,
-            stream_id: frame.stream_id,
-            tracing_id: frame.tracing_id,
-            opcode: frame.opcode,
+            version,
+            stream_id,
+            tracing_id,
+            opcode,
         })
     }
 
     /// Count "cells" only from an unparsed Cassandra frame
     pub(crate) fn cell_count(bytes: &[u8]) -> Result<NonZeroU32> {
-        let frame = RawCassandraFrame::from_buffer(bytes, Compression::None)
-            .map_err(|e| anyhow!("{e:?}"))?
-            .envelope;
+        let (header, body) = split(bytes)?;
 
-        Ok(match frame.opcode {
-            Opcode::Batch => get_batch_len(&frame.body)?,
-            _ => nonzero!(1u32),
-        })
+        Ok(
+            match Opcode::try_from(header[4]).map_err(|e| anyhow!("{e:?}"))? {
+                Opcode::Batch => get_batch_len(body)?,
+                _ => nonzero!(1u32),
+            },
+        )
     }
 }
 
@@ -159,6 +196,8 @@ impl CassandraFrame {
                                 consistency: body.query_params.consistency,
                                 serial_consistency: body.query_params.serial_consistency,
                                 timestamp: body.query_params.timestamp,
+                                keyspace: body.query_params.keyspace,
+                                now_in_seconds: body.query_params.now_in_seconds,
                             })
                         }
                     }
@@ -280,6 +319,8 @@ impl CassandraFrame {
                         consistency: body.consistency,
                         serial_consistency: body.serial_consistency,
                         timestamp: body.timestamp,
+                        keyspace: body.keyspace,
+                        now_in_seconds: body.now_in_seconds,
                     })
                 } else {
                     unreachable!("We already know the operation is a batch")
@@ -384,6 +425,61 @@ impl CassandraOperation {
         }
     }
 
+    /// Return the keyspace specified by the protocol v5 keyspace flag of a QUERY or BATCH
+    pub fn keyspace_flag(&self) -> Option<&str> {
+        match self {
+            CassandraOperation::Query { params, .. } => params.keyspace.as_deref(),
+            CassandraOperation::Batch(batch) => batch.keyspace.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Return the consistency level of a QUERY, EXECUTE or BATCH
+    pub fn consistency(&self) -> Option<Consistency> {
+        match self {
+            CassandraOperation::Query { params, .. } => Some(params.consistency),
+            CassandraOperation::Execute(execute) => Some(execute.query_parameters.consistency),
+            CassandraOperation::Batch(batch) => Some(batch.consistency),
+            _ => None,
+        }
+    }
+
+    /// Set the consistency level of a QUERY, EXECUTE or BATCH, any other operation is left unchanged
+    pub fn set_consistency(&mut self, consistency: Consistency) {
+        match self {
+            CassandraOperation::Query { params, .. } => params.consistency = consistency,
+            CassandraOperation::Execute(execute) => {
+                execute.query_parameters.consistency = consistency
+            }
+            CassandraOperation::Batch(batch) => batch.consistency = consistency,
+            _ => {}
+        }
+    }
+
+    /// Return the serial consistency level of a QUERY, EXECUTE or BATCH, if the request specifies one
+    pub fn serial_consistency(&self) -> Option<Consistency> {
+        match self {
+            CassandraOperation::Query { params, .. } => params.serial_consistency,
+            CassandraOperation::Execute(execute) => execute.query_parameters.serial_consistency,
+            CassandraOperation::Batch(batch) => batch.serial_consistency,
+            _ => None,
+        }
+    }
+
+    /// Set the serial consistency level of a QUERY, EXECUTE or BATCH, any other operation is left unchanged
+    pub fn set_serial_consistency(&mut self, serial_consistency: Consistency) {
+        match self {
+            CassandraOperation::Query { params, .. } => {
+                params.serial_consistency = Some(serial_consistency)
+            }
+            CassandraOperation::Execute(execute) => {
+                execute.query_parameters.serial_consistency = Some(serial_consistency)
+            }
+            CassandraOperation::Batch(batch) => batch.serial_consistency = Some(serial_consistency),
+            _ => {}
+        }
+    }
+
     fn to_direction(&self) -> Direction {
         match self {
             CassandraOperation::Query { .. } => Direction::Request,
@@ -473,8 +569,8 @@ impl CassandraOperation {
             CassandraOperation::Batch(batch) => BodyReqBatch {
                 batch_type: batch.ty,
                 consistency: batch.consistency,
-                keyspace: None,
-                now_in_seconds: None,
+                keyspace: batch.keyspace,
+                now_in_seconds: batch.now_in_seconds,
                 queries: batch
                     .queries
                     .into_iter()
@@ -596,6 +692,21 @@ pub fn parse_statement_single(cql: &str) -> CassandraStatement {
     }
 }
 
+/// The body of a PREPARE starts with the statement as a `[long string]`
+pub fn prepare_query(body: &[u8]) -> Option<&str> {
+    let len = usize::try_from(i32::from_be_bytes(body.get(0..4)?.try_into().ok()?)).ok()?;
+    std::str::from_utf8(body.get(4..4 + len)?).ok()
+}
+
+/// Replace the statement of a PREPARE body, keeping the protocol v5 flags and keyspace that follow it
+pub fn set_prepare_query(body: &mut Vec<u8>, query: &str) {
+    let rest = prepare_query(body).map_or(0, |old| 4 + old.len());
+    let mut new_body = (query.len() as i32).to_be_bytes().to_vec();
+    new_body.extend(query.as_bytes());
+    new_body.extend(&body[rest..]);
+    *body = new_body;
+}
+
 fn from_string_value(value: &str) -> CassandraType {
     if value.starts_with('\'') || value.starts_with("$$") {
         CassandraType::Varchar(Operand::unescape(value))
@@ -681,6 +792,24 @@ pub struct CassandraBatch {
     consistency: Consistency,
     serial_consistency: Option<Consistency>,
     timestamp: Option<CLong>,
+    keyspace: Option<String>,
+    now_in_seconds: Option<CInt>,
+}
+
+impl BatchStatement {
+    pub fn ty(&self) -> &BatchStatementType {
+        &self.ty
+    }
+
+    pub fn values(&self) -> &QueryValues {
+        &self.values
+    }
+}
+
+impl CassandraBatch {
+    pub fn queries(&self) -> &[BatchStatement] {
+        &self.queries
+    }
 }
 
 impl Display for CassandraFrame {
@@ -810,9 +939,20 @@ impl Display for CassandraFrame {
 
 #[cfg(test)]
 mod test {
-    use crate::frame::cassandra::{parse_statement_single, to_cassandra_type};
+    use crate::frame::cassandra::{
+        parse_statement_single, raw_frame, to_cassandra_type, BatchStatement, BatchStatementType,
+        CassandraBatch, CassandraFrame, CassandraOperation,
+    };
+    use bytes::Bytes;
+    use cassandra_protocol::compression::Compression;
+    use cassandra_protocol::consistency::Consistency;
+    use cassandra_protocol::frame::message_batch::BatchType;
+    use cassandra_protocol::frame::message_execute::BodyReqExecuteOwned;
+    use cassandra_protocol::frame::{Flags, Opcode, Version};
+    use cassandra_protocol::query::{QueryParams, QueryValues};
     use cassandra_protocol::types::cassandra_type::CassandraType;
     use cassandra_protocol::types::prelude::Blob;
+    use cassandra_protocol::types::CBytesShort;
     use cql3_parser::cassandra_statement::CassandraStatement;
     use cql3_parser::common::{FQName, Identifier, Operand, RelationElement, RelationOperator};
     use cql3_parser::insert::{Insert, InsertValues};
@@ -821,6 +961,95 @@ mod test {
     use std::str::FromStr;
     use uuid::Uuid;
 
+    fn encode(frame: CassandraFrame) -> Bytes {
+        frame
+            .encode()
+            .encode_with(Compression::None)
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn raw_frame_v5_metadata() {
+        let tracing_id = Uuid::new_v4();
+        let mut bytes = encode(CassandraFrame {
+            version: Version::V5,
+            stream_id: 10,
+            tracing_id: None,
+            warnings: vec![],
+            operation: CassandraOperation::Ready(vec![]),
+        })
+        .to_vec();
+
+        let metadata = raw_frame::metadata(&bytes).unwrap();
+        assert_eq!(metadata.version, Version::V5);
+        assert_eq!(metadata.stream_id, 10);
+        assert_eq!(metadata.tracing_id, None);
+        assert_eq!(metadata.opcode, Opcode::Ready);
+
+        // set the tracing flag and prefix the body with the tracing id
+        bytes[1] |= Flags::TRACING.bits();
+        bytes[5..9].copy_from_slice(&16u32.to_be_bytes());
+        bytes.extend(tracing_id.as_bytes());
+        let metadata = raw_frame::metadata(&bytes).unwrap();
+        assert_eq!(metadata.tracing_id, Some(tracing_id));
+
+        assert!(raw_frame::metadata(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn raw_frame_v5_cell_count() {
+        let query = parse_statement_single("INSERT INTO ks.tb (id) VALUES (1)");
+        let bytes = encode(CassandraFrame {
+            version: Version::V5,
+            stream_id: 1,
+            tracing_id: None,
+            warnings: vec![],
+            operation: CassandraOperation::Batch(CassandraBatch {
+                ty: BatchType::Logged,
+                queries: vec![
+                    BatchStatement {
+                        ty: BatchStatementType::Statement(Box::new(query.clone())),
+                        values: QueryValues::SimpleValues(vec![]),
+                    },
+                    BatchStatement {
+                        ty: BatchStatementType::Statement(Box::new(query)),
+                        values: QueryValues::SimpleValues(vec![]),
+                    },
+                ],
+                consistency: Consistency::One,
+                serial_consistency: None,
+                timestamp: None,
+                keyspace: Some("ks".to_owned()),
+                now_in_seconds: Some(100),
+            }),
+        });
+
+        assert_eq!(raw_frame::cell_count(&bytes).unwrap().get(), 2);
+    }
+
+    #[test]
+    fn v5_execute_result_metadata_id() {
+        let execute = CassandraFrame {
+            version: Version::V5,
+            stream_id: 1,
+            tracing_id: None,
+            warnings: vec![],
+            operation: CassandraOperation::Execute(Box::new(BodyReqExecuteOwned {
+                id: CBytesShort::new(vec![1, 2, 3]),
+                result_metadata_id: Some(CBytesShort::new(vec![4, 5, 6])),
+                query_parameters: QueryParams {
+                    keyspace: Some("ks".to_owned()),
+                    now_in_seconds: Some(100),
+                    ..QueryParams::default()
+                },
+            })),
+        };
+
+        let bytes = encode(execute.clone());
+        assert_eq!(CassandraFrame::from_bytes(bytes).unwrap(), execute);
+    }
+
     #[test]
     fn cql_insert() {
         let query = r#"INSERT INTO test_cache_keyspace_batch_insert.test_table (id, x, name) VALUES (1, 11, 'foo')"#;
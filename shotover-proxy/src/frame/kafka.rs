@@ -0,0 +1,599 @@
+use crate::message::QueryType;
+use anyhow::{anyhow, bail, ensure, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// The api keys that shotover needs to be aware of, all other requests are passed through without inspecting their bodies
+pub mod api_key {
+    pub const PRODUCE: i16 = 0;
+    pub const FETCH: i16 = 1;
+    pub const LIST_OFFSETS: i16 = 2;
+    pub const METADATA: i16 = 3;
+    pub const OFFSET_COMMIT: i16 = 8;
+    pub const OFFSET_FETCH: i16 = 9;
+    pub const FIND_COORDINATOR: i16 = 10;
+    pub const JOIN_GROUP: i16 = 11;
+    pub const HEARTBEAT: i16 = 12;
+    pub const LEAVE_GROUP: i16 = 13;
+    pub const SYNC_GROUP: i16 = 14;
+    pub const DESCRIBE_GROUPS: i16 = 15;
+    pub const LIST_GROUPS: i16 = 16;
+    pub const SASL_HANDSHAKE: i16 = 17;
+    pub const API_VERSIONS: i16 = 18;
+    pub const CREATE_TOPICS: i16 = 19;
+    pub const DELETE_TOPICS: i16 = 20;
+    pub const DELETE_RECORDS: i16 = 21;
+    pub const INIT_PRODUCER_ID: i16 = 22;
+    pub const SASL_AUTHENTICATE: i16 = 36;
+
+    pub fn name(api_key: i16) -> &'static str {
+        match api_key {
+            PRODUCE => "Produce",
+            FETCH => "Fetch",
+            LIST_OFFSETS => "ListOffsets",
+            METADATA => "Metadata",
+            OFFSET_COMMIT => "OffsetCommit",
+            OFFSET_FETCH => "OffsetFetch",
+            FIND_COORDINATOR => "FindCoordinator",
+            JOIN_GROUP => "JoinGroup",
+            HEARTBEAT => "Heartbeat",
+            LEAVE_GROUP => "LeaveGroup",
+            SYNC_GROUP => "SyncGroup",
+            DESCRIBE_GROUPS => "DescribeGroups",
+            LIST_GROUPS => "ListGroups",
+            SASL_HANDSHAKE => "SaslHandshake",
+            API_VERSIONS => "ApiVersions",
+            CREATE_TOPICS => "CreateTopics",
+            DELETE_TOPICS => "DeleteTopics",
+            DELETE_RECORDS => "DeleteRecords",
+            INIT_PRODUCER_ID => "InitProducerId",
+            SASL_AUTHENTICATE => "SaslAuthenticate",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Every kafka message starts with its length, not including the 4 bytes of the length itself
+pub const SIZE_PREFIX_LEN: usize = 4;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum KafkaFrame {
+    Request(KafkaRequest),
+    Response(KafkaResponse),
+}
+
+impl KafkaFrame {
+    /// Only requests can be parsed from bytes alone, parsing a response requires knowing the api key and version of its request.
+    pub fn from_bytes(bytes: Bytes) -> Result<Self> {
+        KafkaRequest::from_bytes(bytes).map(KafkaFrame::Request)
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) -> Result<()> {
+        let start = dst.len();
+        dst.put_i32(0);
+        match self {
+            KafkaFrame::Request(request) => request.encode_without_size(dst),
+            KafkaFrame::Response(response) => response.encode_without_size(dst)?,
+        }
+        let size = (dst.len() - start - SIZE_PREFIX_LEN) as i32;
+        dst[start..start + SIZE_PREFIX_LEN].copy_from_slice(&size.to_be_bytes());
+        Ok(())
+    }
+
+    pub fn get_query_type(&self) -> QueryType {
+        match self {
+            KafkaFrame::Request(request) => match request.header.api_key {
+                api_key::FETCH | api_key::LIST_OFFSETS | api_key::METADATA => QueryType::Read,
+                api_key::PRODUCE
+                | api_key::CREATE_TOPICS
+                | api_key::DELETE_TOPICS
+                | api_key::DELETE_RECORDS => QueryType::Write,
+                _ => QueryType::ReadWrite,
+            },
+            KafkaFrame::Response(_) => QueryType::Read,
+        }
+    }
+}
+
+impl Display for KafkaFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            KafkaFrame::Request(request) => write!(
+                f,
+                "{} v{} request correlation_id={}",
+                api_key::name(request.header.api_key),
+                request.header.api_version,
+                request.header.correlation_id
+            ),
+            KafkaFrame::Response(response) => write!(
+                f,
+                "{} v{} response correlation_id={}",
+                api_key::name(response.api_key),
+                response.api_version,
+                response.correlation_id
+            ),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct RequestHeader {
+    pub api_key: i16,
+    pub api_version: i16,
+    pub correlation_id: i32,
+    pub client_id: Option<Bytes>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct KafkaRequest {
+    pub header: RequestHeader,
+    /// Everything following the client id, including the tagged fields of flexible request headers
+    pub body: Bytes,
+}
+
+impl KafkaRequest {
+    fn from_bytes(mut bytes: Bytes) -> Result<Self> {
+        read_size_prefix(&mut bytes)?;
+        let header = RequestHeader {
+            api_key: read_i16(&mut bytes)?,
+            api_version: read_i16(&mut bytes)?,
+            correlation_id: read_i32(&mut bytes)?,
+            // The client id remains a non-compact string even in flexible request headers
+            client_id: read_nullable_string(&mut bytes, false)?,
+        };
+        Ok(KafkaRequest {
+            header,
+            body: bytes,
+        })
+    }
+
+    fn encode_without_size(&self, dst: &mut BytesMut) {
+        dst.put_i16(self.header.api_key);
+        dst.put_i16(self.header.api_version);
+        dst.put_i32(self.header.correlation_id);
+        put_nullable_string(dst, &self.header.client_id, false);
+        dst.put_slice(&self.body);
+    }
+
+    /// Kafka does not respond to a produce request with acks set to 0, every other request receives a response.
+    pub fn expects_response(&self) -> bool {
+        if self.header.api_key != api_key::PRODUCE {
+            return true;
+        }
+        let acks = || -> Result<i16> {
+            let mut body = self.body.clone();
+            let version = self.header.api_version;
+            if version >= 9 {
+                read_tagged_fields(&mut body)?;
+            }
+            if version >= 3 {
+                read_nullable_string(&mut body, version >= 9)?;
+            }
+            read_i16(&mut body)
+        };
+        acks().map(|acks| acks != 0).unwrap_or(true)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct KafkaResponse {
+    /// The api key of the request this is a response to, kafka does not include it in the response itself
+    pub api_key: i16,
+    /// The api version of the request this is a response to, kafka does not include it in the response itself
+    pub api_version: i16,
+    pub correlation_id: i32,
+    /// The tagged fields of a flexible response header, only split out from the body when the body is parsed
+    pub header_tagged_fields: Bytes,
+    pub body: KafkaResponseBody,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum KafkaResponseBody {
+    Metadata(MetadataResponse),
+    FindCoordinator(FindCoordinatorResponse),
+    /// A response that shotover does not need to inspect, contains everything following the correlation id
+    Raw(Bytes),
+    /// Kafka never responds to a produce request with acks set to 0.
+    /// Shotover still needs a response for every request passing through the chain, so this placeholder is used and then dropped instead of being sent to the client.
+    NoResponse,
+}
+
+impl KafkaResponse {
+    /// Parse a response to a request with the provided api key and version
+    pub fn from_bytes(mut bytes: Bytes, api_key: i16, api_version: i16) -> Result<Self> {
+        read_size_prefix(&mut bytes)?;
+        let correlation_id = read_i32(&mut bytes)?;
+
+        let (header_tagged_fields, body) = match api_key {
+            api_key::METADATA => {
+                let header_tagged_fields = if api_version >= 9 {
+                    read_tagged_fields(&mut bytes)?
+                } else {
+                    Bytes::new()
+                };
+                let body = MetadataResponse::parse(&mut bytes, api_version)?;
+                (header_tagged_fields, KafkaResponseBody::Metadata(body))
+            }
+            api_key::FIND_COORDINATOR => {
+                let header_tagged_fields = if api_version >= 3 {
+                    read_tagged_fields(&mut bytes)?
+                } else {
+                    Bytes::new()
+                };
+                let body = FindCoordinatorResponse::parse(&mut bytes, api_version)?;
+                (
+                    header_tagged_fields,
+                    KafkaResponseBody::FindCoordinator(body),
+                )
+            }
+            _ => (Bytes::new(), KafkaResponseBody::Raw(bytes)),
+        };
+
+        Ok(KafkaResponse {
+            api_key,
+            api_version,
+            correlation_id,
+            header_tagged_fields,
+            body,
+        })
+    }
+
+    fn encode_without_size(&self, dst: &mut BytesMut) -> Result<()> {
+        dst.put_i32(self.correlation_id);
+        dst.put_slice(&self.header_tagged_fields);
+        match &self.body {
+            KafkaResponseBody::Metadata(metadata) => metadata.encode(dst, self.api_version),
+            KafkaResponseBody::FindCoordinator(find_coordinator) => {
+                find_coordinator.encode(dst, self.api_version)?
+            }
+            KafkaResponseBody::Raw(body) => dst.put_slice(body),
+            KafkaResponseBody::NoResponse => {
+                bail!("Cannot encode the placeholder for a produce request that has no response")
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the provided function on the address of every broker in the response.
+    /// Used to point clients at shotover instead of at the brokers directly.
+    pub fn rewrite_broker_addresses(&mut self, mut rewrite: impl FnMut(&mut Bytes, &mut i32)) {
+        match &mut self.body {
+            KafkaResponseBody::Metadata(metadata) => {
+                for broker in &mut metadata.brokers {
+                    rewrite(&mut broker.host, &mut broker.port);
+                }
+            }
+            KafkaResponseBody::FindCoordinator(find_coordinator) => {
+                for coordinator in &mut find_coordinator.coordinators {
+                    rewrite(&mut coordinator.host, &mut coordinator.port);
+                }
+            }
+            KafkaResponseBody::Raw(_) | KafkaResponseBody::NoResponse => {}
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct MetadataResponse {
+    /// Only present from v3
+    pub throttle_time_ms: Option<i32>,
+    pub brokers: Vec<MetadataBroker>,
+    /// Everything following the brokers, which shotover does not need to inspect
+    pub rest: Bytes,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct MetadataBroker {
+    pub node_id: i32,
+    pub host: Bytes,
+    pub port: i32,
+    /// Only present from v1
+    pub rack: Option<Bytes>,
+    /// Only present from v9
+    pub tagged_fields: Bytes,
+}
+
+impl MetadataResponse {
+    fn parse(bytes: &mut Bytes, version: i16) -> Result<Self> {
+        let compact = version >= 9;
+        let throttle_time_ms = if version >= 3 {
+            Some(read_i32(bytes)?)
+        } else {
+            None
+        };
+
+        let broker_count = read_array_len(bytes, compact)?;
+        let mut brokers = Vec::with_capacity(broker_count);
+        for _ in 0..broker_count {
+            brokers.push(MetadataBroker {
+                node_id: read_i32(bytes)?,
+                host: read_string(bytes, compact)?,
+                port: read_i32(bytes)?,
+                rack: if version >= 1 {
+                    read_nullable_string(bytes, compact)?
+                } else {
+                    None
+                },
+                tagged_fields: if compact {
+                    read_tagged_fields(bytes)?
+                } else {
+                    Bytes::new()
+                },
+            });
+        }
+
+        Ok(MetadataResponse {
+            throttle_time_ms,
+            brokers,
+            rest: std::mem::take(bytes),
+        })
+    }
+
+    fn encode(&self, dst: &mut BytesMut, version: i16) {
+        let compact = version >= 9;
+        if let Some(throttle_time_ms) = self.throttle_time_ms {
+            dst.put_i32(throttle_time_ms);
+        }
+        put_array_len(dst, self.brokers.len(), compact);
+        for broker in &self.brokers {
+            dst.put_i32(broker.node_id);
+            put_string(dst, &broker.host, compact);
+            dst.put_i32(broker.port);
+            if version >= 1 {
+                put_nullable_string(dst, &broker.rack, compact);
+            }
+            dst.put_slice(&broker.tagged_fields);
+        }
+        dst.put_slice(&self.rest);
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct FindCoordinatorResponse {
+    /// Only present from v1
+    pub throttle_time_ms: Option<i32>,
+    /// Before v4 a request can only look up a single coordinator, so there is exactly one
+    pub coordinators: Vec<Coordinator>,
+    /// Only present from v3
+    pub tagged_fields: Bytes,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Coordinator {
+    /// Only present from v4
+    pub key: Option<Bytes>,
+    pub node_id: i32,
+    pub host: Bytes,
+    pub port: i32,
+    pub error_code: i16,
+    /// Only present from v1
+    pub error_message: Option<Bytes>,
+    /// Only present from v4
+    pub tagged_fields: Bytes,
+}
+
+impl FindCoordinatorResponse {
+    fn parse(bytes: &mut Bytes, version: i16) -> Result<Self> {
+        let compact = version >= 3;
+        let throttle_time_ms = if version >= 1 {
+            Some(read_i32(bytes)?)
+        } else {
+            None
+        };
+
+        let coordinators = if version >= 4 {
+            let count = read_array_len(bytes, compact)?;
+            let mut coordinators = Vec::with_capacity(count);
+            for _ in 0..count {
+                coordinators.push(Coordinator {
+                    key: Some(read_string(bytes, compact)?),
+                    node_id: read_i32(bytes)?,
+                    host: read_string(bytes, compact)?,
+                    port: read_i32(bytes)?,
+                    error_code: read_i16(bytes)?,
+                    error_message: read_nullable_string(bytes, compact)?,
+                    tagged_fields: read_tagged_fields(bytes)?,
+                });
+            }
+            coordinators
+        } else {
+            let error_code = read_i16(bytes)?;
+            let error_message = if version >= 1 {
+                read_nullable_string(bytes, compact)?
+            } else {
+                None
+            };
+            vec![Coordinator {
+                key: None,
+                node_id: read_i32(bytes)?,
+                host: read_string(bytes, compact)?,
+                port: read_i32(bytes)?,
+                error_code,
+                error_message,
+                tagged_fields: Bytes::new(),
+            }]
+        };
+
+        let tagged_fields = if compact {
+            read_tagged_fields(bytes)?
+        } else {
+            Bytes::new()
+        };
+        ensure!(
+            !bytes.has_remaining(),
+            "FindCoordinator response contained unexpected trailing bytes"
+        );
+
+        Ok(FindCoordinatorResponse {
+            throttle_time_ms,
+            coordinators,
+            tagged_fields,
+        })
+    }
+
+    fn encode(&self, dst: &mut BytesMut, version: i16) -> Result<()> {
+        let compact = version >= 3;
+        if let Some(throttle_time_ms) = self.throttle_time_ms {
+            dst.put_i32(throttle_time_ms);
+        }
+
+        if version >= 4 {
+            put_array_len(dst, self.coordinators.len(), compact);
+            for coordinator in &self.coordinators {
+                let key = coordinator
+                    .key
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("FindCoordinator v4+ coordinators must have a key"))?;
+                put_string(dst, key, compact);
+                dst.put_i32(coordinator.node_id);
+                put_string(dst, &coordinator.host, compact);
+                dst.put_i32(coordinator.port);
+                dst.put_i16(coordinator.error_code);
+                put_nullable_string(dst, &coordinator.error_message, compact);
+                dst.put_slice(&coordinator.tagged_fields);
+            }
+        } else {
+            let coordinator = match self.coordinators.as_slice() {
+                [coordinator] => coordinator,
+                _ => bail!("FindCoordinator responses before v4 must have exactly one coordinator"),
+            };
+            dst.put_i16(coordinator.error_code);
+            if version >= 1 {
+                put_nullable_string(dst, &coordinator.error_message, compact);
+            }
+            dst.put_i32(coordinator.node_id);
+            put_string(dst, &coordinator.host, compact);
+            dst.put_i32(coordinator.port);
+        }
+
+        dst.put_slice(&self.tagged_fields);
+        Ok(())
+    }
+}
+
+fn read_size_prefix(bytes: &mut Bytes) -> Result<()> {
+    let size = read_i32(bytes)?;
+    ensure!(
+        size as usize == bytes.remaining(),
+        "kafka message size prefix of {size} does not match actual size of {}",
+        bytes.remaining()
+    );
+    Ok(())
+}
+
+fn read_i16(bytes: &mut Bytes) -> Result<i16> {
+    ensure!(bytes.remaining() >= 2, "kafka message ended unexpectedly");
+    Ok(bytes.get_i16())
+}
+
+fn read_i32(bytes: &mut Bytes) -> Result<i32> {
+    ensure!(bytes.remaining() >= 4, "kafka message ended unexpectedly");
+    Ok(bytes.get_i32())
+}
+
+fn read_unsigned_varint(bytes: &mut Bytes) -> Result<u32> {
+    let mut value = 0u32;
+    for i in 0..5 {
+        ensure!(bytes.has_remaining(), "kafka message ended unexpectedly");
+        let byte = bytes.get_u8();
+        value |= ((byte & 0x7f) as u32) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("kafka varint is longer than 5 bytes")
+}
+
+fn put_unsigned_varint(dst: &mut BytesMut, mut value: u32) {
+    while value >= 0x80 {
+        dst.put_u8((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    dst.put_u8(value as u8);
+}
+
+fn read_bytes(bytes: &mut Bytes, len: usize) -> Result<Bytes> {
+    ensure!(bytes.remaining() >= len, "kafka message ended unexpectedly");
+    Ok(bytes.split_to(len))
+}
+
+/// Compact strings are used by flexible versions and prefix the length + 1 as a varint, leaving 0 to represent null
+fn read_nullable_string(bytes: &mut Bytes, compact: bool) -> Result<Option<Bytes>> {
+    let len = if compact {
+        read_unsigned_varint(bytes)? as i64 - 1
+    } else {
+        read_i16(bytes)? as i64
+    };
+    if len < 0 {
+        Ok(None)
+    } else {
+        read_bytes(bytes, len as usize).map(Some)
+    }
+}
+
+fn read_string(bytes: &mut Bytes, compact: bool) -> Result<Bytes> {
+    read_nullable_string(bytes, compact)?
+        .ok_or_else(|| anyhow!("kafka string was unexpectedly null"))
+}
+
+fn put_nullable_string(dst: &mut BytesMut, string: &Option<Bytes>, compact: bool) {
+    match string {
+        Some(string) => put_string(dst, string, compact),
+        None if compact => put_unsigned_varint(dst, 0),
+        None => dst.put_i16(-1),
+    }
+}
+
+fn put_string(dst: &mut BytesMut, string: &Bytes, compact: bool) {
+    if compact {
+        put_unsigned_varint(dst, string.len() as u32 + 1);
+    } else {
+        dst.put_i16(string.len() as i16);
+    }
+    dst.put_slice(string);
+}
+
+fn read_array_len(bytes: &mut Bytes, compact: bool) -> Result<usize> {
+    let len = if compact {
+        read_unsigned_varint(bytes)? as i64 - 1
+    } else {
+        read_i32(bytes)? as i64
+    };
+    ensure!(len >= 0, "kafka array was unexpectedly null");
+    Ok(len as usize)
+}
+
+fn put_array_len(dst: &mut BytesMut, len: usize, compact: bool) {
+    if compact {
+        put_unsigned_varint(dst, len as u32 + 1);
+    } else {
+        dst.put_i32(len as i32);
+    }
+}
+
+/// Returns the raw bytes of a tagged field section, shotover does not need to understand any tagged fields so they are passed through untouched
+fn read_tagged_fields(bytes: &mut Bytes) -> Result<Bytes> {
+    let original = bytes.clone();
+    let count = read_unsigned_varint(bytes)?;
+    for _ in 0..count {
+        read_unsigned_varint(bytes)?;
+        let size = read_unsigned_varint(bytes)? as usize;
+        read_bytes(bytes, size)?;
+    }
+    Ok(original.slice(..original.len() - bytes.len()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{put_unsigned_varint, read_unsigned_varint};
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_unsigned_varint() {
+        for value in [0, 1, 127, 128, 300, 16383, 16384, u32::MAX] {
+            let mut dst = BytesMut::new();
+            put_unsigned_varint(&mut dst, value);
+            assert_eq!(read_unsigned_varint(&mut dst.freeze()).unwrap(), value);
+        }
+    }
+}
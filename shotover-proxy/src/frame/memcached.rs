@@ -0,0 +1,1549 @@
+use crate::message::QueryType;
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+/// Length of the header that begins every binary protocol packet
+const BINARY_HEADER_LEN: usize = 24;
+const BINARY_REQUEST_MAGIC: u8 = 0x80;
+const BINARY_RESPONSE_MAGIC: u8 = 0x81;
+/// memcached rejects text protocol lines longer than this, so there is no point waiting for the rest of the line
+const MAX_TEXT_LINE_LEN: usize = 2048;
+
+const STATUS_SUCCESS: u16 = 0x0000;
+const STATUS_KEY_NOT_FOUND: u16 = 0x0001;
+const STATUS_KEY_EXISTS: u16 = 0x0002;
+const STATUS_VALUE_TOO_LARGE: u16 = 0x0003;
+const STATUS_INVALID_ARGUMENTS: u16 = 0x0004;
+const STATUS_ITEM_NOT_STORED: u16 = 0x0005;
+const STATUS_NON_NUMERIC_VALUE: u16 = 0x0006;
+const STATUS_UNKNOWN_COMMAND: u16 = 0x0081;
+const STATUS_OUT_OF_MEMORY: u16 = 0x0082;
+
+/// Functions for operations on an unparsed memcached frame
+pub mod raw_frame {
+    use super::{MemcachedFrame, MemcachedMetadata};
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    /// Parse metadata only from an unparsed memcached frame
+    pub(crate) fn metadata(bytes: &Bytes) -> Result<MemcachedMetadata> {
+        Ok(MemcachedFrame::from_bytes(bytes.clone())?.metadata())
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MemcachedProtocol {
+    Text,
+    Binary,
+}
+
+pub struct MemcachedMetadata {
+    pub protocol: MemcachedProtocol,
+    /// Not known for text protocol responses
+    pub command: Option<MemcachedCommand>,
+    pub opaque: u32,
+}
+
+impl MemcachedMetadata {
+    /// Create a response to the request this metadata was taken from that reports the provided error
+    pub fn error_response(&self, kind: MemcachedErrorKind, message: &str) -> MemcachedFrame {
+        MemcachedFrame::Response(MemcachedResponse {
+            protocol: self.protocol,
+            command: self.command,
+            status: MemcachedStatus::Error(MemcachedError {
+                kind,
+                message: Bytes::copy_from_slice(message.as_bytes()),
+            }),
+            cas: 0,
+            opaque: self.opaque,
+        })
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum MemcachedFrame {
+    Request(MemcachedRequest),
+    Response(MemcachedResponse),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MemcachedCommand {
+    Get,
+    /// A get that also returns the cas value of each item, only exists in the text protocol
+    Gets,
+    /// A get that also returns the key of the item, only exists in the binary protocol
+    GetK,
+    Set,
+    Add,
+    Replace,
+    Append,
+    Prepend,
+    /// Only exists in the text protocol, the binary protocol sets the cas field of any storage command instead
+    Cas,
+    Delete,
+    Incr,
+    Decr,
+    Touch,
+    FlushAll,
+    Version,
+    /// Only exists in the binary protocol
+    Noop,
+    Quit,
+}
+
+impl MemcachedCommand {
+    pub fn is_retrieval(&self) -> bool {
+        matches!(
+            self,
+            MemcachedCommand::Get | MemcachedCommand::Gets | MemcachedCommand::GetK
+        )
+    }
+
+    /// The name of the command as used by the text protocol
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemcachedCommand::Get => "get",
+            MemcachedCommand::Gets => "gets",
+            MemcachedCommand::GetK => "getk",
+            MemcachedCommand::Set => "set",
+            MemcachedCommand::Add => "add",
+            MemcachedCommand::Replace => "replace",
+            MemcachedCommand::Append => "append",
+            MemcachedCommand::Prepend => "prepend",
+            MemcachedCommand::Cas => "cas",
+            MemcachedCommand::Delete => "delete",
+            MemcachedCommand::Incr => "incr",
+            MemcachedCommand::Decr => "decr",
+            MemcachedCommand::Touch => "touch",
+            MemcachedCommand::FlushAll => "flush_all",
+            MemcachedCommand::Version => "version",
+            MemcachedCommand::Noop => "noop",
+            MemcachedCommand::Quit => "quit",
+        }
+    }
+
+    /// The binary protocol opcode for this command, quiet opcodes suppress the response when it would be uninteresting
+    pub fn binary_opcode(&self, quiet: bool) -> u8 {
+        match (self, quiet) {
+            (MemcachedCommand::Get | MemcachedCommand::Gets, false) => 0x00,
+            (MemcachedCommand::Get | MemcachedCommand::Gets, true) => 0x09,
+            (MemcachedCommand::GetK, false) => 0x0c,
+            (MemcachedCommand::GetK, true) => 0x0d,
+            (MemcachedCommand::Set | MemcachedCommand::Cas, false) => 0x01,
+            (MemcachedCommand::Set | MemcachedCommand::Cas, true) => 0x11,
+            (MemcachedCommand::Add, false) => 0x02,
+            (MemcachedCommand::Add, true) => 0x12,
+            (MemcachedCommand::Replace, false) => 0x03,
+            (MemcachedCommand::Replace, true) => 0x13,
+            (MemcachedCommand::Delete, false) => 0x04,
+            (MemcachedCommand::Delete, true) => 0x14,
+            (MemcachedCommand::Incr, false) => 0x05,
+            (MemcachedCommand::Incr, true) => 0x15,
+            (MemcachedCommand::Decr, false) => 0x06,
+            (MemcachedCommand::Decr, true) => 0x16,
+            (MemcachedCommand::Quit, false) => 0x07,
+            (MemcachedCommand::Quit, true) => 0x17,
+            (MemcachedCommand::FlushAll, false) => 0x08,
+            (MemcachedCommand::FlushAll, true) => 0x18,
+            (MemcachedCommand::Noop, _) => 0x0a,
+            (MemcachedCommand::Version, _) => 0x0b,
+            (MemcachedCommand::Append, false) => 0x0e,
+            (MemcachedCommand::Append, true) => 0x19,
+            (MemcachedCommand::Prepend, false) => 0x0f,
+            (MemcachedCommand::Prepend, true) => 0x1a,
+            (MemcachedCommand::Touch, _) => 0x1c,
+        }
+    }
+
+    /// Returns the command and whether the opcode was quiet
+    fn from_binary_opcode(opcode: u8) -> Option<(MemcachedCommand, bool)> {
+        Some(match opcode {
+            0x00 => (MemcachedCommand::Get, false),
+            0x09 => (MemcachedCommand::Get, true),
+            0x0c => (MemcachedCommand::GetK, false),
+            0x0d => (MemcachedCommand::GetK, true),
+            0x01 => (MemcachedCommand::Set, false),
+            0x11 => (MemcachedCommand::Set, true),
+            0x02 => (MemcachedCommand::Add, false),
+            0x12 => (MemcachedCommand::Add, true),
+            0x03 => (MemcachedCommand::Replace, false),
+            0x13 => (MemcachedCommand::Replace, true),
+            0x04 => (MemcachedCommand::Delete, false),
+            0x14 => (MemcachedCommand::Delete, true),
+            0x05 => (MemcachedCommand::Incr, false),
+            0x15 => (MemcachedCommand::Incr, true),
+            0x06 => (MemcachedCommand::Decr, false),
+            0x16 => (MemcachedCommand::Decr, true),
+            0x07 => (MemcachedCommand::Quit, false),
+            0x17 => (MemcachedCommand::Quit, true),
+            0x08 => (MemcachedCommand::FlushAll, false),
+            0x18 => (MemcachedCommand::FlushAll, true),
+            0x0a => (MemcachedCommand::Noop, false),
+            0x0b => (MemcachedCommand::Version, false),
+            0x0e => (MemcachedCommand::Append, false),
+            0x19 => (MemcachedCommand::Append, true),
+            0x0f => (MemcachedCommand::Prepend, false),
+            0x1a => (MemcachedCommand::Prepend, true),
+            0x1c => (MemcachedCommand::Touch, false),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct MemcachedRequest {
+    pub protocol: MemcachedProtocol,
+    pub command: MemcachedCommand,
+    /// Retrievals in the text protocol may have many keys, every other command has a single key or none at all
+    pub keys: Vec<Bytes>,
+    pub flags: u32,
+    pub exptime: u32,
+    pub cas: u64,
+    /// The amount to increment or decrement by
+    pub delta: u64,
+    /// The value used when incrementing or decrementing a missing key, only exists in the binary protocol
+    pub initial: u64,
+    pub value: Bytes,
+    /// Set by the `noreply` option of the text protocol and the quiet opcodes of the binary protocol
+    pub noreply: bool,
+    /// Returned unchanged in the response, only exists in the binary protocol
+    pub opaque: u32,
+}
+
+impl MemcachedRequest {
+    pub fn new(protocol: MemcachedProtocol, command: MemcachedCommand) -> Self {
+        MemcachedRequest {
+            protocol,
+            command,
+            keys: vec![],
+            flags: 0,
+            exptime: 0,
+            cas: 0,
+            delta: 0,
+            initial: 0,
+            value: Bytes::new(),
+            noreply: false,
+            opaque: 0,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct MemcachedResponse {
+    pub protocol: MemcachedProtocol,
+    /// The command this is a response to, only known for the binary protocol
+    pub command: Option<MemcachedCommand>,
+    pub status: MemcachedStatus,
+    pub cas: u64,
+    pub opaque: u32,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum MemcachedStatus {
+    /// The items found by a retrieval, a miss is empty in the text protocol and `NotFound` in the binary protocol
+    Values(Vec<MemcachedValue>),
+    Stored,
+    NotStored,
+    Exists,
+    NotFound,
+    Deleted,
+    Touched,
+    Ok,
+    /// The new value after an increment or decrement
+    Number(u64),
+    Version(Bytes),
+    Error(MemcachedError),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct MemcachedValue {
+    /// Always present in the text protocol, only present for GetK in the binary protocol
+    pub key: Bytes,
+    pub flags: u32,
+    /// Only present for gets in the text protocol, always present in the binary protocol
+    pub cas: Option<u64>,
+    pub data: Bytes,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct MemcachedError {
+    pub kind: MemcachedErrorKind,
+    pub message: Bytes,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MemcachedErrorKind {
+    /// The command was not recognized
+    Error,
+    /// The request was invalid
+    ClientError,
+    /// The server failed to handle a valid request
+    ServerError,
+}
+
+impl MemcachedFrame {
+    /// Parse a buffer known to contain a single complete frame
+    pub fn from_bytes(bytes: Bytes) -> Result<Self> {
+        let mut buffer = BytesMut::from(bytes.as_ref());
+        let frame = match bytes.first() {
+            Some(&BINARY_REQUEST_MAGIC) | Some(&BINARY_RESPONSE_MAGIC) => {
+                decode_binary(&mut buffer)?
+            }
+            _ => match decode_text_request(&mut buffer.clone()) {
+                Ok(frame) => frame,
+                Err(_) => decode_text_response(&mut buffer)?,
+            },
+        };
+        frame
+            .map(|(frame, _)| frame)
+            .ok_or_else(|| anyhow!("incomplete memcached frame"))
+    }
+
+    /// Parse a request from the start of src, returning None if the request is not yet complete.
+    /// The bytes of the request are removed from src and returned alongside the frame.
+    pub fn decode_request(src: &mut BytesMut) -> Result<Option<(MemcachedFrame, Bytes)>> {
+        match src.first() {
+            None => Ok(None),
+            Some(&BINARY_REQUEST_MAGIC) => decode_binary(src),
+            Some(_) => decode_text_request(src),
+        }
+    }
+
+    /// Parse a response from the start of src, returning None if the response is not yet complete.
+    /// The bytes of the response are removed from src and returned alongside the frame.
+    pub fn decode_response(src: &mut BytesMut) -> Result<Option<(MemcachedFrame, Bytes)>> {
+        match src.first() {
+            None => Ok(None),
+            Some(&BINARY_RESPONSE_MAGIC) => decode_binary(src),
+            Some(_) => decode_text_response(src),
+        }
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) -> Result<()> {
+        match self {
+            MemcachedFrame::Request(request) => match request.protocol {
+                MemcachedProtocol::Text => encode_text_request(request, dst),
+                MemcachedProtocol::Binary => encode_binary_request(request, dst),
+            },
+            MemcachedFrame::Response(response) => match response.protocol {
+                MemcachedProtocol::Text => encode_text_response(response, dst),
+                MemcachedProtocol::Binary => encode_binary_response(response, dst),
+            },
+        }
+    }
+
+    pub fn protocol(&self) -> MemcachedProtocol {
+        match self {
+            MemcachedFrame::Request(request) => request.protocol,
+            MemcachedFrame::Response(response) => response.protocol,
+        }
+    }
+
+    pub(crate) fn metadata(&self) -> MemcachedMetadata {
+        match self {
+            MemcachedFrame::Request(request) => MemcachedMetadata {
+                protocol: request.protocol,
+                command: Some(request.command),
+                opaque: request.opaque,
+            },
+            MemcachedFrame::Response(response) => MemcachedMetadata {
+                protocol: response.protocol,
+                command: response.command,
+                opaque: response.opaque,
+            },
+        }
+    }
+
+    pub fn get_query_type(&self) -> QueryType {
+        match self {
+            MemcachedFrame::Request(request) => match request.command {
+                MemcachedCommand::Get
+                | MemcachedCommand::Gets
+                | MemcachedCommand::GetK
+                | MemcachedCommand::Version
+                | MemcachedCommand::Noop
+                | MemcachedCommand::Quit => QueryType::Read,
+                _ => QueryType::Write,
+            },
+            MemcachedFrame::Response(_) => QueryType::Read,
+        }
+    }
+
+    /// Returns true if the response indicates that the request was not successful, a retrieval miss is not considered a failure
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            MemcachedFrame::Response(MemcachedResponse {
+                status: MemcachedStatus::Error(_),
+                ..
+            })
+        )
+    }
+}
+
+impl Display for MemcachedFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            MemcachedFrame::Request(request) => {
+                write!(
+                    f,
+                    "{:?} request {}",
+                    request.protocol,
+                    request.command.name()
+                )?;
+                for key in &request.keys {
+                    write!(f, " {}", String::from_utf8_lossy(key))?;
+                }
+                Ok(())
+            }
+            MemcachedFrame::Response(response) => {
+                write!(f, "{:?} response {:?}", response.protocol, response.status)
+            }
+        }
+    }
+}
+
+fn find_crlf(src: &[u8], start: usize) -> Option<usize> {
+    src.get(start..)?
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .map(|position| start + position)
+}
+
+/// Returns the end of the line beginning at start, or None if the line is incomplete
+fn find_line_end(src: &[u8], start: usize) -> Result<Option<usize>> {
+    match find_crlf(src, start) {
+        Some(end) => Ok(Some(end)),
+        None if src.len() - start > MAX_TEXT_LINE_LEN => {
+            bail!("memcached text protocol line exceeds maximum length")
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_token<T: FromStr>(token: Option<&&[u8]>, name: &str) -> Result<T> {
+    let token = token.ok_or_else(|| anyhow!("missing {name}"))?;
+    std::str::from_utf8(token)
+        .ok()
+        .and_then(|token| token.parse().ok())
+        .ok_or_else(|| anyhow!("invalid {name} {:?}", String::from_utf8_lossy(token)))
+}
+
+fn parse_key(token: Option<&&[u8]>) -> Result<Bytes> {
+    token
+        .map(|key| Bytes::copy_from_slice(key))
+        .ok_or_else(|| anyhow!("missing key"))
+}
+
+fn decode_text_request(src: &mut BytesMut) -> Result<Option<(MemcachedFrame, Bytes)>> {
+    let line_end = match find_line_end(src, 0)? {
+        Some(line_end) => line_end,
+        None => return Ok(None),
+    };
+    let mut tokens: Vec<&[u8]> = src[..line_end]
+        .split(|byte| *byte == b' ')
+        .filter(|token| !token.is_empty())
+        .collect();
+    if tokens.is_empty() {
+        bail!("empty memcached command");
+    }
+    let command_name = tokens.remove(0);
+    let noreply = tokens.last() == Some(&b"noreply".as_slice());
+    if noreply {
+        tokens.pop();
+    }
+
+    let command = match command_name {
+        b"get" => MemcachedCommand::Get,
+        b"gets" => MemcachedCommand::Gets,
+        b"set" => MemcachedCommand::Set,
+        b"add" => MemcachedCommand::Add,
+        b"replace" => MemcachedCommand::Replace,
+        b"append" => MemcachedCommand::Append,
+        b"prepend" => MemcachedCommand::Prepend,
+        b"cas" => MemcachedCommand::Cas,
+        b"delete" => MemcachedCommand::Delete,
+        b"incr" => MemcachedCommand::Incr,
+        b"decr" => MemcachedCommand::Decr,
+        b"touch" => MemcachedCommand::Touch,
+        b"flush_all" => MemcachedCommand::FlushAll,
+        b"version" => MemcachedCommand::Version,
+        b"quit" => MemcachedCommand::Quit,
+        command => bail!(
+            "unknown memcached command {:?}",
+            String::from_utf8_lossy(command)
+        ),
+    };
+    let mut request = MemcachedRequest::new(MemcachedProtocol::Text, command);
+    request.noreply = noreply;
+
+    let mut len = line_end + 2;
+    let mut value_range = None;
+    match command {
+        MemcachedCommand::Get | MemcachedCommand::Gets => {
+            if tokens.is_empty() {
+                bail!("missing key");
+            }
+            request.keys = tokens
+                .iter()
+                .map(|key| Bytes::copy_from_slice(key))
+                .collect();
+        }
+        MemcachedCommand::Set
+        | MemcachedCommand::Add
+        | MemcachedCommand::Replace
+        | MemcachedCommand::Append
+        | MemcachedCommand::Prepend
+        | MemcachedCommand::Cas => {
+            request.keys = vec![parse_key(tokens.first())?];
+            request.flags = parse_token(tokens.get(1), "flags")?;
+            request.exptime = parse_token(tokens.get(2), "exptime")?;
+            let value_len: usize = parse_token(tokens.get(3), "bytes")?;
+            if command == MemcachedCommand::Cas {
+                request.cas = parse_token(tokens.get(4), "cas unique")?;
+            }
+
+            if src.len() < len + value_len + 2 {
+                return Ok(None);
+            }
+            if &src[len + value_len..len + value_len + 2] != b"\r\n" {
+                bail!("data block was not terminated by \\r\\n");
+            }
+            value_range = Some(len..len + value_len);
+            len += value_len + 2;
+        }
+        MemcachedCommand::Delete => {
+            request.keys = vec![parse_key(tokens.first())?];
+        }
+        MemcachedCommand::Incr | MemcachedCommand::Decr => {
+            request.keys = vec![parse_key(tokens.first())?];
+            request.delta = parse_token(tokens.get(1), "value")?;
+        }
+        MemcachedCommand::Touch => {
+            request.keys = vec![parse_key(tokens.first())?];
+            request.exptime = parse_token(tokens.get(1), "exptime")?;
+        }
+        MemcachedCommand::FlushAll => {
+            if tokens.first().is_some() {
+                request.exptime = parse_token(tokens.first(), "delay")?;
+            }
+        }
+        _ => {}
+    }
+
+    let bytes = src.split_to(len).freeze();
+    if let Some(value_range) = value_range {
+        request.value = bytes.slice(value_range);
+    }
+    Ok(Some((MemcachedFrame::Request(request), bytes)))
+}
+
+fn decode_text_response(src: &mut BytesMut) -> Result<Option<(MemcachedFrame, Bytes)>> {
+    let mut values = vec![];
+    let mut position = 0;
+    let status = loop {
+        let line_end = match find_line_end(src, position)? {
+            Some(line_end) => line_end,
+            None => return Ok(None),
+        };
+        let line = &src[position..line_end];
+        let next_line = line_end + 2;
+
+        if let Some(value_line) = line.strip_prefix(b"VALUE ") {
+            let tokens: Vec<&[u8]> = value_line
+                .split(|byte| *byte == b' ')
+                .filter(|token| !token.is_empty())
+                .collect();
+            let key = parse_key(tokens.first())?;
+            let flags = parse_token(tokens.get(1), "flags")?;
+            let value_len: usize = parse_token(tokens.get(2), "bytes")?;
+            let cas = match tokens.get(3) {
+                Some(_) => Some(parse_token(tokens.get(3), "cas unique")?),
+                None => None,
+            };
+
+            if src.len() < next_line + value_len + 2 {
+                return Ok(None);
+            }
+            values.push(MemcachedValue {
+                key,
+                flags,
+                cas,
+                data: Bytes::copy_from_slice(&src[next_line..next_line + value_len]),
+            });
+            position = next_line + value_len + 2;
+            continue;
+        }
+
+        let status = match line {
+            b"END" => MemcachedStatus::Values(std::mem::take(&mut values)),
+            _ if !values.is_empty() => bail!("retrieval response was not terminated by END"),
+            b"STORED" => MemcachedStatus::Stored,
+            b"NOT_STORED" => MemcachedStatus::NotStored,
+            b"EXISTS" => MemcachedStatus::Exists,
+            b"NOT_FOUND" => MemcachedStatus::NotFound,
+            b"DELETED" => MemcachedStatus::Deleted,
+            b"TOUCHED" => MemcachedStatus::Touched,
+            b"OK" => MemcachedStatus::Ok,
+            b"ERROR" => MemcachedStatus::Error(MemcachedError {
+                kind: MemcachedErrorKind::Error,
+                message: Bytes::new(),
+            }),
+            _ => {
+                if let Some(message) = line.strip_prefix(b"CLIENT_ERROR ") {
+                    MemcachedStatus::Error(MemcachedError {
+                        kind: MemcachedErrorKind::ClientError,
+                        message: Bytes::copy_from_slice(message),
+                    })
+                } else if let Some(message) = line.strip_prefix(b"SERVER_ERROR ") {
+                    MemcachedStatus::Error(MemcachedError {
+                        kind: MemcachedErrorKind::ServerError,
+                        message: Bytes::copy_from_slice(message),
+                    })
+                } else if let Some(version) = line.strip_prefix(b"VERSION ") {
+                    MemcachedStatus::Version(Bytes::copy_from_slice(version))
+                } else {
+                    MemcachedStatus::Number(parse_token(Some(&line), "response")?)
+                }
+            }
+        };
+        position = next_line;
+        break status;
+    };
+
+    let bytes = src.split_to(position).freeze();
+    Ok(Some((
+        MemcachedFrame::Response(MemcachedResponse {
+            protocol: MemcachedProtocol::Text,
+            command: None,
+            status,
+            cas: 0,
+            opaque: 0,
+        }),
+        bytes,
+    )))
+}
+
+fn encode_text_request(request: &MemcachedRequest, dst: &mut BytesMut) -> Result<()> {
+    dst.put_slice(request.command.name().as_bytes());
+    for key in &request.keys {
+        dst.put_u8(b' ');
+        dst.put_slice(key);
+    }
+    match request.command {
+        MemcachedCommand::Set
+        | MemcachedCommand::Add
+        | MemcachedCommand::Replace
+        | MemcachedCommand::Append
+        | MemcachedCommand::Prepend => {
+            dst.put_slice(
+                format!(
+                    " {} {} {}",
+                    request.flags,
+                    request.exptime,
+                    request.value.len()
+                )
+                .as_bytes(),
+            );
+        }
+        MemcachedCommand::Cas => {
+            dst.put_slice(
+                format!(
+                    " {} {} {} {}",
+                    request.flags,
+                    request.exptime,
+                    request.value.len(),
+                    request.cas
+                )
+                .as_bytes(),
+            );
+        }
+        MemcachedCommand::Incr | MemcachedCommand::Decr => {
+            dst.put_slice(format!(" {}", request.delta).as_bytes());
+        }
+        MemcachedCommand::Touch => {
+            dst.put_slice(format!(" {}", request.exptime).as_bytes());
+        }
+        MemcachedCommand::FlushAll if request.exptime != 0 => {
+            dst.put_slice(format!(" {}", request.exptime).as_bytes());
+        }
+        MemcachedCommand::GetK | MemcachedCommand::Noop => {
+            bail!(
+                "{} is not supported by the memcached text protocol",
+                request.command.name()
+            )
+        }
+        _ => {}
+    }
+    if request.noreply {
+        dst.put_slice(b" noreply");
+    }
+    dst.put_slice(b"\r\n");
+
+    if matches!(
+        request.command,
+        MemcachedCommand::Set
+            | MemcachedCommand::Add
+            | MemcachedCommand::Replace
+            | MemcachedCommand::Append
+            | MemcachedCommand::Prepend
+            | MemcachedCommand::Cas
+    ) {
+        dst.put_slice(&request.value);
+        dst.put_slice(b"\r\n");
+    }
+    Ok(())
+}
+
+fn encode_text_response(response: &MemcachedResponse, dst: &mut BytesMut) -> Result<()> {
+    match &response.status {
+        MemcachedStatus::Values(values) => {
+            for value in values {
+                dst.put_slice(b"VALUE ");
+                dst.put_slice(&value.key);
+                dst.put_slice(format!(" {} {}", value.flags, value.data.len()).as_bytes());
+                if let Some(cas) = value.cas {
+                    dst.put_slice(format!(" {cas}").as_bytes());
+                }
+                dst.put_slice(b"\r\n");
+                dst.put_slice(&value.data);
+                dst.put_slice(b"\r\n");
+            }
+            dst.put_slice(b"END");
+        }
+        MemcachedStatus::Stored => dst.put_slice(b"STORED"),
+        MemcachedStatus::NotStored => dst.put_slice(b"NOT_STORED"),
+        MemcachedStatus::Exists => dst.put_slice(b"EXISTS"),
+        MemcachedStatus::NotFound => dst.put_slice(b"NOT_FOUND"),
+        MemcachedStatus::Deleted => dst.put_slice(b"DELETED"),
+        MemcachedStatus::Touched => dst.put_slice(b"TOUCHED"),
+        MemcachedStatus::Ok => dst.put_slice(b"OK"),
+        MemcachedStatus::Number(number) => dst.put_slice(number.to_string().as_bytes()),
+        MemcachedStatus::Version(version) => {
+            dst.put_slice(b"VERSION ");
+            dst.put_slice(version);
+        }
+        MemcachedStatus::Error(error) => match error.kind {
+            MemcachedErrorKind::Error => dst.put_slice(b"ERROR"),
+            MemcachedErrorKind::ClientError => {
+                dst.put_slice(b"CLIENT_ERROR ");
+                dst.put_slice(&error.message);
+            }
+            MemcachedErrorKind::ServerError => {
+                dst.put_slice(b"SERVER_ERROR ");
+                dst.put_slice(&error.message);
+            }
+        },
+    }
+    dst.put_slice(b"\r\n");
+    Ok(())
+}
+
+fn decode_binary(src: &mut BytesMut) -> Result<Option<(MemcachedFrame, Bytes)>> {
+    if src.len() < BINARY_HEADER_LEN {
+        return Ok(None);
+    }
+    let body_len = u32::from_be_bytes(src[8..12].try_into()?) as usize;
+    if src.len() < BINARY_HEADER_LEN + body_len {
+        return Ok(None);
+    }
+
+    let bytes = src.split_to(BINARY_HEADER_LEN + body_len).freeze();
+    Ok(Some((parse_binary(&bytes)?, bytes)))
+}
+
+fn parse_binary(bytes: &Bytes) -> Result<MemcachedFrame> {
+    let mut header = &bytes[..BINARY_HEADER_LEN];
+    let magic = header.get_u8();
+    let opcode = header.get_u8();
+    let key_len = header.get_u16() as usize;
+    let extras_len = header.get_u8() as usize;
+    let _data_type = header.get_u8();
+    let status = header.get_u16();
+    let body_len = header.get_u32() as usize;
+    let opaque = header.get_u32();
+    let cas = header.get_u64();
+
+    if extras_len + key_len > body_len {
+        bail!("memcached binary packet extras and key are longer than the body");
+    }
+    let mut extras = bytes.slice(BINARY_HEADER_LEN..BINARY_HEADER_LEN + extras_len);
+    let key = bytes.slice(BINARY_HEADER_LEN + extras_len..BINARY_HEADER_LEN + extras_len + key_len);
+    let value = bytes.slice(BINARY_HEADER_LEN + extras_len + key_len..BINARY_HEADER_LEN + body_len);
+
+    let (command, quiet) = MemcachedCommand::from_binary_opcode(opcode)
+        .ok_or_else(|| anyhow!("unknown memcached binary opcode {opcode:#04x}"))?;
+
+    match magic {
+        BINARY_REQUEST_MAGIC => {
+            let mut request = MemcachedRequest::new(MemcachedProtocol::Binary, command);
+            request.noreply = quiet;
+            request.opaque = opaque;
+            request.cas = cas;
+            if !key.is_empty() {
+                request.keys = vec![key];
+            }
+            request.value = value;
+            match command {
+                MemcachedCommand::Set | MemcachedCommand::Add | MemcachedCommand::Replace => {
+                    if extras.len() != 8 {
+                        bail!("storage commands require 8 bytes of extras");
+                    }
+                    request.flags = extras.get_u32();
+                    request.exptime = extras.get_u32();
+                }
+                MemcachedCommand::Incr | MemcachedCommand::Decr => {
+                    if extras.len() != 20 {
+                        bail!("incr and decr require 20 bytes of extras");
+                    }
+                    request.delta = extras.get_u64();
+                    request.initial = extras.get_u64();
+                    request.exptime = extras.get_u32();
+                }
+                MemcachedCommand::Touch | MemcachedCommand::FlushAll if extras.len() == 4 => {
+                    request.exptime = extras.get_u32();
+                }
+                _ => {}
+            }
+            Ok(MemcachedFrame::Request(request))
+        }
+        BINARY_RESPONSE_MAGIC => {
+            let status = match status {
+                STATUS_SUCCESS => match command {
+                    MemcachedCommand::Get | MemcachedCommand::GetK => {
+                        let flags = if extras.len() == 4 {
+                            extras.get_u32()
+                        } else {
+                            0
+                        };
+                        MemcachedStatus::Values(vec![MemcachedValue {
+                            key,
+                            flags,
+                            cas: Some(cas),
+                            data: value,
+                        }])
+                    }
+                    MemcachedCommand::Set
+                    | MemcachedCommand::Add
+                    | MemcachedCommand::Replace
+                    | MemcachedCommand::Append
+                    | MemcachedCommand::Prepend => MemcachedStatus::Stored,
+                    MemcachedCommand::Delete => MemcachedStatus::Deleted,
+                    MemcachedCommand::Incr | MemcachedCommand::Decr => {
+                        if value.len() != 8 {
+                            bail!("incr and decr responses require an 8 byte value");
+                        }
+                        MemcachedStatus::Number(value.clone().get_u64())
+                    }
+                    MemcachedCommand::Touch => MemcachedStatus::Touched,
+                    MemcachedCommand::Version => MemcachedStatus::Version(value),
+                    _ => MemcachedStatus::Ok,
+                },
+                STATUS_KEY_NOT_FOUND => MemcachedStatus::NotFound,
+                STATUS_KEY_EXISTS => MemcachedStatus::Exists,
+                STATUS_ITEM_NOT_STORED => MemcachedStatus::NotStored,
+                STATUS_UNKNOWN_COMMAND => MemcachedStatus::Error(MemcachedError {
+                    kind: MemcachedErrorKind::Error,
+                    message: value,
+                }),
+                STATUS_VALUE_TOO_LARGE | STATUS_INVALID_ARGUMENTS | STATUS_NON_NUMERIC_VALUE => {
+                    MemcachedStatus::Error(MemcachedError {
+                        kind: MemcachedErrorKind::ClientError,
+                        message: value,
+                    })
+                }
+                _ => MemcachedStatus::Error(MemcachedError {
+                    kind: MemcachedErrorKind::ServerError,
+                    message: value,
+                }),
+            };
+            Ok(MemcachedFrame::Response(MemcachedResponse {
+                protocol: MemcachedProtocol::Binary,
+                command: Some(command),
+                status,
+                cas,
+                opaque,
+            }))
+        }
+        magic => bail!("unknown memcached binary magic {magic:#04x}"),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn encode_binary_packet(
+    dst: &mut BytesMut,
+    magic: u8,
+    opcode: u8,
+    status: u16,
+    opaque: u32,
+    cas: u64,
+    extras: &[u8],
+    key: &[u8],
+    value: &[u8],
+) {
+    dst.put_u8(magic);
+    dst.put_u8(opcode);
+    dst.put_u16(key.len() as u16);
+    dst.put_u8(extras.len() as u8);
+    dst.put_u8(0);
+    dst.put_u16(status);
+    dst.put_u32((extras.len() + key.len() + value.len()) as u32);
+    dst.put_u32(opaque);
+    dst.put_u64(cas);
+    dst.put_slice(extras);
+    dst.put_slice(key);
+    dst.put_slice(value);
+}
+
+fn encode_binary_request(request: &MemcachedRequest, dst: &mut BytesMut) -> Result<()> {
+    let mut extras = BytesMut::new();
+    match request.command {
+        MemcachedCommand::Set
+        | MemcachedCommand::Add
+        | MemcachedCommand::Replace
+        | MemcachedCommand::Cas => {
+            extras.put_u32(request.flags);
+            extras.put_u32(request.exptime);
+        }
+        MemcachedCommand::Incr | MemcachedCommand::Decr => {
+            extras.put_u64(request.delta);
+            extras.put_u64(request.initial);
+            extras.put_u32(request.exptime);
+        }
+        MemcachedCommand::Touch => extras.put_u32(request.exptime),
+        MemcachedCommand::FlushAll if request.exptime != 0 => extras.put_u32(request.exptime),
+        _ => {}
+    }
+
+    let key = match request.keys.as_slice() {
+        [] => Bytes::new(),
+        [key] => key.clone(),
+        _ => bail!("the memcached binary protocol only supports a single key per request"),
+    };
+
+    encode_binary_packet(
+        dst,
+        BINARY_REQUEST_MAGIC,
+        request.command.binary_opcode(request.noreply),
+        0,
+        request.opaque,
+        request.cas,
+        &extras,
+        &key,
+        &request.value,
+    );
+    Ok(())
+}
+
+fn encode_binary_response(response: &MemcachedResponse, dst: &mut BytesMut) -> Result<()> {
+    let command = response
+        .command
+        .ok_or_else(|| anyhow!("binary protocol responses must specify their command"))?;
+    let opcode = command.binary_opcode(false);
+
+    let mut extras = BytesMut::new();
+    let mut key = Bytes::new();
+    let mut value = Bytes::new();
+    let mut cas = response.cas;
+    let status = match &response.status {
+        MemcachedStatus::Values(values) => match values.as_slice() {
+            [] => STATUS_KEY_NOT_FOUND,
+            [item] => {
+                extras.put_u32(item.flags);
+                if command == MemcachedCommand::GetK {
+                    key = item.key.clone();
+                }
+                value = item.data.clone();
+                cas = item.cas.unwrap_or(cas);
+                STATUS_SUCCESS
+            }
+            _ => bail!("the memcached binary protocol only supports a single value per response"),
+        },
+        MemcachedStatus::Number(number) => {
+            value = Bytes::copy_from_slice(&number.to_be_bytes());
+            STATUS_SUCCESS
+        }
+        MemcachedStatus::Version(version) => {
+            value = version.clone();
+            STATUS_SUCCESS
+        }
+        MemcachedStatus::Stored
+        | MemcachedStatus::Deleted
+        | MemcachedStatus::Touched
+        | MemcachedStatus::Ok => STATUS_SUCCESS,
+        MemcachedStatus::NotStored => STATUS_ITEM_NOT_STORED,
+        MemcachedStatus::Exists => STATUS_KEY_EXISTS,
+        MemcachedStatus::NotFound => STATUS_KEY_NOT_FOUND,
+        MemcachedStatus::Error(error) => {
+            value = error.message.clone();
+            match error.kind {
+                MemcachedErrorKind::Error => STATUS_UNKNOWN_COMMAND,
+                MemcachedErrorKind::ClientError => STATUS_INVALID_ARGUMENTS,
+                MemcachedErrorKind::ServerError => STATUS_OUT_OF_MEMORY,
+            }
+        }
+    };
+
+    encode_binary_packet(
+        dst,
+        BINARY_RESPONSE_MAGIC,
+        opcode,
+        status,
+        response.opaque,
+        cas,
+        &extras,
+        &key,
+        &value,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod memcached_frame_tests {
+    use super::*;
+
+    /// Decodes a request that makes up all of `raw`, checks it encodes back to `raw` and returns it
+    fn request_round_trip(raw: &[u8]) -> MemcachedRequest {
+        let mut src = BytesMut::from(raw);
+        let (frame, bytes) = MemcachedFrame::decode_request(&mut src).unwrap().unwrap();
+        assert!(src.is_empty());
+        assert_eq!(bytes, raw);
+        assert_eq!(MemcachedFrame::from_bytes(bytes).unwrap(), frame);
+
+        let mut dst = BytesMut::new();
+        frame.encode(&mut dst).unwrap();
+        assert_eq!(dst, raw);
+
+        match frame {
+            MemcachedFrame::Request(request) => request,
+            frame => panic!("expected a request but was {frame:?}"),
+        }
+    }
+
+    /// Decodes a response that makes up all of `raw`, checks it encodes back to `raw` and returns its status
+    fn response_round_trip(raw: &[u8]) -> MemcachedStatus {
+        let mut src = BytesMut::from(raw);
+        let (frame, bytes) = MemcachedFrame::decode_response(&mut src).unwrap().unwrap();
+        assert!(src.is_empty());
+        assert_eq!(bytes, raw);
+
+        let mut dst = BytesMut::new();
+        frame.encode(&mut dst).unwrap();
+        assert_eq!(dst, raw);
+
+        match frame {
+            MemcachedFrame::Response(response) => response.status,
+            frame => panic!("expected a response but was {frame:?}"),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn binary_packet(
+        magic: u8,
+        opcode: u8,
+        status: u16,
+        opaque: u32,
+        cas: u64,
+        extras: &[u8],
+        key: &[u8],
+        value: &[u8],
+    ) -> Vec<u8> {
+        let mut dst = BytesMut::new();
+        encode_binary_packet(
+            &mut dst, magic, opcode, status, opaque, cas, extras, key, value,
+        );
+        dst.to_vec()
+    }
+
+    fn binary_request(opcode: u8, extras: &[u8], key: &[u8], value: &[u8]) -> Vec<u8> {
+        binary_packet(BINARY_REQUEST_MAGIC, opcode, 0, 7, 0, extras, key, value)
+    }
+
+    fn binary_response(
+        opcode: u8,
+        status: u16,
+        extras: &[u8],
+        key: &[u8],
+        value: &[u8],
+    ) -> Vec<u8> {
+        binary_packet(
+            BINARY_RESPONSE_MAGIC,
+            opcode,
+            status,
+            7,
+            3,
+            extras,
+            key,
+            value,
+        )
+    }
+
+    fn value(key: &str, flags: u32, cas: Option<u64>, data: &str) -> MemcachedValue {
+        MemcachedValue {
+            key: Bytes::copy_from_slice(key.as_bytes()),
+            flags,
+            cas,
+            data: Bytes::copy_from_slice(data.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_text_retrieval_requests() {
+        let request = request_round_trip(b"get foo bar\r\n");
+        assert_eq!(request.command, MemcachedCommand::Get);
+        assert_eq!(request.keys, vec!["foo", "bar"]);
+
+        let request = request_round_trip(b"gets foo\r\n");
+        assert_eq!(request.command, MemcachedCommand::Gets);
+        assert_eq!(request.keys, vec!["foo"]);
+    }
+
+    #[test]
+    fn test_text_storage_requests() {
+        for (raw, command) in [
+            (
+                b"set foo 1 2 3\r\nbar\r\n".as_slice(),
+                MemcachedCommand::Set,
+            ),
+            (
+                b"add foo 1 2 3\r\nbar\r\n".as_slice(),
+                MemcachedCommand::Add,
+            ),
+            (
+                b"replace foo 1 2 3\r\nbar\r\n".as_slice(),
+                MemcachedCommand::Replace,
+            ),
+            (
+                b"append foo 1 2 3\r\nbar\r\n".as_slice(),
+                MemcachedCommand::Append,
+            ),
+            (
+                b"prepend foo 1 2 3\r\nbar\r\n".as_slice(),
+                MemcachedCommand::Prepend,
+            ),
+        ] {
+            let request = request_round_trip(raw);
+            assert_eq!(request.command, command);
+            assert_eq!(request.keys, vec!["foo"]);
+            assert_eq!(request.flags, 1);
+            assert_eq!(request.exptime, 2);
+            assert_eq!(request.value, "bar");
+            assert!(!request.noreply);
+        }
+
+        let request = request_round_trip(b"cas foo 1 2 3 99 noreply\r\nbar\r\n");
+        assert_eq!(request.command, MemcachedCommand::Cas);
+        assert_eq!(request.cas, 99);
+        assert_eq!(request.value, "bar");
+        assert!(request.noreply);
+
+        // the value may contain \r\n as its length is given up front
+        let request = request_round_trip(b"set foo 0 0 4\r\n\r\n\r\n\r\n");
+        assert_eq!(request.value, "\r\n\r\n");
+    }
+
+    #[test]
+    fn test_text_other_requests() {
+        let request = request_round_trip(b"delete foo noreply\r\n");
+        assert_eq!(request.command, MemcachedCommand::Delete);
+        assert_eq!(request.keys, vec!["foo"]);
+        assert!(request.noreply);
+
+        let request = request_round_trip(b"incr foo 5\r\n");
+        assert_eq!(request.command, MemcachedCommand::Incr);
+        assert_eq!(request.delta, 5);
+
+        let request = request_round_trip(b"decr foo 5\r\n");
+        assert_eq!(request.command, MemcachedCommand::Decr);
+        assert_eq!(request.delta, 5);
+
+        let request = request_round_trip(b"touch foo 10\r\n");
+        assert_eq!(request.command, MemcachedCommand::Touch);
+        assert_eq!(request.exptime, 10);
+
+        let request = request_round_trip(b"flush_all\r\n");
+        assert_eq!(request.command, MemcachedCommand::FlushAll);
+        assert_eq!(request.exptime, 0);
+
+        let request = request_round_trip(b"flush_all 10\r\n");
+        assert_eq!(request.exptime, 10);
+
+        assert_eq!(
+            request_round_trip(b"version\r\n").command,
+            MemcachedCommand::Version
+        );
+        assert_eq!(
+            request_round_trip(b"quit\r\n").command,
+            MemcachedCommand::Quit
+        );
+    }
+
+    #[test]
+    fn test_text_incomplete_and_invalid_requests() {
+        for raw in [
+            b"get foo".as_slice(),
+            b"set foo 0 0 3\r\n".as_slice(),
+            b"set foo 0 0 3\r\nba".as_slice(),
+        ] {
+            let mut src = BytesMut::from(raw);
+            assert!(MemcachedFrame::decode_request(&mut src).unwrap().is_none());
+            assert_eq!(src, raw);
+        }
+
+        for raw in [
+            b"bogus foo\r\n".as_slice(),
+            b"get\r\n".as_slice(),
+            b"set foo 0 0 3\r\nbarbaz\r\n".as_slice(),
+            b"incr foo bar\r\n".as_slice(),
+        ] {
+            assert!(MemcachedFrame::decode_request(&mut BytesMut::from(raw)).is_err());
+        }
+
+        // the binary only commands can not be sent with the text protocol
+        let mut request = MemcachedRequest::new(MemcachedProtocol::Text, MemcachedCommand::Noop);
+        assert!(MemcachedFrame::Request(request.clone())
+            .encode(&mut BytesMut::new())
+            .is_err());
+        request.command = MemcachedCommand::GetK;
+        assert!(MemcachedFrame::Request(request)
+            .encode(&mut BytesMut::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_text_responses() {
+        assert_eq!(
+            response_round_trip(b"VALUE foo 1 3\r\nbar\r\nVALUE baz 0 0 99\r\n\r\nEND\r\n"),
+            MemcachedStatus::Values(vec![
+                value("foo", 1, None, "bar"),
+                value("baz", 0, Some(99), "")
+            ])
+        );
+        assert_eq!(
+            response_round_trip(b"END\r\n"),
+            MemcachedStatus::Values(vec![])
+        );
+        assert_eq!(response_round_trip(b"STORED\r\n"), MemcachedStatus::Stored);
+        assert_eq!(
+            response_round_trip(b"NOT_STORED\r\n"),
+            MemcachedStatus::NotStored
+        );
+        assert_eq!(response_round_trip(b"EXISTS\r\n"), MemcachedStatus::Exists);
+        assert_eq!(
+            response_round_trip(b"NOT_FOUND\r\n"),
+            MemcachedStatus::NotFound
+        );
+        assert_eq!(
+            response_round_trip(b"DELETED\r\n"),
+            MemcachedStatus::Deleted
+        );
+        assert_eq!(
+            response_round_trip(b"TOUCHED\r\n"),
+            MemcachedStatus::Touched
+        );
+        assert_eq!(response_round_trip(b"OK\r\n"), MemcachedStatus::Ok);
+        assert_eq!(response_round_trip(b"42\r\n"), MemcachedStatus::Number(42));
+        assert_eq!(
+            response_round_trip(b"VERSION 1.6.17\r\n"),
+            MemcachedStatus::Version(Bytes::from_static(b"1.6.17"))
+        );
+        assert_eq!(
+            response_round_trip(b"ERROR\r\n"),
+            MemcachedStatus::Error(MemcachedError {
+                kind: MemcachedErrorKind::Error,
+                message: Bytes::new(),
+            })
+        );
+        assert_eq!(
+            response_round_trip(b"CLIENT_ERROR bad data chunk\r\n"),
+            MemcachedStatus::Error(MemcachedError {
+                kind: MemcachedErrorKind::ClientError,
+                message: Bytes::from_static(b"bad data chunk"),
+            })
+        );
+        assert_eq!(
+            response_round_trip(b"SERVER_ERROR out of memory\r\n"),
+            MemcachedStatus::Error(MemcachedError {
+                kind: MemcachedErrorKind::ServerError,
+                message: Bytes::from_static(b"out of memory"),
+            })
+        );
+
+        // a retrieval response is only complete once END is received
+        let raw = b"VALUE foo 1 3\r\nbar\r\n";
+        let mut src = BytesMut::from(raw.as_slice());
+        assert!(MemcachedFrame::decode_response(&mut src).unwrap().is_none());
+        assert_eq!(src, raw.as_slice());
+    }
+
+    #[test]
+    fn test_binary_storage_requests() {
+        let extras = [0, 0, 0, 1, 0, 0, 0, 2];
+        for (opcode, command, noreply) in [
+            (0x01, MemcachedCommand::Set, false),
+            (0x11, MemcachedCommand::Set, true),
+            (0x02, MemcachedCommand::Add, false),
+            (0x12, MemcachedCommand::Add, true),
+            (0x03, MemcachedCommand::Replace, false),
+            (0x13, MemcachedCommand::Replace, true),
+        ] {
+            let request = request_round_trip(&binary_request(opcode, &extras, b"foo", b"bar"));
+            assert_eq!(request.protocol, MemcachedProtocol::Binary);
+            assert_eq!(request.command, command);
+            assert_eq!(request.noreply, noreply);
+            assert_eq!(request.keys, vec!["foo"]);
+            assert_eq!(request.flags, 1);
+            assert_eq!(request.exptime, 2);
+            assert_eq!(request.value, "bar");
+            assert_eq!(request.opaque, 7);
+        }
+
+        for (opcode, command, noreply) in [
+            (0x0e, MemcachedCommand::Append, false),
+            (0x19, MemcachedCommand::Append, true),
+            (0x0f, MemcachedCommand::Prepend, false),
+            (0x1a, MemcachedCommand::Prepend, true),
+        ] {
+            let request = request_round_trip(&binary_request(opcode, &[], b"foo", b"bar"));
+            assert_eq!(request.command, command);
+            assert_eq!(request.noreply, noreply);
+            assert_eq!(request.value, "bar");
+        }
+
+        // a compare and swap is a set with the cas field of the header set
+        let raw = binary_packet(
+            BINARY_REQUEST_MAGIC,
+            0x01,
+            0,
+            0,
+            99,
+            &extras,
+            b"foo",
+            b"bar",
+        );
+        let request = request_round_trip(&raw);
+        assert_eq!(request.command, MemcachedCommand::Set);
+        assert_eq!(request.cas, 99);
+
+        let mut request = MemcachedRequest::new(MemcachedProtocol::Binary, MemcachedCommand::Cas);
+        request.keys = vec![Bytes::from_static(b"foo")];
+        request.flags = 1;
+        request.exptime = 2;
+        request.cas = 99;
+        request.value = Bytes::from_static(b"bar");
+        let mut dst = BytesMut::new();
+        MemcachedFrame::Request(request).encode(&mut dst).unwrap();
+        assert_eq!(dst, raw);
+    }
+
+    #[test]
+    fn test_binary_other_requests() {
+        for (opcode, command, noreply) in [
+            (0x00, MemcachedCommand::Get, false),
+            (0x09, MemcachedCommand::Get, true),
+            (0x0c, MemcachedCommand::GetK, false),
+            (0x0d, MemcachedCommand::GetK, true),
+            (0x04, MemcachedCommand::Delete, false),
+            (0x14, MemcachedCommand::Delete, true),
+        ] {
+            let request = request_round_trip(&binary_request(opcode, &[], b"foo", b""));
+            assert_eq!(request.command, command);
+            assert_eq!(request.noreply, noreply);
+            assert_eq!(request.keys, vec!["foo"]);
+        }
+
+        let extras = [0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 10];
+        for (opcode, command, noreply) in [
+            (0x05, MemcachedCommand::Incr, false),
+            (0x15, MemcachedCommand::Incr, true),
+            (0x06, MemcachedCommand::Decr, false),
+            (0x16, MemcachedCommand::Decr, true),
+        ] {
+            let request = request_round_trip(&binary_request(opcode, &extras, b"foo", b""));
+            assert_eq!(request.command, command);
+            assert_eq!(request.noreply, noreply);
+            assert_eq!(request.delta, 5);
+            assert_eq!(request.initial, 1);
+            assert_eq!(request.exptime, 10);
+        }
+
+        let request = request_round_trip(&binary_request(0x1c, &[0, 0, 0, 10], b"foo", b""));
+        assert_eq!(request.command, MemcachedCommand::Touch);
+        assert_eq!(request.exptime, 10);
+
+        let request = request_round_trip(&binary_request(0x08, &[], b"", b""));
+        assert_eq!(request.command, MemcachedCommand::FlushAll);
+        assert!(request.keys.is_empty());
+        let request = request_round_trip(&binary_request(0x18, &[0, 0, 0, 10], b"", b""));
+        assert_eq!(request.command, MemcachedCommand::FlushAll);
+        assert_eq!(request.exptime, 10);
+        assert!(request.noreply);
+
+        for (opcode, command, noreply) in [
+            (0x0a, MemcachedCommand::Noop, false),
+            (0x0b, MemcachedCommand::Version, false),
+            (0x07, MemcachedCommand::Quit, false),
+            (0x17, MemcachedCommand::Quit, true),
+        ] {
+            let request = request_round_trip(&binary_request(opcode, &[], b"", b""));
+            assert_eq!(request.command, command);
+            assert_eq!(request.noreply, noreply);
+        }
+    }
+
+    #[test]
+    fn test_binary_invalid_requests() {
+        // incomplete header and body
+        let raw = binary_request(0x01, &[0; 8], b"foo", b"bar");
+        for len in [10, raw.len() - 1] {
+            let mut src = BytesMut::from(&raw[..len]);
+            assert!(MemcachedFrame::decode_request(&mut src).unwrap().is_none());
+            assert_eq!(src.len(), len);
+        }
+
+        // unknown opcode
+        assert!(MemcachedFrame::decode_request(&mut BytesMut::from(
+            binary_request(0x55, &[], b"", b"").as_slice()
+        ))
+        .is_err());
+        // storage command missing its extras
+        assert!(MemcachedFrame::decode_request(&mut BytesMut::from(
+            binary_request(0x01, &[], b"foo", b"bar").as_slice()
+        ))
+        .is_err());
+
+        // the binary protocol only has a single key per request
+        let mut request = MemcachedRequest::new(MemcachedProtocol::Binary, MemcachedCommand::Get);
+        request.keys = vec![Bytes::from_static(b"foo"), Bytes::from_static(b"bar")];
+        assert!(MemcachedFrame::Request(request)
+            .encode(&mut BytesMut::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_binary_responses() {
+        assert_eq!(
+            response_round_trip(&binary_response(
+                0x00,
+                STATUS_SUCCESS,
+                &[0, 0, 0, 1],
+                b"",
+                b"bar"
+            )),
+            MemcachedStatus::Values(vec![value("", 1, Some(3), "bar")])
+        );
+        assert_eq!(
+            response_round_trip(&binary_response(
+                0x0c,
+                STATUS_SUCCESS,
+                &[0, 0, 0, 1],
+                b"foo",
+                b"bar"
+            )),
+            MemcachedStatus::Values(vec![value("foo", 1, Some(3), "bar")])
+        );
+        assert_eq!(
+            response_round_trip(&binary_response(0x00, STATUS_KEY_NOT_FOUND, &[], b"", b"")),
+            MemcachedStatus::NotFound
+        );
+
+        for opcode in [0x01, 0x02, 0x03, 0x0e, 0x0f] {
+            assert_eq!(
+                response_round_trip(&binary_response(opcode, STATUS_SUCCESS, &[], b"", b"")),
+                MemcachedStatus::Stored
+            );
+        }
+        assert_eq!(
+            response_round_trip(&binary_response(0x02, STATUS_KEY_EXISTS, &[], b"", b"")),
+            MemcachedStatus::Exists
+        );
+        assert_eq!(
+            response_round_trip(&binary_response(
+                0x03,
+                STATUS_ITEM_NOT_STORED,
+                &[],
+                b"",
+                b""
+            )),
+            MemcachedStatus::NotStored
+        );
+        assert_eq!(
+            response_round_trip(&binary_response(0x04, STATUS_SUCCESS, &[], b"", b"")),
+            MemcachedStatus::Deleted
+        );
+        for opcode in [0x05, 0x06] {
+            assert_eq!(
+                response_round_trip(&binary_response(
+                    opcode,
+                    STATUS_SUCCESS,
+                    &[],
+                    b"",
+                    &42u64.to_be_bytes()
+                )),
+                MemcachedStatus::Number(42)
+            );
+        }
+        assert_eq!(
+            response_round_trip(&binary_response(0x1c, STATUS_SUCCESS, &[], b"", b"")),
+            MemcachedStatus::Touched
+        );
+        assert_eq!(
+            response_round_trip(&binary_response(0x0b, STATUS_SUCCESS, &[], b"", b"1.6.17")),
+            MemcachedStatus::Version(Bytes::from_static(b"1.6.17"))
+        );
+        for opcode in [0x08, 0x0a, 0x07] {
+            assert_eq!(
+                response_round_trip(&binary_response(opcode, STATUS_SUCCESS, &[], b"", b"")),
+                MemcachedStatus::Ok
+            );
+        }
+
+        assert_eq!(
+            response_round_trip(&binary_response(
+                0x01,
+                STATUS_UNKNOWN_COMMAND,
+                &[],
+                b"",
+                b"Unknown command"
+            )),
+            MemcachedStatus::Error(MemcachedError {
+                kind: MemcachedErrorKind::Error,
+                message: Bytes::from_static(b"Unknown command"),
+            })
+        );
+        assert_eq!(
+            response_round_trip(&binary_response(
+                0x05,
+                STATUS_INVALID_ARGUMENTS,
+                &[],
+                b"",
+                b"Invalid arguments"
+            )),
+            MemcachedStatus::Error(MemcachedError {
+                kind: MemcachedErrorKind::ClientError,
+                message: Bytes::from_static(b"Invalid arguments"),
+            })
+        );
+        assert_eq!(
+            response_round_trip(&binary_response(
+                0x01,
+                STATUS_OUT_OF_MEMORY,
+                &[],
+                b"",
+                b"Out of memory"
+            )),
+            MemcachedStatus::Error(MemcachedError {
+                kind: MemcachedErrorKind::ServerError,
+                message: Bytes::from_static(b"Out of memory"),
+            })
+        );
+
+        // incr and decr responses must contain the new value
+        assert!(MemcachedFrame::decode_response(&mut BytesMut::from(
+            binary_response(0x05, STATUS_SUCCESS, &[], b"", b"42").as_slice()
+        ))
+        .is_err());
+    }
+}
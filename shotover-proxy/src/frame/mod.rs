@@ -1,8 +1,16 @@
 pub mod cassandra;
+pub mod kafka;
+pub mod memcached;
+pub mod postgres;
 
 pub use cassandra::{CassandraFrame, CassandraOperation, CassandraResult};
+pub use kafka::KafkaFrame;
+pub use memcached::MemcachedFrame;
+pub use postgres::PostgresFrame;
 pub use redis_protocol::resp2::types::Frame as RedisFrame;
+pub use redis_protocol::resp3::types::Frame as Resp3Frame;
 
+use crate::codec::redis::resp3_to_resp2;
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use std::fmt::{Display, Formatter, Result as FmtResult};
@@ -11,6 +19,9 @@ use std::fmt::{Display, Formatter, Result as FmtResult};
 pub enum MessageType {
     Redis,
     Cassandra,
+    Memcached,
+    Kafka,
+    Postgres,
     None,
 }
 
@@ -18,6 +29,12 @@ pub enum MessageType {
 pub enum Frame {
     Cassandra(CassandraFrame),
     Redis(RedisFrame),
+    /// A RESP3 frame, only ever sent to clients as RESP3 is not used to communicate with redis.
+    /// It is translated to the equivalent RESP2 frame when sent to a client that has not negotiated RESP3.
+    Resp3(Resp3Frame),
+    Memcached(MemcachedFrame),
+    Kafka(KafkaFrame),
+    Postgres(PostgresFrame),
     None,
 }
 
@@ -28,6 +45,9 @@ impl Frame {
             MessageType::Redis => redis_protocol::resp2::decode::decode(&bytes)
                 .map(|x| Frame::Redis(x.unwrap().0))
                 .map_err(|e| anyhow!("{e:?}")),
+            MessageType::Memcached => MemcachedFrame::from_bytes(bytes).map(Frame::Memcached),
+            MessageType::Kafka => KafkaFrame::from_bytes(bytes).map(Frame::Kafka),
+            MessageType::Postgres => PostgresFrame::from_bytes(bytes).map(Frame::Postgres),
             MessageType::None => Ok(Frame::None),
         }
     }
@@ -35,7 +55,11 @@ impl Frame {
     pub fn name(&self) -> &'static str {
         match self {
             Frame::Redis(_) => "Redis",
+            Frame::Resp3(_) => "Resp3",
             Frame::Cassandra(_) => "Cassandra",
+            Frame::Memcached(_) => "Memcached",
+            Frame::Kafka(_) => "Kafka",
+            Frame::Postgres(_) => "Postgres",
             Frame::None => "None",
         }
     }
@@ -43,7 +67,10 @@ impl Frame {
     pub fn get_type(&self) -> MessageType {
         match self {
             Frame::Cassandra(_) => MessageType::Cassandra,
-            Frame::Redis(_) => MessageType::Redis,
+            Frame::Redis(_) | Frame::Resp3(_) => MessageType::Redis,
+            Frame::Memcached(_) => MessageType::Memcached,
+            Frame::Kafka(_) => MessageType::Kafka,
+            Frame::Postgres(_) => MessageType::Postgres,
             Frame::None => MessageType::None,
         }
     }
@@ -61,6 +88,7 @@ impl Frame {
     pub fn into_redis(self) -> Result<RedisFrame> {
         match self {
             Frame::Redis(frame) => Ok(frame),
+            Frame::Resp3(frame) => Ok(resp3_to_resp2(frame)),
             Frame::None => Ok(RedisFrame::Null),
             frame => Err(anyhow!(
                 "Expected redis frame but received {} frame",
@@ -78,6 +106,36 @@ impl Frame {
             )),
         }
     }
+
+    pub fn into_memcached(self) -> Result<MemcachedFrame> {
+        match self {
+            Frame::Memcached(frame) => Ok(frame),
+            frame => Err(anyhow!(
+                "Expected memcached frame but received {} frame",
+                frame.name()
+            )),
+        }
+    }
+
+    pub fn into_kafka(self) -> Result<KafkaFrame> {
+        match self {
+            Frame::Kafka(frame) => Ok(frame),
+            frame => Err(anyhow!(
+                "Expected kafka frame but received {} frame",
+                frame.name()
+            )),
+        }
+    }
+
+    pub fn into_postgres(self) -> Result<PostgresFrame> {
+        match self {
+            Frame::Postgres(frame) => Ok(frame),
+            frame => Err(anyhow!(
+                "Expected postgres frame but received {} frame",
+                frame.name()
+            )),
+        }
+    }
 }
 
 impl Display for Frame {
@@ -85,6 +143,10 @@ impl Display for Frame {
         match self {
             Frame::Cassandra(frame) => write!(f, "Cassandra {}", frame),
             Frame::Redis(frame) => write!(f, "Redis {:?})", frame),
+            Frame::Resp3(frame) => write!(f, "Resp3 {:?}", frame),
+            Frame::Memcached(frame) => write!(f, "Memcached {}", frame),
+            Frame::Kafka(frame) => write!(f, "Kafka {}", frame),
+            Frame::Postgres(frame) => write!(f, "Postgres {}", frame),
             Frame::None => write!(f, "None"),
         }
     }
@@ -0,0 +1,785 @@
+use crate::message::QueryType;
+use anyhow::{anyhow, bail, ensure, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+pub mod raw_frame {
+    use super::{PostgresFrame, PostgresMetadata};
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    /// Parse metadata only from an unparsed postgres frame
+    pub(crate) fn metadata(bytes: &Bytes) -> Result<PostgresMetadata> {
+        Ok(PostgresFrame::from_bytes(bytes.clone())?.metadata())
+    }
+}
+
+/// Protocol version 3.0, the only version spoken by postgres since 7.4
+pub const PROTOCOL_VERSION_3: i32 = 196608;
+pub const SSL_REQUEST_CODE: i32 = 80877103;
+pub const GSSENC_REQUEST_CODE: i32 = 80877104;
+pub const CANCEL_REQUEST_CODE: i32 = 80877102;
+
+/// The type of every message sent by the server.
+/// Shotover only needs to inspect the type of most backend messages so their bodies are kept as raw bytes.
+pub mod backend_tag {
+    pub const AUTHENTICATION: u8 = b'R';
+    pub const BACKEND_KEY_DATA: u8 = b'K';
+    pub const BIND_COMPLETE: u8 = b'2';
+    pub const CLOSE_COMPLETE: u8 = b'3';
+    pub const COMMAND_COMPLETE: u8 = b'C';
+    pub const COPY_BOTH_RESPONSE: u8 = b'W';
+    pub const COPY_DATA: u8 = b'd';
+    pub const COPY_DONE: u8 = b'c';
+    pub const COPY_IN_RESPONSE: u8 = b'G';
+    pub const COPY_OUT_RESPONSE: u8 = b'H';
+    pub const DATA_ROW: u8 = b'D';
+    pub const EMPTY_QUERY_RESPONSE: u8 = b'I';
+    pub const ERROR_RESPONSE: u8 = b'E';
+    pub const FUNCTION_CALL_RESPONSE: u8 = b'V';
+    pub const NEGOTIATE_PROTOCOL_VERSION: u8 = b'v';
+    pub const NO_DATA: u8 = b'n';
+    pub const NOTICE_RESPONSE: u8 = b'N';
+    pub const NOTIFICATION_RESPONSE: u8 = b'A';
+    pub const PARAMETER_DESCRIPTION: u8 = b't';
+    pub const PARAMETER_STATUS: u8 = b'S';
+    pub const PARSE_COMPLETE: u8 = b'1';
+    pub const PORTAL_SUSPENDED: u8 = b's';
+    pub const READY_FOR_QUERY: u8 = b'Z';
+    pub const ROW_DESCRIPTION: u8 = b'T';
+
+    pub fn name(tag: u8) -> &'static str {
+        match tag {
+            AUTHENTICATION => "Authentication",
+            BACKEND_KEY_DATA => "BackendKeyData",
+            BIND_COMPLETE => "BindComplete",
+            CLOSE_COMPLETE => "CloseComplete",
+            COMMAND_COMPLETE => "CommandComplete",
+            COPY_BOTH_RESPONSE => "CopyBothResponse",
+            COPY_DATA => "CopyData",
+            COPY_DONE => "CopyDone",
+            COPY_IN_RESPONSE => "CopyInResponse",
+            COPY_OUT_RESPONSE => "CopyOutResponse",
+            DATA_ROW => "DataRow",
+            EMPTY_QUERY_RESPONSE => "EmptyQueryResponse",
+            ERROR_RESPONSE => "ErrorResponse",
+            FUNCTION_CALL_RESPONSE => "FunctionCallResponse",
+            NEGOTIATE_PROTOCOL_VERSION => "NegotiateProtocolVersion",
+            NO_DATA => "NoData",
+            NOTICE_RESPONSE => "NoticeResponse",
+            NOTIFICATION_RESPONSE => "NotificationResponse",
+            PARAMETER_DESCRIPTION => "ParameterDescription",
+            PARAMETER_STATUS => "ParameterStatus",
+            PARSE_COMPLETE => "ParseComplete",
+            PORTAL_SUSPENDED => "PortalSuspended",
+            READY_FOR_QUERY => "ReadyForQuery",
+            ROW_DESCRIPTION => "RowDescription",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// The startup family of messages is not prefixed by a type byte.
+/// Their length always starts with a 0 byte, which no type byte uses, so they can be told apart from every other message by their first byte alone.
+fn is_untagged(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&0)
+}
+
+/// Returns the length of the first message in `bytes`, or `None` if the length is not yet known.
+pub fn message_len(bytes: &[u8]) -> Result<Option<usize>> {
+    let header_len = if is_untagged(bytes) { 0 } else { 1 };
+    if bytes.len() < header_len + 4 {
+        return Ok(None);
+    }
+    let len = i32::from_be_bytes(bytes[header_len..header_len + 4].try_into().unwrap());
+    ensure!(len >= 4, "postgres message has invalid length {len}");
+    Ok(Some(header_len + len as usize))
+}
+
+/// Parse, Bind, Describe, Execute and Close are not answered until the client sends a Sync or Flush,
+/// so a client never waits for a response directly after sending one of them.
+pub fn is_buffered_by_server(message: &[u8]) -> bool {
+    matches!(message.first(), Some(b'P' | b'B' | b'D' | b'E' | b'C'))
+}
+
+pub struct PostgresMetadata {
+    /// A request that will be answered with a ReadyForQuery message, or a response that contains one.
+    pub ready_for_query: bool,
+}
+
+impl PostgresMetadata {
+    /// Create a response to the message this metadata was taken from that reports the provided error.
+    /// If the client is waiting for the server to become ready for the next query it is told the server is idle,
+    /// which will be incorrect when the error occurs within a transaction.
+    pub fn error_response(&self, code: &str, message: &str) -> PostgresFrame {
+        let mut messages = vec![BackendMessage::error(code, message)];
+        if self.ready_for_query {
+            messages.push(BackendMessage::Tagged {
+                tag: backend_tag::READY_FOR_QUERY,
+                body: Bytes::from_static(b"I"),
+            });
+        }
+        PostgresFrame::Response(messages)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum PostgresFrame {
+    /// Every message sent by the client up until it waits for the server to respond.
+    Request(Vec<FrontendMessage>),
+    /// Every message sent by the server in response to a request, which is empty for requests that the server does not respond to.
+    Response(Vec<BackendMessage>),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum FrontendMessage {
+    Startup {
+        protocol_version: i32,
+        parameters: Vec<(Bytes, Bytes)>,
+    },
+    SslRequest,
+    GssEncRequest,
+    CancelRequest {
+        process_id: i32,
+        secret_key: i32,
+    },
+    /// PasswordMessage, SASLInitialResponse, SASLResponse and GSSResponse all share a type byte, the server knows which one to expect.
+    Password(Bytes),
+    Query(String),
+    Parse {
+        statement: Bytes,
+        query: String,
+        parameter_types: Vec<i32>,
+    },
+    Bind(Bytes),
+    Describe(Bytes),
+    Execute(Bytes),
+    Close(Bytes),
+    Sync,
+    Flush,
+    FunctionCall(Bytes),
+    CopyData(Bytes),
+    CopyDone,
+    CopyFail(Bytes),
+    Terminate,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum BackendMessage {
+    /// The single byte answer to an SSLRequest or GSSENCRequest.
+    /// Only ever sent by shotover itself, which always declines since TLS is handled by shotover instead.
+    EncryptionNotSupported,
+    Tagged {
+        tag: u8,
+        body: Bytes,
+    },
+}
+
+impl PostgresFrame {
+    /// Only requests can be parsed from bytes alone, the sink codec parses responses itself since it needs to know which request they belong to.
+    pub fn from_bytes(mut bytes: Bytes) -> Result<Self> {
+        let mut messages = vec![];
+        while !bytes.is_empty() {
+            let len = message_len(&bytes)?
+                .ok_or_else(|| anyhow!("postgres message ended unexpectedly"))?;
+            ensure!(bytes.len() >= len, "postgres message ended unexpectedly");
+            messages.push(FrontendMessage::from_bytes(bytes.split_to(len))?);
+        }
+        Ok(PostgresFrame::Request(messages))
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) {
+        match self {
+            PostgresFrame::Request(messages) => {
+                for message in messages {
+                    message.encode(dst);
+                }
+            }
+            PostgresFrame::Response(messages) => {
+                for message in messages {
+                    message.encode(dst);
+                }
+            }
+        }
+    }
+
+    pub fn metadata(&self) -> PostgresMetadata {
+        let ready_for_query = match self {
+            PostgresFrame::Request(messages) => messages.iter().any(|message| {
+                matches!(
+                    message,
+                    FrontendMessage::Query(_)
+                        | FrontendMessage::Sync
+                        | FrontendMessage::FunctionCall(_)
+                )
+            }),
+            PostgresFrame::Response(messages) => messages
+                .iter()
+                .any(|message| message.tag() == Some(backend_tag::READY_FOR_QUERY)),
+        };
+        PostgresMetadata { ready_for_query }
+    }
+
+    /// Whether postgres will send any messages in response to this request.
+    pub fn expects_response(&self) -> bool {
+        match self {
+            PostgresFrame::Request(messages) => messages.iter().any(|message| {
+                !matches!(
+                    message,
+                    FrontendMessage::Flush
+                        | FrontendMessage::CopyData(_)
+                        | FrontendMessage::Terminate
+                        | FrontendMessage::CancelRequest { .. }
+                )
+            }),
+            PostgresFrame::Response(_) => false,
+        }
+    }
+
+    pub fn get_query_type(&self) -> QueryType {
+        match self {
+            PostgresFrame::Request(messages) => messages
+                .iter()
+                .filter_map(|message| match message {
+                    FrontendMessage::Query(query) => get_query_type(query),
+                    FrontendMessage::Parse { query, .. } => get_query_type(query),
+                    _ => None,
+                })
+                .reduce(combine_query_types)
+                .unwrap_or(QueryType::Read),
+            PostgresFrame::Response(_) => QueryType::Read,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        match self {
+            PostgresFrame::Request(_) => false,
+            PostgresFrame::Response(messages) => messages
+                .iter()
+                .any(|message| message.tag() == Some(backend_tag::ERROR_RESPONSE)),
+        }
+    }
+}
+
+impl Display for PostgresFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            PostgresFrame::Request(messages) => {
+                write!(f, "Request(")?;
+                for (i, message) in messages.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    match message {
+                        FrontendMessage::Query(query) => write!(f, "Query {query:?}")?,
+                        FrontendMessage::Parse { query, .. } => write!(f, "Parse {query:?}")?,
+                        message => write!(f, "{}", message.name())?,
+                    }
+                }
+                write!(f, ")")
+            }
+            PostgresFrame::Response(messages) => {
+                write!(f, "Response(")?;
+                for (i, message) in messages.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", message.name())?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl FrontendMessage {
+    fn from_bytes(mut bytes: Bytes) -> Result<Self> {
+        if is_untagged(&bytes) {
+            bytes.advance(4);
+            let code = read_i32(&mut bytes)?;
+            return Ok(match code {
+                SSL_REQUEST_CODE => FrontendMessage::SslRequest,
+                GSSENC_REQUEST_CODE => FrontendMessage::GssEncRequest,
+                CANCEL_REQUEST_CODE => FrontendMessage::CancelRequest {
+                    process_id: read_i32(&mut bytes)?,
+                    secret_key: read_i32(&mut bytes)?,
+                },
+                protocol_version => {
+                    ensure!(
+                        protocol_version >> 16 == 3,
+                        "unsupported postgres protocol version {}.{}",
+                        protocol_version >> 16,
+                        protocol_version & 0xFFFF
+                    );
+                    let mut parameters = vec![];
+                    loop {
+                        let name = read_cstring(&mut bytes)?;
+                        if name.is_empty() {
+                            break;
+                        }
+                        parameters.push((name, read_cstring(&mut bytes)?));
+                    }
+                    FrontendMessage::Startup {
+                        protocol_version,
+                        parameters,
+                    }
+                }
+            });
+        }
+
+        let tag = bytes.get_u8();
+        bytes.advance(4);
+        Ok(match tag {
+            b'p' => FrontendMessage::Password(bytes),
+            b'Q' => FrontendMessage::Query(read_string(&mut bytes)?),
+            b'P' => {
+                let statement = read_cstring(&mut bytes)?;
+                let query = read_string(&mut bytes)?;
+                let count = read_i16(&mut bytes)?;
+                let mut parameter_types = Vec::with_capacity(count.max(0) as usize);
+                for _ in 0..count {
+                    parameter_types.push(read_i32(&mut bytes)?);
+                }
+                FrontendMessage::Parse {
+                    statement,
+                    query,
+                    parameter_types,
+                }
+            }
+            b'B' => FrontendMessage::Bind(bytes),
+            b'D' => FrontendMessage::Describe(bytes),
+            b'E' => FrontendMessage::Execute(bytes),
+            b'C' => FrontendMessage::Close(bytes),
+            b'S' => FrontendMessage::Sync,
+            b'H' => FrontendMessage::Flush,
+            b'F' => FrontendMessage::FunctionCall(bytes),
+            b'd' => FrontendMessage::CopyData(bytes),
+            b'c' => FrontendMessage::CopyDone,
+            b'f' => FrontendMessage::CopyFail(bytes),
+            b'X' => FrontendMessage::Terminate,
+            tag => bail!("unknown postgres frontend message type {:?}", tag as char),
+        })
+    }
+
+    fn encode(&self, dst: &mut BytesMut) {
+        let (tag, code) = match self {
+            FrontendMessage::Startup {
+                protocol_version, ..
+            } => (None, Some(*protocol_version)),
+            FrontendMessage::SslRequest => (None, Some(SSL_REQUEST_CODE)),
+            FrontendMessage::GssEncRequest => (None, Some(GSSENC_REQUEST_CODE)),
+            FrontendMessage::CancelRequest { .. } => (None, Some(CANCEL_REQUEST_CODE)),
+            FrontendMessage::Password(_) => (Some(b'p'), None),
+            FrontendMessage::Query(_) => (Some(b'Q'), None),
+            FrontendMessage::Parse { .. } => (Some(b'P'), None),
+            FrontendMessage::Bind(_) => (Some(b'B'), None),
+            FrontendMessage::Describe(_) => (Some(b'D'), None),
+            FrontendMessage::Execute(_) => (Some(b'E'), None),
+            FrontendMessage::Close(_) => (Some(b'C'), None),
+            FrontendMessage::Sync => (Some(b'S'), None),
+            FrontendMessage::Flush => (Some(b'H'), None),
+            FrontendMessage::FunctionCall(_) => (Some(b'F'), None),
+            FrontendMessage::CopyData(_) => (Some(b'd'), None),
+            FrontendMessage::CopyDone => (Some(b'c'), None),
+            FrontendMessage::CopyFail(_) => (Some(b'f'), None),
+            FrontendMessage::Terminate => (Some(b'X'), None),
+        };
+
+        if let Some(tag) = tag {
+            dst.put_u8(tag);
+        }
+        let start = dst.len();
+        dst.put_i32(0);
+        if let Some(code) = code {
+            dst.put_i32(code);
+        }
+        match self {
+            FrontendMessage::Startup { parameters, .. } => {
+                for (name, value) in parameters {
+                    put_cstring(dst, name);
+                    put_cstring(dst, value);
+                }
+                dst.put_u8(0);
+            }
+            FrontendMessage::CancelRequest {
+                process_id,
+                secret_key,
+            } => {
+                dst.put_i32(*process_id);
+                dst.put_i32(*secret_key);
+            }
+            FrontendMessage::Query(query) => put_cstring(dst, query.as_bytes()),
+            FrontendMessage::Parse {
+                statement,
+                query,
+                parameter_types,
+            } => {
+                put_cstring(dst, statement);
+                put_cstring(dst, query.as_bytes());
+                dst.put_i16(parameter_types.len() as i16);
+                for parameter_type in parameter_types {
+                    dst.put_i32(*parameter_type);
+                }
+            }
+            FrontendMessage::Password(body)
+            | FrontendMessage::Bind(body)
+            | FrontendMessage::Describe(body)
+            | FrontendMessage::Execute(body)
+            | FrontendMessage::Close(body)
+            | FrontendMessage::FunctionCall(body)
+            | FrontendMessage::CopyData(body)
+            | FrontendMessage::CopyFail(body) => dst.put_slice(body),
+            FrontendMessage::SslRequest
+            | FrontendMessage::GssEncRequest
+            | FrontendMessage::Sync
+            | FrontendMessage::Flush
+            | FrontendMessage::CopyDone
+            | FrontendMessage::Terminate => {}
+        }
+        let len = (dst.len() - start) as i32;
+        dst[start..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrontendMessage::Startup { .. } => "Startup",
+            FrontendMessage::SslRequest => "SSLRequest",
+            FrontendMessage::GssEncRequest => "GSSENCRequest",
+            FrontendMessage::CancelRequest { .. } => "CancelRequest",
+            FrontendMessage::Password(_) => "Password",
+            FrontendMessage::Query(_) => "Query",
+            FrontendMessage::Parse { .. } => "Parse",
+            FrontendMessage::Bind(_) => "Bind",
+            FrontendMessage::Describe(_) => "Describe",
+            FrontendMessage::Execute(_) => "Execute",
+            FrontendMessage::Close(_) => "Close",
+            FrontendMessage::Sync => "Sync",
+            FrontendMessage::Flush => "Flush",
+            FrontendMessage::FunctionCall(_) => "FunctionCall",
+            FrontendMessage::CopyData(_) => "CopyData",
+            FrontendMessage::CopyDone => "CopyDone",
+            FrontendMessage::CopyFail(_) => "CopyFail",
+            FrontendMessage::Terminate => "Terminate",
+        }
+    }
+}
+
+impl BackendMessage {
+    /// Parse a single tagged backend message
+    pub fn from_bytes(mut bytes: Bytes) -> Result<Self> {
+        ensure!(bytes.len() >= 5, "postgres message ended unexpectedly");
+        let tag = bytes.get_u8();
+        bytes.advance(4);
+        Ok(BackendMessage::Tagged { tag, body: bytes })
+    }
+
+    /// An ErrorResponse with the provided SQLSTATE code
+    pub fn error(code: &str, message: &str) -> Self {
+        let mut body = BytesMut::new();
+        for (field, value) in [
+            (b'S', "ERROR"),
+            (b'V', "ERROR"),
+            (b'C', code),
+            (b'M', message),
+        ] {
+            body.put_u8(field);
+            put_cstring(&mut body, value.as_bytes());
+        }
+        body.put_u8(0);
+        BackendMessage::Tagged {
+            tag: backend_tag::ERROR_RESPONSE,
+            body: body.freeze(),
+        }
+    }
+
+    pub fn tag(&self) -> Option<u8> {
+        match self {
+            BackendMessage::EncryptionNotSupported => None,
+            BackendMessage::Tagged { tag, .. } => Some(*tag),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackendMessage::EncryptionNotSupported => "EncryptionNotSupported",
+            BackendMessage::Tagged { tag, .. } => backend_tag::name(*tag),
+        }
+    }
+
+    fn encode(&self, dst: &mut BytesMut) {
+        match self {
+            BackendMessage::EncryptionNotSupported => dst.put_u8(b'N'),
+            BackendMessage::Tagged { tag, body } => {
+                dst.put_u8(*tag);
+                dst.put_i32(body.len() as i32 + 4);
+                dst.put_slice(body);
+            }
+        }
+    }
+}
+
+fn read_i16(bytes: &mut Bytes) -> Result<i16> {
+    ensure!(
+        bytes.remaining() >= 2,
+        "postgres message ended unexpectedly"
+    );
+    Ok(bytes.get_i16())
+}
+
+fn read_i32(bytes: &mut Bytes) -> Result<i32> {
+    ensure!(
+        bytes.remaining() >= 4,
+        "postgres message ended unexpectedly"
+    );
+    Ok(bytes.get_i32())
+}
+
+/// Reads a null terminated string, not including the terminator
+fn read_cstring(bytes: &mut Bytes) -> Result<Bytes> {
+    let len = bytes
+        .iter()
+        .position(|x| *x == 0)
+        .ok_or_else(|| anyhow!("postgres string is missing its null terminator"))?;
+    let string = bytes.split_to(len);
+    bytes.advance(1);
+    Ok(string)
+}
+
+fn read_string(bytes: &mut Bytes) -> Result<String> {
+    String::from_utf8(read_cstring(bytes)?.to_vec())
+        .map_err(|e| anyhow!(e).context("postgres string is not valid utf8"))
+}
+
+fn put_cstring(dst: &mut BytesMut, string: &[u8]) {
+    dst.put_slice(string);
+    dst.put_u8(0);
+}
+
+fn combine_query_types(a: QueryType, b: QueryType) -> QueryType {
+    match (a, b) {
+        (QueryType::SchemaChange, _) | (_, QueryType::SchemaChange) => QueryType::SchemaChange,
+        (a, b) if a == b => a,
+        _ => QueryType::ReadWrite,
+    }
+}
+
+/// Classifies every statement in the provided SQL by its leading keywords.
+/// Returns `None` when there are no statements that read or write data, e.g. transaction control or session configuration.
+fn get_query_type(sql: &str) -> Option<QueryType> {
+    split_statements(sql)
+        .iter()
+        .filter_map(|words| statement_query_type(words))
+        .reduce(combine_query_types)
+}
+
+fn statement_query_type(words: &[Word]) -> Option<QueryType> {
+    let first = words.first()?;
+    match first.text.as_str() {
+        "SELECT" | "VALUES" | "TABLE" | "SHOW" | "FETCH" => Some(QueryType::Read),
+        "INSERT" | "UPDATE" | "DELETE" | "MERGE" | "TRUNCATE" | "REFRESH" => Some(QueryType::Write),
+        "CREATE" | "ALTER" | "DROP" | "COMMENT" | "GRANT" | "REVOKE" => {
+            Some(QueryType::SchemaChange)
+        }
+        // Procedures and anonymous code blocks can do anything
+        "CALL" | "DO" => Some(QueryType::ReadWrite),
+        // The direction of a COPY follows the table or query being copied
+        "COPY" => {
+            let direction = words
+                .iter()
+                .filter(|word| word.depth == 0)
+                .find(|word| word.text == "FROM" || word.text == "TO")?;
+            if direction.text == "FROM" {
+                Some(QueryType::Write)
+            } else {
+                Some(QueryType::Read)
+            }
+        }
+        // Common table expressions may themselves modify data
+        "WITH" => {
+            if words
+                .iter()
+                .any(|word| matches!(word.text.as_str(), "INSERT" | "UPDATE" | "DELETE" | "MERGE"))
+            {
+                Some(QueryType::Write)
+            } else {
+                Some(QueryType::Read)
+            }
+        }
+        // EXPLAIN only executes the statement when ANALYZE is used
+        "EXPLAIN" => {
+            let statement = words[1..].iter().position(|word| {
+                word.depth == 0 && !matches!(word.text.as_str(), "ANALYZE" | "VERBOSE")
+            })? + 1;
+            if words[1..statement]
+                .iter()
+                .any(|word| word.text == "ANALYZE")
+            {
+                statement_query_type(&words[statement..])
+            } else {
+                Some(QueryType::Read)
+            }
+        }
+        "PREPARE" => {
+            let statement = words
+                .iter()
+                .position(|word| word.depth == 0 && word.text == "AS")?
+                + 1;
+            statement_query_type(&words[statement..])
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct Word {
+    /// Uppercased
+    text: String,
+    /// How many parentheses the word is nested within
+    depth: usize,
+}
+
+/// Splits SQL into statements of unquoted words, skipping over comments, literals and quoted identifiers
+fn split_statements(sql: &str) -> Vec<Vec<Word>> {
+    let mut statements = vec![];
+    let mut words = vec![];
+    let mut depth = 0;
+    let mut chars = sql.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            ';' => {
+                if !words.is_empty() {
+                    statements.push(std::mem::take(&mut words));
+                }
+                depth = 0;
+            }
+            '(' => depth += 1,
+            ')' => depth = usize::saturating_sub(depth, 1),
+            '-' if matches!(chars.peek(), Some((_, '-'))) => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut nesting = 1;
+                while let Some((_, c)) = chars.next() {
+                    match (c, chars.peek()) {
+                        ('*', Some((_, '/'))) => {
+                            chars.next();
+                            nesting -= 1;
+                            if nesting == 0 {
+                                break;
+                            }
+                        }
+                        ('/', Some((_, '*'))) => {
+                            chars.next();
+                            nesting += 1;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            // A doubled quote within a literal or identifier is an escaped quote,
+            // which is handled by treating it as two adjacent literals.
+            '\'' | '"' => {
+                for (_, x) in chars.by_ref() {
+                    if x == c {
+                        break;
+                    }
+                }
+            }
+            '$' => {
+                // Dollar quoted literals such as $$text$$ or $tag$text$tag$
+                let tag_end = sql[i + 1..]
+                    .find(|x: char| !(x.is_alphanumeric() || x == '_'))
+                    .map(|x| i + 1 + x);
+                if let Some(tag_end) = tag_end {
+                    if sql[tag_end..].starts_with('$') {
+                        let tag = &sql[i..=tag_end];
+                        let end = sql[tag_end + 1..]
+                            .find(tag)
+                            .map(|x| tag_end + 1 + x + tag.len())
+                            .unwrap_or(sql.len());
+                        while matches!(chars.peek(), Some((x, _)) if *x < end) {
+                            chars.next();
+                        }
+                    }
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = sql.len();
+                while let Some((j, x)) = chars.peek() {
+                    if x.is_alphanumeric() || *x == '_' || *x == '$' {
+                        chars.next();
+                    } else {
+                        end = *j;
+                        break;
+                    }
+                }
+                words.push(Word {
+                    text: sql[i..end].to_uppercase(),
+                    depth,
+                });
+            }
+            _ => {}
+        }
+    }
+    if !words.is_empty() {
+        statements.push(words);
+    }
+    statements
+}
+
+#[cfg(test)]
+mod test {
+    use super::get_query_type;
+    use crate::message::QueryType;
+
+    #[test]
+    fn test_get_query_type() {
+        let cases = [
+            ("SELECT * FROM foo", Some(QueryType::Read)),
+            ("  select 1;", Some(QueryType::Read)),
+            ("INSERT INTO foo VALUES (1, 'a;b')", Some(QueryType::Write)),
+            ("update foo set bar = 1", Some(QueryType::Write)),
+            ("-- comment\nDELETE FROM foo", Some(QueryType::Write)),
+            (
+                "/* a /* nested */ comment */ TRUNCATE foo",
+                Some(QueryType::Write),
+            ),
+            ("CREATE TABLE foo (id int)", Some(QueryType::SchemaChange)),
+            ("COPY foo FROM STDIN", Some(QueryType::Write)),
+            ("COPY (SELECT * FROM foo) TO STDOUT", Some(QueryType::Read)),
+            (
+                "WITH moved AS (DELETE FROM foo RETURNING *) INSERT INTO bar SELECT * FROM moved",
+                Some(QueryType::Write),
+            ),
+            (
+                "WITH x AS (SELECT 1) SELECT * FROM x",
+                Some(QueryType::Read),
+            ),
+            ("EXPLAIN DELETE FROM foo", Some(QueryType::Read)),
+            ("EXPLAIN ANALYZE DELETE FROM foo", Some(QueryType::Write)),
+            (
+                "PREPARE p AS INSERT INTO foo VALUES ($1)",
+                Some(QueryType::Write),
+            ),
+            ("SELECT $$INSERT$$; SELECT 'DELETE'", Some(QueryType::Read)),
+            (
+                "SELECT 1; INSERT INTO foo VALUES (1)",
+                Some(QueryType::ReadWrite),
+            ),
+            (
+                "BEGIN; INSERT INTO foo VALUES (1); COMMIT",
+                Some(QueryType::Write),
+            ),
+            ("SET search_path TO foo", None),
+            ("", None),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(get_query_type(sql), expected, "{sql}");
+        }
+    }
+}
@@ -25,8 +25,10 @@ pub mod error;
 pub mod frame;
 pub mod message;
 mod observability;
+pub mod proxy_protocol;
 pub mod runner;
 mod server;
+mod socket;
 pub mod sources;
 pub mod tls;
 pub mod transforms;
@@ -2,11 +2,15 @@ use crate::codec::redis::redis_query_type;
 use crate::frame::{
     cassandra,
     cassandra::{to_cassandra_type, CassandraMetadata, CassandraOperation},
+    memcached,
+    memcached::{MemcachedErrorKind, MemcachedMetadata},
+    postgres,
+    postgres::PostgresMetadata,
 };
-use crate::frame::{CassandraFrame, Frame, MessageType, RedisFrame};
+use crate::frame::{CassandraFrame, Frame, MessageType, RedisFrame, Resp3Frame};
 use anyhow::{anyhow, Result};
 use bigdecimal::BigDecimal;
-use bytes::{Buf, Bytes};
+use bytes::{Buf, Bytes, BytesMut};
 use bytes_utils::Str;
 use cassandra_protocol::frame::Serialize as FrameSerialize;
 use cassandra_protocol::types::CInt;
@@ -35,11 +39,17 @@ use uuid::Uuid;
 pub enum Metadata {
     Cassandra(CassandraMetadata),
     Redis,
+    Memcached(MemcachedMetadata),
+    Kafka,
+    Postgres(PostgresMetadata),
     None,
 }
 
 pub type Messages = Vec<Message>;
 
+const KAFKA_RESPONSE_UNSUPPORTED: &str =
+    "kafka responses cannot be generated without knowing the response schema of each request";
+
 /// The Message type is designed to effeciently abstract over the message being in various states of processing.
 ///
 /// Usually a message is received and starts off containing just raw bytes (or possibly raw bytes + frame)
@@ -183,12 +193,19 @@ impl Message {
                 message_type,
             } => match message_type {
                 MessageType::Redis => nonzero!(1u32),
+                MessageType::Memcached => nonzero!(1u32),
+                MessageType::Kafka => nonzero!(1u32),
+                MessageType::Postgres => nonzero!(1u32),
                 MessageType::None => nonzero!(1u32),
                 MessageType::Cassandra => cassandra::raw_frame::cell_count(bytes)?,
             },
             MessageInner::Modified { frame } | MessageInner::Parsed { frame, .. } => match frame {
                 Frame::Cassandra(frame) => frame.cell_count()?,
                 Frame::Redis(_) => nonzero!(1u32),
+                Frame::Resp3(_) => nonzero!(1u32),
+                Frame::Memcached(_) => nonzero!(1u32),
+                Frame::Kafka(_) => nonzero!(1u32),
+                Frame::Postgres(_) => nonzero!(1u32),
                 Frame::None => nonzero!(1u32),
             },
         })
@@ -208,9 +225,12 @@ impl Message {
     }
 
     // TODO: this could be optimized to avoid parsing the cassandra sql
-    pub fn to_filtered_reply(&mut self) -> Message {
-        Message::from_frame(match self.frame().unwrap() {
-            Frame::Redis(_) => Frame::Redis(RedisFrame::Error(
+    pub fn to_filtered_reply(&mut self) -> Result<Message> {
+        let frame = self
+            .frame()
+            .ok_or_else(|| anyhow!("Failed to parse message"))?;
+        Ok(Message::from_frame(match frame {
+            Frame::Redis(_) | Frame::Resp3(_) => Frame::Redis(RedisFrame::Error(
                 "ERR Message was filtered out by shotover".into(),
             )),
             Frame::Cassandra(frame) => Frame::Cassandra(CassandraFrame {
@@ -223,22 +243,45 @@ impl Message {
                 tracing_id: frame.tracing_id,
                 warnings: vec![],
             }),
+            Frame::Memcached(frame) => Frame::Memcached(frame.metadata().error_response(
+                MemcachedErrorKind::ServerError,
+                "Message was filtered out by shotover",
+            )),
+            Frame::Kafka(_) => return Err(anyhow!(KAFKA_RESPONSE_UNSUPPORTED)),
+            Frame::Postgres(frame) => Frame::Postgres(
+                frame
+                    .metadata()
+                    .error_response("XX000", "Message was filtered out by shotover"),
+            ),
             Frame::None => Frame::None,
-        })
+        }))
     }
 
     pub fn get_query_type(&mut self) -> QueryType {
         match self.frame() {
             Some(Frame::Cassandra(cassandra)) => cassandra.get_query_type(),
             Some(Frame::Redis(redis)) => redis_query_type(redis), // free-standing function as we cant define methods on RedisFrame
+            Some(Frame::Resp3(Resp3Frame::Push { .. })) => QueryType::PubSubMessage,
+            Some(Frame::Resp3(_)) => QueryType::ReadWrite,
+            Some(Frame::Memcached(memcached)) => memcached.get_query_type(),
+            Some(Frame::Kafka(kafka)) => kafka.get_query_type(),
+            Some(Frame::Postgres(postgres)) => postgres.get_query_type(),
             Some(Frame::None) => QueryType::ReadWrite,
             None => QueryType::ReadWrite,
         }
     }
 
     // TODO: replace with a to_error_reply, should be easier to reason about
-    pub fn set_error(&mut self, error: String) {
-        *self = Message::from_frame(match self.metadata().unwrap() {
+    pub fn set_error(&mut self, error: String) -> Result<()> {
+        *self = Message::from_error(self.metadata()?, error)
+            .ok_or_else(|| anyhow!(KAFKA_RESPONSE_UNSUPPORTED))?;
+        Ok(())
+    }
+
+    /// Create an error response to the request that `metadata` was taken from.
+    /// Returns `None` for kafka as its responses cannot be generated without knowing the response schema of each request.
+    pub fn from_error(metadata: Metadata, error: String) -> Option<Message> {
+        let mut message = Message::from_frame(match metadata {
             Metadata::Redis => {
                 Frame::Redis(RedisFrame::Error(Str::from_inner(error.into()).unwrap()))
             }
@@ -252,9 +295,17 @@ impl Message {
                 tracing_id: frame.tracing_id,
                 warnings: vec![],
             }),
+            Metadata::Memcached(metadata) => {
+                Frame::Memcached(metadata.error_response(MemcachedErrorKind::ServerError, &error))
+            }
+            Metadata::Kafka => return None,
+            Metadata::Postgres(metadata) => {
+                Frame::Postgres(metadata.error_response("XX000", &error))
+            }
             Metadata::None => Frame::None,
         });
-        self.invalidate_cache();
+        message.invalidate_cache();
+        Some(message)
     }
 
     /// Get metadata for this `Message`
@@ -268,11 +319,21 @@ impl Message {
                     Ok(Metadata::Cassandra(cassandra::raw_frame::metadata(bytes)?))
                 }
                 MessageType::Redis => Ok(Metadata::Redis),
+                MessageType::Memcached => {
+                    Ok(Metadata::Memcached(memcached::raw_frame::metadata(bytes)?))
+                }
+                MessageType::Kafka => Ok(Metadata::Kafka),
+                MessageType::Postgres => {
+                    Ok(Metadata::Postgres(postgres::raw_frame::metadata(bytes)?))
+                }
                 MessageType::None => Ok(Metadata::None),
             },
             MessageInner::Parsed { frame, .. } | MessageInner::Modified { frame } => match frame {
                 Frame::Cassandra(frame) => Ok(Metadata::Cassandra(frame.metadata())),
-                Frame::Redis(_) => Ok(Metadata::Redis),
+                Frame::Redis(_) | Frame::Resp3(_) => Ok(Metadata::Redis),
+                Frame::Memcached(frame) => Ok(Metadata::Memcached(frame.metadata())),
+                Frame::Kafka(_) => Ok(Metadata::Kafka),
+                Frame::Postgres(frame) => Ok(Metadata::Postgres(frame.metadata())),
                 Frame::None => Ok(Metadata::None),
             },
         }
@@ -300,6 +361,13 @@ impl Message {
             Metadata::Redis => {
                 unimplemented!()
             }
+            Metadata::Memcached(metadata) => Frame::Memcached(
+                metadata.error_response(MemcachedErrorKind::ServerError, "Server overloaded"),
+            ),
+            Metadata::Kafka => return Err(anyhow!(KAFKA_RESPONSE_UNSUPPORTED)),
+            Metadata::Postgres(metadata) => {
+                Frame::Postgres(metadata.error_response("53000", "Server overloaded"))
+            }
             Metadata::None => Frame::None,
         });
 
@@ -328,6 +396,10 @@ impl Message {
                 match frame {
                     Frame::Cassandra(cassandra) => Some(cassandra.stream_id),
                     Frame::Redis(_) => None,
+                    Frame::Resp3(_) => None,
+                    Frame::Memcached(_) => None,
+                    Frame::Kafka(_) => None,
+                    Frame::Postgres(_) => None,
                     Frame::None => None,
                 }
             }
@@ -335,6 +407,41 @@ impl Message {
         }
     }
 
+    /// Sets the stream_id without parsing the rest of the frame.
+    /// Messages in any other protocol are left unchanged.
+    pub fn set_stream_id(&mut self, stream_id: i16) {
+        fn set_bytes_stream_id(bytes: &mut Bytes, stream_id: i16) {
+            let mut new_bytes = BytesMut::from(bytes.as_ref());
+            new_bytes[2..4].copy_from_slice(&stream_id.to_be_bytes());
+            *bytes = new_bytes.freeze();
+        }
+
+        match self.inner.as_mut().unwrap() {
+            MessageInner::RawBytes {
+                bytes,
+                message_type: MessageType::Cassandra,
+            } => {
+                const HEADER_LEN: usize = 9;
+                if bytes.len() >= HEADER_LEN {
+                    set_bytes_stream_id(bytes, stream_id);
+                }
+            }
+            MessageInner::RawBytes { .. } => {}
+            MessageInner::Parsed {
+                bytes,
+                frame: Frame::Cassandra(frame),
+            } => {
+                set_bytes_stream_id(bytes, stream_id);
+                frame.stream_id = stream_id;
+            }
+            MessageInner::Parsed { .. } => {}
+            MessageInner::Modified {
+                frame: Frame::Cassandra(frame),
+            } => frame.stream_id = stream_id,
+            MessageInner::Modified { .. } => {}
+        }
+    }
+
     pub fn to_high_level_string(&mut self) -> String {
         if let Some(response) = self.frame() {
             format!("{}", response)
@@ -500,6 +607,42 @@ impl From<&RedisFrame> for MessageValue {
     }
 }
 
+impl From<Resp3Frame> for MessageValue {
+    fn from(f: Resp3Frame) -> Self {
+        match f {
+            Resp3Frame::BlobString { data, .. }
+            | Resp3Frame::VerbatimString { data, .. }
+            | Resp3Frame::ChunkedString(data) => MessageValue::Bytes(data),
+            Resp3Frame::SimpleString { data, .. } => {
+                MessageValue::Strings(String::from_utf8_lossy(&data).to_string())
+            }
+            Resp3Frame::SimpleError { data, .. } => MessageValue::Strings(data.to_string()),
+            Resp3Frame::BlobError { data, .. } => {
+                MessageValue::Strings(String::from_utf8_lossy(&data).to_string())
+            }
+            Resp3Frame::Boolean { data, .. } => MessageValue::Boolean(data),
+            Resp3Frame::Number { data, .. } => MessageValue::Integer(data, IntSize::I64),
+            Resp3Frame::Double { data, .. } => MessageValue::Double(OrderedFloat(data)),
+            Resp3Frame::BigNumber { data, .. } => match BigInt::parse_bytes(&data, 10) {
+                Some(number) => MessageValue::Varint(number),
+                None => MessageValue::Bytes(data),
+            },
+            Resp3Frame::Array { data, .. } | Resp3Frame::Push { data, .. } => {
+                MessageValue::List(data.into_iter().map(MessageValue::from).collect())
+            }
+            Resp3Frame::Set { data, .. } => {
+                MessageValue::Set(data.into_iter().map(MessageValue::from).collect())
+            }
+            Resp3Frame::Map { data, .. } => MessageValue::Map(
+                data.into_iter()
+                    .map(|(key, value)| (MessageValue::from(key), MessageValue::from(value)))
+                    .collect(),
+            ),
+            Resp3Frame::Null | Resp3Frame::Hello { .. } => MessageValue::Null,
+        }
+    }
+}
+
 impl From<MessageValue> for RedisFrame {
     fn from(value: MessageValue) -> RedisFrame {
         match value {
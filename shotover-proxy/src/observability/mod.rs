@@ -1,3 +1,4 @@
+use crate::runner::ReloadTopologySender;
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use hyper::{
@@ -8,6 +9,7 @@ use metrics_exporter_prometheus::PrometheusHandle;
 use std::convert::Infallible;
 use std::str;
 use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::oneshot;
 use tracing::{error, trace};
 use tracing_subscriber::reload::Handle;
 use tracing_subscriber::EnvFilter;
@@ -17,6 +19,7 @@ pub struct LogFilterHttpExporter<S> {
     recorder_handle: PrometheusHandle,
     address: SocketAddr,
     tracing_handle: Handle<EnvFilter, S>,
+    reload_topology_tx: ReloadTopologySender,
 }
 
 /// Sets the `tracing_suscriber` filter level to the value of `bytes` on `handle`
@@ -32,6 +35,18 @@ where
     handle.reload(new_filter).map_err(|e| format!("{e}"))
 }
 
+/// Asks the runner to reload the topology file and waits for the reload to complete
+async fn reload_topology(reload_topology_tx: &ReloadTopologySender) -> Result<(), String> {
+    let (result_tx, result_rx) = oneshot::channel();
+    reload_topology_tx
+        .send(result_tx)
+        .map_err(|_| "shotover is shutting down".to_string())?;
+    result_rx
+        .await
+        .map_err(|_| "shotover is shutting down".to_string())?
+        .map_err(|e| format!("{e:?}"))
+}
+
 fn rsp(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
     Response::builder()
         .status(status)
@@ -50,11 +65,13 @@ where
         recorder_handle: PrometheusHandle,
         address: SocketAddr,
         tracing_handle: Handle<EnvFilter, S>,
+        reload_topology_tx: ReloadTopologySender,
     ) -> Self {
         LogFilterHttpExporter {
             recorder_handle,
             address,
             tracing_handle,
+            reload_topology_tx,
         }
     }
 
@@ -69,15 +86,18 @@ where
     async fn async_run_inner(self) -> Result<()> {
         let recorder_handle = Arc::new(self.recorder_handle);
         let tracing_handle = Arc::new(self.tracing_handle);
+        let reload_topology_tx = self.reload_topology_tx;
 
         let make_svc = make_service_fn(move |_| {
             let recorder_handle = recorder_handle.clone();
             let tracing_handle = tracing_handle.clone();
+            let reload_topology_tx = reload_topology_tx.clone();
 
             async move {
                 Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
                     let recorder_handle = recorder_handle.clone();
                     let tracing_handle = tracing_handle.clone();
+                    let reload_topology_tx = reload_topology_tx.clone();
 
                     async move {
                         let response = match (req.method(), req.uri().path()) {
@@ -100,7 +120,20 @@ where
                                     }
                                 }
                             }
-                            _ => rsp(StatusCode::NOT_FOUND, "try '/filter' or `/metrics`"),
+                            (&Method::POST, "/reload") => {
+                                trace!("reloading topology");
+                                match reload_topology(&reload_topology_tx).await {
+                                    Err(error) => {
+                                        error!(%error, "reloading topology failed!");
+                                        rsp(StatusCode::INTERNAL_SERVER_ERROR, error)
+                                    }
+                                    Ok(()) => rsp(StatusCode::NO_CONTENT, Body::empty()),
+                                }
+                            }
+                            _ => rsp(
+                                StatusCode::NOT_FOUND,
+                                "try '/filter', `/metrics` or `/reload`",
+                            ),
                         };
                         Ok::<_, Infallible>(response)
                     }
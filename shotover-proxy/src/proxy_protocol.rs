@@ -0,0 +1,340 @@
+//! Support for the [PROXY protocol](https://www.haproxy.org/download/2.6/doc/proxy-protocol.txt) used by
+//! L4 load balancers such as HAProxy to pass the address of the original client to the server behind them.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How long a connection may take to send its PROXY protocol header before it is closed
+pub const READ_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// The maximum length of a v1 header including the trailing CRLF
+const V1_MAX_LEN: usize = 107;
+
+const V2_COMMAND_LOCAL: u8 = 0x20;
+const V2_COMMAND_PROXY: u8 = 0x21;
+const V2_FAMILY_UNSPEC: u8 = 0x00;
+const V2_FAMILY_TCP4: u8 = 0x11;
+const V2_FAMILY_TCP6: u8 = 0x21;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+/// The addresses of the original connection as described by a PROXY protocol header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProxyHeader {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+/// Read a v1 or v2 PROXY protocol header from the start of the stream.
+/// No bytes past the end of the header are consumed so the stream can then be used for TLS or the codec.
+///
+/// Returns `None` when the header does not carry an address, e.g. v1 `UNKNOWN`, v2 `LOCAL` or a unix socket address,
+/// in which case the address of the connection itself should be used.
+pub async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<ProxyHeader>> {
+    // Both v1 and v2 headers are at least as long as the v2 signature
+    let mut start = [0; 12];
+    stream
+        .read_exact(&mut start)
+        .await
+        .context("Failed to read PROXY protocol header")?;
+
+    if start == V2_SIGNATURE {
+        let mut fixed = [0; 4];
+        stream
+            .read_exact(&mut fixed)
+            .await
+            .context("Failed to read PROXY protocol v2 header")?;
+        let len = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+        let mut addresses = vec![0; len];
+        stream
+            .read_exact(&mut addresses)
+            .await
+            .context("Failed to read PROXY protocol v2 addresses")?;
+        parse_v2(fixed[0], fixed[1], &addresses)
+    } else if start.starts_with(V1_PREFIX) {
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                bail!("PROXY protocol v1 header is longer than {V1_MAX_LEN} bytes");
+            }
+            line.push(
+                stream
+                    .read_u8()
+                    .await
+                    .context("Failed to read PROXY protocol v1 header")?,
+            );
+        }
+        parse_v1(&line[..line.len() - 2])
+    } else {
+        Err(anyhow!(
+            "Connection did not start with a PROXY protocol header"
+        ))
+    }
+}
+
+fn parse_v1(line: &[u8]) -> Result<Option<ProxyHeader>> {
+    let line = std::str::from_utf8(line).context("PROXY protocol v1 header is not valid utf8")?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", protocol @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] =>
+        {
+            let source: IpAddr = source
+                .parse()
+                .with_context(|| format!("Invalid source address {source}"))?;
+            let destination: IpAddr = destination
+                .parse()
+                .with_context(|| format!("Invalid destination address {destination}"))?;
+            if source.is_ipv4() != (*protocol == "TCP4")
+                || destination.is_ipv4() != (*protocol == "TCP4")
+            {
+                bail!("PROXY protocol v1 addresses do not match protocol {protocol}");
+            }
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(source, parse_port(source_port)?),
+                destination: SocketAddr::new(destination, parse_port(destination_port)?),
+            }))
+        }
+        _ => Err(anyhow!("Invalid PROXY protocol v1 header {line:?}")),
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16> {
+    port.parse()
+        .with_context(|| format!("Invalid PROXY protocol v1 port {port}"))
+}
+
+fn parse_v2(command: u8, family: u8, addresses: &[u8]) -> Result<Option<ProxyHeader>> {
+    if command >> 4 != 2 {
+        bail!("Unsupported PROXY protocol version {}", command >> 4);
+    }
+    match command {
+        V2_COMMAND_LOCAL => return Ok(None),
+        V2_COMMAND_PROXY => {}
+        _ => bail!("Unsupported PROXY protocol v2 command {}", command & 0x0F),
+    }
+
+    // Only the address family is of interest, the transport protocol is ignored.
+    // Any TLVs following the addresses are also ignored.
+    match family >> 4 {
+        0x1 => {
+            if addresses.len() < 12 {
+                bail!("PROXY protocol v2 IPv4 addresses are truncated");
+            }
+            let source: [u8; 4] = addresses[0..4].try_into().unwrap();
+            let destination: [u8; 4] = addresses[4..8].try_into().unwrap();
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(
+                    Ipv4Addr::from(source).into(),
+                    u16::from_be_bytes([addresses[8], addresses[9]]),
+                ),
+                destination: SocketAddr::new(
+                    Ipv4Addr::from(destination).into(),
+                    u16::from_be_bytes([addresses[10], addresses[11]]),
+                ),
+            }))
+        }
+        0x2 => {
+            if addresses.len() < 36 {
+                bail!("PROXY protocol v2 IPv6 addresses are truncated");
+            }
+            let source: [u8; 16] = addresses[0..16].try_into().unwrap();
+            let destination: [u8; 16] = addresses[16..32].try_into().unwrap();
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(
+                    Ipv6Addr::from(source).into(),
+                    u16::from_be_bytes([addresses[32], addresses[33]]),
+                ),
+                destination: SocketAddr::new(
+                    Ipv6Addr::from(destination).into(),
+                    u16::from_be_bytes([addresses[34], addresses[35]]),
+                ),
+            }))
+        }
+        // AF_UNSPEC and AF_UNIX do not carry an ip address
+        0x0 | 0x3 => Ok(None),
+        family => Err(anyhow!(
+            "Unsupported PROXY protocol v2 address family {family}"
+        )),
+    }
+}
+
+impl ProxyHeader {
+    /// Encode a PROXY protocol header to send at the start of a connection.
+    /// When `header` is `None` a header is sent that tells the receiver to use the address of the connection itself.
+    pub fn encode(header: Option<ProxyHeader>, version: ProxyProtocolVersion) -> Vec<u8> {
+        let header = header.map(ProxyHeader::with_matching_families);
+        match version {
+            ProxyProtocolVersion::V1 => match header {
+                Some(header) => format!(
+                    "PROXY {} {} {} {} {}\r\n",
+                    if header.source.is_ipv4() {
+                        "TCP4"
+                    } else {
+                        "TCP6"
+                    },
+                    header.source.ip(),
+                    header.destination.ip(),
+                    header.source.port(),
+                    header.destination.port(),
+                )
+                .into_bytes(),
+                None => b"PROXY UNKNOWN\r\n".to_vec(),
+            },
+            ProxyProtocolVersion::V2 => {
+                let mut bytes = V2_SIGNATURE.to_vec();
+                match header {
+                    Some(header) => {
+                        let (family, source, destination) =
+                            match (header.source.ip(), header.destination.ip()) {
+                                (IpAddr::V4(source), IpAddr::V4(destination)) => (
+                                    V2_FAMILY_TCP4,
+                                    source.octets().to_vec(),
+                                    destination.octets().to_vec(),
+                                ),
+                                (source, destination) => (
+                                    V2_FAMILY_TCP6,
+                                    to_ipv6(source).octets().to_vec(),
+                                    to_ipv6(destination).octets().to_vec(),
+                                ),
+                            };
+                        bytes.push(V2_COMMAND_PROXY);
+                        bytes.push(family);
+                        let len = source.len() * 2 + 4;
+                        bytes.extend_from_slice(&(len as u16).to_be_bytes());
+                        bytes.extend_from_slice(&source);
+                        bytes.extend_from_slice(&destination);
+                        bytes.extend_from_slice(&header.source.port().to_be_bytes());
+                        bytes.extend_from_slice(&header.destination.port().to_be_bytes());
+                    }
+                    None => {
+                        bytes.push(V2_COMMAND_LOCAL);
+                        bytes.push(V2_FAMILY_UNSPEC);
+                        bytes.extend_from_slice(&0u16.to_be_bytes());
+                    }
+                }
+                bytes
+            }
+        }
+    }
+
+    /// Both addresses in a header must belong to the same family, so when they differ the IPv4 address is mapped to IPv6.
+    fn with_matching_families(self) -> ProxyHeader {
+        if self.source.is_ipv4() == self.destination.is_ipv4() {
+            self
+        } else {
+            ProxyHeader {
+                source: SocketAddr::new(to_ipv6(self.source.ip()).into(), self.source.port()),
+                destination: SocketAddr::new(
+                    to_ipv6(self.destination.ip()).into(),
+                    self.destination.port(),
+                ),
+            }
+        }
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    #[tokio::test]
+    async fn test_read_v1() {
+        let mut stream: &[u8] = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET /";
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            Some(ProxyHeader {
+                source: "192.168.0.1:56324".parse().unwrap(),
+                destination: "192.168.0.11:443".parse().unwrap(),
+            })
+        );
+        // bytes following the header are left in the stream
+        assert_eq!(stream, b"GET /");
+
+        let mut stream: &[u8] = b"PROXY TCP6 ::1 fe80::1 1000 2000\r\n";
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            Some(ProxyHeader {
+                source: "[::1]:1000".parse().unwrap(),
+                destination: "[fe80::1]:2000".parse().unwrap(),
+            })
+        );
+
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut stream).await.unwrap(), None);
+
+        let mut stream: &[u8] = b"PROXY TCP6 192.168.0.1 192.168.0.11 56324 443\r\n";
+        assert!(read_header(&mut stream).await.is_err());
+
+        let mut stream: &[u8] = b"*1\r\n$4\r\nPING\r\n";
+        assert!(read_header(&mut stream).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_v2() {
+        let mut stream: &[u8] =
+            &hex!("0d0a0d0a000d0a515549540a 21 11 000c c0a80001 c0a8000b dc04 01bb 2a");
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            Some(ProxyHeader {
+                source: "192.168.0.1:56324".parse().unwrap(),
+                destination: "192.168.0.11:443".parse().unwrap(),
+            })
+        );
+        assert_eq!(stream, &[0x2a_u8]);
+
+        // LOCAL command, used by load balancers for health checks
+        let mut stream: &[u8] = &hex!("0d0a0d0a000d0a515549540a 20 00 0000");
+        assert_eq!(read_header(&mut stream).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_encode_round_trip() {
+        let headers = [
+            Some(ProxyHeader {
+                source: "10.0.0.1:1234".parse().unwrap(),
+                destination: "10.0.0.2:9042".parse().unwrap(),
+            }),
+            Some(ProxyHeader {
+                source: "[2001:db8::1]:1234".parse().unwrap(),
+                destination: "[2001:db8::2]:6379".parse().unwrap(),
+            }),
+            None,
+        ];
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            for header in headers {
+                let bytes = ProxyHeader::encode(header, version);
+                assert_eq!(read_header(&mut bytes.as_slice()).await.unwrap(), header);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_mixed_families() {
+        let header = ProxyHeader {
+            source: "10.0.0.1:1234".parse().unwrap(),
+            destination: "[2001:db8::2]:9042".parse().unwrap(),
+        };
+        assert_eq!(
+            ProxyHeader::encode(Some(header), ProxyProtocolVersion::V1),
+            b"PROXY TCP6 ::ffff:10.0.0.1 2001:db8::2 1234 9042\r\n"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::config::topology::Topology;
+use crate::config::topology::{RunningTopology, Topology};
 use crate::config::Config;
 use crate::observability::LogFilterHttpExporter;
 use crate::transforms::Transforms;
@@ -10,7 +10,7 @@ use std::env;
 use std::net::SocketAddr;
 use tokio::runtime::{self, Handle as RuntimeHandle, Runtime};
 use tokio::signal::unix::{signal, SignalKind};
-use tokio::sync::watch;
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::task::JoinHandle;
 use tracing::{debug, error, info};
 use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
@@ -48,29 +48,42 @@ impl Default for ConfigOpts {
     }
 }
 
+/// Requests the running shotover to reload its topology file.
+/// The result of the reload is sent back over the provided oneshot channel.
+pub type ReloadTopologySender = mpsc::UnboundedSender<oneshot::Sender<Result<()>>>;
+type ReloadTopologyReceiver = mpsc::UnboundedReceiver<oneshot::Sender<Result<()>>>;
+
 pub struct Runner {
     runtime: Option<Runtime>,
     runtime_handle: RuntimeHandle,
     topology: Topology,
+    topology_file: String,
     config: Config,
     tracing: TracingState,
+    reload_topology_tx: ReloadTopologySender,
+    reload_topology_rx: ReloadTopologyReceiver,
 }
 
 impl Runner {
     pub fn new(params: ConfigOpts) -> Result<Self> {
         let config = Config::from_file(params.config_file)?;
-        let topology = Topology::from_file(params.topology_file)?;
+        let topology = Topology::from_file(params.topology_file.clone())?;
 
         let tracing = TracingState::new(config.main_log_level.as_str())?;
 
         let (runtime_handle, runtime) = Runner::get_runtime(params.stack_size, params.core_threads);
 
+        let (reload_topology_tx, reload_topology_rx) = mpsc::unbounded_channel();
+
         Ok(Runner {
             runtime,
             runtime_handle,
             topology,
+            topology_file: params.topology_file,
             config,
             tracing,
+            reload_topology_tx,
+            reload_topology_rx,
         })
     }
 
@@ -80,7 +93,12 @@ impl Runner {
         metrics::set_boxed_recorder(Box::new(recorder))?;
 
         let socket: SocketAddr = self.config.observability_interface.parse()?;
-        let exporter = LogFilterHttpExporter::new(handle, socket, self.tracing.handle.clone());
+        let exporter = LogFilterHttpExporter::new(
+            handle,
+            socket,
+            self.tracing.handle.clone(),
+            self.reload_topology_tx.clone(),
+        );
 
         self.runtime_handle.spawn(exporter.async_run());
 
@@ -90,15 +108,20 @@ impl Runner {
     pub fn run_spawn(self) -> RunnerSpawned {
         let (trigger_shutdown_tx, trigger_shutdown_rx) = watch::channel(false);
 
-        let join_handle =
-            self.runtime_handle
-                .spawn(run(self.topology, self.config, trigger_shutdown_rx));
+        let join_handle = self.runtime_handle.spawn(run(
+            self.topology,
+            self.topology_file,
+            self.config,
+            trigger_shutdown_rx,
+            self.reload_topology_rx,
+        ));
 
         RunnerSpawned {
             runtime_handle: self.runtime_handle,
             runtime: self.runtime,
             tracing_guard: self.tracing.guard,
             trigger_shutdown_tx,
+            reload_topology_tx: self.reload_topology_tx,
             join_handle,
         }
     }
@@ -122,8 +145,26 @@ impl Runner {
             trigger_shutdown_tx.send(true).unwrap();
         });
 
-        self.runtime_handle
-            .block_on(run(self.topology, self.config, trigger_shutdown_rx))
+        let reload_topology_tx = self.reload_topology_tx;
+        self.runtime_handle.spawn(async move {
+            let mut hangup = signal(SignalKind::hangup()).unwrap();
+            while hangup.recv().await.is_some() {
+                info!("received SIGHUP");
+                // The result of the reload is logged by the runner so there is no need to wait for it here.
+                let (result_tx, _) = oneshot::channel();
+                if reload_topology_tx.send(result_tx).is_err() {
+                    return;
+                }
+            }
+        });
+
+        self.runtime_handle.block_on(run(
+            self.topology,
+            self.topology_file,
+            self.config,
+            trigger_shutdown_rx,
+            self.reload_topology_rx,
+        ))
     }
 
     /// Get handle for an existing runtime or create one
@@ -209,12 +250,15 @@ pub struct RunnerSpawned {
     pub join_handle: JoinHandle<Result<()>>,
     pub tracing_guard: WorkerGuard,
     pub trigger_shutdown_tx: watch::Sender<bool>,
+    pub reload_topology_tx: ReloadTopologySender,
 }
 
 pub async fn run(
     topology: Topology,
+    topology_file: String,
     config: Config,
-    trigger_shutdown_rx: watch::Receiver<bool>,
+    mut trigger_shutdown_rx: watch::Receiver<bool>,
+    mut reload_topology_rx: ReloadTopologyReceiver,
 ) -> Result<()> {
     info!("Starting Shotover {}", crate_version!());
     info!(configuration = ?config);
@@ -230,19 +274,49 @@ pub async fn run(
         std::mem::size_of::<Wrapper<'_>>()
     );
 
-    match topology.run_chains(trigger_shutdown_rx).await {
-        Ok(sources) => {
-            futures::future::join_all(sources.into_iter().map(|x| x.into_join_handle())).await;
-            info!("Shotover was shutdown cleanly.");
-            Ok(())
-        }
+    let mut running = match RunningTopology::start(&topology).await {
+        Ok(running) => running,
         Err(error) => {
             error!("{:?}", error);
-            Err(anyhow!(
+            return Err(anyhow!(
                 "Shotover failed to initialize, the fatal error was logged."
-            ))
+            ));
+        }
+    };
+
+    // Check we didn't receive a shutdown signal before the receiver was created
+    if !*trigger_shutdown_rx.borrow() {
+        loop {
+            tokio::select! {
+                _ = trigger_shutdown_rx.changed() => break,
+                Some(result_tx) = reload_topology_rx.recv() => {
+                    let result = reload_topology(&mut running, &topology_file).await;
+                    // The requester may not be interested in the result
+                    result_tx.send(result).ok();
+                }
+            }
+        }
+    }
+
+    running.shutdown().await;
+    info!("Shotover was shutdown cleanly.");
+    Ok(())
+}
+
+async fn reload_topology(running: &mut RunningTopology, topology_file: &str) -> Result<()> {
+    info!("Reloading topology from {topology_file}");
+    let result = match Topology::from_file(topology_file.to_owned()) {
+        Ok(topology) => {
+            info!(topology = ?topology);
+            running.reload(&topology).await
         }
+        Err(err) => Err(err),
+    };
+    match &result {
+        Ok(()) => info!("Topology was reloaded"),
+        Err(err) => error!("Failed to reload topology: {:?}", err),
     }
+    result
 }
 
 #[test]
@@ -1,13 +1,17 @@
-use crate::message::Messages;
+use crate::codec::CodecBuilder;
+use crate::message::{Message, Messages, Metadata};
+use crate::proxy_protocol::{self, ProxyHeader};
+use crate::socket::{Listener, Stream};
+use crate::sources::ChainErrorPolicy;
 use crate::tls::TlsAcceptor;
 use crate::transforms::chain::TransformChain;
 use crate::transforms::Wrapper;
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, Result};
 use futures::{SinkExt, StreamExt};
-use metrics::{register_gauge, Gauge};
-use std::sync::Arc;
+use metrics::{counter, register_gauge, Gauge};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::{mpsc, watch, Semaphore};
 use tokio::time;
@@ -26,6 +30,8 @@ pub enum CodecReadError {
     Io(std::io::Error),
     /// Respond to the client with the provided messages and then close the connection
     RespondAndThenCloseConnection(Messages),
+    /// Respond to the client with the provided messages in place of the rejected request and keep reading from the connection
+    Respond(Messages),
 }
 
 impl From<std::io::Error> for CodecReadError {
@@ -46,7 +52,7 @@ impl<T: Encoder<Messages, Error = anyhow::Error> + Clone + Send> CodecWriteHalf
 pub trait Codec: CodecReadHalf + CodecWriteHalf {}
 impl<T: CodecReadHalf + CodecWriteHalf> Codec for T {}
 
-pub struct TcpCodecListener<C: Codec> {
+pub struct TcpCodecListener<C: CodecBuilder> {
     /// Shared database handle.
     ///
     /// Contains the key / value store as well as the broadcast channels for
@@ -54,12 +60,15 @@ pub struct TcpCodecListener<C: Codec> {
     ///
     /// This is a wrapper around an `Arc`. This enables `db` to be cloned and
     /// passed into the per connection state (`Handler`).
-    chain: TransformChain,
+    ///
+    /// The chain is replaced when the topology is reloaded, each connection
+    /// keeps using the chain that was present when it was accepted.
+    chain: Arc<Mutex<TransformChain>>,
 
     source_name: String,
 
-    /// TCP listener supplied by the `run` caller.
-    listener: Option<TcpListener>,
+    /// TCP or unix socket listener supplied by the `run` caller.
+    listener: Option<Listener>,
     listen_addr: String,
     hard_connection_limit: bool,
 
@@ -87,6 +96,11 @@ pub struct TcpCodecListener<C: Codec> {
 
     tls: Option<TlsAcceptor>,
 
+    /// When true every connection must start with a PROXY protocol header describing the real client address.
+    proxy_protocol: bool,
+
+    chain_error_policy: ChainErrorPolicy,
+
     /// Keep track of how many connections we have received so we can use it as a request id.
     connection_count: u64,
 
@@ -96,10 +110,10 @@ pub struct TcpCodecListener<C: Codec> {
     timeout: Option<u64>,
 }
 
-impl<C: Codec + 'static> TcpCodecListener<C> {
+impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
     #![allow(clippy::too_many_arguments)]
     pub async fn new(
-        chain: TransformChain,
+        chain: Arc<Mutex<TransformChain>>,
         source_name: String,
         listen_addr: String,
         hard_connection_limit: bool,
@@ -107,13 +121,15 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
         limit_connections: Arc<Semaphore>,
         trigger_shutdown_rx: watch::Receiver<bool>,
         tls: Option<TlsAcceptor>,
+        proxy_protocol: bool,
+        chain_error_policy: ChainErrorPolicy,
         timeout: Option<u64>,
     ) -> Result<Self> {
         let available_connections_gauge =
             register_gauge!("shotover_available_connections", "source" => source_name.clone());
         available_connections_gauge.set(limit_connections.available_permits() as f64);
 
-        let listener = Some(create_listener(&listen_addr).await?);
+        let listener = Some(Listener::bind(&listen_addr).await?);
 
         Ok(TcpCodecListener {
             chain,
@@ -125,6 +141,8 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
             limit_connections,
             trigger_shutdown_rx,
             tls,
+            proxy_protocol,
+            chain_error_policy,
             connection_count: 0,
             available_connections_gauge,
             timeout,
@@ -164,7 +182,7 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
                 match self.limit_connections.try_acquire() {
                     Ok(p) => {
                         if self.listener.is_none() {
-                            self.listener = Some(create_listener(&self.listen_addr).await?);
+                            self.listener = Some(Listener::bind(&self.listen_addr).await?);
                         }
                         p.forget();
                     }
@@ -180,7 +198,7 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
             } else {
                 self.limit_connections.acquire().await?.forget();
                 if self.listener.is_none() {
-                    self.listener = Some(create_listener(&self.listen_addr).await?);
+                    self.listener = Some(Listener::bind(&self.listen_addr).await?);
                 }
             }
 
@@ -193,28 +211,46 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
             self.available_connections_gauge
                 .set(self.limit_connections.available_permits() as f64);
 
-            let peer = socket
-                .peer_addr()
-                .map(|p| format!("{}", p.ip()))
-                .unwrap_or_else(|_| "Unknown peer".to_string());
-
-            let conn_string = socket
-                .peer_addr()
-                .map(|p| format!("{}:{}", p.ip(), p.port()))
-                .unwrap_or_else(|_| "Unknown peer".to_string());
+            let (peer, conn_string, proxy_header) = match &socket {
+                Stream::Tcp(socket) => {
+                    socket.set_nodelay(true)?;
+                    (
+                        socket
+                            .peer_addr()
+                            .map(|p| format!("{}", p.ip()))
+                            .unwrap_or_else(|_| "Unknown peer".to_string()),
+                        socket
+                            .peer_addr()
+                            .map(|p| format!("{}:{}", p.ip(), p.port()))
+                            .unwrap_or_else(|_| "Unknown peer".to_string()),
+                        socket
+                            .peer_addr()
+                            .and_then(|source| {
+                                Ok(ProxyHeader {
+                                    source,
+                                    destination: socket.local_addr()?,
+                                })
+                            })
+                            .ok(),
+                    )
+                }
+                // Clients connecting over a unix socket are usually unnamed so the socket we listen on is the best description we have.
+                Stream::Unix(_) => (self.listen_addr.clone(), self.listen_addr.clone(), None),
+            };
 
             // Create the necessary per-connection handler state.
-            socket.set_nodelay(true)?;
-
             let (pushed_messages_tx, pushed_messages_rx) =
                 tokio::sync::mpsc::unbounded_channel::<Messages>();
 
             let mut handler = Handler {
                 chain: self
                     .chain
+                    .lock()
+                    .unwrap()
                     .clone_with_pushed_messages_tx(pushed_messages_tx.clone()),
                 client_details: peer,
                 conn_details: conn_string,
+                proxy_header,
                 source_details: self.source_name.clone(),
 
                 // The connection state needs a handle to the max connections
@@ -228,6 +264,8 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
 
                 terminate_tasks: None,
                 tls: self.tls.clone(),
+                proxy_protocol: self.proxy_protocol,
+                chain_error_policy: self.chain_error_policy,
                 timeout: self.timeout,
             };
 
@@ -239,7 +277,21 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
                     tracing::debug!("New connection from {}", handler.conn_details);
 
                     // Process the connection. If an error is encountered, log it.
-                    if let Err(err) = handler.run(socket, pushed_messages_rx).await {
+                    let result = match socket {
+                        Stream::Tcp(socket) => match socket.local_addr() {
+                            Ok(local_addr) => {
+                                handler.run(socket, local_addr, pushed_messages_rx).await
+                            }
+                            Err(err) => Err(err.into()),
+                        },
+                        // Unix sockets have no ip address, so the address the source received messages on is left unspecified.
+                        Stream::Unix(socket) => {
+                            handler
+                                .run(socket, ([0, 0, 0, 0], 0).into(), pushed_messages_rx)
+                                .await
+                        }
+                    };
+                    if let Err(err) = result {
                         error!(
                             "{:?}",
                             err.context("connection was unexpectedly terminated")
@@ -256,10 +308,13 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
     }
 
     pub async fn shutdown(&mut self) {
-        match self
-            .chain
+        // Stop accepting connections, this also removes the socket file of a unix socket listener
+        self.listener = None;
+
+        let mut chain = self.chain.lock().unwrap().clone();
+        match chain
             .process_request(
-                Wrapper::flush_with_chain_name(self.chain.name.clone()),
+                Wrapper::flush_with_chain_name(chain.name.clone()),
                 "".into(),
             )
             .await
@@ -279,7 +334,7 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
     /// After the second failure, the task waits for 2 seconds. Each subsequent
     /// failure doubles the wait time. If accepting fails on the 6th try after
     /// waiting for 64 seconds, then this function returns with an error.
-    async fn accept(&mut self) -> Result<TcpStream> {
+    async fn accept(&mut self) -> Result<Stream> {
         let mut backoff = 1;
 
         // Try to accept a few times
@@ -287,7 +342,7 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
             // Perform the accept operation. If a socket is successfully
             // accepted, return it. Otherwise, save the error.
             match self.listener.as_mut().unwrap().accept().await {
-                Ok((socket, _)) => return Ok(socket),
+                Ok(socket) => return Ok(socket),
                 Err(err) => {
                     if backoff > 64 {
                         // Accept has failed too many times. Return the error.
@@ -305,13 +360,7 @@ impl<C: Codec + 'static> TcpCodecListener<C> {
     }
 }
 
-async fn create_listener(listen_addr: &str) -> Result<TcpListener> {
-    TcpListener::bind(listen_addr)
-        .await
-        .map_err(|e| anyhow!("{} address={}", e, listen_addr))
-}
-
-pub struct Handler<C: Codec> {
+pub struct Handler<C: CodecBuilder> {
     /// Shared source handle.
     ///
     /// When a command is received from `connection`, it is applied with `db`.
@@ -320,8 +369,8 @@ pub struct Handler<C: Codec> {
     chain: TransformChain,
     client_details: String,
     conn_details: String,
-
-    #[allow(dead_code)]
+    /// The addresses of the client connection, as received in a PROXY protocol header when `proxy_protocol` is enabled.
+    proxy_header: Option<ProxyHeader>,
     source_details: String,
     codec: C,
 
@@ -353,13 +402,15 @@ pub struct Handler<C: Codec> {
 
     terminate_tasks: Option<watch::Sender<()>>,
     tls: Option<TlsAcceptor>,
+    proxy_protocol: bool,
+    chain_error_policy: ChainErrorPolicy,
 
     /// Timeout in seconds after which to kill an idle connection. No timeout means connections will never be timed out.
     timeout: Option<u64>,
 }
 
 fn spawn_read_write_tasks<
-    C: Codec + 'static,
+    C: CodecBuilder + 'static,
     R: AsyncRead + Unpin + Send + 'static,
     W: AsyncWrite + Unpin + Send + 'static,
 >(
@@ -371,8 +422,9 @@ fn spawn_read_write_tasks<
     out_tx: UnboundedSender<Messages>,
     mut terminate_tasks_rx: watch::Receiver<()>,
 ) {
-    let mut reader = FramedRead::new(rx, codec.clone());
-    let mut writer = FramedWrite::new(tx, codec);
+    let (decoder, encoder) = codec.build();
+    let mut reader = FramedRead::new(rx, decoder);
+    let mut writer = FramedWrite::new(tx, encoder);
 
     // Shutdown flows
     //
@@ -389,6 +441,8 @@ fn spawn_read_write_tasks<
     // reader task
     tokio::spawn(
         async move {
+            // After the decoder returns an error the reader yields `None` once before it resumes reading
+            let mut resume_after_error = false;
             loop {
                 tokio::select! {
                     result = reader.next() => {
@@ -406,6 +460,13 @@ fn spawn_read_write_tasks<
                                     }
                                     return;
                                 }
+                                Err(CodecReadError::Respond(messages)) => {
+                                    if let Err(err) = out_tx.send(messages) {
+                                        error!("Failed to send Respond message: {:?}", err);
+                                        return;
+                                    }
+                                    resume_after_error = true;
+                                }
                                 Err(CodecReadError::Parser(err)) => {
                                     warn!("failed to decode message: {:?}", err);
                                     return;
@@ -415,6 +476,8 @@ fn spawn_read_write_tasks<
                                     return;
                                 }
                             }
+                        } else if std::mem::take(&mut resume_after_error) {
+                            continue;
                         } else {
                             debug!("client has closed the connection");
                             return;
@@ -456,7 +519,7 @@ fn spawn_read_write_tasks<
     );
 }
 
-impl<C: Codec + 'static> Handler<C> {
+impl<C: CodecBuilder + 'static> Handler<C> {
     /// Process a single connection.
     ///
     /// Request frames are read from the socket and processed. Responses are
@@ -464,12 +527,29 @@ impl<C: Codec + 'static> Handler<C> {
     ///
     /// When the shutdown signal is received, the connection is processed until
     /// it reaches a safe state, at which point it is terminated.
-    pub async fn run(
+    pub async fn run<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
         &mut self,
-        stream: TcpStream,
+        mut stream: S,
+        local_addr: SocketAddr,
         mut pushed_messages_rx: UnboundedReceiver<Messages>,
     ) -> Result<()> {
         debug!("Handler run() started");
+
+        // The PROXY protocol header is sent in the clear before any TLS handshake or protocol messages
+        if self.proxy_protocol {
+            self.proxy_header = timeout(
+                proxy_protocol::READ_HEADER_TIMEOUT,
+                proxy_protocol::read_header(&mut stream),
+            )
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for PROXY protocol header"))??;
+            if let Some(header) = self.proxy_header {
+                self.client_details = header.source.ip().to_string();
+                self.conn_details = header.source.to_string();
+                debug!("PROXY protocol header received for {}", self.conn_details);
+            }
+        }
+
         // As long as the shutdown signal has not been received, try to read a
         // new request frame.
         let mut idle_time_seconds: u64 = 1;
@@ -480,8 +560,6 @@ impl<C: Codec + 'static> Handler<C> {
         let (in_tx, mut in_rx) = mpsc::unbounded_channel::<Messages>();
         let (out_tx, out_rx) = mpsc::unbounded_channel::<Messages>();
 
-        let local_addr = stream.local_addr()?;
-
         if let Some(tls) = &self.tls {
             let tls_stream = tls.accept(stream).await?;
             let (rx, tx) = tokio::io::split(tls_stream);
@@ -495,7 +573,7 @@ impl<C: Codec + 'static> Handler<C> {
                 terminate_rx,
             );
         } else {
-            let (rx, tx) = stream.into_split();
+            let (rx, tx) = tokio::io::split(stream);
             spawn_read_write_tasks(
                 self.codec.clone(),
                 rx,
@@ -550,21 +628,47 @@ impl<C: Codec + 'static> Handler<C> {
             debug!("Received raw message {:?}", messages);
             debug!("client details: {:?}", &self.client_details);
 
+            // Pushed messages have no requests to respond to, so errors in the reverse chain always close the connection.
+            let requests_metadata = match self.chain_error_policy {
+                ChainErrorPolicy::RespondWithError if !reverse_chain => messages
+                    .iter()
+                    .map(|message| message.metadata())
+                    .collect::<Result<Vec<_>>>()
+                    .ok(),
+                _ => None,
+            };
+
             let wrapper = Wrapper::new_with_client_details(
                 messages,
                 self.client_details.clone(),
+                self.proxy_header,
                 self.chain.name.clone(),
                 local_addr,
             );
 
-            let modified_messages = if reverse_chain {
+            let result = if reverse_chain {
                 self.chain.process_request_rev(wrapper).await
             } else {
                 self.chain
                     .process_request(wrapper, self.client_details.clone())
                     .await
-            }
-            .context("chain failed to send and/or receive messages")?;
+            };
+
+            let modified_messages = match result {
+                Ok(modified_messages) => modified_messages,
+                Err(err) => {
+                    let err = err.context("chain failed to send and/or receive messages");
+                    match requests_metadata.and_then(error_responses) {
+                        Some(responses) => {
+                            // The details of the error are only logged as they may expose internals of the deployment to clients
+                            error!("{:#}", err);
+                            counter!("shotover_chain_error_responses", responses.len() as u64, "source" => self.source_details.clone());
+                            responses
+                        }
+                        None => return Err(err),
+                    }
+                }
+            };
 
             debug!("sending message: {:?}", modified_messages);
             // send the result of the process up stream
@@ -574,7 +678,22 @@ impl<C: Codec + 'static> Handler<C> {
     }
 }
 
-impl<C: Codec> Drop for Handler<C> {
+/// Create a generic error response for each request, or `None` if the protocol does not support generating error responses.
+fn error_responses(requests_metadata: Vec<Metadata>) -> Option<Messages> {
+    const ERROR: &str = "Internal shotover error";
+    requests_metadata
+        .into_iter()
+        .map(|metadata| {
+            let error = match metadata {
+                Metadata::Redis => format!("ERR {}", ERROR),
+                _ => ERROR.to_owned(),
+            };
+            Message::from_error(metadata, error)
+        })
+        .collect()
+}
+
+impl<C: CodecBuilder> Drop for Handler<C> {
     fn drop(&mut self) {
         // Add a permit back to the semaphore.
         //
@@ -0,0 +1,139 @@
+//! Sources and sinks accept either a tcp address such as `127.0.0.1:6379` or a unix socket path given as `unix:/path/to/socket`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::Permissions;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::time::timeout;
+
+const UNIX_PREFIX: &str = "unix:";
+
+/// Unix socket files created by sources can be connected to by the user and group that shotover runs as.
+const UNIX_SOCKET_PERMISSIONS: u32 = 0o660;
+
+/// Returns the path of the socket if `address` is a unix socket address.
+pub fn unix_socket_path(address: &str) -> Option<&Path> {
+    address.strip_prefix(UNIX_PREFIX).map(Path::new)
+}
+
+/// Connect to a unix socket, giving up after 3 seconds.
+pub async fn connect_unix(path: &Path) -> Result<UnixStream> {
+    timeout(Duration::from_secs(3), UnixStream::connect(path))
+        .await
+        .map_err(|_| {
+            anyhow!("Unix socket {path:?} did not respond to connection attempt within 3 seconds")
+        })?
+        .with_context(|| format!("Failed to connect to unix socket {path:?}"))
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    /// The socket file at `path` is removed when the listener is dropped.
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+}
+
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Listener {
+    pub async fn bind(listen_addr: &str) -> Result<Listener> {
+        match unix_socket_path(listen_addr) {
+            Some(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)
+                    .map_err(|e| anyhow!("{} address={}", e, listen_addr))?;
+                std::fs::set_permissions(path, Permissions::from_mode(UNIX_SOCKET_PERMISSIONS))
+                    .with_context(|| format!("Failed to set permissions of {path:?}"))?;
+                Ok(Listener::Unix {
+                    listener,
+                    path: path.to_owned(),
+                })
+            }
+            None => TcpListener::bind(listen_addr)
+                .await
+                .map(Listener::Tcp)
+                .map_err(|e| anyhow!("{} address={}", e, listen_addr)),
+        }
+    }
+
+    pub async fn accept(&self) -> std::io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().await.map(|(s, _)| Stream::Tcp(s)),
+            Listener::Unix { listener, .. } => {
+                listener.accept().await.map(|(s, _)| Stream::Unix(s))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix { path, .. } = self {
+            if let Err(err) = std::fs::remove_file(path) {
+                tracing::warn!("Failed to remove unix socket {path:?}: {err}");
+            }
+        }
+    }
+}
+
+/// A socket file left behind by a previous shotover process that was not cleanly shutdown would prevent binding,
+/// so remove it as long as nothing is still listening on it.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(anyhow!(err).context(format!("Failed to inspect {path:?}"))),
+    };
+    if !metadata.file_type().is_socket() {
+        bail!("Cannot listen on {path:?} as it already exists and is not a unix socket");
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        bail!("Cannot listen on {path:?} as another process is already listening on it");
+    }
+    std::fs::remove_file(path)
+        .with_context(|| format!("Failed to remove stale unix socket {path:?}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unix_listener_lifecycle() {
+        let dir = std::env::temp_dir().join(format!("shotover-socket-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.sock");
+        let address = format!("unix:{}", path.display());
+
+        let listener = Listener::bind(&address).await.unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            UNIX_SOCKET_PERMISSIONS
+        );
+
+        // cant bind while another listener is active
+        assert!(Listener::bind(&address).await.is_err());
+
+        let _client = connect_unix(&path).await.unwrap();
+        assert!(matches!(listener.accept().await.unwrap(), Stream::Unix(_)));
+
+        std::mem::drop(listener);
+        assert!(!path.exists());
+
+        // a socket left behind by a crashed process is replaced
+        let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        std::mem::drop(stale);
+        assert!(path.exists());
+        let listener = Listener::bind(&address).await.unwrap();
+        std::mem::drop(listener);
+
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -1,29 +1,31 @@
-use crate::codec::cassandra::CassandraCodec;
+use crate::codec::cassandra::CassandraCodecBuilder;
 use crate::server::TcpCodecListener;
-use crate::sources::Sources;
+use crate::sources::{ChainErrorPolicy, Sources};
 use crate::tls::{TlsAcceptor, TlsAcceptorConfig};
 use crate::transforms::chain::TransformChain;
 use anyhow::Result;
 use serde::Deserialize;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::runtime::Handle;
 use tokio::sync::{watch, Semaphore};
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct CassandraConfig {
     pub listen_addr: String,
     pub connection_limit: Option<usize>,
     pub hard_connection_limit: Option<bool>,
     pub tls: Option<TlsAcceptorConfig>,
+    pub proxy_protocol: Option<bool>,
+    pub chain_error_policy: Option<ChainErrorPolicy>,
     pub timeout: Option<u64>,
 }
 
 impl CassandraConfig {
     pub async fn get_source(
         &self,
-        chain: &TransformChain,
+        chain: Arc<Mutex<TransformChain>>,
         trigger_shutdown_rx: watch::Receiver<bool>,
     ) -> Result<Vec<Sources>> {
         Ok(vec![Sources::Cassandra(
@@ -34,6 +36,8 @@ impl CassandraConfig {
                 self.connection_limit,
                 self.hard_connection_limit,
                 self.tls.clone(),
+                self.proxy_protocol,
+                self.chain_error_policy,
                 self.timeout,
             )
             .await?,
@@ -51,12 +55,14 @@ pub struct CassandraSource {
 impl CassandraSource {
     #![allow(clippy::too_many_arguments)]
     pub async fn new(
-        chain: &TransformChain,
+        chain: Arc<Mutex<TransformChain>>,
         listen_addr: String,
         mut trigger_shutdown_rx: watch::Receiver<bool>,
         connection_limit: Option<usize>,
         hard_connection_limit: Option<bool>,
         tls: Option<TlsAcceptorConfig>,
+        proxy_protocol: Option<bool>,
+        chain_error_policy: Option<ChainErrorPolicy>,
         timeout: Option<u64>,
     ) -> Result<CassandraSource> {
         let name = "CassandraSource";
@@ -64,14 +70,16 @@ impl CassandraSource {
         info!("Starting Cassandra source on [{}]", listen_addr);
 
         let mut listener = TcpCodecListener::new(
-            chain.clone(),
+            chain,
             name.to_string(),
             listen_addr.clone(),
             hard_connection_limit.unwrap_or(false),
-            CassandraCodec::new(),
+            CassandraCodecBuilder::new(),
             Arc::new(Semaphore::new(connection_limit.unwrap_or(512))),
             trigger_shutdown_rx.clone(),
             tls.map(TlsAcceptor::new).transpose()?,
+            proxy_protocol.unwrap_or(false),
+            chain_error_policy.unwrap_or(ChainErrorPolicy::CloseConnection),
             timeout,
         )
         .await?;
@@ -0,0 +1,102 @@
+use crate::codec::kafka::KafkaCodecBuilder;
+use crate::server::TcpCodecListener;
+use crate::sources::{ChainErrorPolicy, Sources};
+use crate::tls::{TlsAcceptor, TlsAcceptorConfig};
+use crate::transforms::chain::TransformChain;
+use anyhow::Result;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct KafkaConfig {
+    pub listen_addr: String,
+    pub connection_limit: Option<usize>,
+    pub hard_connection_limit: Option<bool>,
+    pub tls: Option<TlsAcceptorConfig>,
+    pub timeout: Option<u64>,
+}
+
+impl KafkaConfig {
+    pub async fn get_source(
+        &self,
+        chain: Arc<Mutex<TransformChain>>,
+        trigger_shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<Vec<Sources>> {
+        KafkaSource::new(
+            chain,
+            self.listen_addr.clone(),
+            trigger_shutdown_rx,
+            self.connection_limit,
+            self.hard_connection_limit,
+            self.tls.clone(),
+            self.timeout,
+        )
+        .await
+        .map(|x| vec![Sources::Kafka(x)])
+    }
+}
+
+#[derive(Debug)]
+pub struct KafkaSource {
+    pub name: &'static str,
+    pub join_handle: JoinHandle<()>,
+    pub listen_addr: String,
+}
+
+impl KafkaSource {
+    #![allow(clippy::too_many_arguments)]
+    pub async fn new(
+        chain: Arc<Mutex<TransformChain>>,
+        listen_addr: String,
+        mut trigger_shutdown_rx: watch::Receiver<bool>,
+        connection_limit: Option<usize>,
+        hard_connection_limit: Option<bool>,
+        tls: Option<TlsAcceptorConfig>,
+        timeout: Option<u64>,
+    ) -> Result<KafkaSource> {
+        info!("Starting Kafka source on [{}]", listen_addr);
+        let name = "KafkaSource";
+
+        let mut listener = TcpCodecListener::new(
+            chain,
+            name.to_string(),
+            listen_addr.clone(),
+            hard_connection_limit.unwrap_or(false),
+            KafkaCodecBuilder::new(),
+            Arc::new(Semaphore::new(connection_limit.unwrap_or(512))),
+            trigger_shutdown_rx.clone(),
+            tls.map(TlsAcceptor::new).transpose()?,
+            false,
+            // kafka error responses can not be generated without knowing the response schema of each request
+            ChainErrorPolicy::CloseConnection,
+            timeout,
+        )
+        .await?;
+
+        let join_handle = Handle::current().spawn(async move {
+            // Check we didn't receive a shutdown signal before the receiver was created
+            if !*trigger_shutdown_rx.borrow() {
+                tokio::select! {
+                    res = listener.run() => {
+                        if let Err(err) = res {
+                            error!(cause = %err, "failed to accept connection");
+                        }
+                    }
+                    _ = trigger_shutdown_rx.changed() => {
+                        listener.shutdown().await;
+                    }
+                }
+            }
+        });
+
+        Ok(KafkaSource {
+            name,
+            join_handle,
+            listen_addr,
+        })
+    }
+}
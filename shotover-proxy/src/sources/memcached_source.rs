@@ -0,0 +1,104 @@
+use crate::codec::memcached::MemcachedCodecBuilder;
+use crate::server::TcpCodecListener;
+use crate::sources::{ChainErrorPolicy, Sources};
+use crate::tls::{TlsAcceptor, TlsAcceptorConfig};
+use crate::transforms::chain::TransformChain;
+use anyhow::Result;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MemcachedConfig {
+    pub listen_addr: String,
+    pub connection_limit: Option<usize>,
+    pub hard_connection_limit: Option<bool>,
+    pub tls: Option<TlsAcceptorConfig>,
+    pub chain_error_policy: Option<ChainErrorPolicy>,
+    pub timeout: Option<u64>,
+}
+
+impl MemcachedConfig {
+    pub async fn get_source(
+        &self,
+        chain: Arc<Mutex<TransformChain>>,
+        trigger_shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<Vec<Sources>> {
+        MemcachedSource::new(
+            chain,
+            self.listen_addr.clone(),
+            trigger_shutdown_rx,
+            self.connection_limit,
+            self.hard_connection_limit,
+            self.tls.clone(),
+            self.chain_error_policy,
+            self.timeout,
+        )
+        .await
+        .map(|x| vec![Sources::Memcached(x)])
+    }
+}
+
+#[derive(Debug)]
+pub struct MemcachedSource {
+    pub name: &'static str,
+    pub join_handle: JoinHandle<()>,
+    pub listen_addr: String,
+}
+
+impl MemcachedSource {
+    #![allow(clippy::too_many_arguments)]
+    pub async fn new(
+        chain: Arc<Mutex<TransformChain>>,
+        listen_addr: String,
+        mut trigger_shutdown_rx: watch::Receiver<bool>,
+        connection_limit: Option<usize>,
+        hard_connection_limit: Option<bool>,
+        tls: Option<TlsAcceptorConfig>,
+        chain_error_policy: Option<ChainErrorPolicy>,
+        timeout: Option<u64>,
+    ) -> Result<MemcachedSource> {
+        info!("Starting Memcached source on [{}]", listen_addr);
+        let name = "MemcachedSource";
+
+        let mut listener = TcpCodecListener::new(
+            chain,
+            name.to_string(),
+            listen_addr.clone(),
+            hard_connection_limit.unwrap_or(false),
+            MemcachedCodecBuilder::new(),
+            Arc::new(Semaphore::new(connection_limit.unwrap_or(512))),
+            trigger_shutdown_rx.clone(),
+            tls.map(TlsAcceptor::new).transpose()?,
+            false,
+            chain_error_policy.unwrap_or(ChainErrorPolicy::CloseConnection),
+            timeout,
+        )
+        .await?;
+
+        let join_handle = Handle::current().spawn(async move {
+            // Check we didn't receive a shutdown signal before the receiver was created
+            if !*trigger_shutdown_rx.borrow() {
+                tokio::select! {
+                    res = listener.run() => {
+                        if let Err(err) = res {
+                            error!(cause = %err, "failed to accept connection");
+                        }
+                    }
+                    _ = trigger_shutdown_rx.changed() => {
+                        listener.shutdown().await;
+                    }
+                }
+            }
+        });
+
+        Ok(MemcachedSource {
+            name,
+            join_handle,
+            listen_addr,
+        })
+    }
+}
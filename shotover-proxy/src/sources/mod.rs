@@ -1,18 +1,28 @@
 use crate::sources::cassandra_source::{CassandraConfig, CassandraSource};
+use crate::sources::kafka_source::{KafkaConfig, KafkaSource};
+use crate::sources::memcached_source::{MemcachedConfig, MemcachedSource};
+use crate::sources::postgres_source::{PostgresConfig, PostgresSource};
 use crate::sources::redis_source::{RedisConfig, RedisSource};
 use crate::transforms::chain::TransformChain;
 use anyhow::Result;
 use serde::Deserialize;
+use std::sync::{Arc, Mutex};
 use tokio::sync::watch;
 use tokio::task::JoinHandle;
 
 pub mod cassandra_source;
+pub mod kafka_source;
+pub mod memcached_source;
+pub mod postgres_source;
 pub mod redis_source;
 
 #[derive(Debug)]
 pub enum Sources {
     Cassandra(CassandraSource),
     Redis(RedisSource),
+    Memcached(MemcachedSource),
+    Kafka(KafkaSource),
+    Postgres(PostgresSource),
 }
 
 impl Sources {
@@ -20,25 +30,43 @@ impl Sources {
         match self {
             Sources::Cassandra(c) => c.join_handle,
             Sources::Redis(r) => r.join_handle,
+            Sources::Memcached(m) => m.join_handle,
+            Sources::Kafka(k) => k.join_handle,
+            Sources::Postgres(p) => p.join_handle,
         }
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// How a source handles its chain failing to produce responses for a batch of requests.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ChainErrorPolicy {
+    /// Close the client connection, dropping any other requests in flight on it.
+    CloseConnection,
+    /// Respond to each request in the batch with a protocol level error and keep the connection open.
+    RespondWithError,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub enum SourcesConfig {
     Cassandra(CassandraConfig),
     Redis(RedisConfig),
+    Memcached(MemcachedConfig),
+    Kafka(KafkaConfig),
+    Postgres(PostgresConfig),
 }
 
 impl SourcesConfig {
     pub(crate) async fn get_source(
         &self,
-        chain: &TransformChain,
+        chain: Arc<Mutex<TransformChain>>,
         trigger_shutdown_rx: watch::Receiver<bool>,
     ) -> Result<Vec<Sources>> {
         match self {
             SourcesConfig::Cassandra(c) => c.get_source(chain, trigger_shutdown_rx).await,
             SourcesConfig::Redis(r) => r.get_source(chain, trigger_shutdown_rx).await,
+            SourcesConfig::Memcached(m) => m.get_source(chain, trigger_shutdown_rx).await,
+            SourcesConfig::Kafka(k) => k.get_source(chain, trigger_shutdown_rx).await,
+            SourcesConfig::Postgres(p) => p.get_source(chain, trigger_shutdown_rx).await,
         }
     }
 }
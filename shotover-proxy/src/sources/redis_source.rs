@@ -1,29 +1,31 @@
-use crate::codec::redis::RedisCodec;
+use crate::codec::redis::RedisCodecBuilder;
 use crate::server::TcpCodecListener;
-use crate::sources::Sources;
+use crate::sources::{ChainErrorPolicy, Sources};
 use crate::tls::{TlsAcceptor, TlsAcceptorConfig};
 use crate::transforms::chain::TransformChain;
 use anyhow::Result;
 use serde::Deserialize;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::runtime::Handle;
 use tokio::sync::{watch, Semaphore};
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct RedisConfig {
     pub listen_addr: String,
     pub connection_limit: Option<usize>,
     pub hard_connection_limit: Option<bool>,
     pub tls: Option<TlsAcceptorConfig>,
+    pub proxy_protocol: Option<bool>,
+    pub chain_error_policy: Option<ChainErrorPolicy>,
     pub timeout: Option<u64>,
 }
 
 impl RedisConfig {
     pub async fn get_source(
         &self,
-        chain: &TransformChain,
+        chain: Arc<Mutex<TransformChain>>,
         trigger_shutdown_rx: watch::Receiver<bool>,
     ) -> Result<Vec<Sources>> {
         RedisSource::new(
@@ -33,6 +35,8 @@ impl RedisConfig {
             self.connection_limit,
             self.hard_connection_limit,
             self.tls.clone(),
+            self.proxy_protocol,
+            self.chain_error_policy,
             self.timeout,
         )
         .await
@@ -50,26 +54,30 @@ pub struct RedisSource {
 impl RedisSource {
     #![allow(clippy::too_many_arguments)]
     pub async fn new(
-        chain: &TransformChain,
+        chain: Arc<Mutex<TransformChain>>,
         listen_addr: String,
         mut trigger_shutdown_rx: watch::Receiver<bool>,
         connection_limit: Option<usize>,
         hard_connection_limit: Option<bool>,
         tls: Option<TlsAcceptorConfig>,
+        proxy_protocol: Option<bool>,
+        chain_error_policy: Option<ChainErrorPolicy>,
         timeout: Option<u64>,
     ) -> Result<RedisSource> {
         info!("Starting Redis source on [{}]", listen_addr);
         let name = "RedisSource";
 
         let mut listener = TcpCodecListener::new(
-            chain.clone(),
+            chain,
             name.to_string(),
             listen_addr.clone(),
             hard_connection_limit.unwrap_or(false),
-            RedisCodec::new(),
+            RedisCodecBuilder::new(),
             Arc::new(Semaphore::new(connection_limit.unwrap_or(512))),
             trigger_shutdown_rx.clone(),
             tls.map(TlsAcceptor::new).transpose()?,
+            proxy_protocol.unwrap_or(false),
+            chain_error_policy.unwrap_or(ChainErrorPolicy::CloseConnection),
             timeout,
         )
         .await?;
@@ -5,10 +5,9 @@ use serde::{Deserialize, Serialize};
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpStream;
 use tokio_openssl::SslStream;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TlsAcceptorConfig {
     /// Path to the certificate authority in PEM format
     pub certificate_authority_path: String,
@@ -36,9 +35,12 @@ impl TlsAcceptor {
         })
     }
 
-    pub async fn accept(&self, tcp_stream: TcpStream) -> Result<SslStream<TcpStream>> {
+    pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+    ) -> Result<SslStream<S>> {
         let ssl = Ssl::new(self.acceptor.context())?;
-        let mut ssl_stream = SslStream::new(ssl, tcp_stream)?;
+        let mut ssl_stream = SslStream::new(ssl, stream)?;
 
         Pin::new(&mut ssl_stream)
             .accept()
@@ -81,26 +83,29 @@ impl TlsConnector {
         })
     }
 
-    pub async fn connect_unverified_hostname(
+    pub async fn connect_unverified_hostname<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
-        tcp_stream: TcpStream,
-    ) -> Result<SslStream<TcpStream>> {
+        stream: S,
+    ) -> Result<SslStream<S>> {
         let ssl = self
             .connector
             .configure()?
             .verify_hostname(false)
             .into_ssl("localhost")?;
 
-        let mut ssl_stream = SslStream::new(ssl, tcp_stream)?;
+        let mut ssl_stream = SslStream::new(ssl, stream)?;
         Pin::new(&mut ssl_stream).connect().await?;
 
         Ok(ssl_stream)
     }
 
-    pub async fn connect(&self, tcp_stream: TcpStream) -> Result<SslStream<TcpStream>> {
+    pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+    ) -> Result<SslStream<S>> {
         let ssl = self.connector.configure()?.into_ssl("localhost")?;
 
-        let mut ssl_stream = SslStream::new(ssl, tcp_stream)?;
+        let mut ssl_stream = SslStream::new(ssl, stream)?;
         Pin::new(&mut ssl_stream).connect().await?;
 
         Ok(ssl_stream)
@@ -111,6 +116,5 @@ impl TlsConnector {
 /// So we need to use this trait when creating trait objects that need both AsyncRead and AsyncWrite
 pub trait AsyncStream: AsyncRead + AsyncWrite {}
 
-/// We need to tell rust that these types implement AsyncStream even though they already implement AsyncRead and AsyncWrite
-impl AsyncStream for tokio_openssl::SslStream<TcpStream> {}
-impl AsyncStream for TcpStream {}
+/// Any type that implements both AsyncRead and AsyncWrite, such as a tcp stream, a unix stream or a tls stream wrapping either, is an AsyncStream
+impl<T: AsyncRead + AsyncWrite> AsyncStream for T {}
@@ -0,0 +1,546 @@
+use crate::error::ChainResponse;
+use crate::frame::{CassandraFrame, CassandraOperation, Frame};
+use crate::message::{Message, Metadata};
+use crate::transforms::cassandra::{respond_or_forward, Handling};
+use crate::transforms::{Transform, Transforms, Wrapper};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use cassandra_protocol::frame::Version;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const PASSWORD_AUTHENTICATOR: &str = "org.apache.cassandra.auth.PasswordAuthenticator";
+const UNAUTHENTICATED_REQUEST: &str =
+    "Shotover requires the client to authenticate before sending requests";
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CassandraAuthenticateConfig {
+    /// Where the users that clients authenticate as are read from.
+    pub credentials: CredentialsSource,
+    /// The credentials that shotover authenticates to cassandra with, keyed by the role of the authenticated user.
+    pub service_credentials: HashMap<String, ServiceCredentials>,
+}
+
+/// Both sources contain an entry of the form `username:bcrypt_hash:role` for each user, separated by newlines or commas.
+#[derive(Deserialize, Debug, Clone)]
+pub enum CredentialsSource {
+    /// The path of a file containing the entries.
+    File(String),
+    /// The name of an environment variable containing the entries.
+    Env(String),
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ServiceCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for ServiceCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServiceCredentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CassandraAuthenticateConfig {
+    pub async fn get_transform(&self) -> Result<Transforms> {
+        let credentials = match &self.credentials {
+            CredentialsSource::File(path) => std::fs::read_to_string(path).map_err(|e| {
+                anyhow!(e).context(format!("Failed to read credentials file {path}"))
+            })?,
+            CredentialsSource::Env(variable) => std::env::var(variable).map_err(|e| {
+                anyhow!(e).context(format!("Failed to read credentials from ${variable}"))
+            })?,
+        };
+        let users = parse_users(&credentials)?;
+
+        for (username, user) in &users {
+            if !self.service_credentials.contains_key(&user.role) {
+                return Err(anyhow!(
+                    "the role {} of user {username} has no service_credentials",
+                    user.role
+                ));
+            }
+        }
+
+        let dummy_password_hash = bcrypt::hash("", dummy_hash_cost(&users))?;
+
+        Ok(Transforms::CassandraAuthenticate(CassandraAuthenticate {
+            users: Arc::new(users),
+            dummy_password_hash: Arc::new(dummy_password_hash),
+            service_credentials: Arc::new(self.service_credentials.clone()),
+            upstream_auth_disabled: false,
+            authenticated_user: None,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct User {
+    password_hash: String,
+    role: String,
+}
+
+fn parse_users(credentials: &str) -> Result<HashMap<String, User>> {
+    credentials
+        .split(|c| c == '\n' || c == ',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty() && !entry.starts_with('#'))
+        .enumerate()
+        .map(|(i, entry)| {
+            let mut parts = entry.splitn(3, ':');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(username), Some(password_hash), Some(role)) => Ok((
+                    username.to_owned(),
+                    User {
+                        password_hash: password_hash.to_owned(),
+                        role: role.to_owned(),
+                    },
+                )),
+                // The entry itself is not included in the error as it contains a password hash
+                _ => Err(anyhow!(
+                    "credentials entry {} is not of the form username:bcrypt_hash:role",
+                    i + 1
+                )),
+            }
+        })
+        .collect()
+}
+
+/// The cost of the hash that unknown users are verified against, the highest cost of any user so that verifying takes at least as long.
+fn dummy_hash_cost(users: &HashMap<String, User>) -> u32 {
+    users
+        .values()
+        .filter_map(|user| user.password_hash.split('$').nth(2)?.parse().ok())
+        .max()
+        .unwrap_or(bcrypt::DEFAULT_COST)
+}
+
+/// Terminates PasswordAuthenticator authentication of the client against its own set of users,
+/// so that clients do not need cassandra credentials.
+/// The client's AUTH_RESPONSE is rewritten to the service credentials of the user's role before it is sent on to the sink,
+/// which uses it to authenticate every connection it makes to cassandra.
+///
+/// The authenticated user is recorded in [`Wrapper::authenticated_user`] for the transforms that follow.
+#[derive(Clone)]
+pub struct CassandraAuthenticate {
+    /// Keyed by username
+    users: Arc<HashMap<String, User>>,
+    /// Verified against when the user does not exist, so that the time taken to respond does not reveal which users exist.
+    dummy_password_hash: Arc<String>,
+    service_credentials: Arc<HashMap<String, ServiceCredentials>>,
+    /// Set when cassandra responds to STARTUP with READY as it does not require authentication,
+    /// the client is still required to authenticate with shotover.
+    upstream_auth_disabled: bool,
+    authenticated_user: Option<String>,
+}
+
+/// How the response to a forwarded request is processed
+enum Action {
+    /// The response from the next transform is returned unchanged
+    Forward,
+    /// A STARTUP, a READY response is replaced with AUTHENTICATE
+    ForwardStartup,
+    /// An AUTH_RESPONSE rewritten to the service credentials of `username`'s role
+    ForwardAuthResponse { username: String },
+}
+
+impl CassandraAuthenticate {
+    async fn handle(&mut self, mut message: Message) -> Result<Handling<Action>> {
+        let (version, stream_id, auth_response) = match message.frame() {
+            Some(Frame::Cassandra(frame)) => match &frame.operation {
+                CassandraOperation::Startup(_) => {
+                    return Ok(Handling::Forward(message, Action::ForwardStartup))
+                }
+                CassandraOperation::Options(_) => {
+                    return Ok(Handling::Forward(message, Action::Forward))
+                }
+                CassandraOperation::AuthResponse(body) => {
+                    (frame.version, frame.stream_id, parse_plain_token(body))
+                }
+                _ if self.authenticated_user.is_none() => {
+                    let error = error(ErrorType::Unauthorized, UNAUTHENTICATED_REQUEST);
+                    return Ok(Handling::Respond(response(
+                        frame.version,
+                        frame.stream_id,
+                        error,
+                    )));
+                }
+                _ => return Ok(Handling::Forward(message, Action::Forward)),
+            },
+            // A request that could not be parsed can not be shown to be part of the handshake, so it is rejected
+            _ if self.authenticated_user.is_none() => {
+                return match message.metadata() {
+                    Ok(Metadata::Cassandra(metadata)) => {
+                        let error = error(ErrorType::Unauthorized, UNAUTHENTICATED_REQUEST);
+                        Ok(Handling::Respond(response(
+                            metadata.version,
+                            metadata.stream_id,
+                            error,
+                        )))
+                    }
+                    _ => Err(anyhow!(
+                        "received a request that could not be parsed before the client authenticated"
+                    )),
+                };
+            }
+            _ => return Ok(Handling::Forward(message, Action::Forward)),
+        };
+
+        let role = match auth_response {
+            Some((username, password)) => self
+                .verify(&username, password)
+                .await
+                .map(|role| (username, role)),
+            None => None,
+        };
+        let (username, role) = match role {
+            Some(role) => role,
+            None => {
+                return Ok(Handling::Respond(response(
+                    version,
+                    stream_id,
+                    error(
+                        ErrorType::Authentication,
+                        "Provided username and/or password are incorrect",
+                    ),
+                )))
+            }
+        };
+
+        if self.upstream_auth_disabled {
+            tracing::info!("client authenticated as {username}");
+            self.authenticated_user = Some(username);
+            return Ok(Handling::Respond(response(
+                version,
+                stream_id,
+                CassandraOperation::AuthSuccess((-1i32).to_be_bytes().to_vec()),
+            )));
+        }
+
+        let service_credentials = &self.service_credentials[&role];
+        if let Some(Frame::Cassandra(frame)) = message.frame() {
+            frame.operation = CassandraOperation::AuthResponse(plain_token(
+                &service_credentials.username,
+                &service_credentials.password,
+            ));
+        }
+        message.invalidate_cache();
+        Ok(Handling::Forward(
+            message,
+            Action::ForwardAuthResponse { username },
+        ))
+    }
+
+    /// Returns the role of the user when the password is correct
+    async fn verify(&self, username: &str, password: String) -> Option<String> {
+        let (password_hash, role) = match self.users.get(username) {
+            Some(user) => (user.password_hash.clone(), Some(user.role.clone())),
+            None => (self.dummy_password_hash.as_ref().clone(), None),
+        };
+        // bcrypt is deliberately slow so it must not block the runtime
+        let valid = tokio::task::spawn_blocking(move || bcrypt::verify(password, &password_hash))
+            .await
+            .ok()?
+            .ok()?;
+        role.filter(|_| valid)
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraAuthenticate {
+    async fn transform<'a>(&'a mut self, mut message_wrapper: Wrapper<'a>) -> ChainResponse {
+        let mut requests = Vec::with_capacity(message_wrapper.messages.len());
+        for message in std::mem::take(&mut message_wrapper.messages) {
+            requests.push(self.handle(message).await?);
+        }
+        message_wrapper.authenticated_user = self.authenticated_user.clone();
+
+        respond_or_forward(&mut message_wrapper, requests, |action, mut received| {
+            Ok(match action {
+                Action::Forward => received,
+                Action::ForwardStartup => {
+                    let ready = match received.frame() {
+                        Some(Frame::Cassandra(CassandraFrame {
+                            operation: CassandraOperation::Ready(_),
+                            version,
+                            stream_id,
+                            ..
+                        })) => Some((*version, *stream_id)),
+                        _ => None,
+                    };
+                    match ready {
+                        Some((version, stream_id)) => {
+                            self.upstream_auth_disabled = true;
+                            response(
+                                version,
+                                stream_id,
+                                CassandraOperation::Authenticate(authenticate_body()),
+                            )
+                        }
+                        None => received,
+                    }
+                }
+                Action::ForwardAuthResponse { username } => {
+                    if let Some(Frame::Cassandra(CassandraFrame {
+                        operation: CassandraOperation::AuthSuccess(_),
+                        ..
+                    })) = received.frame()
+                    {
+                        tracing::info!("client authenticated as {username}");
+                        self.authenticated_user = Some(username);
+                    }
+                    received
+                }
+            })
+        })
+        .await
+    }
+}
+
+fn response(version: Version, stream_id: i16, operation: CassandraOperation) -> Message {
+    Message::from_frame(Frame::Cassandra(CassandraFrame {
+        version,
+        stream_id,
+        tracing_id: None,
+        warnings: vec![],
+        operation,
+    }))
+}
+
+fn error(ty: ErrorType, message: &str) -> CassandraOperation {
+    CassandraOperation::Error(ErrorBody {
+        message: message.into(),
+        ty,
+    })
+}
+
+/// The body of an AUTHENTICATE response is the `[string]` class name of the authenticator
+fn authenticate_body() -> Vec<u8> {
+    let mut body = (PASSWORD_AUTHENTICATOR.len() as u16).to_be_bytes().to_vec();
+    body.extend(PASSWORD_AUTHENTICATOR.as_bytes());
+    body
+}
+
+/// The body of an AUTH_RESPONSE is a `[bytes]` SASL token, which is `\0username\0password` for the PLAIN mechanism
+fn parse_plain_token(body: &[u8]) -> Option<(String, String)> {
+    let len = i32::from_be_bytes(body.get(0..4)?.try_into().ok()?);
+    let token = body.get(4..4 + usize::try_from(len).ok()?)?;
+
+    let mut parts = token.split(|b| *b == 0);
+    let _authorization_id = parts.next()?;
+    let username = String::from_utf8(parts.next()?.to_vec()).ok()?;
+    let password = String::from_utf8(parts.next()?.to_vec()).ok()?;
+    match parts.next() {
+        Some(_) => None,
+        None => Some((username, password)),
+    }
+}
+
+fn plain_token(username: &str, password: &str) -> Vec<u8> {
+    let len = 2 + username.len() + password.len();
+    let mut body = Vec::with_capacity(4 + len);
+    body.extend((len as i32).to_be_bytes());
+    body.push(0);
+    body.extend(username.as_bytes());
+    body.push(0);
+    body.extend(password.as_bytes());
+    body
+}
+
+#[cfg(test)]
+mod test_authenticate {
+    use super::*;
+    use crate::frame::cassandra::parse_statement_single;
+    use crate::frame::MessageType;
+    use crate::transforms::loopback::Loopback;
+    use bytes::Bytes;
+    use cassandra_protocol::query::QueryParams;
+
+    fn authenticate() -> CassandraAuthenticate {
+        let mut users = HashMap::new();
+        users.insert(
+            "alice".to_owned(),
+            User {
+                password_hash: bcrypt::hash("secret", 4).unwrap(),
+                role: "analytics".to_owned(),
+            },
+        );
+        let mut service_credentials = HashMap::new();
+        service_credentials.insert(
+            "analytics".to_owned(),
+            ServiceCredentials {
+                username: "analytics_service".to_owned(),
+                password: "service_secret".to_owned(),
+            },
+        );
+        CassandraAuthenticate {
+            users: Arc::new(users),
+            dummy_password_hash: Arc::new(bcrypt::hash("", 4).unwrap()),
+            service_credentials: Arc::new(service_credentials),
+            upstream_auth_disabled: false,
+            authenticated_user: None,
+        }
+    }
+
+    fn request(operation: CassandraOperation) -> Message {
+        response(Version::V4, 1, operation)
+    }
+
+    async fn transform(authenticate: &mut CassandraAuthenticate, message: Message) -> Message {
+        let mut chain = vec![Transforms::Loopback(Loopback::default())];
+        let mut message_wrapper = Wrapper::new(vec![message]);
+        message_wrapper.reset(&mut chain);
+        authenticate
+            .transform(message_wrapper)
+            .await
+            .unwrap()
+            .pop()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_users() {
+        let users =
+            parse_users("alice:$2b$04$abc:analytics\n# comment\n, bob:$2b$04$def:app\n").unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(
+            users["bob"],
+            User {
+                password_hash: "$2b$04$def".to_owned(),
+                role: "app".to_owned()
+            }
+        );
+
+        assert!(parse_users("alice:$2b$04$abc").is_err());
+
+        assert_eq!(dummy_hash_cost(&users), 4);
+        assert_eq!(dummy_hash_cost(&HashMap::new()), bcrypt::DEFAULT_COST);
+    }
+
+    #[test]
+    fn test_plain_token() {
+        assert_eq!(
+            parse_plain_token(&plain_token("user", "pass")),
+            Some(("user".to_owned(), "pass".to_owned()))
+        );
+        assert_eq!(parse_plain_token(&[0, 0, 0, 9, 0]), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_authentication() {
+        let mut authenticate = authenticate();
+
+        // requests are rejected until the client authenticates
+        let query = request(CassandraOperation::Query {
+            query: Box::new(parse_statement_single("SELECT * FROM ks.table")),
+            params: Box::new(QueryParams::default()),
+        });
+        let mut response = transform(&mut authenticate, query).await;
+        assert!(matches!(
+            response.frame(),
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Error(ErrorBody {
+                    ty: ErrorType::Unauthorized,
+                    ..
+                }),
+                ..
+            }))
+        ));
+
+        let mut response = transform(
+            &mut authenticate,
+            request(CassandraOperation::AuthResponse(plain_token(
+                "alice", "wrong",
+            ))),
+        )
+        .await;
+        assert!(matches!(
+            response.frame(),
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Error(ErrorBody {
+                    ty: ErrorType::Authentication,
+                    ..
+                }),
+                ..
+            }))
+        ));
+
+        // an unknown user is rejected the same way as a wrong password
+        let mut response = transform(
+            &mut authenticate,
+            request(CassandraOperation::AuthResponse(plain_token(
+                "mallory", "secret",
+            ))),
+        )
+        .await;
+        assert!(matches!(
+            response.frame(),
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Error(ErrorBody {
+                    ty: ErrorType::Authentication,
+                    ..
+                }),
+                ..
+            }))
+        ));
+
+        // the loopback returns the rewritten AUTH_RESPONSE, which contains the service credentials
+        let mut response = transform(
+            &mut authenticate,
+            request(CassandraOperation::AuthResponse(plain_token(
+                "alice", "secret",
+            ))),
+        )
+        .await;
+        assert_eq!(
+            response.frame(),
+            Some(&mut Frame::Cassandra(CassandraFrame {
+                version: Version::V4,
+                stream_id: 1,
+                tracing_id: None,
+                warnings: vec![],
+                operation: CassandraOperation::AuthResponse(plain_token(
+                    "analytics_service",
+                    "service_secret"
+                )),
+            }))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_unparseable_request_rejected() {
+        let mut authenticate = authenticate();
+
+        // A v4 QUERY whose body is not a valid query
+        let query = Message::from_bytes(
+            Bytes::from_static(&[
+                0x04, 0x00, 0x00, 0x05, 0x07, 0x00, 0x00, 0x00, 0x02, 0xFF, 0xFF,
+            ]),
+            MessageType::Cassandra,
+        );
+        let mut response = transform(&mut authenticate, query).await;
+        assert!(matches!(
+            response.frame(),
+            Some(Frame::Cassandra(CassandraFrame {
+                stream_id: 5,
+                operation: CassandraOperation::Error(ErrorBody {
+                    ty: ErrorType::Unauthorized,
+                    ..
+                }),
+                ..
+            }))
+        ));
+
+        // Without even a valid header there is no way to respond so the chain fails
+        let garbage = Message::from_bytes(Bytes::from_static(&[0xFF]), MessageType::Cassandra);
+        let mut chain = vec![Transforms::Loopback(Loopback::default())];
+        let mut message_wrapper = Wrapper::new(vec![garbage]);
+        message_wrapper.reset(&mut chain);
+        assert!(authenticate.transform(message_wrapper).await.is_err());
+    }
+}
@@ -0,0 +1,600 @@
+use crate::error::ChainResponse;
+use crate::frame::cassandra::{parse_statement_single, prepare_query, BatchStatementType};
+use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
+use crate::message::Message;
+use crate::transforms::cassandra::{respond_or_forward, Handling};
+use crate::transforms::{Transform, Transforms, Wrapper};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType, UnpreparedError};
+use cassandra_protocol::types::CBytesShort;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use cql3_parser::common::{FQName, Identifier};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CassandraAuthorizeConfig {
+    /// A statement is only sent on when at least one of the permissions grants it.
+    pub permissions: Vec<PermissionConfig>,
+}
+
+/// Grants the listed statement kinds to the clients matching every field that is set.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PermissionConfig {
+    /// Matched against the user the client authenticated as. Defaults to any user.
+    pub users: Option<Vec<String>>,
+    /// Matched against the client's ip address. Defaults to any address.
+    pub client_ips: Option<Vec<IpAddr>>,
+    /// The keyspaces the statement may act on. Defaults to any keyspace.
+    pub keyspaces: Option<Vec<String>>,
+    /// The tables, in the form `keyspace.table`, the statement may act on. Defaults to any table.
+    pub tables: Option<Vec<String>>,
+    pub statements: Vec<StatementKind>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum StatementKind {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    Truncate,
+    Use,
+    /// Creating, altering or dropping keyspaces, tables, types, functions and so on
+    Schema,
+    /// Managing roles, users and permissions
+    Auth,
+    /// Any statement that shotover could not parse
+    Other,
+}
+
+impl CassandraAuthorizeConfig {
+    pub async fn get_transform(&self) -> Result<Transforms> {
+        let permissions = self
+            .permissions
+            .iter()
+            .map(Permission::new)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Transforms::CassandraAuthorize(CassandraAuthorize {
+            permissions: Arc::new(permissions),
+            keyspace: None,
+            prepared: HashMap::new(),
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Permission {
+    users: Option<Vec<String>>,
+    client_ips: Option<Vec<IpAddr>>,
+    keyspaces: Option<Vec<Identifier>>,
+    tables: Option<Vec<FQName>>,
+    statements: Vec<StatementKind>,
+}
+
+impl Permission {
+    fn new(config: &PermissionConfig) -> Result<Self> {
+        let tables = match &config.tables {
+            Some(tables) => Some(
+                tables
+                    .iter()
+                    .map(|table| {
+                        let name = FQName::parse(table);
+                        match name.keyspace {
+                            Some(_) => Ok(name),
+                            None => {
+                                Err(anyhow!("table {table} must be of the form keyspace.table"))
+                            }
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+
+        Ok(Permission {
+            users: config.users.clone(),
+            client_ips: config.client_ips.clone(),
+            keyspaces: config
+                .keyspaces
+                .as_ref()
+                .map(|keyspaces| keyspaces.iter().map(|k| Identifier::parse(k)).collect()),
+            tables,
+            statements: config.statements.clone(),
+        })
+    }
+
+    fn grants(&self, client: &Client, kind: StatementKind, resource: &Resource) -> bool {
+        self.statements.contains(&kind)
+            && match &self.users {
+                Some(users) => client
+                    .user
+                    .map_or(false, |user| users.iter().any(|u| u == user)),
+                None => true,
+            }
+            && match &self.client_ips {
+                Some(client_ips) => client.ip.map_or(false, |ip| client_ips.contains(&ip)),
+                None => true,
+            }
+            && match &self.keyspaces {
+                Some(keyspaces) => resource
+                    .keyspace
+                    .as_ref()
+                    .map_or(false, |keyspace| keyspaces.contains(keyspace)),
+                None => true,
+            }
+            && match &self.tables {
+                Some(tables) => resource
+                    .table
+                    .as_ref()
+                    .map_or(false, |table| tables.contains(table)),
+                None => true,
+            }
+    }
+}
+
+struct Client<'a> {
+    user: Option<&'a str>,
+    ip: Option<IpAddr>,
+}
+
+/// The keyspace and table a statement acts on
+struct Resource {
+    keyspace: Option<Identifier>,
+    table: Option<FQName>,
+}
+
+/// Rejects QUERY, PREPARE, EXECUTE and BATCH requests containing statements that are not granted by the configured permissions.
+#[derive(Debug, Clone)]
+pub struct CassandraAuthorize {
+    permissions: Arc<Vec<Permission>>,
+    /// The keyspace set by the last USE statement, used to resolve unqualified names in PREPARE requests
+    keyspace: Option<Identifier>,
+    /// The statements prepared on this connection, EXECUTE and BATCH only contain the id of a prepared statement.
+    prepared: HashMap<CBytesShort, CassandraStatement>,
+}
+
+impl CassandraAuthorize {
+    /// A forwarded PREPARE carries its statement, which is recorded against the id in the response.
+    fn handle(
+        &mut self,
+        mut message: Message,
+        client: &Client,
+    ) -> Handling<Option<CassandraStatement>> {
+        let frame = match message.frame() {
+            Some(Frame::Cassandra(frame)) => frame,
+            _ => return Handling::Forward(message, None),
+        };
+
+        let result = match &frame.operation {
+            CassandraOperation::Query { query, .. } => self.authorize(client, query),
+            CassandraOperation::Prepare(body) => {
+                let statement = parse_prepare_body(body);
+                match self.authorize(client, &statement) {
+                    Ok(()) => return Handling::Forward(message, Some(statement)),
+                    Err(err) => Err(err),
+                }
+            }
+            CassandraOperation::Execute(execute) => self.authorize_prepared(client, &execute.id),
+            CassandraOperation::Batch(batch) => {
+                batch
+                    .queries()
+                    .iter()
+                    .try_for_each(|query| match query.ty() {
+                        BatchStatementType::Statement(statement) => {
+                            self.authorize(client, statement)
+                        }
+                        BatchStatementType::PreparedId(id) => self.authorize_prepared(client, id),
+                    })
+            }
+            _ => Ok(()),
+        };
+
+        match result {
+            Ok(()) => {
+                if let CassandraOperation::Query { query, .. } = &frame.operation {
+                    if let CassandraStatement::Use(keyspace) = query.as_ref() {
+                        self.keyspace = Some(keyspace.clone());
+                    }
+                }
+                Handling::Forward(message, None)
+            }
+            Err(err) => Handling::Respond(Message::from_frame(Frame::Cassandra(CassandraFrame {
+                version: frame.version,
+                stream_id: frame.stream_id,
+                tracing_id: None,
+                warnings: vec![],
+                operation: CassandraOperation::Error(err),
+            }))),
+        }
+    }
+
+    fn authorize_prepared(&self, client: &Client, id: &CBytesShort) -> Result<(), ErrorBody> {
+        match self.prepared.get(id) {
+            Some(statement) => self.authorize(client, statement),
+            // The client will prepare the statement again, allowing us to inspect it
+            None => Err(ErrorBody {
+                message: "Statement was not prepared on this connection".into(),
+                ty: ErrorType::Unprepared(UnpreparedError { id: id.clone() }),
+            }),
+        }
+    }
+
+    fn authorize(&self, client: &Client, statement: &CassandraStatement) -> Result<(), ErrorBody> {
+        let kind = statement_kind(statement);
+        let resource = resource(statement, self.keyspace.as_ref());
+        if self
+            .permissions
+            .iter()
+            .any(|permission| permission.grants(client, kind, &resource))
+        {
+            return Ok(());
+        }
+
+        let client = match (client.user, client.ip) {
+            (Some(user), _) => format!("User {user}"),
+            (None, Some(ip)) => format!("Client {ip}"),
+            (None, None) => "Client".to_owned(),
+        };
+        let resource = match (resource.table, resource.keyspace) {
+            (Some(table), _) => format!(" on table {table}"),
+            (None, Some(keyspace)) => format!(" on keyspace {keyspace}"),
+            (None, None) => String::new(),
+        };
+        Err(ErrorBody {
+            message: format!("{client} has no {kind:?} permission{resource}"),
+            ty: ErrorType::Unauthorized,
+        })
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraAuthorize {
+    async fn transform<'a>(&'a mut self, mut message_wrapper: Wrapper<'a>) -> ChainResponse {
+        let user = message_wrapper.authenticated_user.clone();
+        let client = Client {
+            user: user.as_deref(),
+            ip: client_ip(&message_wrapper.client_details),
+        };
+
+        let requests = std::mem::take(&mut message_wrapper.messages)
+            .into_iter()
+            .map(|message| self.handle(message, &client))
+            .collect();
+
+        respond_or_forward(&mut message_wrapper, requests, |statement, mut response| {
+            if let Some(statement) = statement {
+                if let Some(Frame::Cassandra(CassandraFrame {
+                    operation: CassandraOperation::Result(CassandraResult::Prepared(prepared)),
+                    ..
+                })) = response.frame()
+                {
+                    self.prepared.insert(prepared.id.clone(), statement);
+                }
+            }
+            Ok(response)
+        })
+        .await
+    }
+}
+
+/// Depending on the source, `client_details` is either the client's ip address or its socket address.
+fn client_ip(client_details: &str) -> Option<IpAddr> {
+    client_details
+        .parse::<SocketAddr>()
+        .map(|address| address.ip())
+        .or_else(|_| client_details.parse())
+        .ok()
+}
+
+fn parse_prepare_body(body: &[u8]) -> CassandraStatement {
+    match prepare_query(body) {
+        Some(query) => parse_statement_single(query),
+        None => CassandraStatement::Unknown(String::new()),
+    }
+}
+
+fn statement_kind(statement: &CassandraStatement) -> StatementKind {
+    match statement {
+        CassandraStatement::Select(_) => StatementKind::Select,
+        CassandraStatement::Insert(_) => StatementKind::Insert,
+        CassandraStatement::Update(_) => StatementKind::Update,
+        CassandraStatement::Delete(_) => StatementKind::Delete,
+        CassandraStatement::Truncate(_) => StatementKind::Truncate,
+        CassandraStatement::Use(_) => StatementKind::Use,
+        CassandraStatement::AlterKeyspace(_)
+        | CassandraStatement::AlterMaterializedView(_)
+        | CassandraStatement::AlterTable(_)
+        | CassandraStatement::AlterType(_)
+        | CassandraStatement::CreateAggregate(_)
+        | CassandraStatement::CreateFunction(_)
+        | CassandraStatement::CreateIndex(_)
+        | CassandraStatement::CreateKeyspace(_)
+        | CassandraStatement::CreateMaterializedView(_)
+        | CassandraStatement::CreateTable(_)
+        | CassandraStatement::CreateTrigger(_)
+        | CassandraStatement::CreateType(_)
+        | CassandraStatement::DropAggregate(_)
+        | CassandraStatement::DropFunction(_)
+        | CassandraStatement::DropIndex(_)
+        | CassandraStatement::DropKeyspace(_)
+        | CassandraStatement::DropMaterializedView(_)
+        | CassandraStatement::DropTable(_)
+        | CassandraStatement::DropTrigger(_)
+        | CassandraStatement::DropType(_) => StatementKind::Schema,
+        CassandraStatement::AlterRole(_)
+        | CassandraStatement::AlterUser(_)
+        | CassandraStatement::CreateRole(_)
+        | CassandraStatement::CreateUser(_)
+        | CassandraStatement::DropRole(_)
+        | CassandraStatement::DropUser(_)
+        | CassandraStatement::Grant(_)
+        | CassandraStatement::Revoke(_)
+        | CassandraStatement::ListPermissions(_)
+        | CassandraStatement::ListRoles(_) => StatementKind::Auth,
+        CassandraStatement::ApplyBatch | CassandraStatement::Unknown(_) => StatementKind::Other,
+    }
+}
+
+/// Unqualified names are resolved against `keyspace`
+fn resource(statement: &CassandraStatement, keyspace: Option<&Identifier>) -> Resource {
+    let qualify = |name: &FQName| FQName {
+        keyspace: name.keyspace.clone().or_else(|| keyspace.cloned()),
+        name: name.name.clone(),
+    };
+    let table_resource = |name: &FQName| {
+        let name = qualify(name);
+        Resource {
+            keyspace: name.keyspace.clone(),
+            table: name.keyspace.is_some().then(|| name),
+        }
+    };
+    let keyspace_object = |name: &FQName| Resource {
+        keyspace: qualify(name).keyspace,
+        table: None,
+    };
+    let keyspace_resource = |name: &Identifier| Resource {
+        keyspace: Some(name.clone()),
+        table: None,
+    };
+
+    match statement {
+        CassandraStatement::Select(x) => table_resource(&x.table_name),
+        CassandraStatement::Insert(x) => table_resource(&x.table_name),
+        CassandraStatement::Update(x) => table_resource(&x.table_name),
+        CassandraStatement::Delete(x) => table_resource(&x.table_name),
+        CassandraStatement::Truncate(name) => table_resource(name),
+        CassandraStatement::AlterMaterializedView(x) => table_resource(&x.name),
+        CassandraStatement::AlterTable(x) => table_resource(&x.name),
+        CassandraStatement::CreateIndex(x) => table_resource(&x.table),
+        CassandraStatement::CreateMaterializedView(x) => table_resource(&x.name),
+        CassandraStatement::CreateTable(x) => table_resource(&x.name),
+        CassandraStatement::DropMaterializedView(x) => table_resource(&x.name),
+        CassandraStatement::DropTable(x) => table_resource(&x.name),
+        CassandraStatement::AlterType(x) => keyspace_object(&x.name),
+        CassandraStatement::CreateAggregate(x) => keyspace_object(&x.name),
+        CassandraStatement::CreateFunction(x) => keyspace_object(&x.name),
+        CassandraStatement::CreateTrigger(x) => keyspace_object(&x.name),
+        CassandraStatement::CreateType(x) => keyspace_object(&x.name),
+        CassandraStatement::DropAggregate(x) => keyspace_object(&x.name),
+        CassandraStatement::DropFunction(x) => keyspace_object(&x.name),
+        CassandraStatement::DropIndex(x) => keyspace_object(&x.name),
+        CassandraStatement::DropTrigger(x) => keyspace_object(&x.name),
+        CassandraStatement::DropType(x) => keyspace_object(&x.name),
+        CassandraStatement::AlterKeyspace(x) => keyspace_resource(&x.name),
+        CassandraStatement::CreateKeyspace(x) => keyspace_resource(&x.name),
+        CassandraStatement::DropKeyspace(x) => keyspace_resource(&x.name),
+        CassandraStatement::Use(name) => keyspace_resource(name),
+        CassandraStatement::AlterRole(_)
+        | CassandraStatement::AlterUser(_)
+        | CassandraStatement::ApplyBatch
+        | CassandraStatement::CreateRole(_)
+        | CassandraStatement::CreateUser(_)
+        | CassandraStatement::DropRole(_)
+        | CassandraStatement::DropUser(_)
+        | CassandraStatement::Grant(_)
+        | CassandraStatement::ListPermissions(_)
+        | CassandraStatement::ListRoles(_)
+        | CassandraStatement::Revoke(_)
+        | CassandraStatement::Unknown(_) => Resource {
+            keyspace: None,
+            table: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test_authorize {
+    use super::*;
+    use crate::transforms::loopback::Loopback;
+    use cassandra_protocol::frame::message_execute::BodyReqExecuteOwned;
+    use cassandra_protocol::frame::Version;
+    use cassandra_protocol::query::QueryParams;
+
+    fn authorize(permissions: Vec<PermissionConfig>) -> CassandraAuthorize {
+        CassandraAuthorize {
+            permissions: Arc::new(
+                permissions
+                    .iter()
+                    .map(|p| Permission::new(p).unwrap())
+                    .collect(),
+            ),
+            keyspace: None,
+            prepared: HashMap::new(),
+        }
+    }
+
+    fn analytics_select() -> PermissionConfig {
+        PermissionConfig {
+            users: Some(vec!["analytics".to_owned()]),
+            client_ips: None,
+            keyspaces: Some(vec!["metrics".to_owned()]),
+            tables: None,
+            statements: vec![StatementKind::Select],
+        }
+    }
+
+    fn request(operation: CassandraOperation) -> Message {
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: 1,
+            tracing_id: None,
+            warnings: vec![],
+            operation,
+        }))
+    }
+
+    fn query(cql: &str) -> Message {
+        request(CassandraOperation::Query {
+            query: Box::new(parse_statement_single(cql)),
+            params: Box::new(QueryParams::default()),
+        })
+    }
+
+    async fn transform(
+        authorize: &mut CassandraAuthorize,
+        user: &str,
+        message: Message,
+    ) -> Option<ErrorType> {
+        transform_from(authorize, user, "127.0.0.1", message).await
+    }
+
+    async fn transform_from(
+        authorize: &mut CassandraAuthorize,
+        user: &str,
+        client_details: &str,
+        message: Message,
+    ) -> Option<ErrorType> {
+        let mut chain = vec![Transforms::Loopback(Loopback::default())];
+        let mut message_wrapper = Wrapper::new(vec![message]);
+        message_wrapper.authenticated_user = Some(user.to_owned());
+        message_wrapper.client_details = client_details.to_owned();
+        message_wrapper.reset(&mut chain);
+        let mut response = authorize
+            .transform(message_wrapper)
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        match response.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Error(ErrorBody { ty, .. }),
+                ..
+            })) => Some(ty.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_tables_must_be_qualified() {
+        let mut permission = analytics_select();
+        permission.tables = Some(vec!["events".to_owned()]);
+        assert!(Permission::new(&permission).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_authorize_query() {
+        let mut authorize = authorize(vec![analytics_select()]);
+
+        let select = "SELECT * FROM metrics.events";
+        assert_eq!(
+            transform(&mut authorize, "analytics", query(select)).await,
+            None
+        );
+        assert_eq!(
+            transform(&mut authorize, "app", query(select)).await,
+            Some(ErrorType::Unauthorized)
+        );
+        assert_eq!(
+            transform(
+                &mut authorize,
+                "analytics",
+                query("INSERT INTO metrics.events (id) VALUES (1)")
+            )
+            .await,
+            Some(ErrorType::Unauthorized)
+        );
+        assert_eq!(
+            transform(
+                &mut authorize,
+                "analytics",
+                query("DROP TABLE metrics.events")
+            )
+            .await,
+            Some(ErrorType::Unauthorized)
+        );
+        assert_eq!(
+            transform(
+                &mut authorize,
+                "analytics",
+                query("SELECT * FROM other.events")
+            )
+            .await,
+            Some(ErrorType::Unauthorized)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authorize_client_ips() {
+        let mut permission = analytics_select();
+        permission.client_ips = Some(vec!["10.0.0.1".parse().unwrap()]);
+        let mut authorize = authorize(vec![permission]);
+
+        let select = "SELECT * FROM metrics.events";
+        for client_details in ["10.0.0.1", "10.0.0.1:50000"] {
+            assert_eq!(
+                transform_from(&mut authorize, "analytics", client_details, query(select)).await,
+                None
+            );
+        }
+        for client_details in ["10.0.0.2", "10.0.0.2:50000", "/tmp/shotover.sock"] {
+            assert_eq!(
+                transform_from(&mut authorize, "analytics", client_details, query(select)).await,
+                Some(ErrorType::Unauthorized)
+            );
+        }
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert_eq!(client_ip("10.0.0.1"), Some(ip));
+        assert_eq!(client_ip("10.0.0.1:9042"), Some(ip));
+        assert_eq!(client_ip("[::1]:9042"), Some("::1".parse().unwrap()));
+        assert_eq!(client_ip("unix:/tmp/shotover.sock"), None);
+    }
+
+    #[tokio::test]
+    async fn test_authorize_unknown_prepared_id() {
+        let mut authorize = authorize(vec![analytics_select()]);
+        let id = CBytesShort::new(vec![1, 2, 3]);
+
+        let execute = request(CassandraOperation::Execute(Box::new(BodyReqExecuteOwned {
+            id: id.clone(),
+            result_metadata_id: None,
+            query_parameters: QueryParams::default(),
+        })));
+        assert_eq!(
+            transform(&mut authorize, "analytics", execute).await,
+            Some(ErrorType::Unprepared(UnpreparedError { id }))
+        );
+    }
+
+    #[test]
+    fn test_prepare_body() {
+        let cql = "SELECT * FROM events";
+        let mut body = (cql.len() as i32).to_be_bytes().to_vec();
+        body.extend(cql.as_bytes());
+        let statement = parse_prepare_body(&body);
+
+        let keyspace = Identifier::parse("metrics");
+        let resource = resource(&statement, Some(&keyspace));
+        assert_eq!(resource.keyspace, Some(keyspace));
+        assert_eq!(resource.table, Some(FQName::parse("metrics.events")));
+        assert_eq!(statement_kind(&statement), StatementKind::Select);
+    }
+}
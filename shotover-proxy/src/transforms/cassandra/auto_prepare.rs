@@ -0,0 +1,637 @@
+use crate::error::ChainResponse;
+use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
+use crate::message::{Message, Metadata};
+use crate::transforms::cassandra::sink_cluster::get_unused_stream_id;
+use crate::transforms::cassandra::sink_cluster::partition_key::{
+    identifier_name, serialize_literal,
+};
+use crate::transforms::cassandra::{respond_or_forward, Handling};
+use crate::transforms::{Transform, Transforms, Wrapper};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use cassandra_protocol::frame::message_execute::BodyReqExecuteOwned;
+use cassandra_protocol::frame::message_result::{ColType, RowsMetadataFlags};
+use cassandra_protocol::frame::Version;
+use cassandra_protocol::query::{QueryParams, QueryValues};
+use cassandra_protocol::types::value::Value;
+use cassandra_protocol::types::CBytesShort;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use cql3_parser::common::{Operand, RelationElement};
+use cql3_parser::insert::InsertValues;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The PREPARE flag in protocol v5 indicating that the keyspace to prepare the statement in follows the flags
+const PREPARE_WITH_KEYSPACE: i32 = 0x01;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CassandraAutoPrepareConfig {
+    /// The number of times a normalised statement must be sent before it is prepared. Defaults to 2.
+    pub prepare_after: Option<usize>,
+    /// The maximum number of normalised statements tracked per connection. Defaults to 1000.
+    pub max_statements: Option<usize>,
+}
+
+impl CassandraAutoPrepareConfig {
+    pub async fn get_transform(&self) -> Result<Transforms> {
+        Ok(Transforms::CassandraAutoPrepare(CassandraAutoPrepare {
+            prepare_after: self.prepare_after.unwrap_or(2).max(1),
+            max_statements: self.max_statements.unwrap_or(1000),
+            keyspace: None,
+            statements: HashMap::new(),
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    /// The number of times the statement has been sent as a QUERY
+    Seen(usize),
+    Prepared {
+        id: CBytesShort,
+        result_metadata_id: Option<CBytesShort>,
+        /// The type of each bind marker
+        types: Vec<ColType>,
+    },
+    /// The statement could not be prepared or its bind markers can not be serialized, it is always sent as a QUERY
+    Unsupported,
+}
+
+/// The keyspace that unqualified table names resolve to, `None` when the statement's table is qualified, and the CQL of the normalised statement.
+/// The same CQL is a different statement in each keyspace.
+type StatementKey = (Option<String>, String);
+
+/// Converts QUERY requests into EXECUTE requests of a prepared statement with the literals of the query replaced by bind markers.
+#[derive(Debug, Clone)]
+pub struct CassandraAutoPrepare {
+    prepare_after: usize,
+    max_statements: usize,
+    /// The keyspace set by the last USE statement
+    keyspace: Option<String>,
+    statements: HashMap<StatementKey, Statement>,
+}
+
+enum Conversion {
+    None,
+    /// The QUERY was converted into an EXECUTE of the normalised statement, `query` is the QUERY as the client sent it
+    Execute {
+        key: StatementKey,
+        query: Message,
+    },
+    /// The normalised statement should be prepared
+    Prepare(StatementKey),
+}
+
+/// How the response to a forwarded request is processed
+enum Forwarded {
+    Request,
+    Execute { key: StatementKey, query: Message },
+    Prepare(StatementKey),
+}
+
+impl CassandraAutoPrepare {
+    fn convert(&mut self, message: &mut Message) -> Conversion {
+        let (query, params) = match message.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Query { query, params },
+                ..
+            })) => (query, params),
+            _ => return Conversion::None,
+        };
+        if let CassandraStatement::Use(keyspace) = query.as_ref() {
+            self.keyspace = Some(identifier_name(keyspace));
+            return Conversion::None;
+        }
+        // Queries with bind values already have bind markers.
+        // Paging states are only valid for the statement that created them.
+        if params.values.is_some() || params.paging_state.is_some() {
+            return Conversion::None;
+        }
+        let (statement, literals) = match normalise(query) {
+            Some(normalised) => normalised,
+            None => return Conversion::None,
+        };
+        let keyspace = if is_qualified(&statement) {
+            None
+        } else {
+            match params.keyspace.clone().or_else(|| self.keyspace.clone()) {
+                Some(keyspace) => Some(keyspace),
+                // Cassandra will reject the statement as it has no keyspace
+                None => return Conversion::None,
+            }
+        };
+        let key = (keyspace, statement.to_string());
+
+        let execute = match self.statements.get_mut(&key) {
+            Some(Statement::Seen(count)) => {
+                *count += 1;
+                return if *count == self.prepare_after {
+                    Conversion::Prepare(key)
+                } else {
+                    Conversion::None
+                };
+            }
+            Some(Statement::Prepared {
+                id,
+                result_metadata_id,
+                types,
+            }) => match bind_values(&literals, types) {
+                Some(values) => BodyReqExecuteOwned {
+                    id: id.clone(),
+                    result_metadata_id: result_metadata_id.clone(),
+                    query_parameters: QueryParams {
+                        values: Some(QueryValues::SimpleValues(values)),
+                        ..params.as_ref().clone()
+                    },
+                },
+                None => return Conversion::None,
+            },
+            Some(Statement::Unsupported) => return Conversion::None,
+            None => {
+                if self.statements.len() >= self.max_statements {
+                    return Conversion::None;
+                }
+                self.statements.insert(key.clone(), Statement::Seen(1));
+                return if self.prepare_after == 1 {
+                    Conversion::Prepare(key)
+                } else {
+                    Conversion::None
+                };
+            }
+        };
+
+        let query = message.clone();
+        if let Some(Frame::Cassandra(frame)) = message.frame() {
+            frame.operation = CassandraOperation::Execute(Box::new(execute));
+        }
+        message.invalidate_cache();
+        Conversion::Execute { key, query }
+    }
+
+    fn prepared(&mut self, key: StatementKey, response: &mut Message) {
+        let statement = match response.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(CassandraResult::Prepared(prepared)),
+                ..
+            })) => {
+                let types: Vec<ColType> = prepared
+                    .metadata
+                    .col_specs
+                    .iter()
+                    .map(|col_spec| col_spec.col_type.id)
+                    .collect();
+                if types.iter().all(|ty| type_name(*ty).is_some()) {
+                    Statement::Prepared {
+                        id: prepared.id.clone(),
+                        result_metadata_id: prepared.result_metadata_id.clone(),
+                        types,
+                    }
+                } else {
+                    Statement::Unsupported
+                }
+            }
+            _ => {
+                tracing::debug!("failed to prepare normalised statement {}", key.1);
+                Statement::Unsupported
+            }
+        };
+        self.statements.insert(key, statement);
+    }
+
+    /// Map the response to an EXECUTE back to the response the client expects for its QUERY.
+    /// Returns the QUERY to send in place of the EXECUTE when the statement is no longer prepared,
+    /// as the client never prepared the statement so it can not handle an unprepared error.
+    fn map_execute_response(
+        &mut self,
+        key: &StatementKey,
+        query: Message,
+        response: &mut Message,
+    ) -> Option<Message> {
+        let operation = match response.frame() {
+            Some(Frame::Cassandra(CassandraFrame { operation, .. })) => operation,
+            _ => return None,
+        };
+
+        if let CassandraOperation::Error(ErrorBody {
+            ty: ErrorType::Unprepared(_),
+            ..
+        }) = operation
+        {
+            self.statements.remove(key);
+            return Some(query);
+        }
+
+        if let CassandraOperation::Result(CassandraResult::Rows { metadata, .. }) = operation {
+            if metadata.flags.contains(RowsMetadataFlags::METADATA_CHANGED) {
+                // Only a response to an EXECUTE can report new result metadata, use it for the following EXECUTEs.
+                if let Some(Statement::Prepared {
+                    result_metadata_id, ..
+                }) = self.statements.get_mut(key)
+                {
+                    *result_metadata_id = metadata.new_metadata_id.clone();
+                }
+                metadata.flags.remove(RowsMetadataFlags::METADATA_CHANGED);
+                metadata.new_metadata_id = None;
+                response.invalidate_cache();
+            }
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraAutoPrepare {
+    async fn transform<'a>(&'a mut self, mut message_wrapper: Wrapper<'a>) -> ChainResponse {
+        let mut forwarded = Vec::with_capacity(message_wrapper.messages.len());
+        let mut prepares = vec![];
+        for message in message_wrapper.messages.iter_mut() {
+            forwarded.push(match self.convert(message) {
+                Conversion::None => Forwarded::Request,
+                Conversion::Execute { key, query } => Forwarded::Execute { key, query },
+                Conversion::Prepare(key) => {
+                    if let Ok(Metadata::Cassandra(metadata)) = message.metadata() {
+                        prepares.push((key, metadata.version));
+                    }
+                    Forwarded::Request
+                }
+            });
+        }
+
+        let request_count = message_wrapper.messages.len();
+        for (key, version) in prepares {
+            let stream_id = get_unused_stream_id(&message_wrapper.messages)?;
+            message_wrapper
+                .messages
+                .push(prepare_message(&key, version, stream_id));
+            forwarded.push(Forwarded::Prepare(key));
+        }
+
+        let requests = std::mem::take(&mut message_wrapper.messages)
+            .into_iter()
+            .zip(forwarded)
+            .map(|(message, forwarded)| Handling::Forward(message, forwarded))
+            .collect();
+        // Every request is forwarded, so responses are mapped in the same order as the requests
+        let mut index = 0;
+        let mut retries = vec![];
+        let mut responses =
+            respond_or_forward(&mut message_wrapper, requests, |forwarded, mut response| {
+                match forwarded {
+                    Forwarded::Request => {}
+                    Forwarded::Execute { key, query } => {
+                        if let Some(query) = self.map_execute_response(&key, query, &mut response) {
+                            retries.push((index, query));
+                        }
+                    }
+                    Forwarded::Prepare(key) => self.prepared(key, &mut response),
+                }
+                index += 1;
+                Ok(response)
+            })
+            .await?;
+        // The responses to the PREPAREs sent by shotover are not for the client
+        responses.truncate(request_count);
+
+        if !retries.is_empty() {
+            let (indexes, queries): (Vec<_>, Vec<_>) = retries.into_iter().unzip();
+            let retried = message_wrapper.call_next_transform_with(queries).await?;
+            if retried.len() < indexes.len() {
+                return Err(anyhow!(
+                    "the next transform returned fewer responses than requests"
+                ));
+            }
+            for (i, response) in indexes.into_iter().zip(retried) {
+                responses[i] = response;
+            }
+        }
+        Ok(responses)
+    }
+}
+
+fn prepare_message((keyspace, cql): &StatementKey, version: Version, stream_id: i16) -> Message {
+    // The body is the statement as a `[long string]`, followed by the flags in protocol v5 and the keyspace as a `[string]` if the flags say so.
+    // Before protocol v5 the statement is prepared in the keyspace set by USE, which is the keyspace in the key.
+    let mut body = (cql.len() as i32).to_be_bytes().to_vec();
+    body.extend(cql.as_bytes());
+    if version == Version::V5 {
+        match keyspace {
+            Some(keyspace) => {
+                body.extend(PREPARE_WITH_KEYSPACE.to_be_bytes());
+                body.extend((keyspace.len() as u16).to_be_bytes());
+                body.extend(keyspace.as_bytes());
+            }
+            None => body.extend(0i32.to_be_bytes()),
+        }
+    }
+
+    Message::from_frame(Frame::Cassandra(CassandraFrame {
+        version,
+        stream_id,
+        tracing_id: None,
+        warnings: vec![],
+        operation: CassandraOperation::Prepare(body),
+    }))
+}
+
+/// Whether the keyspace of the table the statement acts on is given in the statement
+fn is_qualified(statement: &CassandraStatement) -> bool {
+    let table_name = match statement {
+        CassandraStatement::Select(select) => &select.table_name,
+        CassandraStatement::Update(update) => &update.table_name,
+        CassandraStatement::Delete(delete) => &delete.table_name,
+        CassandraStatement::Insert(insert) => &insert.table_name,
+        _ => return false,
+    };
+    table_name.keyspace.is_some()
+}
+
+/// Replace the literals compared against in the WHERE clause, or given in the VALUES of an INSERT, with bind markers.
+/// Returns the normalised statement and the literals in bind marker order, or `None` if there were no literals.
+fn normalise(statement: &CassandraStatement) -> Option<(CassandraStatement, Vec<String>)> {
+    let mut statement = statement.clone();
+    let mut literals = vec![];
+    match &mut statement {
+        CassandraStatement::Select(select) => {
+            bind_relations(&mut select.where_clause, &mut literals)
+        }
+        CassandraStatement::Update(update) => {
+            bind_relations(&mut update.where_clause, &mut literals)
+        }
+        CassandraStatement::Delete(delete) => {
+            bind_relations(&mut delete.where_clause, &mut literals)
+        }
+        CassandraStatement::Insert(insert) => match &mut insert.values {
+            InsertValues::Values(values) => {
+                for value in values {
+                    bind_operand(value, &mut literals);
+                }
+            }
+            InsertValues::Json(_) => return None,
+        },
+        _ => return None,
+    }
+
+    if literals.is_empty() {
+        None
+    } else {
+        Some((statement, literals))
+    }
+}
+
+fn bind_relations(where_clause: &mut [RelationElement], literals: &mut Vec<String>) {
+    for relation in where_clause {
+        bind_operand(&mut relation.value, literals);
+    }
+}
+
+fn bind_operand(operand: &mut Operand, literals: &mut Vec<String>) {
+    if let Operand::Const(_) = operand {
+        if let Operand::Const(literal) = std::mem::replace(operand, Operand::Param("?".into())) {
+            literals.push(literal);
+        }
+    }
+}
+
+fn bind_values(literals: &[String], types: &[ColType]) -> Option<Vec<Value>> {
+    if literals.len() != types.len() {
+        return None;
+    }
+    literals
+        .iter()
+        .zip(types)
+        .map(|(literal, ty)| serialize_literal(literal, type_name(*ty)?).map(Value::Some))
+        .collect()
+}
+
+/// The name of the types that literals can be serialized into
+fn type_name(ty: ColType) -> Option<&'static str> {
+    match ty {
+        ColType::Ascii => Some("ascii"),
+        ColType::Varchar => Some("text"),
+        ColType::Inet => Some("inet"),
+        ColType::Blob => Some("blob"),
+        ColType::Boolean => Some("boolean"),
+        ColType::Tinyint => Some("tinyint"),
+        ColType::Smallint => Some("smallint"),
+        ColType::Int => Some("int"),
+        ColType::Bigint => Some("bigint"),
+        ColType::Timestamp => Some("timestamp"),
+        ColType::Varint => Some("varint"),
+        ColType::Float => Some("float"),
+        ColType::Double => Some("double"),
+        ColType::Uuid => Some("uuid"),
+        ColType::Timeuuid => Some("timeuuid"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_auto_prepare {
+    use super::*;
+    use crate::frame::cassandra::parse_statement_single;
+    use crate::transforms::loopback::Loopback;
+    use cassandra_protocol::frame::message_error::UnpreparedError;
+
+    fn query(cql: &str) -> Message {
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: 1,
+            tracing_id: None,
+            warnings: vec![],
+            operation: CassandraOperation::Query {
+                query: Box::new(parse_statement_single(cql)),
+                params: Box::new(QueryParams::default()),
+            },
+        }))
+    }
+
+    #[test]
+    fn test_normalise() {
+        let (statement, literals) = normalise(&parse_statement_single(
+            "SELECT * FROM ks.tb WHERE id = 1 AND name = 'foo'",
+        ))
+        .unwrap();
+        assert_eq!(
+            statement.to_string(),
+            "SELECT * FROM ks.tb WHERE id = ? AND name = ?"
+        );
+        assert_eq!(literals, vec!["1".to_owned(), "'foo'".to_owned()]);
+
+        let (statement, literals) = normalise(&parse_statement_single(
+            "INSERT INTO ks.tb (id, name) VALUES (1, 'foo')",
+        ))
+        .unwrap();
+        assert_eq!(
+            statement.to_string(),
+            "INSERT INTO ks.tb (id, name) VALUES (?, ?)"
+        );
+        assert_eq!(literals, vec!["1".to_owned(), "'foo'".to_owned()]);
+
+        assert!(normalise(&parse_statement_single("SELECT * FROM ks.tb")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_convert_to_execute() {
+        let mut auto_prepare = CassandraAutoPrepare {
+            prepare_after: 2,
+            max_statements: 1000,
+            keyspace: None,
+            statements: HashMap::new(),
+        };
+        let cql = "SELECT * FROM ks.tb WHERE id = 1 AND name = 'foo'";
+
+        // the loopback echoes the PREPARE so the statement is marked as unsupported
+        for _ in 0..2 {
+            let mut chain = vec![Transforms::Loopback(Loopback::default())];
+            let mut message_wrapper = Wrapper::new(vec![query(cql)]);
+            message_wrapper.reset(&mut chain);
+            let responses = auto_prepare.transform(message_wrapper).await.unwrap();
+            assert_eq!(responses.len(), 1);
+        }
+        assert!(matches!(
+            auto_prepare.statements.get(&(
+                None,
+                "SELECT * FROM ks.tb WHERE id = ? AND name = ?".to_owned()
+            )),
+            Some(Statement::Unsupported)
+        ));
+
+        let id = CBytesShort::new(vec![1, 2, 3]);
+        auto_prepare.statements.insert(
+            (
+                None,
+                "SELECT * FROM ks.tb WHERE id = ? AND name = ?".to_owned(),
+            ),
+            Statement::Prepared {
+                id: id.clone(),
+                result_metadata_id: None,
+                types: vec![ColType::Int, ColType::Varchar],
+            },
+        );
+        let mut message = query(cql);
+        assert!(matches!(
+            auto_prepare.convert(&mut message),
+            Conversion::Execute { .. }
+        ));
+        match message.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Execute(execute),
+                ..
+            })) => {
+                assert_eq!(execute.id, id);
+                assert_eq!(
+                    execute.query_parameters.values,
+                    Some(QueryValues::SimpleValues(vec![
+                        Value::Some(1i32.to_be_bytes().to_vec()),
+                        Value::Some(b"foo".to_vec()),
+                    ]))
+                );
+            }
+            _ => panic!("expected an EXECUTE"),
+        }
+    }
+
+    fn prepared(auto_prepare: &mut CassandraAutoPrepare, key: StatementKey) {
+        auto_prepare.statements.insert(
+            key,
+            Statement::Prepared {
+                id: CBytesShort::new(vec![1, 2, 3]),
+                result_metadata_id: None,
+                types: vec![ColType::Int],
+            },
+        );
+    }
+
+    #[test]
+    fn test_keyspace_switch() {
+        let mut auto_prepare = CassandraAutoPrepare {
+            prepare_after: 2,
+            max_statements: 1000,
+            keyspace: None,
+            statements: HashMap::new(),
+        };
+        let normalised = "SELECT * FROM tb WHERE id = ?".to_owned();
+        prepared(
+            &mut auto_prepare,
+            (Some("ks1".to_owned()), normalised.clone()),
+        );
+
+        // without a keyspace cassandra rejects the unqualified table, so the query is left alone
+        let cql = "SELECT * FROM tb WHERE id = 1";
+        assert!(matches!(
+            auto_prepare.convert(&mut query(cql)),
+            Conversion::None
+        ));
+
+        auto_prepare.convert(&mut query("USE ks1"));
+        assert!(matches!(
+            auto_prepare.convert(&mut query(cql)),
+            Conversion::Execute { .. }
+        ));
+
+        // the same CQL in another keyspace is a different statement that has not been prepared
+        auto_prepare.convert(&mut query("USE ks2"));
+        assert!(matches!(
+            auto_prepare.convert(&mut query(cql)),
+            Conversion::None
+        ));
+        assert!(matches!(
+            auto_prepare
+                .statements
+                .get(&(Some("ks2".to_owned()), normalised)),
+            Some(Statement::Seen(1))
+        ));
+
+        // the v5 keyspace flag takes precedence over USE
+        let mut message = query(cql);
+        if let Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Query { params, .. },
+            ..
+        })) = message.frame()
+        {
+            params.keyspace = Some("ks1".to_owned());
+        }
+        assert!(matches!(
+            auto_prepare.convert(&mut message),
+            Conversion::Execute { .. }
+        ));
+    }
+
+    #[test]
+    fn test_unprepared_response() {
+        let mut auto_prepare = CassandraAutoPrepare {
+            prepare_after: 2,
+            max_statements: 1000,
+            keyspace: None,
+            statements: HashMap::new(),
+        };
+        let key = (None, "SELECT * FROM ks.tb WHERE id = ?".to_owned());
+        prepared(&mut auto_prepare, key.clone());
+
+        let mut original = query("SELECT * FROM ks.tb WHERE id = 1");
+        let (key, query) = match auto_prepare.convert(&mut original.clone()) {
+            Conversion::Execute { key, query } => (key, query),
+            _ => panic!("expected the query to be converted"),
+        };
+
+        let mut response = Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: 1,
+            tracing_id: None,
+            warnings: vec![],
+            operation: CassandraOperation::Error(ErrorBody {
+                message: "unprepared".into(),
+                ty: ErrorType::Unprepared(UnpreparedError {
+                    id: CBytesShort::new(vec![1, 2, 3]),
+                }),
+            }),
+        }));
+        // the original query is sent again instead of returning the error to the client
+        let mut retry = auto_prepare
+            .map_execute_response(&key, query, &mut response)
+            .unwrap();
+        assert_eq!(retry.frame(), original.frame());
+        assert!(!auto_prepare.statements.contains_key(&key));
+    }
+}
@@ -5,40 +5,76 @@ use crate::server::CodecReadError;
 use crate::tls::TlsConnector;
 use crate::transforms::util::Response;
 use crate::transforms::Messages;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use cassandra_protocol::frame::Opcode;
 use derivative::Derivative;
 use futures::stream::FuturesOrdered;
 use futures::{SinkExt, StreamExt};
 use halfbrown::HashMap;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::io::{split, AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio::io::{split, AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::timeout;
 use tokio_util::codec::{FramedRead, FramedWrite};
 use tracing::{error, Instrument};
 
+/// Called with the response to a request sent through [`CassandraConnection::send_observed`] once it arrives,
+/// or with `None` if the request fails without a response because the connection failed or was closed.
+/// Observers are called from the task reading responses from the connection, so they must not block.
+pub type Observer = Box<dyn FnOnce(Option<&mut Message>) + Send>;
+
+/// Calls each observer exactly once, with `None` if dropped before a response arrives.
+#[derive(Default)]
+struct Observers(Vec<Observer>);
+
+impl Observers {
+    fn respond(mut self, response: &mut Message) {
+        for observer in self.0.drain(..) {
+            observer(Some(&mut *response));
+        }
+    }
+}
+
+impl Drop for Observers {
+    fn drop(&mut self) {
+        for observer in self.0.drain(..) {
+            observer(None);
+        }
+    }
+}
+
 /// Represents a `Request` to a `CassandraConnection`
-#[derive(Debug)]
+#[derive(Derivative)]
+#[derivative(Debug)]
 struct Request {
     message: Message,
     return_chan: oneshot::Sender<Response>,
+    /// The stream_id the message was sent to the destination with
     message_id: i16,
+    /// The stream_id the message had before it was sent, which the response is given again
+    original_stream_id: i16,
+    #[derivative(Debug = "ignore")]
+    observers: Observers,
 }
 
 #[derive(Clone, Derivative)]
 #[derivative(Debug)]
 pub struct CassandraConnection {
     connection: mpsc::UnboundedSender<Request>,
+    /// Stream ids of requests sent on this connection that have not yet received a response
+    in_flight_stream_ids: Arc<Mutex<HashSet<i16>>>,
 }
 
 impl CassandraConnection {
     pub async fn new<A: ToSocketAddrs + std::fmt::Debug>(
         host: A,
         codec: CassandraCodec,
-        mut tls: Option<TlsConnector>,
+        tls: Option<TlsConnector>,
         pushed_messages_tx: Option<mpsc::UnboundedSender<Messages>>,
+        proxy_header: Option<Vec<u8>>,
     ) -> Result<Self> {
         let tcp_stream = timeout(Duration::from_secs(3), TcpStream::connect(&host))
             .await
@@ -53,12 +89,38 @@ impl CassandraConnection {
                     .context(format!("Failed to connect to cassandra node: {:?}", host))
             })?;
 
+        CassandraConnection::new_with_stream(
+            tcp_stream,
+            codec,
+            tls,
+            pushed_messages_tx,
+            proxy_header,
+        )
+        .await
+    }
+
+    /// Create a connection to a cassandra node over an already established stream, such as a unix socket.
+    pub async fn new_with_stream<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        mut stream: S,
+        codec: CassandraCodec,
+        mut tls: Option<TlsConnector>,
+        pushed_messages_tx: Option<mpsc::UnboundedSender<Messages>>,
+        proxy_header: Option<Vec<u8>>,
+    ) -> Result<Self> {
+        if let Some(proxy_header) = proxy_header {
+            stream
+                .write_all(&proxy_header)
+                .await
+                .context("Failed to send PROXY protocol header to cassandra node")?;
+        }
+
         let (out_tx, out_rx) = mpsc::unbounded_channel::<Request>();
         let (return_tx, return_rx) = mpsc::unbounded_channel::<Request>();
         let (rx_process_has_shutdown_tx, rx_process_has_shutdown_rx) = oneshot::channel::<()>();
+        let in_flight_stream_ids = Arc::new(Mutex::new(HashSet::new()));
 
         if let Some(tls) = tls.as_mut() {
-            let tls_stream = tls.connect(tcp_stream).await?;
+            let tls_stream = tls.connect(stream).await?;
             let (read, write) = split(tls_stream);
             tokio::spawn(
                 tx_process(
@@ -76,12 +138,13 @@ impl CassandraConnection {
                     return_rx,
                     codec.clone(),
                     pushed_messages_tx,
+                    in_flight_stream_ids.clone(),
                     rx_process_has_shutdown_tx,
                 )
                 .in_current_span(),
             );
         } else {
-            let (read, write) = split(tcp_stream);
+            let (read, write) = split(stream);
             tokio::spawn(
                 tx_process(
                     write,
@@ -98,29 +161,66 @@ impl CassandraConnection {
                     return_rx,
                     codec.clone(),
                     pushed_messages_tx,
+                    in_flight_stream_ids.clone(),
                     rx_process_has_shutdown_tx,
                 )
                 .in_current_span(),
             );
         };
 
-        Ok(CassandraConnection { connection: out_tx })
+        Ok(CassandraConnection {
+            connection: out_tx,
+            in_flight_stream_ids,
+        })
     }
 
     /// Send a `Message` to this `CassandraConnection` and expect a response on `return_chan`
+    ///
+    /// Dropping the receiver of `return_chan` abandons the request, its response will be discarded when it arrives.
     pub fn send(&self, message: Message, return_chan: oneshot::Sender<Response>) -> Result<()> {
-        // Convert the message to `Request` and send upstream
-        if let Some(message_id) = message.stream_id() {
-            self.connection
-                .send(Request {
-                    message,
-                    return_chan,
-                    message_id,
-                })
-                .map_err(|x| x.into())
-        } else {
-            Err(anyhow!("no cassandra frame found"))
+        self.send_observed(message, return_chan, vec![])
+    }
+
+    /// Send a `Message` to this `CassandraConnection` like [`CassandraConnection::send`], also passing its response to each of `observers`.
+    /// The observers are called even if the request was abandoned.
+    pub fn send_observed(
+        &self,
+        mut message: Message,
+        return_chan: oneshot::Sender<Response>,
+        observers: Vec<Observer>,
+    ) -> Result<()> {
+        let original_stream_id = message
+            .stream_id()
+            .ok_or_else(|| anyhow!("no cassandra frame found"))?;
+
+        let message_id = {
+            let mut in_flight_stream_ids = self.in_flight_stream_ids.lock().unwrap();
+            // An abandoned request keeps its stream id until its response arrives,
+            // so a new request reusing that stream id is sent with an unused one instead to keep their responses apart.
+            let message_id = if in_flight_stream_ids.contains(&original_stream_id) {
+                (0..i16::MAX)
+                    .find(|id| !in_flight_stream_ids.contains(id))
+                    .ok_or_else(|| anyhow!("Ran out of stream ids"))?
+            } else {
+                original_stream_id
+            };
+            in_flight_stream_ids.insert(message_id);
+            message_id
+        };
+        if message_id != original_stream_id {
+            message.set_stream_id(message_id);
         }
+
+        // Convert the message to `Request` and send upstream
+        self.connection
+            .send(Request {
+                message,
+                return_chan,
+                message_id,
+                original_stream_id,
+                observers: Observers(observers),
+            })
+            .map_err(|x| x.into())
     }
 }
 
@@ -176,9 +276,18 @@ async fn rx_process<T: AsyncRead>(
     return_rx: mpsc::UnboundedReceiver<Request>,
     codec: CassandraCodec,
     pushed_messages_tx: Option<mpsc::UnboundedSender<Messages>>,
+    in_flight_stream_ids: Arc<Mutex<HashSet<i16>>>,
     rx_process_has_shutdown_tx: oneshot::Sender<()>,
 ) {
-    if let Err(err) = rx_process_fallible(read, return_rx, codec, pushed_messages_tx).await {
+    if let Err(err) = rx_process_fallible(
+        read,
+        return_rx,
+        codec,
+        pushed_messages_tx,
+        in_flight_stream_ids,
+    )
+    .await
+    {
         error!("{:?}", err.context("rx_process task terminated"));
     }
 
@@ -191,9 +300,10 @@ async fn rx_process_fallible<T: AsyncRead>(
     mut return_rx: mpsc::UnboundedReceiver<Request>,
     codec: CassandraCodec,
     pushed_messages_tx: Option<mpsc::UnboundedSender<Messages>>,
+    in_flight_stream_ids: Arc<Mutex<HashSet<i16>>>,
 ) -> Result<()> {
     let mut reader = FramedRead::new(read, codec);
-    let mut return_channel_map: HashMap<i16, (oneshot::Sender<Response>, Message)> = HashMap::new();
+    let mut return_channel_map: HashMap<i16, Request> = HashMap::new();
 
     let mut return_message_map: HashMap<i16, Message> = HashMap::new();
 
@@ -212,9 +322,8 @@ async fn rx_process_fallible<T: AsyncRead>(
                                     None => {
                                         return_message_map.insert(stream_id, m);
                                     },
-                                    Some((return_tx, original)) => {
-                                        return_tx.send(Response { original, response: Ok(m) })
-                                            .map_err(|_| anyhow!("couldn't send message"))?;
+                                    Some(request) => {
+                                        return_response(&in_flight_stream_ids, request, m);
                                     }
                                 }
                             }
@@ -231,14 +340,13 @@ async fn rx_process_fallible<T: AsyncRead>(
                 }
             },
             original_request = return_rx.recv() => {
-                if let Some(Request { message, return_chan, message_id }) = original_request {
-                    match return_message_map.remove(&message_id) {
+                if let Some(request) = original_request {
+                    match return_message_map.remove(&request.message_id) {
                         None => {
-                            return_channel_map.insert(message_id, (return_chan, message));
+                            return_channel_map.insert(request.message_id, request);
                         }
                         Some(m) => {
-                            return_chan.send(Response { original: message, response: Ok(m) })
-                                .map_err(|_| anyhow!("couldn't send message"))?;
+                            return_response(&in_flight_stream_ids, request, m);
                         }
                     }
                 } else {
@@ -249,6 +357,32 @@ async fn rx_process_fallible<T: AsyncRead>(
     }
 }
 
+fn return_response(
+    in_flight_stream_ids: &Mutex<HashSet<i16>>,
+    request: Request,
+    mut response: Message,
+) {
+    let Request {
+        message,
+        return_chan,
+        message_id,
+        original_stream_id,
+        observers,
+    } = request;
+    in_flight_stream_ids.lock().unwrap().remove(&message_id);
+    if message_id != original_stream_id {
+        response.set_stream_id(original_stream_id);
+    }
+    observers.respond(&mut response);
+    // The receiver is dropped when the request was abandoned, so there is no one to give the response to.
+    return_chan
+        .send(Response {
+            original: message,
+            response: Ok(response),
+        })
+        .ok();
+}
+
 pub async fn receive(
     timeout_duration: Option<Duration>,
     failed_requests: &metrics::Counter,
@@ -257,31 +391,83 @@ pub async fn receive(
     let expected_size = results.len();
     let mut responses = Vec::with_capacity(expected_size);
     while responses.len() < expected_size {
-        if let Some(timeout_duration) = timeout_duration {
-            match timeout(
+        responses.push(
+            receive_with_timeout(
                 timeout_duration,
-                receive_message(failed_requests, &mut results),
+                failed_requests,
+                &mut results,
+                responses.len(),
+                expected_size,
             )
-            .await
-            {
-                Ok(response) => {
-                    responses.push(response?);
-                }
-                Err(_) => {
-                    return Err(anyhow!(
-                        "timed out waiting for responses, received {:?} responses but expected {:?} responses",
-                        responses.len(),
-                        expected_size
-                    ));
-                }
-            }
-        } else {
-            responses.push(receive_message(failed_requests, &mut results).await?);
-        }
+            .await??,
+        );
+    }
+    Ok(responses)
+}
+
+/// Like `receive` but a request whose connection failed before it received a response results in an `Err` for that request alone,
+/// allowing the caller to retry it.
+pub async fn receive_each(
+    timeout_duration: Option<Duration>,
+    failed_requests: &metrics::Counter,
+    mut results: FuturesOrdered<oneshot::Receiver<Response>>,
+) -> Result<Vec<Result<Message>>> {
+    let expected_size = results.len();
+    let mut responses = Vec::with_capacity(expected_size);
+    while responses.len() < expected_size {
+        responses.push(
+            receive_with_timeout(
+                timeout_duration,
+                failed_requests,
+                &mut results,
+                responses.len(),
+                expected_size,
+            )
+            .await?,
+        );
     }
     Ok(responses)
 }
 
+/// Send a single request to `connection` and wait for its response.
+/// The outer `Result` fails when the response times out while the inner `Result` fails when the connection fails.
+pub async fn send_and_receive(
+    connection: &CassandraConnection,
+    request: Message,
+    timeout_duration: Option<Duration>,
+    failed_requests: &metrics::Counter,
+) -> Result<Result<Message>> {
+    let (return_chan_tx, return_chan_rx) = oneshot::channel();
+    if let Err(err) = connection.send(request, return_chan_tx) {
+        return Ok(Err(err));
+    }
+
+    let mut results = FuturesOrdered::new();
+    results.push_back(return_chan_rx);
+    receive_with_timeout(timeout_duration, failed_requests, &mut results, 0, 1).await
+}
+
+async fn receive_with_timeout(
+    timeout_duration: Option<Duration>,
+    failed_requests: &metrics::Counter,
+    results: &mut FuturesOrdered<oneshot::Receiver<Response>>,
+    received: usize,
+    expected_size: usize,
+) -> Result<Result<Message>> {
+    match timeout_duration {
+        Some(timeout_duration) => timeout(timeout_duration, receive_message(failed_requests, results))
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "timed out waiting for responses, received {:?} responses but expected {:?} responses",
+                    received,
+                    expected_size
+                )
+            }),
+        None => Ok(receive_message(failed_requests, results).await),
+    }
+}
+
 pub async fn receive_message(
     failed_requests: &metrics::Counter,
     results: &mut FuturesOrdered<oneshot::Receiver<Response>>,
@@ -305,7 +491,7 @@ pub async fn receive_message(
                 mut original,
                 response: Err(err),
             } => {
-                original.set_error(err.to_string());
+                original.set_error(err.to_string())?;
                 Ok(original)
             }
         },
@@ -0,0 +1,504 @@
+use crate::error::ChainResponse;
+use crate::frame::cassandra::{parse_statement_single, prepare_query, BatchStatementType};
+use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
+use crate::message::Message;
+use crate::transforms::cassandra::{respond_or_forward, Handling};
+use crate::transforms::{Transform, Transforms, Wrapper};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use cassandra_protocol::consistency::Consistency;
+use cassandra_protocol::types::CBytesShort;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use cql3_parser::common::{FQName, Identifier};
+use metrics::counter;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CassandraConsistencyConfig {
+    /// Applied to requests on tables without a table or keyspace policy. Defaults to leaving the consistency unchanged.
+    pub default: Option<ConsistencyPolicy>,
+    /// Keyed by keyspace name. Defaults to no keyspace policies.
+    pub keyspaces: Option<HashMap<String, ConsistencyPolicy>>,
+    /// Keyed by `keyspace.table`, takes precedence over the keyspace's policy. Defaults to no table policies.
+    pub tables: Option<HashMap<String, ConsistencyPolicy>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ConsistencyPolicy {
+    /// Weaker consistency levels are raised to this level.
+    pub minimum: Option<ConsistencyLevel>,
+    /// Stronger consistency levels are lowered to this level.
+    pub maximum: Option<ConsistencyLevel>,
+    /// Every request uses this level, `minimum` and `maximum` are ignored.
+    pub fixed: Option<ConsistencyLevel>,
+    /// The serial consistency used by lightweight transactions, either Serial or LocalSerial.
+    pub serial: Option<ConsistencyLevel>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ConsistencyLevel {
+    Any,
+    One,
+    Two,
+    Three,
+    Quorum,
+    All,
+    LocalQuorum,
+    EachQuorum,
+    Serial,
+    LocalSerial,
+    LocalOne,
+}
+
+impl From<ConsistencyLevel> for Consistency {
+    fn from(level: ConsistencyLevel) -> Self {
+        match level {
+            ConsistencyLevel::Any => Consistency::Any,
+            ConsistencyLevel::One => Consistency::One,
+            ConsistencyLevel::Two => Consistency::Two,
+            ConsistencyLevel::Three => Consistency::Three,
+            ConsistencyLevel::Quorum => Consistency::Quorum,
+            ConsistencyLevel::All => Consistency::All,
+            ConsistencyLevel::LocalQuorum => Consistency::LocalQuorum,
+            ConsistencyLevel::EachQuorum => Consistency::EachQuorum,
+            ConsistencyLevel::Serial => Consistency::Serial,
+            ConsistencyLevel::LocalSerial => Consistency::LocalSerial,
+            ConsistencyLevel::LocalOne => Consistency::LocalOne,
+        }
+    }
+}
+
+/// Consistency levels ordered by the number of replicas that must respond
+fn strength(consistency: Consistency) -> u8 {
+    match consistency {
+        Consistency::Any => 0,
+        Consistency::One | Consistency::LocalOne => 1,
+        Consistency::Two => 2,
+        Consistency::Three => 3,
+        Consistency::LocalQuorum | Consistency::LocalSerial => 4,
+        Consistency::Quorum | Consistency::Serial => 5,
+        Consistency::EachQuorum => 6,
+        Consistency::All => 7,
+    }
+}
+
+impl ConsistencyPolicy {
+    fn validate(&self, name: &str) -> Result<()> {
+        if let (Some(minimum), Some(maximum)) = (self.minimum, self.maximum) {
+            if strength(minimum.into()) > strength(maximum.into()) {
+                return Err(anyhow!(
+                    "the consistency policy of {name} has a minimum of {minimum:?} which is stronger than its maximum of {maximum:?}"
+                ));
+            }
+        }
+        if let Some(serial) = self.serial {
+            if !matches!(
+                serial,
+                ConsistencyLevel::Serial | ConsistencyLevel::LocalSerial
+            ) {
+                return Err(anyhow!(
+                    "the consistency policy of {name} has a serial consistency of {serial:?} but it must be Serial or LocalSerial"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, consistency: Consistency) -> Consistency {
+        if let Some(fixed) = self.fixed {
+            return fixed.into();
+        }
+        match (self.minimum, self.maximum) {
+            (Some(minimum), _) if strength(consistency) < strength(minimum.into()) => {
+                minimum.into()
+            }
+            (_, Some(maximum)) if strength(consistency) > strength(maximum.into()) => {
+                maximum.into()
+            }
+            _ => consistency,
+        }
+    }
+}
+
+impl CassandraConsistencyConfig {
+    pub async fn get_transform(&self) -> Result<Transforms> {
+        if let Some(default) = &self.default {
+            default.validate("default")?;
+        }
+
+        let mut keyspaces = HashMap::new();
+        for (keyspace, policy) in self.keyspaces.iter().flatten() {
+            policy.validate(keyspace)?;
+            keyspaces.insert(
+                identifier_name(&Identifier::parse(keyspace)),
+                policy.clone(),
+            );
+        }
+
+        let mut tables = HashMap::new();
+        for (table, policy) in self.tables.iter().flatten() {
+            policy.validate(table)?;
+            let name = FQName::parse(table);
+            let keyspace = match &name.keyspace {
+                Some(keyspace) => identifier_name(keyspace),
+                None => return Err(anyhow!("table {table} must be of the form keyspace.table")),
+            };
+            tables.insert((keyspace, identifier_name(&name.name)), policy.clone());
+        }
+
+        Ok(Transforms::CassandraConsistency(CassandraConsistency {
+            default: self.default.clone(),
+            keyspaces: Arc::new(keyspaces),
+            tables: Arc::new(tables),
+            keyspace: None,
+            prepared: HashMap::new(),
+        }))
+    }
+}
+
+/// Keyspace and table name as stored by cassandra
+type TableName = (String, String);
+
+#[derive(Debug, Clone, Default)]
+struct StatementInfo {
+    /// `None` for statements on an unknown table
+    table: Option<TableName>,
+    /// A lightweight transaction, the only kind of statement that the serial consistency applies to
+    conditional: bool,
+}
+
+/// Overrides the consistency and serial consistency of QUERY, EXECUTE and BATCH requests according to the policy of the tables they act on.
+#[derive(Debug, Clone)]
+pub struct CassandraConsistency {
+    default: Option<ConsistencyPolicy>,
+    keyspaces: Arc<HashMap<String, ConsistencyPolicy>>,
+    tables: Arc<HashMap<TableName, ConsistencyPolicy>>,
+    /// The keyspace set by the last USE statement, used for unqualified table names when the request has no keyspace of its own
+    keyspace: Option<String>,
+    /// Statements prepared on this connection, keyed by the id that EXECUTE and BATCH requests refer to them by.
+    prepared: HashMap<CBytesShort, StatementInfo>,
+}
+
+impl CassandraConsistency {
+    fn policy(&self, table: Option<&TableName>) -> Option<&ConsistencyPolicy> {
+        table
+            .and_then(|table| {
+                self.tables
+                    .get(table)
+                    .or_else(|| self.keyspaces.get(&table.0))
+            })
+            .or(self.default.as_ref())
+    }
+
+    fn statement_info(
+        &self,
+        statement: &CassandraStatement,
+        keyspace: Option<&str>,
+    ) -> StatementInfo {
+        StatementInfo {
+            table: statement_table(statement, keyspace.or(self.keyspace.as_deref())),
+            conditional: is_conditional(statement),
+        }
+    }
+
+    /// Each statement of the request
+    fn statements(&self, operation: &CassandraOperation) -> Vec<StatementInfo> {
+        let keyspace = operation.keyspace_flag();
+        match operation {
+            CassandraOperation::Query { query, .. } => vec![self.statement_info(query, keyspace)],
+            CassandraOperation::Execute(execute) => {
+                vec![self.prepared.get(&execute.id).cloned().unwrap_or_default()]
+            }
+            CassandraOperation::Batch(batch) => batch
+                .queries()
+                .iter()
+                .map(|query| match query.ty() {
+                    BatchStatementType::Statement(statement) => {
+                        self.statement_info(statement, keyspace)
+                    }
+                    BatchStatementType::PreparedId(id) => {
+                        self.prepared.get(id).cloned().unwrap_or_default()
+                    }
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn enforce(&self, message: &mut Message) {
+        let operation = match message.frame() {
+            Some(Frame::Cassandra(CassandraFrame { operation, .. })) => operation,
+            _ => return,
+        };
+        let current = match operation.consistency() {
+            Some(consistency) => consistency,
+            None => return,
+        };
+
+        let statements = self.statements(operation);
+        let policies: Vec<&ConsistencyPolicy> = statements
+            .iter()
+            .filter_map(|statement| self.policy(statement.table.as_ref()))
+            .collect();
+
+        let mut changed = false;
+        let consistency = policies
+            .iter()
+            .fold(current, |consistency, policy| policy.apply(consistency));
+        if consistency != current {
+            tracing::debug!("overriding consistency {current:?} with {consistency:?}");
+            counter!("shotover_cassandra_consistency_overrides", 1, "from" => format!("{current:?}"), "to" => format!("{consistency:?}"));
+            operation.set_consistency(consistency);
+            changed = true;
+        }
+
+        // Setting a serial consistency on a statement that is not a lightweight transaction would be ignored by cassandra
+        let current_serial = operation.serial_consistency();
+        let serial = statements
+            .iter()
+            .filter(|statement| statement.conditional)
+            .filter_map(|statement| self.policy(statement.table.as_ref()))
+            .rev()
+            .find_map(|policy| policy.serial);
+        if let Some(serial) = serial {
+            let serial = Consistency::from(serial);
+            if current_serial != Some(serial) {
+                tracing::debug!("overriding serial consistency {current_serial:?} with {serial:?}");
+                counter!("shotover_cassandra_serial_consistency_overrides", 1, "from" => format!("{current_serial:?}"), "to" => format!("{serial:?}"));
+                operation.set_serial_consistency(serial);
+                changed = true;
+            }
+        }
+
+        if changed {
+            message.invalidate_cache();
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraConsistency {
+    async fn transform<'a>(&'a mut self, mut message_wrapper: Wrapper<'a>) -> ChainResponse {
+        let mut requests = Vec::with_capacity(message_wrapper.messages.len());
+        for mut message in std::mem::take(&mut message_wrapper.messages) {
+            self.enforce(&mut message);
+            // A forwarded PREPARE carries its statement, which is recorded against the id in the response
+            let prepared = match message.frame() {
+                Some(Frame::Cassandra(CassandraFrame { operation, .. })) => match operation {
+                    CassandraOperation::Query { query, .. } => {
+                        if let CassandraStatement::Use(keyspace) = query.as_ref() {
+                            self.keyspace = Some(identifier_name(keyspace));
+                        }
+                        None
+                    }
+                    CassandraOperation::Prepare(body) => Some(
+                        prepare_query(body)
+                            .map(|query| self.statement_info(&parse_statement_single(query), None))
+                            .unwrap_or_default(),
+                    ),
+                    _ => None,
+                },
+                _ => None,
+            };
+            requests.push(Handling::Forward(message, prepared));
+        }
+
+        respond_or_forward(&mut message_wrapper, requests, |statement, mut response| {
+            if let Some(mut statement) = statement {
+                if let Some(Frame::Cassandra(CassandraFrame {
+                    operation: CassandraOperation::Result(CassandraResult::Prepared(prepared)),
+                    ..
+                })) = response.frame()
+                {
+                    let table_spec = prepared.metadata.global_table_spec.as_ref().or_else(|| {
+                        prepared
+                            .metadata
+                            .col_specs
+                            .first()
+                            .and_then(|col_spec| col_spec.table_spec.as_ref())
+                    });
+                    if let Some(table_spec) = table_spec {
+                        statement.table =
+                            Some((table_spec.ks_name.clone(), table_spec.table_name.clone()));
+                    }
+                    self.prepared.insert(prepared.id.clone(), statement);
+                }
+            }
+            Ok(response)
+        })
+        .await
+    }
+}
+
+/// Unquoted identifiers are case insensitive while cassandra stores the case sensitive name.
+fn identifier_name(identifier: &Identifier) -> String {
+    match identifier {
+        Identifier::Unquoted(name) => name.to_lowercase(),
+        Identifier::Quoted(name) => name.clone(),
+    }
+}
+
+fn is_conditional(statement: &CassandraStatement) -> bool {
+    match statement {
+        CassandraStatement::Insert(insert) => insert.if_not_exists,
+        CassandraStatement::Update(update) => update.if_exists || !update.if_clause.is_empty(),
+        CassandraStatement::Delete(delete) => delete.if_exist || !delete.if_clause.is_empty(),
+        _ => false,
+    }
+}
+
+fn statement_table(statement: &CassandraStatement, keyspace: Option<&str>) -> Option<TableName> {
+    let name = match statement {
+        CassandraStatement::Select(select) => &select.table_name,
+        CassandraStatement::Insert(insert) => &insert.table_name,
+        CassandraStatement::Update(update) => &update.table_name,
+        CassandraStatement::Delete(delete) => &delete.table_name,
+        _ => return None,
+    };
+    let keyspace = match &name.keyspace {
+        Some(keyspace) => identifier_name(keyspace),
+        None => keyspace?.to_owned(),
+    };
+    Some((keyspace, identifier_name(&name.name)))
+}
+
+#[cfg(test)]
+mod test_consistency {
+    use super::*;
+    use crate::frame::cassandra::parse_statement_single;
+    use crate::transforms::loopback::Loopback;
+    use cassandra_protocol::frame::Version;
+    use cassandra_protocol::query::QueryParams;
+
+    fn policy(
+        minimum: Option<ConsistencyLevel>,
+        maximum: Option<ConsistencyLevel>,
+        fixed: Option<ConsistencyLevel>,
+    ) -> ConsistencyPolicy {
+        ConsistencyPolicy {
+            minimum,
+            maximum,
+            fixed,
+            serial: None,
+        }
+    }
+
+    #[test]
+    fn test_apply_policy() {
+        let policy = policy(
+            Some(ConsistencyLevel::LocalQuorum),
+            Some(ConsistencyLevel::Quorum),
+            None,
+        );
+        assert_eq!(policy.apply(Consistency::One), Consistency::LocalQuorum);
+        assert_eq!(policy.apply(Consistency::All), Consistency::Quorum);
+        assert_eq!(policy.apply(Consistency::Quorum), Consistency::Quorum);
+
+        let policy = ConsistencyPolicy {
+            fixed: Some(ConsistencyLevel::LocalOne),
+            ..policy
+        };
+        assert_eq!(policy.apply(Consistency::All), Consistency::LocalOne);
+    }
+
+    #[test]
+    fn test_validate_policy() {
+        assert!(policy(
+            Some(ConsistencyLevel::All),
+            Some(ConsistencyLevel::One),
+            None
+        )
+        .validate("test")
+        .is_err());
+
+        let mut policy = policy(None, None, None);
+        policy.serial = Some(ConsistencyLevel::Quorum);
+        assert!(policy.validate("test").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_override_query_consistency() {
+        let config = CassandraConsistencyConfig {
+            default: Some(policy(None, Some(ConsistencyLevel::LocalQuorum), None)),
+            keyspaces: None,
+            tables: Some(
+                [(
+                    "ks.critical".to_owned(),
+                    ConsistencyPolicy {
+                        serial: Some(ConsistencyLevel::LocalSerial),
+                        ..policy(Some(ConsistencyLevel::LocalQuorum), None, None)
+                    },
+                )]
+                .into_iter()
+                .collect(),
+            ),
+        };
+        let mut transform = match config.get_transform().await.unwrap() {
+            Transforms::CassandraConsistency(transform) => transform,
+            _ => unreachable!(),
+        };
+
+        let query = |cql: &str, consistency: Consistency| {
+            Message::from_frame(Frame::Cassandra(CassandraFrame {
+                version: Version::V4,
+                stream_id: 1,
+                tracing_id: None,
+                warnings: vec![],
+                operation: CassandraOperation::Query {
+                    query: Box::new(parse_statement_single(cql)),
+                    params: Box::new(QueryParams {
+                        consistency,
+                        ..QueryParams::default()
+                    }),
+                },
+            }))
+        };
+
+        let mut chain = vec![Transforms::Loopback(Loopback::default())];
+        let mut message_wrapper = Wrapper::new(vec![
+            query(
+                "INSERT INTO ks.critical (id) VALUES (1) IF NOT EXISTS",
+                Consistency::One,
+            ),
+            query("SELECT * FROM ks.other", Consistency::All),
+            query(
+                "UPDATE ks.critical SET x = 1 WHERE id = 1",
+                Consistency::One,
+            ),
+            query("USE ks", Consistency::One),
+            query("SELECT * FROM critical", Consistency::One),
+        ]);
+        message_wrapper.reset(&mut chain);
+        let mut responses = transform.transform(message_wrapper).await.unwrap();
+
+        match responses[0].frame() {
+            Some(Frame::Cassandra(CassandraFrame { operation, .. })) => {
+                assert_eq!(operation.consistency(), Some(Consistency::LocalQuorum));
+                assert_eq!(
+                    operation.serial_consistency(),
+                    Some(Consistency::LocalSerial)
+                );
+            }
+            _ => panic!("expected a cassandra frame"),
+        }
+        // the serial consistency is only set on lightweight transactions
+        for i in [1, 2] {
+            match responses[i].frame() {
+                Some(Frame::Cassandra(CassandraFrame { operation, .. })) => {
+                    assert_eq!(operation.consistency(), Some(Consistency::LocalQuorum));
+                    assert_eq!(operation.serial_consistency(), None);
+                }
+                _ => panic!("expected a cassandra frame"),
+            }
+        }
+        // the unqualified table is resolved against the keyspace of the USE statement, so the table's minimum applies
+        match responses[4].frame() {
+            Some(Frame::Cassandra(CassandraFrame { operation, .. })) => {
+                assert_eq!(operation.consistency(), Some(Consistency::LocalQuorum));
+            }
+            _ => panic!("expected a cassandra frame"),
+        }
+    }
+}
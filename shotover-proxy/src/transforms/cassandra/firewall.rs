@@ -0,0 +1,300 @@
+use crate::error::ChainResponse;
+use crate::frame::cassandra::{parse_statement_single, prepare_query, set_prepare_query};
+use crate::frame::{CassandraFrame, CassandraOperation, Frame};
+use crate::message::Message;
+use crate::transforms::cassandra::{respond_or_forward, Handling};
+use crate::transforms::{Transform, Transforms, Wrapper};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use cql3_parser::cassandra_statement::CassandraStatement;
+use cql3_parser::common::{Operand, RelationElement, RelationOperator};
+use metrics::counter;
+use serde::Deserialize;
+use std::sync::Arc;
+use strum_macros::IntoStaticStr;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CassandraFirewallConfig {
+    /// Every statement is checked against each rule in order.
+    pub rules: Vec<FirewallRule>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FirewallRule {
+    /// Identifies the rule in logs, errors and metrics.
+    pub name: String,
+    pub matches: RuleMatch,
+    pub mode: RuleMode,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub enum RuleMatch {
+    /// SELECT statements using ALLOW FILTERING
+    AllowFiltering,
+    Truncate,
+    DropKeyspace,
+    DropTable,
+    /// SELECT statements with neither a WHERE clause nor a LIMIT.
+    /// A WHERE clause that does not restrict the partition key is not matched, as the firewall does not know the schema.
+    SelectWithoutWhereOrLimit,
+    /// SELECT, UPDATE and DELETE statements with an IN relation of more than the given number of values
+    InListLongerThan(usize),
+}
+
+#[derive(Deserialize, Debug, Clone, IntoStaticStr)]
+pub enum RuleMode {
+    /// Log a warning and send the statement on unchanged
+    LogOnly,
+    /// Respond with an error instead of sending the statement on
+    Reject,
+    /// Limit the number of rows returned by the matching SELECT statement to `limit`
+    Rewrite { limit: i32 },
+}
+
+impl CassandraFirewallConfig {
+    pub async fn get_transform(&self) -> Result<Transforms> {
+        for rule in &self.rules {
+            if let RuleMode::Rewrite { .. } = rule.mode {
+                if !matches!(
+                    rule.matches,
+                    RuleMatch::AllowFiltering | RuleMatch::SelectWithoutWhereOrLimit
+                ) {
+                    return Err(anyhow!(
+                        "rule {} can not use Rewrite, only the AllowFiltering and SelectWithoutWhereOrLimit rules can be rewritten",
+                        rule.name
+                    ));
+                }
+            }
+        }
+
+        Ok(Transforms::CassandraFirewall(CassandraFirewall {
+            rules: Arc::new(self.rules.clone()),
+        }))
+    }
+}
+
+impl RuleMatch {
+    fn is_match(&self, statement: &CassandraStatement) -> bool {
+        match (self, statement) {
+            (RuleMatch::AllowFiltering, CassandraStatement::Select(select)) => select.filtering,
+            (RuleMatch::Truncate, CassandraStatement::Truncate(_)) => true,
+            (RuleMatch::DropKeyspace, CassandraStatement::DropKeyspace(_)) => true,
+            (RuleMatch::DropTable, CassandraStatement::DropTable(_)) => true,
+            (RuleMatch::SelectWithoutWhereOrLimit, CassandraStatement::Select(select)) => {
+                select.where_clause.is_empty() && select.limit.is_none()
+            }
+            (RuleMatch::InListLongerThan(max), CassandraStatement::Select(select)) => {
+                longest_in_list(&select.where_clause) > *max
+            }
+            (RuleMatch::InListLongerThan(max), CassandraStatement::Update(update)) => {
+                longest_in_list(&update.where_clause) > *max
+            }
+            (RuleMatch::InListLongerThan(max), CassandraStatement::Delete(delete)) => {
+                longest_in_list(&delete.where_clause) > *max
+            }
+            _ => false,
+        }
+    }
+}
+
+fn longest_in_list(where_clause: &[RelationElement]) -> usize {
+    where_clause
+        .iter()
+        .filter(|relation| matches!(relation.oper, RelationOperator::In))
+        .map(|relation| match &relation.value {
+            Operand::Tuple(values) => values.len(),
+            Operand::List(values) | Operand::Set(values) => values.len(),
+            _ => 1,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Checks the statements of QUERY, PREPARE and BATCH requests against the configured rules.
+#[derive(Debug, Clone)]
+pub struct CassandraFirewall {
+    rules: Arc<Vec<FirewallRule>>,
+}
+
+impl CassandraFirewall {
+    /// Returns true if the statement was rewritten, or the name of the rule that rejected it
+    fn check(&self, statement: &mut CassandraStatement) -> Result<bool, &str> {
+        let mut rewritten = false;
+        for rule in self.rules.iter() {
+            if !rule.matches.is_match(statement) {
+                continue;
+            }
+
+            let mode: &'static str = (&rule.mode).into();
+            counter!("shotover_cassandra_firewall_hits", 1, "rule" => rule.name.clone(), "mode" => mode);
+            match &rule.mode {
+                RuleMode::LogOnly => {
+                    tracing::warn!(
+                        "statement matched firewall rule {}: {}",
+                        rule.name,
+                        statement
+                    )
+                }
+                RuleMode::Reject => return Err(&rule.name),
+                RuleMode::Rewrite { limit } => {
+                    if let CassandraStatement::Select(select) = statement {
+                        select.limit = Some(select.limit.map_or(*limit, |l| l.min(*limit)));
+                        rewritten = true;
+                    }
+                }
+            }
+        }
+        Ok(rewritten)
+    }
+
+    /// Returns the error to respond with if the message is rejected
+    fn check_message(&self, message: &mut Message) -> Option<Message> {
+        let mut rewritten = false;
+        let rejected_by = match message.frame() {
+            Some(Frame::Cassandra(frame)) => {
+                let result = match &mut frame.operation {
+                    CassandraOperation::Prepare(body) => match prepare_query(body) {
+                        Some(query) => {
+                            let mut statement = parse_statement_single(query);
+                            let result = self.check(&mut statement);
+                            if let Ok(true) = result {
+                                set_prepare_query(body, &statement.to_string());
+                            }
+                            result
+                        }
+                        None => Ok(false),
+                    },
+                    operation => operation
+                        .queries()
+                        .map(|statement| self.check(statement))
+                        .try_fold(false, |acc, result| result.map(|r| acc || r)),
+                };
+                match result {
+                    Ok(r) => {
+                        rewritten = r;
+                        None
+                    }
+                    Err(rule) => Some((frame.version, frame.stream_id, rule.to_owned())),
+                }
+            }
+            _ => None,
+        };
+
+        if rewritten {
+            message.invalidate_cache();
+        }
+
+        rejected_by.map(|(version, stream_id, rule)| {
+            Message::from_frame(Frame::Cassandra(CassandraFrame {
+                version,
+                stream_id,
+                tracing_id: None,
+                warnings: vec![],
+                operation: CassandraOperation::Error(ErrorBody {
+                    message: format!("Statement rejected by shotover firewall rule {rule}"),
+                    ty: ErrorType::Invalid,
+                }),
+            }))
+        })
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraFirewall {
+    async fn transform<'a>(&'a mut self, mut message_wrapper: Wrapper<'a>) -> ChainResponse {
+        let requests = std::mem::take(&mut message_wrapper.messages)
+            .into_iter()
+            .map(|mut message| match self.check_message(&mut message) {
+                Some(error) => Handling::Respond(error),
+                None => Handling::Forward(message, ()),
+            })
+            .collect();
+
+        respond_or_forward(&mut message_wrapper, requests, |(), response| Ok(response)).await
+    }
+}
+
+#[cfg(test)]
+mod test_firewall {
+    use super::*;
+
+    fn single_rule(matches: RuleMatch, mode: RuleMode) -> CassandraFirewall {
+        CassandraFirewall {
+            rules: Arc::new(vec![FirewallRule {
+                name: "test_rule".to_owned(),
+                matches,
+                mode,
+            }]),
+        }
+    }
+
+    fn check(firewall: &CassandraFirewall, cql: &str) -> Result<Option<String>, String> {
+        let mut statement = parse_statement_single(cql);
+        match firewall.check(&mut statement) {
+            Ok(true) => Ok(Some(statement.to_string())),
+            Ok(false) => Ok(None),
+            Err(rule) => Err(rule.to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_reject() {
+        let firewall = single_rule(RuleMatch::AllowFiltering, RuleMode::Reject);
+        assert_eq!(
+            check(&firewall, "SELECT * FROM ks.tb WHERE x = 1 ALLOW FILTERING"),
+            Err("test_rule".to_owned())
+        );
+        assert_eq!(
+            check(&firewall, "SELECT * FROM ks.tb WHERE x = 1"),
+            Ok(None)
+        );
+
+        let firewall = single_rule(RuleMatch::Truncate, RuleMode::Reject);
+        assert_eq!(
+            check(&firewall, "TRUNCATE ks.tb"),
+            Err("test_rule".to_owned())
+        );
+
+        let firewall = single_rule(RuleMatch::InListLongerThan(2), RuleMode::Reject);
+        assert_eq!(
+            check(&firewall, "SELECT * FROM ks.tb WHERE id IN (1, 2, 3)"),
+            Err("test_rule".to_owned())
+        );
+        assert_eq!(
+            check(&firewall, "SELECT * FROM ks.tb WHERE id IN (1, 2)"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_log_only() {
+        let firewall = single_rule(RuleMatch::DropKeyspace, RuleMode::LogOnly);
+        assert_eq!(check(&firewall, "DROP KEYSPACE ks"), Ok(None));
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let firewall = single_rule(
+            RuleMatch::SelectWithoutWhereOrLimit,
+            RuleMode::Rewrite { limit: 100 },
+        );
+        assert_eq!(
+            check(&firewall, "SELECT * FROM ks.tb"),
+            Ok(Some("SELECT * FROM ks.tb LIMIT 100".to_owned()))
+        );
+        assert_eq!(check(&firewall, "SELECT * FROM ks.tb LIMIT 10"), Ok(None));
+    }
+
+    #[test]
+    fn test_rewrite_prepare_body() {
+        let query = "SELECT * FROM ks.tb";
+        let mut body = (query.len() as i32).to_be_bytes().to_vec();
+        body.extend(query.as_bytes());
+        body.extend([0, 0, 0, 0]);
+
+        set_prepare_query(&mut body, "SELECT * FROM ks.tb LIMIT 100");
+        assert_eq!(prepare_query(&body), Some("SELECT * FROM ks.tb LIMIT 100"));
+        assert_eq!(body[body.len() - 4..], [0, 0, 0, 0]);
+    }
+}
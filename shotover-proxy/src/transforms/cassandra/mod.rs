@@ -1,4 +1,90 @@
+use crate::codec::cassandra::CassandraCodec;
+use crate::error::ChainResponse;
+use crate::message::Message;
+use crate::transforms::Wrapper;
+use anyhow::{anyhow, Result};
+use cassandra_protocol::compression::Compression;
+use serde::Deserialize;
+
+pub mod authenticate;
+pub mod authorize;
+pub mod auto_prepare;
 mod connection;
+pub mod consistency;
+pub mod firewall;
 pub mod peers_rewrite;
+pub mod retry;
 pub mod sink_cluster;
 pub mod sink_single;
+
+/// The compression shotover requests from cassandra, configured independently of the compression requested by the client.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum CassandraCompression {
+    None,
+    Lz4,
+    Snappy,
+}
+
+impl From<CassandraCompression> for Compression {
+    fn from(compression: CassandraCompression) -> Self {
+        match compression {
+            CassandraCompression::None => Compression::None,
+            CassandraCompression::Lz4 => Compression::Lz4,
+            CassandraCompression::Snappy => Compression::Snappy,
+        }
+    }
+}
+
+/// Create a codec for a connection to cassandra.
+/// When compression is not configured, the compression requested by the client is passed through to cassandra.
+fn create_codec(compression: Option<CassandraCompression>) -> CassandraCodec {
+    match compression {
+        Some(compression) => CassandraCodec::new_with_compression(compression.into()),
+        None => CassandraCodec::new(),
+    }
+}
+
+/// How a transform handles a request: either it responds to the request itself,
+/// or it forwards the request to the next transform along with whatever it needs to process the response.
+pub(crate) enum Handling<T> {
+    Respond(Message),
+    Forward(Message, T),
+}
+
+/// Sends the forwarded requests to the next transform and passes each of their responses through `map_response`.
+/// Returns a response for every request, in the same order as `requests`.
+pub(crate) async fn respond_or_forward<T>(
+    message_wrapper: &mut Wrapper<'_>,
+    requests: Vec<Handling<T>>,
+    mut map_response: impl FnMut(T, Message) -> Result<Message>,
+) -> ChainResponse {
+    let mut forwarded = Vec::with_capacity(requests.len());
+    // Ok holds a response from the transform itself, Err the state of a forwarded request waiting for its response
+    let mut handled = Vec::with_capacity(requests.len());
+    for request in requests {
+        handled.push(match request {
+            Handling::Respond(response) => Ok(response),
+            Handling::Forward(request, state) => {
+                forwarded.push(request);
+                Err(state)
+            }
+        });
+    }
+
+    let mut responses = message_wrapper
+        .call_next_transform_with(forwarded)
+        .await?
+        .into_iter();
+    handled
+        .into_iter()
+        .map(|handled| match handled {
+            Ok(response) => Ok(response),
+            Err(state) => {
+                let response = responses.next().ok_or_else(|| {
+                    anyhow!("the next transform returned fewer responses than requests")
+                })?;
+                map_response(state, response)
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,392 @@
+use crate::frame::cassandra::{parse_statement_single, prepare_query};
+use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
+use crate::message::Message;
+use anyhow::Result;
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use cassandra_protocol::types::CBytesShort;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use metrics::counter;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+const DEFAULT_MAX_RETRIES: u32 = 1;
+const DEFAULT_BACKOFF_MS: u64 = 10;
+/// Statements prepared after this many SELECT statements are treated as writes
+const MAX_PREPARED_READS: usize = 10_000;
+
+/// Decides which failed requests a cassandra sink sends again, similar to the default retry policy of the java driver.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RetryPolicyConfig {
+    /// The maximum number of times a request is retried after the node responds with an `Overloaded` error, defaults to 1.
+    pub max_overloaded_retries: Option<u32>,
+    /// The maximum number of times a request is retried after the node responds with an `Unavailable` error, defaults to 1.
+    pub max_unavailable_retries: Option<u32>,
+    /// The maximum number of times a request is retried after the node responds with a `ReadTimeout` error, defaults to 1.
+    pub max_read_timeout_retries: Option<u32>,
+    /// The maximum number of times a request is retried after the node responds with a `WriteTimeout` error, defaults to 1.
+    pub max_write_timeout_retries: Option<u32>,
+    /// The maximum number of times a request is retried after the connection to the node fails, defaults to 1.
+    pub max_connection_failure_retries: Option<u32>,
+    /// The delay before the first retry of a request in milliseconds, doubled for each following retry, defaults to 10.
+    pub backoff_ms: Option<u64>,
+    /// Writes are only retried when they are idempotent, which shotover cannot tell from the request.
+    /// Set this to true when every write sent through shotover is idempotent, defaults to false.
+    pub writes_are_idempotent: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryReason {
+    Overloaded,
+    Unavailable,
+    ReadTimeout,
+    WriteTimeout,
+    ConnectionFailure,
+}
+
+impl RetryReason {
+    /// The reason to retry a request that received `response`, `Err` meaning that the connection failed before a response was received.
+    pub fn from_response(response: &mut Result<Message>) -> Option<RetryReason> {
+        match response {
+            Ok(message) => match message.frame() {
+                Some(Frame::Cassandra(CassandraFrame {
+                    operation: CassandraOperation::Error(ErrorBody { ty, .. }),
+                    ..
+                })) => match ty {
+                    ErrorType::Overloaded => Some(RetryReason::Overloaded),
+                    ErrorType::Unavailable(_) => Some(RetryReason::Unavailable),
+                    ErrorType::ReadTimeout(_) => Some(RetryReason::ReadTimeout),
+                    ErrorType::WriteTimeout(_) => Some(RetryReason::WriteTimeout),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Err(_) => Some(RetryReason::ConnectionFailure),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            RetryReason::Overloaded => "overloaded",
+            RetryReason::Unavailable => "unavailable",
+            RetryReason::ReadTimeout => "read_timeout",
+            RetryReason::WriteTimeout => "write_timeout",
+            RetryReason::ConnectionFailure => "connection_failure",
+        }
+    }
+}
+
+/// The retries performed so far for a single request.
+#[derive(Debug, Default)]
+pub struct RetryState {
+    overloaded: u32,
+    unavailable: u32,
+    read_timeout: u32,
+    write_timeout: u32,
+    connection_failure: u32,
+}
+
+impl RetryState {
+    fn count(&mut self, reason: RetryReason) -> &mut u32 {
+        match reason {
+            RetryReason::Overloaded => &mut self.overloaded,
+            RetryReason::Unavailable => &mut self.unavailable,
+            RetryReason::ReadTimeout => &mut self.read_timeout,
+            RetryReason::WriteTimeout => &mut self.write_timeout,
+            RetryReason::ConnectionFailure => &mut self.connection_failure,
+        }
+    }
+
+    fn total(&self) -> u32 {
+        self.overloaded
+            + self.unavailable
+            + self.read_timeout
+            + self.write_timeout
+            + self.connection_failure
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_overloaded_retries: u32,
+    max_unavailable_retries: u32,
+    max_read_timeout_retries: u32,
+    max_write_timeout_retries: u32,
+    max_connection_failure_retries: u32,
+    backoff: Duration,
+    writes_are_idempotent: bool,
+    chain_name: String,
+    transform_name: &'static str,
+}
+
+impl RetryPolicy {
+    pub fn new(
+        config: &RetryPolicyConfig,
+        chain_name: String,
+        transform_name: &'static str,
+    ) -> Self {
+        RetryPolicy {
+            max_overloaded_retries: config.max_overloaded_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            max_unavailable_retries: config
+                .max_unavailable_retries
+                .unwrap_or(DEFAULT_MAX_RETRIES),
+            max_read_timeout_retries: config
+                .max_read_timeout_retries
+                .unwrap_or(DEFAULT_MAX_RETRIES),
+            max_write_timeout_retries: config
+                .max_write_timeout_retries
+                .unwrap_or(DEFAULT_MAX_RETRIES),
+            max_connection_failure_retries: config
+                .max_connection_failure_retries
+                .unwrap_or(DEFAULT_MAX_RETRIES),
+            backoff: Duration::from_millis(config.backoff_ms.unwrap_or(DEFAULT_BACKOFF_MS)),
+            writes_are_idempotent: config.writes_are_idempotent.unwrap_or(false),
+            chain_name,
+            transform_name,
+        }
+    }
+
+    /// Returns how long to wait before sending a request again after it failed for `reason`,
+    /// or `None` when it should not be retried. `is_read` is whether the request is a read, see [`PreparedReads::is_read`].
+    pub fn retry_delay(
+        &self,
+        is_read: bool,
+        reason: RetryReason,
+        state: &mut RetryState,
+    ) -> Option<Duration> {
+        let max_retries = match reason {
+            RetryReason::Overloaded => self.max_overloaded_retries,
+            RetryReason::Unavailable => self.max_unavailable_retries,
+            RetryReason::ReadTimeout => self.max_read_timeout_retries,
+            RetryReason::WriteTimeout => self.max_write_timeout_retries,
+            RetryReason::ConnectionFailure => self.max_connection_failure_retries,
+        };
+        if *state.count(reason) >= max_retries {
+            return None;
+        }
+
+        // An unavailable node rejects the request before executing it, so it is always safe to retry.
+        // Otherwise the request may have been applied, so only requests that can be applied twice are retried.
+        if reason != RetryReason::Unavailable && !self.writes_are_idempotent && !is_read {
+            return None;
+        }
+
+        let delay = self.backoff * 2u32.saturating_pow(state.total());
+        *state.count(reason) += 1;
+        counter!("retries", 1, "chain" => self.chain_name.clone(), "transform" => self.transform_name, "reason" => reason.as_str());
+        Some(delay)
+    }
+}
+
+/// The ids of the prepared SELECT statements, shared between clones as a statement has the same id on every connection.
+#[derive(Debug, Clone, Default)]
+pub struct PreparedReads {
+    ids: Arc<RwLock<HashSet<CBytesShort>>>,
+}
+
+impl PreparedReads {
+    /// Record the statement prepared by the PREPARE `request` when `response` shows that it was prepared.
+    pub fn record(&self, request: &mut Message, response: &mut Message) {
+        let is_select = match request.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Prepare(body),
+                ..
+            })) => prepare_query(body).map_or(false, |query| {
+                matches!(parse_statement_single(query), CassandraStatement::Select(_))
+            }),
+            _ => false,
+        };
+        if !is_select {
+            return;
+        }
+
+        if let Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Result(CassandraResult::Prepared(prepared)),
+            ..
+        })) = response.frame()
+        {
+            let mut ids = self.ids.write().unwrap();
+            if ids.len() < MAX_PREPARED_READS {
+                ids.insert(prepared.id.clone());
+            }
+        }
+    }
+
+    /// A SELECT query or an EXECUTE of a prepared SELECT, which is idempotent and so can be safely sent more than once.
+    /// An EXECUTE of a statement that was not prepared through [`PreparedReads::record`] is not considered a read.
+    pub fn is_read(&self, request: &mut Message) -> bool {
+        match request.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Query { query, .. },
+                ..
+            })) => matches!(query.as_ref(), CassandraStatement::Select(_)),
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Execute(execute),
+                ..
+            })) => self.ids.read().unwrap().contains(&execute.id),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_retry {
+    use super::*;
+    use crate::frame::cassandra::set_prepare_query;
+    use cassandra_protocol::frame::message_execute::BodyReqExecuteOwned;
+    use cassandra_protocol::frame::message_result::{
+        BodyResResultPrepared, PreparedMetadata, RowsMetadata, RowsMetadataFlags,
+    };
+    use cassandra_protocol::frame::Version;
+    use cassandra_protocol::query::QueryParams;
+
+    fn message(operation: CassandraOperation) -> Message {
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: 0,
+            tracing_id: None,
+            warnings: vec![],
+            operation,
+        }))
+    }
+
+    fn query(query: &str) -> Message {
+        message(CassandraOperation::Query {
+            query: Box::new(parse_statement_single(query)),
+            params: Box::new(QueryParams::default()),
+        })
+    }
+
+    fn prepare(query: &str) -> Message {
+        let mut body = vec![];
+        set_prepare_query(&mut body, query);
+        message(CassandraOperation::Prepare(body))
+    }
+
+    fn prepared(id: &CBytesShort) -> Message {
+        message(CassandraOperation::Result(CassandraResult::Prepared(
+            Box::new(BodyResResultPrepared {
+                id: id.clone(),
+                result_metadata_id: None,
+                metadata: PreparedMetadata {
+                    pk_indexes: vec![],
+                    global_table_spec: None,
+                    col_specs: vec![],
+                },
+                result_metadata: RowsMetadata {
+                    flags: RowsMetadataFlags::empty(),
+                    columns_count: 0,
+                    paging_state: None,
+                    new_metadata_id: None,
+                    global_table_spec: None,
+                    col_specs: vec![],
+                },
+            }),
+        )))
+    }
+
+    fn execute(id: &CBytesShort) -> Message {
+        message(CassandraOperation::Execute(Box::new(BodyReqExecuteOwned {
+            id: id.clone(),
+            result_metadata_id: None,
+            query_parameters: QueryParams::default(),
+        })))
+    }
+
+    fn retry_policy(writes_are_idempotent: bool) -> RetryPolicy {
+        RetryPolicy::new(
+            &RetryPolicyConfig {
+                max_overloaded_retries: Some(2),
+                max_unavailable_retries: None,
+                max_read_timeout_retries: None,
+                max_write_timeout_retries: None,
+                max_connection_failure_retries: Some(0),
+                backoff_ms: Some(10),
+                writes_are_idempotent: Some(writes_are_idempotent),
+            },
+            "chain".to_owned(),
+            "transform",
+        )
+    }
+
+    #[test]
+    fn test_retry_limits_and_backoff() {
+        let policy = retry_policy(false);
+        let mut state = RetryState::default();
+
+        assert_eq!(
+            policy.retry_delay(true, RetryReason::Overloaded, &mut state),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(
+            policy.retry_delay(true, RetryReason::ReadTimeout, &mut state),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            policy.retry_delay(true, RetryReason::Overloaded, &mut state),
+            Some(Duration::from_millis(40))
+        );
+        assert_eq!(
+            policy.retry_delay(true, RetryReason::Overloaded, &mut state),
+            None
+        );
+        assert_eq!(
+            policy.retry_delay(true, RetryReason::ReadTimeout, &mut state),
+            None
+        );
+        assert_eq!(
+            policy.retry_delay(true, RetryReason::ConnectionFailure, &mut state),
+            None
+        );
+    }
+
+    #[test]
+    fn test_writes_only_retried_when_idempotent() {
+        let policy = retry_policy(false);
+        assert_eq!(
+            policy.retry_delay(false, RetryReason::WriteTimeout, &mut RetryState::default()),
+            None
+        );
+        assert_eq!(
+            policy.retry_delay(false, RetryReason::Unavailable, &mut RetryState::default()),
+            Some(Duration::from_millis(10))
+        );
+
+        let policy = retry_policy(true);
+        assert_eq!(
+            policy.retry_delay(false, RetryReason::WriteTimeout, &mut RetryState::default()),
+            Some(Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn test_is_read() {
+        let prepared_reads = PreparedReads::default();
+        assert!(prepared_reads.is_read(&mut query("SELECT * FROM ks.table")));
+        assert!(!prepared_reads.is_read(&mut query("INSERT INTO ks.table (id) VALUES (1)")));
+
+        let select_id = CBytesShort::new(vec![1]);
+        let insert_id = CBytesShort::new(vec![2]);
+        let unknown_id = CBytesShort::new(vec![3]);
+        prepared_reads.record(
+            &mut prepare("SELECT * FROM ks.table WHERE id = ?"),
+            &mut prepared(&select_id),
+        );
+        prepared_reads.record(
+            &mut prepare("INSERT INTO ks.table (id) VALUES (?)"),
+            &mut prepared(&insert_id),
+        );
+        prepared_reads.record(
+            &mut prepare("SELECT * FROM ks.table WHERE id = ?"),
+            &mut query("SELECT * FROM ks.table"),
+        );
+
+        // clones share the prepared statements as they are the same on every connection
+        let prepared_reads = prepared_reads.clone();
+        assert!(prepared_reads.is_read(&mut execute(&select_id)));
+        assert!(!prepared_reads.is_read(&mut execute(&insert_id)));
+        // a statement that was not prepared through shotover is assumed to be a write
+        assert!(!prepared_reads.is_read(&mut execute(&unknown_id)));
+    }
+}
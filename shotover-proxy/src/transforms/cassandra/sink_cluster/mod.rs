@@ -3,12 +3,18 @@ use crate::frame::cassandra::{parse_statement_single, CassandraMetadata};
 use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
 use crate::message::{IntSize, Message, MessageValue, Messages};
 use crate::tls::{TlsConnector, TlsConnectorConfig};
-use crate::transforms::cassandra::connection::CassandraConnection;
+use crate::transforms::cassandra::connection::{CassandraConnection, Observer};
+use crate::transforms::cassandra::retry::{
+    PreparedReads, RetryPolicy, RetryPolicyConfig, RetryReason, RetryState,
+};
+use crate::transforms::cassandra::CassandraCompression;
 use crate::transforms::util::Response;
 use crate::transforms::{Transform, Transforms, Wrapper};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use cassandra_protocol::consistency::Consistency;
 use cassandra_protocol::events::ServerEvent;
+use cassandra_protocol::frame::events::StatusChange;
 use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType, UnpreparedError};
 use cassandra_protocol::frame::message_execute::BodyReqExecuteOwned;
 use cassandra_protocol::frame::message_result::PreparedMetadata;
@@ -24,19 +30,30 @@ use futures::StreamExt;
 use itertools::Itertools;
 use metrics::{register_counter, Counter};
 use node::{CassandraNode, ConnectionFactory};
-use node_pool::{GetReplicaErr, NodePool};
+use node_health::{create_health_check_task, HealthCheckConfig, NodeHealth};
+use node_load::{LoadBalancingPolicy, NodeLoad};
+use node_pool::{GetReplicaErr, NodePool, RoutingKey};
+use partition_key::{identifier_name, PartitionKeys};
 use rand::prelude::*;
 use serde::Deserialize;
+use speculative_execution::{
+    SpeculativeConnection, SpeculativeExecution, SpeculativeExecutionConfig,
+};
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot, watch};
-use topology::{create_topology_task, TaskConnectionInfo};
+use topology::{create_topology_task, RemoteNodes, TaskConnectionInfo};
 use uuid::Uuid;
 use version_compare::Cmp;
 
 pub mod node;
+mod node_health;
+mod node_load;
 mod node_pool;
+pub mod partition_key;
 mod routing_key;
+mod speculative_execution;
 mod token_map;
 pub mod topology;
 
@@ -50,6 +67,30 @@ pub struct CassandraSinkClusterConfig {
     pub shotover_nodes: Vec<ShotoverNode>,
     pub tls: Option<TlsConnectorConfig>,
     pub read_timeout: Option<u64>,
+    pub compression: Option<CassandraCompression>,
+    pub speculative_execution: Option<SpeculativeExecutionConfig>,
+    pub retry_policy: Option<RetryPolicyConfig>,
+    pub health_check: Option<HealthCheckConfig>,
+    pub load_balancing: Option<LoadBalancingPolicy>,
+    pub remote_dc_failover: Option<RemoteDcFailoverConfig>,
+    pub schema_agreement: Option<SchemaAgreementConfig>,
+}
+
+/// When every local data center replica of a request is down, or every node in the local rack is down,
+/// the request is sent to a node in a remote data center instead.
+/// Only requests with a consistency level of ANY, ONE or LOCAL_ONE fail over, LOCAL_ONE is upgraded to ONE.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemoteDcFailoverConfig {
+    /// The maximum number of nodes in each remote data center that requests fail over to.
+    pub max_hosts_per_dc: usize,
+}
+
+/// The response to a statement that changes the schema is held back until every node in the data center reports the same schema version,
+/// polling every `poll_interval_ms` milliseconds for at most `timeout_ms` milliseconds.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SchemaAgreementConfig {
+    pub poll_interval_ms: u64,
+    pub timeout_ms: u64,
 }
 
 impl CassandraSinkClusterConfig {
@@ -76,6 +117,13 @@ impl CassandraSinkClusterConfig {
                 local_node,
                 tls,
                 self.read_timeout,
+                self.compression,
+                self.speculative_execution.clone(),
+                self.retry_policy.as_ref(),
+                self.health_check.as_ref(),
+                self.load_balancing,
+                self.remote_dc_failover.clone(),
+                self.schema_agreement.clone(),
             ),
         )))
     }
@@ -102,6 +150,21 @@ pub struct CassandraSinkCluster {
     chain_name: String,
     failed_requests: Counter,
     read_timeout: Option<Duration>,
+    /// Shared by every connection, `None` when speculative execution is disabled
+    speculative_execution: Option<SpeculativeExecution>,
+    /// Connections opened by speculative executions, which are added to the pool for reuse
+    speculative_connections_tx: mpsc::UnboundedSender<(Uuid, CassandraConnection)>,
+    speculative_connections_rx: mpsc::UnboundedReceiver<(Uuid, CassandraConnection)>,
+    retry_policy: Option<RetryPolicy>,
+    prepared_reads: PreparedReads,
+    /// Shared by every connection, `None` when health checking is disabled
+    node_health: Option<Arc<NodeHealth>>,
+    /// Shared by every connection, `None` when nodes are chosen at random
+    node_load: Option<Arc<NodeLoad>>,
+    remote_dc_failover: Option<RemoteDcFailoverConfig>,
+    remote_dc_failovers: Counter,
+    reprepares: Counter,
+    schema_agreement: Option<SchemaAgreementConfig>,
     local_table: FQName,
     peers_table: FQName,
     peers_v2_table: FQName,
@@ -112,12 +175,17 @@ pub struct CassandraSinkCluster {
     /// Addditionally any changes to nodes_rx is observed and copied over.
     pool: NodePool,
     nodes_rx: watch::Receiver<Vec<CassandraNode>>,
+    remote_nodes_rx: watch::Receiver<RemoteNodes>,
+    partition_keys_rx: watch::Receiver<Arc<PartitionKeys>>,
+    /// The keyspace set by the client's most recent USE statement
+    keyspace: Option<String>,
     rng: SmallRng,
     task_handshake_tx: mpsc::Sender<TaskConnectionInfo>,
 }
 
 impl Clone for CassandraSinkCluster {
     fn clone(&self) -> Self {
+        let (speculative_connections_tx, speculative_connections_rx) = mpsc::unbounded_channel();
         Self {
             contact_points: self.contact_points.clone(),
             shotover_peers: self.shotover_peers.clone(),
@@ -128,15 +196,29 @@ impl Clone for CassandraSinkCluster {
             chain_name: self.chain_name.clone(),
             failed_requests: self.failed_requests.clone(),
             read_timeout: self.read_timeout,
+            speculative_execution: self.speculative_execution.clone(),
+            speculative_connections_tx,
+            speculative_connections_rx,
+            retry_policy: self.retry_policy.clone(),
+            prepared_reads: self.prepared_reads.clone(),
+            node_health: self.node_health.clone(),
+            node_load: self.node_load.clone(),
+            remote_dc_failover: self.remote_dc_failover.clone(),
+            remote_dc_failovers: self.remote_dc_failovers.clone(),
+            reprepares: self.reprepares.clone(),
+            schema_agreement: self.schema_agreement.clone(),
             local_table: self.local_table.clone(),
             peers_table: self.peers_table.clone(),
             peers_v2_table: self.peers_v2_table.clone(),
             system_keyspaces: self.system_keyspaces.clone(),
             local_shotover_node: self.local_shotover_node.clone(),
-            pool: NodePool::new(vec![]),
+            pool: NodePool::new(vec![], self.node_health.clone(), self.node_load.clone()),
             // Because the self.nodes_rx is always copied from the original nodes_rx created before any node lists were sent,
             // once a single node list has been sent all new connections will immediately recognize it as a change.
             nodes_rx: self.nodes_rx.clone(),
+            remote_nodes_rx: self.remote_nodes_rx.clone(),
+            partition_keys_rx: self.partition_keys_rx.clone(),
+            keyspace: None,
             rng: SmallRng::from_rng(rand::thread_rng()).unwrap(),
             task_handshake_tx: self.task_handshake_tx.clone(),
         }
@@ -152,22 +234,56 @@ impl CassandraSinkCluster {
         local_shotover_node: ShotoverNode,
         tls: Option<TlsConnector>,
         timeout: Option<u64>,
+        compression: Option<CassandraCompression>,
+        speculative_execution: Option<SpeculativeExecutionConfig>,
+        retry_policy: Option<&RetryPolicyConfig>,
+        health_check: Option<&HealthCheckConfig>,
+        load_balancing: Option<LoadBalancingPolicy>,
+        remote_dc_failover: Option<RemoteDcFailoverConfig>,
+        schema_agreement: Option<SchemaAgreementConfig>,
     ) -> Self {
         let failed_requests = register_counter!("failed_requests", "chain" => chain_name.clone(), "transform" => "CassandraSinkCluster");
+        let remote_dc_failovers = register_counter!("remote_dc_failovers", "chain" => chain_name.clone(), "transform" => "CassandraSinkCluster");
+        let reprepares = register_counter!("reprepares", "chain" => chain_name.clone(), "transform" => "CassandraSinkCluster");
         let receive_timeout = timeout.map(Duration::from_secs);
+        let retry_policy = retry_policy
+            .map(|config| RetryPolicy::new(config, chain_name.clone(), "CassandraSinkCluster"));
+        let speculative_execution =
+            speculative_execution.map(|config| SpeculativeExecution::new(&config, &chain_name));
+        let (speculative_connections_tx, speculative_connections_rx) = mpsc::unbounded_channel();
 
         let (local_nodes_tx, local_nodes_rx) = watch::channel(vec![]);
+        let (remote_nodes_tx, remote_nodes_rx) = watch::channel(RemoteNodes::new());
+        let (partition_keys_tx, partition_keys_rx) =
+            watch::channel(Arc::new(PartitionKeys::default()));
         let (task_handshake_tx, task_handshake_rx) = mpsc::channel(1);
 
         create_topology_task(
             local_nodes_tx,
+            remote_nodes_tx,
+            partition_keys_tx,
             task_handshake_rx,
             local_shotover_node.data_center.clone(),
         );
 
+        let node_health = health_check.map(|config| {
+            let node_health = Arc::new(NodeHealth::new(config, chain_name.clone()));
+            create_health_check_task(
+                node_health.clone(),
+                local_nodes_rx.clone(),
+                ConnectionFactory::new(tls.clone(), compression),
+                Duration::from_millis(config.probe_interval_ms),
+            );
+            node_health
+        });
+        let node_load = match load_balancing {
+            None | Some(LoadBalancingPolicy::Random) => None,
+            Some(policy) => Some(Arc::new(NodeLoad::new(policy))),
+        };
+
         Self {
             contact_points,
-            connection_factory: ConnectionFactory::new(tls),
+            connection_factory: ConnectionFactory::new(tls, compression),
             shotover_peers,
             init_handshake_connection: None,
             init_handshake_address: None,
@@ -175,6 +291,18 @@ impl CassandraSinkCluster {
             chain_name,
             failed_requests,
             read_timeout: receive_timeout,
+            speculative_execution,
+            speculative_connections_tx,
+            speculative_connections_rx,
+            retry_policy,
+            prepared_reads: PreparedReads::default(),
+            pool: NodePool::new(vec![], node_health.clone(), node_load.clone()),
+            node_health,
+            node_load,
+            remote_dc_failover,
+            remote_dc_failovers,
+            reprepares,
+            schema_agreement,
             local_table: FQName::new("system", "local"),
             peers_table: FQName::new("system", "peers"),
             peers_v2_table: FQName::new("system", "peers_v2"),
@@ -184,14 +312,37 @@ impl CassandraSinkCluster {
                 Identifier::parse("system_distributed"),
             ],
             local_shotover_node,
-            pool: NodePool::new(vec![]),
             nodes_rx: local_nodes_rx,
+            remote_nodes_rx,
+            partition_keys_rx,
+            keyspace: None,
             rng: SmallRng::from_rng(rand::thread_rng()).unwrap(),
             task_handshake_tx,
         }
     }
 }
 
+/// Only requests that a single node of any data center can satisfy are allowed to fail over to a remote data center.
+fn allows_remote_dc_failover(message: &mut Message) -> bool {
+    match message.frame() {
+        Some(Frame::Cassandra(CassandraFrame { operation, .. })) => matches!(
+            operation.consistency(),
+            Some(Consistency::Any | Consistency::One | Consistency::LocalOne)
+        ),
+        _ => false,
+    }
+}
+
+/// LOCAL_ONE is upgraded to ONE so that a request failed over to a remote data center may be served by a replica in any data center.
+fn upgrade_local_one(message: &mut Message) {
+    if let Some(Frame::Cassandra(CassandraFrame { operation, .. })) = message.frame() {
+        if operation.consistency() == Some(Consistency::LocalOne) {
+            operation.set_consistency(Consistency::One);
+            message.invalidate_cache();
+        }
+    }
+}
+
 fn create_query(messages: &Messages, query: &str, version: Version) -> Result<Message> {
     let stream_id = get_unused_stream_id(messages)?;
     Ok(Message::from_frame(Frame::Cassandra(CassandraFrame {
@@ -211,6 +362,19 @@ impl CassandraSinkCluster {
         if self.nodes_rx.has_changed()? {
             self.pool.update_nodes(&mut self.nodes_rx);
         }
+        if self.remote_nodes_rx.has_changed()? {
+            self.pool.update_remote_nodes(&mut self.remote_nodes_rx);
+        }
+        if self.partition_keys_rx.has_changed()? {
+            self.pool.update_partition_keys(&mut self.partition_keys_rx);
+        }
+        while let Ok((host_id, connection)) = self.speculative_connections_rx.try_recv() {
+            if let Some(node) = self.pool.node_mut(host_id) {
+                if node.outbound.is_none() {
+                    node.outbound = Some(connection);
+                }
+            }
+        }
 
         let tables_to_rewrite: Vec<TableToRewrite> = messages
             .iter_mut()
@@ -243,7 +407,7 @@ impl CassandraSinkCluster {
                     .unwrap()
             } else {
                 self.pool
-                    .get_random_node_in_dc_rack(&self.local_shotover_node.rack, &mut self.rng)
+                    .select_node_in_dc_rack(&self.local_shotover_node.rack, &mut self.rng)
                     .address
             };
 
@@ -276,8 +440,18 @@ impl CassandraSinkCluster {
 
         let mut responses_future_prepare = FuturesOrdered::new();
 
+        // The requests that may be retried or re-prepared along with the node they were sent to, kept in the same order as responses_future
+        let mut sent_requests = Vec::with_capacity(messages.len());
+
+        // The PREPARE requests along with the index of their response
+        let mut prepare_requests = vec![];
+
         for mut message in messages {
             let (return_chan_tx, return_chan_rx) = oneshot::channel();
+            let mut sent_request = None;
+            if is_prepare_message(&mut message) {
+                prepare_requests.push((responses_future.len(), message.clone()));
+            }
             if self.pool.nodes().is_empty()
                 || !self.init_handshake_complete
                 // system.local and system.peers must be routed to the same node otherwise the system.local node will be amongst the system.peers nodes and a node will be missing
@@ -289,7 +463,10 @@ impl CassandraSinkCluster {
                     .as_mut()
                     .unwrap()
                     .send(message, return_chan_tx)?;
-            } else if is_use_statement(&mut message) {
+            } else if let Some(keyspace) = get_use_keyspace(&mut message) {
+                // Token aware routing of statements that do not specify a keyspace relies on the current keyspace
+                self.keyspace = Some(keyspace);
+
                 // Adding the USE statement to the handshake ensures that any new connection
                 // created will have the correct keyspace setup.
                 self.connection_factory.set_use_message(message.clone());
@@ -334,76 +511,265 @@ impl CassandraSinkCluster {
                     .ok_or_else(|| anyhow!("no connections found in connection pool"))?
                     .send(message, return_chan_tx)?;
             } else {
-                // If the message is an execute we should perform token aware routing
-                if let Some((execute, metadata)) = get_execute_message(&mut message) {
+                let is_read = self.prepared_reads.is_read(&mut message);
+
+                // If the message is an execute, or a query or batch with literal partition key values, we should perform token aware routing
+                let routing_key = if let Some((execute, metadata)) =
+                    get_execute_message(&mut message)
+                {
                     match self
                         .pool
-                        .replica_node(execute, &metadata.version, &mut self.rng)
+                        .execute_routing_key(execute, metadata.version)
                         .await
                     {
-                        Ok(Some(replica_node)) => {
-                            replica_node
-                                .get_connection(&self.connection_factory)
-                                .await?
-                                .send(message, return_chan_tx)?;
+                        Ok(routing_key) => Ok(routing_key),
+                        Err(_) => Err((execute.id.clone(), metadata)),
+                    }
+                } else {
+                    Ok(match message.frame() {
+                        Some(Frame::Cassandra(CassandraFrame {
+                            operation, version, ..
+                        })) => {
+                            self.pool
+                                .query_routing_key(operation, *version, self.keyspace.as_deref())
+                                .await
                         }
-                        Ok(None) => {
-                            let node = self.pool.get_random_node_in_dc_rack(
+                        _ => None,
+                    })
+                };
+
+                match routing_key {
+                    Ok(routing_key) => {
+                        // Set when the request has replicas in the local data center but none of them are up
+                        let mut all_replicas_down = false;
+                        let replica_node = match &routing_key {
+                            Some(routing_key) => match self.pool.replica_node_for_routing_key(
+                                routing_key,
                                 &self.local_shotover_node.rack,
                                 &mut self.rng,
-                            );
-                            node.get_connection(&self.connection_factory)
-                                .await?
-                                .send(message, return_chan_tx)?;
-                        }
-                        Err(GetReplicaErr::NoMetadata) => {
-                            let id = execute.id.clone();
-                            tracing::info!("forcing re-prepare on {:?}", id);
-                            // this shotover node doesn't have the metadata
-                            // send an unprepared error in response to force
-                            // the client to reprepare the query
-                            return_chan_tx
-                                .send(Response {
-                                    original: message.clone(),
-                                    response: Ok(Message::from_frame(Frame::Cassandra(
-                                        CassandraFrame {
-                                            operation: CassandraOperation::Error(ErrorBody {
-                                                message: "Shotover does not have this query's metadata. Please re-prepare on this Shotover host before sending again.".into(),
-                                                ty: ErrorType::Unprepared(UnpreparedError {
-                                                    id,
-                                                }),
-                                            }),
-                                            stream_id: metadata.stream_id,
-                                            tracing_id: metadata.tracing_id,
-                                            version: metadata.version,
-                                            warnings: vec![],
-                                        },
-                                    ))),
-                                }).expect("the receiver is guaranteed to be alive, so this must succeed");
-                        }
-                        Err(GetReplicaErr::Other(err)) => {
-                            return Err(err);
+                            ) {
+                                Ok(replica_node) => replica_node,
+                                Err(GetReplicaErr::NoReplicaUp) => {
+                                    all_replicas_down = true;
+                                    None
+                                }
+                                Err(GetReplicaErr::NoMetadata) => None,
+                            },
+                            None => None,
+                        };
+
+                        let (host_id, connection) = match replica_node {
+                            Some(replica_node) => (
+                                replica_node.host_id,
+                                replica_node
+                                    .get_connection(&self.connection_factory)
+                                    .await?
+                                    .clone(),
+                            ),
+                            // otherwise just send to a random node
+                            None => {
+                                let remote_node = match &self.remote_dc_failover {
+                                    Some(remote_dc_failover)
+                                        if (all_replicas_down
+                                            || !self.pool.has_up_node_in_dc_rack(
+                                                &self.local_shotover_node.rack,
+                                            ))
+                                            && allows_remote_dc_failover(&mut message) =>
+                                    {
+                                        self.pool.select_remote_node(
+                                            remote_dc_failover.max_hosts_per_dc,
+                                            &mut self.rng,
+                                        )
+                                    }
+                                    _ => None,
+                                };
+                                match remote_node {
+                                    Some(node) => {
+                                        upgrade_local_one(&mut message);
+                                        self.remote_dc_failovers.increment(1);
+                                        (
+                                            node.host_id,
+                                            node.get_connection(&self.connection_factory)
+                                                .await?
+                                                .clone(),
+                                        )
+                                    }
+                                    None => {
+                                        let node = self.pool.select_node_in_dc_rack(
+                                            &self.local_shotover_node.rack,
+                                            &mut self.rng,
+                                        );
+                                        (
+                                            node.host_id,
+                                            node.get_connection(&self.connection_factory)
+                                                .await?
+                                                .clone(),
+                                        )
+                                    }
+                                }
+                            }
+                        };
+
+                        // A read is idempotent so it is safe to also send it to another replica when it is slow to respond
+                        let speculative_node = match (&self.speculative_execution, &routing_key) {
+                            (Some(_), Some(routing_key)) if is_read => {
+                                self.pool.select_other_replica(
+                                    routing_key,
+                                    host_id,
+                                    &self.local_shotover_node.rack,
+                                    &mut self.rng,
+                                )
+                            }
+                            // Any node can coordinate a read that cannot be routed to a replica
+                            (Some(_), None) if is_read => {
+                                self.pool.select_node_in_dc_rack_excluding(
+                                    &self.local_shotover_node.rack,
+                                    &[host_id],
+                                    &mut self.rng,
+                                )
+                            }
+                            _ => None,
+                        };
+                        // The connection is only opened once the read is actually speculatively executed
+                        let speculative_connection = match speculative_node {
+                            Some(node) => Some((
+                                node.host_id,
+                                match &node.outbound {
+                                    Some(connection) => {
+                                        SpeculativeConnection::Open(connection.clone())
+                                    }
+                                    None => SpeculativeConnection::Closed {
+                                        address: node.address,
+                                        connection_factory: self
+                                            .connection_factory
+                                            .clone_with_state(),
+                                        opened_tx: self.speculative_connections_tx.clone(),
+                                    },
+                                },
+                            )),
+                            None => None,
+                        };
+
+                        if self.retry_policy.is_some() || uses_prepared_statements(&mut message) {
+                            sent_request = Some((message.clone(), host_id));
                         }
-                    };
 
-                    // otherwise just send to a random node
-                } else {
-                    let node = self
-                        .pool
-                        .get_random_node_in_dc_rack(&self.local_shotover_node.rack, &mut self.rng);
-                    node.get_connection(&self.connection_factory)
-                        .await?
-                        .send(message, return_chan_tx)?;
+                        let node_observers = NodeObservers {
+                            node_health: self.node_health.clone(),
+                            node_load: self.node_load.clone(),
+                        };
+                        let result = match (&self.speculative_execution, speculative_connection) {
+                            (Some(speculative_execution), Some(speculative_connection)) => {
+                                speculative_execution.send(
+                                    message,
+                                    (host_id, connection),
+                                    speculative_connection,
+                                    return_chan_tx,
+                                    node_observers,
+                                )
+                            }
+                            _ => connection.send_observed(
+                                message,
+                                return_chan_tx,
+                                node_observers.for_node(host_id),
+                            ),
+                        };
+                        // When retrying, the dropped return_chan_tx results in the request being retried as a connection failure
+                        if self.retry_policy.is_none() {
+                            result?;
+                        }
+                    }
+                    Err((id, metadata)) => {
+                        tracing::info!("forcing re-prepare on {:?}", id);
+                        // this shotover node doesn't have the metadata
+                        // send an unprepared error in response to force
+                        // the client to reprepare the query
+                        return_chan_tx
+                            .send(Response {
+                                original: message.clone(),
+                                response: Ok(Message::from_frame(Frame::Cassandra(
+                                    CassandraFrame {
+                                        operation: CassandraOperation::Error(ErrorBody {
+                                            message: "Shotover does not have this query's metadata. Please re-prepare on this Shotover host before sending again.".into(),
+                                            ty: ErrorType::Unprepared(UnpreparedError {
+                                                id,
+                                            }),
+                                        }),
+                                        stream_id: metadata.stream_id,
+                                        tracing_id: metadata.tracing_id,
+                                        version: metadata.version,
+                                        warnings: vec![],
+                                    },
+                                ))),
+                            }).expect("the receiver is guaranteed to be alive, so this must succeed");
+                    }
                 }
             }
 
-            responses_future.push_back(return_chan_rx)
+            responses_future.push_back(return_chan_rx);
+            sent_requests.push(sent_request);
         }
 
-        let mut responses =
-            super::connection::receive(self.read_timeout, &self.failed_requests, responses_future)
+        let mut responses = match self.retry_policy.clone() {
+            None => {
+                super::connection::receive(
+                    self.read_timeout,
+                    &self.failed_requests,
+                    responses_future,
+                )
+                .await?
+            }
+            Some(retry_policy) => {
+                let responses = super::connection::receive_each(
+                    self.read_timeout,
+                    &self.failed_requests,
+                    responses_future,
+                )
                 .await?;
 
+                let mut results = Vec::with_capacity(responses.len());
+                for (sent_request, response) in sent_requests.iter_mut().zip(responses) {
+                    results.push(match sent_request {
+                        Some((request, host_id)) => {
+                            self.retry(&retry_policy, request, response, host_id)
+                                .await?
+                        }
+                        None => response?,
+                    });
+                }
+                results
+            }
+        };
+
+        // A node that joined or reconnected after a statement was prepared does not know about it
+        for (response, sent_request) in responses.iter_mut().zip(&sent_requests) {
+            if let Some((request, host_id)) = sent_request {
+                if let Some(id) = get_unprepared_id(response) {
+                    match self.reprepare(request, &id, *host_id).await {
+                        Ok(Some(reprepared_response)) => *response = reprepared_response,
+                        Ok(None) => {}
+                        // Only this request is affected, so the client receives the Unprepared error and re-prepares the statement itself
+                        Err(err) => tracing::warn!(
+                            "failed to re-prepare {:?} on node {}: {:?}",
+                            id,
+                            host_id,
+                            err
+                        ),
+                    }
+                }
+            }
+        }
+
+        for (index, mut request) in prepare_requests {
+            self.prepared_reads
+                .record(&mut request, &mut responses[index]);
+            if let Some((id, metadata)) = get_prepared_result_message(&mut responses[index]) {
+                self.connection_factory
+                    .add_prepare_message(id.clone(), request);
+                self.pool.add_prepared_result(id, metadata).await;
+            }
+        }
+
         {
             let mut prepare_responses = super::connection::receive(
                 self.read_timeout,
@@ -439,6 +805,14 @@ impl CassandraSinkCluster {
             }
         }
 
+        // Drivers wait for schema agreement after a schema change, which they cannot do themselves through shotover
+        if let Some(schema_agreement) = self.schema_agreement.clone() {
+            if let Some(version) = responses.iter_mut().find_map(schema_change_version) {
+                self.wait_for_schema_agreement(&schema_agreement, version)
+                    .await;
+            }
+        }
+
         // When the server indicates that it is ready for normal operation via Ready or AuthSuccess,
         // we have succesfully collected an entire handshake so we mark the handshake as complete.
         if !self.init_handshake_complete {
@@ -470,13 +844,237 @@ impl CassandraSinkCluster {
             self.rewrite_table(table_to_rewrite, &mut responses).await?;
         }
 
-        for response in responses.iter_mut() {
-            if let Some((id, metadata)) = get_prepared_result_message(response) {
-                self.pool.add_prepared_result(id, metadata).await;
+        Ok(responses)
+    }
+
+    /// Send `request` again for as long as `retry_policy` decides that its `response` should be retried.
+    /// Each retry is sent to a node in the rack that the request has not been sent to yet, if there are any.
+    /// `host_id` is updated to the node that the returned response came from.
+    async fn retry(
+        &mut self,
+        retry_policy: &RetryPolicy,
+        request: &mut Message,
+        mut response: Result<Message>,
+        host_id: &mut Uuid,
+    ) -> Result<Message> {
+        let mut state = RetryState::default();
+        let mut tried_host_ids = vec![*host_id];
+        while let Some(reason) = RetryReason::from_response(&mut response) {
+            let delay = match retry_policy.retry_delay(
+                self.prepared_reads.is_read(request),
+                reason,
+                &mut state,
+            ) {
+                Some(delay) => delay,
+                None => break,
+            };
+
+            if reason == RetryReason::ConnectionFailure {
+                // The connection will be recreated the next time the node is used
+                let failed_host_id = *tried_host_ids.last().unwrap();
+                if let Some(node) = self
+                    .pool
+                    .nodes()
+                    .iter_mut()
+                    .find(|node| node.host_id == failed_host_id)
+                {
+                    node.outbound = None;
+                }
             }
+            tokio::time::sleep(delay).await;
+
+            let rack = &self.local_shotover_node.rack;
+            let node = match self.pool.select_node_in_dc_rack_excluding(
+                rack,
+                &tried_host_ids,
+                &mut self.rng,
+            ) {
+                Some(node) => node,
+                None => {
+                    match self
+                        .pool
+                        .select_node_in_dc_rack_excluding(rack, &[], &mut self.rng)
+                    {
+                        Some(node) => node,
+                        None => break,
+                    }
+                }
+            };
+            tried_host_ids.push(node.host_id);
+            *host_id = node.host_id;
+
+            response = match node.get_connection(&self.connection_factory).await {
+                Ok(connection) => {
+                    super::connection::send_and_receive(
+                        connection,
+                        request.clone(),
+                        self.read_timeout,
+                        &self.failed_requests,
+                    )
+                    .await?
+                }
+                Err(err) => Err(err),
+            };
         }
+        response
+    }
 
-        Ok(responses)
+    /// Prepare the statement `id` on the node `host_id` that responded to `request` with an Unprepared error, then send `request` to it again.
+    /// Returns `None` when the statement was not prepared through this transform or the node prepared it under a different id, e.g. because the statement was prepared
+    /// by a client using another keyspace, leaving the client to re-prepare it.
+    async fn reprepare(
+        &mut self,
+        request: &Message,
+        id: &CBytesShort,
+        host_id: Uuid,
+    ) -> Result<Option<Message>> {
+        let prepare_message = match self.connection_factory.get_prepare_message(id) {
+            Some(prepare_message) => prepare_message,
+            None => return Ok(None),
+        };
+        let connection = match self.pool.node_mut(host_id) {
+            Some(node) => node.get_connection(&self.connection_factory).await?.clone(),
+            None => return Ok(None),
+        };
+
+        tracing::info!("re-preparing {:?} on node {}", id, host_id);
+        let mut prepare_response = super::connection::send_and_receive(
+            &connection,
+            prepare_message,
+            self.read_timeout,
+            &self.failed_requests,
+        )
+        .await??;
+        match get_prepared_result_message(&mut prepare_response) {
+            Some((prepared_id, _)) if &prepared_id == id => {}
+            _ => return Ok(None),
+        }
+        self.reprepares.increment(1);
+
+        let response = super::connection::send_and_receive(
+            &connection,
+            request.clone(),
+            self.read_timeout,
+            &self.failed_requests,
+        )
+        .await??;
+        Ok(Some(response))
+    }
+
+    /// Each rack is presented to the client as the shotover nodes configured for it,
+    /// so a rack is down once all of its cassandra nodes are down and is up as soon as any of them is up.
+    /// Returns the status changes of the shotover nodes of the rack, which is empty when the status of the rack is unchanged.
+    fn translate_status_change(&self, status: &StatusChange) -> Vec<ServerEvent> {
+        let local_data_center = self.local_shotover_node.data_center.as_str();
+        let nodes: Vec<(&str, &CassandraNode)> = self
+            .pool
+            .iter_all_nodes()
+            .map(|(data_center, node)| (data_center.unwrap_or(local_data_center), node))
+            .collect();
+
+        let (data_center, rack) = match nodes.iter().find(|(_, node)| node.address == status.addr) {
+            Some((data_center, node)) => (*data_center, node.rack.as_str()),
+            None => return vec![],
+        };
+        let other_node_up = nodes.iter().any(|(node_data_center, node)| {
+            *node_data_center == data_center
+                && node.rack == rack
+                && node.address != status.addr
+                && node.is_up
+        });
+        if other_node_up {
+            return vec![];
+        }
+
+        std::iter::once(&self.local_shotover_node)
+            .chain(&self.shotover_peers)
+            .filter(|shotover_node| {
+                shotover_node.data_center == data_center && shotover_node.rack == rack
+            })
+            .map(|shotover_node| {
+                ServerEvent::StatusChange(StatusChange {
+                    change_type: status.change_type,
+                    addr: shotover_node.address,
+                })
+            })
+            .collect()
+    }
+
+    /// Poll the schema version of every node that is up until they agree or the timeout passes.
+    /// Failing to reach agreement is logged rather than returned, as the schema change itself has succeeded.
+    async fn wait_for_schema_agreement(
+        &mut self,
+        schema_agreement: &SchemaAgreementConfig,
+        version: Version,
+    ) {
+        let poll_interval = Duration::from_millis(schema_agreement.poll_interval_ms);
+        let deadline = Instant::now() + Duration::from_millis(schema_agreement.timeout_ms);
+        loop {
+            match self.fetch_schema_versions(version).await {
+                Ok(schema_versions) if schema_versions.iter().all_equal() => return,
+                Ok(_) => {}
+                Err(err) => tracing::debug!("failed to fetch schema versions: {err:?}"),
+            }
+
+            if Instant::now() + poll_interval > deadline {
+                tracing::warn!(
+                    "schema agreement was not reached within {}ms",
+                    schema_agreement.timeout_ms
+                );
+                return;
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Fetch the schema version of the control connection's node and of every peer that is up, in any data center, from that node's system tables.
+    /// This matches how drivers check for schema agreement and avoids opening a connection to every node.
+    async fn fetch_schema_versions(&mut self, version: Version) -> Result<Vec<Uuid>> {
+        let connection = self
+            .init_handshake_connection
+            .clone()
+            .ok_or_else(|| anyhow!("the control connection has not been created"))?;
+
+        let mut local_response = super::connection::send_and_receive(
+            &connection,
+            system_query(version, "SELECT schema_version FROM system.local"),
+            self.read_timeout,
+            &self.failed_requests,
+        )
+        .await??;
+        let mut schema_versions = vec![get_schema_version(&mut local_response)?];
+
+        let mut peers_response = super::connection::send_and_receive(
+            &connection,
+            system_query(
+                version,
+                "SELECT host_id, schema_version FROM system.peers_v2",
+            ),
+            self.read_timeout,
+            &self.failed_requests,
+        )
+        .await??;
+        if topology::is_peers_v2_does_not_exist_error(&mut peers_response) {
+            peers_response = super::connection::send_and_receive(
+                &connection,
+                system_query(version, "SELECT host_id, schema_version FROM system.peers"),
+                self.read_timeout,
+                &self.failed_requests,
+            )
+            .await??;
+        }
+
+        for (host_id, schema_version) in get_peer_schema_versions(&mut peers_response)? {
+            // A node that is down can not agree, drivers also ignore down nodes
+            let is_down = self
+                .pool
+                .iter_all_nodes()
+                .any(|(_, node)| node.host_id == host_id && !node.is_up);
+            if !is_down {
+                schema_versions.push(schema_version);
+            }
+        }
+        Ok(schema_versions)
     }
 
     async fn complete_handshake(&mut self) -> Result<()> {
@@ -499,7 +1097,7 @@ impl CassandraSinkCluster {
             // Therefore we need to recreate the control connection to ensure that it is in the configured data_center/rack.
             let random_address = self
                 .pool
-                .get_random_node_in_dc_rack(&self.local_shotover_node.rack, &mut self.rng)
+                .select_node_in_dc_rack(&self.local_shotover_node.rack, &mut self.rng)
                 .address;
             self.init_handshake_connection = Some(
                 self.connection_factory
@@ -860,6 +1458,104 @@ enum RewriteTableTy {
     Peers,
 }
 
+fn schema_change_version(message: &mut Message) -> Option<Version> {
+    if let Some(Frame::Cassandra(CassandraFrame {
+        operation: CassandraOperation::Result(CassandraResult::SchemaChange(_)),
+        version,
+        ..
+    })) = message.frame()
+    {
+        return Some(*version);
+    }
+
+    None
+}
+
+fn system_query(version: Version, query: &str) -> Message {
+    Message::from_frame(Frame::Cassandra(CassandraFrame {
+        version,
+        stream_id: 0,
+        tracing_id: None,
+        warnings: vec![],
+        operation: CassandraOperation::Query {
+            query: Box::new(parse_statement_single(query)),
+            params: Box::new(QueryParams::default()),
+        },
+    }))
+}
+
+fn get_schema_version(message: &mut Message) -> Result<Uuid> {
+    if let Some(Frame::Cassandra(CassandraFrame {
+        operation: CassandraOperation::Result(CassandraResult::Rows { rows, .. }),
+        ..
+    })) = message.frame()
+    {
+        if let Some(MessageValue::Uuid(schema_version)) = rows.get(0).and_then(|row| row.get(0)) {
+            return Ok(*schema_version);
+        }
+    }
+
+    Err(anyhow!(
+        "unexpected response to system.local schema_version query {:?}",
+        message
+    ))
+}
+
+/// Returns the host_id and schema_version of each peer, skipping peers whose schema_version is not yet known
+fn get_peer_schema_versions(message: &mut Message) -> Result<Vec<(Uuid, Uuid)>> {
+    if let Some(Frame::Cassandra(CassandraFrame {
+        operation: CassandraOperation::Result(CassandraResult::Rows { rows, .. }),
+        ..
+    })) = message.frame()
+    {
+        return rows
+            .iter()
+            .filter_map(|row| match row.as_slice() {
+                [MessageValue::Uuid(host_id), MessageValue::Uuid(schema_version)] => {
+                    Some(Ok((*host_id, *schema_version)))
+                }
+                [_, MessageValue::Null] => None,
+                row => Some(Err(anyhow!(
+                    "unexpected row in system.peers schema_version query {:?}",
+                    row
+                ))),
+            })
+            .collect();
+    }
+
+    Err(anyhow!(
+        "unexpected response to system.peers schema_version query {:?}",
+        message
+    ))
+}
+
+fn get_unprepared_id(message: &mut Message) -> Option<CBytesShort> {
+    if let Some(Frame::Cassandra(CassandraFrame {
+        operation:
+            CassandraOperation::Error(ErrorBody {
+                ty: ErrorType::Unprepared(UnpreparedError { id }),
+                ..
+            }),
+        ..
+    })) = message.frame()
+    {
+        return Some(id.clone());
+    }
+
+    None
+}
+
+/// EXECUTE and BATCH requests may refer to statements that the node has not prepared.
+fn uses_prepared_statements(message: &mut Message) -> bool {
+    matches!(
+        message.frame(),
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Execute(_) | CassandraOperation::Batch(_),
+            ..
+        }))
+    )
+}
+
 fn get_prepared_result_message(message: &mut Message) -> Option<(CBytesShort, PreparedMetadata)> {
     if let Some(Frame::Cassandra(CassandraFrame {
         operation: CassandraOperation::Result(CassandraResult::Prepared(prepared)),
@@ -895,6 +1591,28 @@ fn get_execute_message(message: &mut Message) -> Option<(&BodyReqExecuteOwned, C
     None
 }
 
+/// Records the outcome of every request sent to a node in `node_health` and `node_load`, when they are enabled.
+#[derive(Clone)]
+struct NodeObservers {
+    node_health: Option<Arc<NodeHealth>>,
+    node_load: Option<Arc<NodeLoad>>,
+}
+
+impl NodeObservers {
+    /// Must only be called right before the request is sent, as `node_load` counts the request as in flight from this point.
+    fn for_node(&self, host_id: Uuid) -> Vec<Observer> {
+        self.node_health
+            .iter()
+            .map(|node_health| node_health.observer(host_id))
+            .chain(
+                self.node_load
+                    .iter()
+                    .map(|node_load| node_load.observer(host_id)),
+            )
+            .collect()
+    }
+}
+
 fn is_prepare_message(message: &mut Message) -> bool {
     if let Some(Frame::Cassandra(CassandraFrame {
         operation: CassandraOperation::Prepare(_),
@@ -907,15 +1625,15 @@ fn is_prepare_message(message: &mut Message) -> bool {
     false
 }
 
-fn is_use_statement(request: &mut Message) -> bool {
+fn get_use_keyspace(request: &mut Message) -> Option<String> {
     if let Some(Frame::Cassandra(frame)) = request.frame() {
         if let CassandraOperation::Query { query, .. } = &mut frame.operation {
-            if let CassandraStatement::Use(_) = query.as_ref() {
-                return true;
+            if let CassandraStatement::Use(keyspace) = query.as_ref() {
+                return Some(identifier_name(keyspace));
             }
         }
     }
-    false
+    None
 }
 
 fn is_ddl_statement(request: &mut Message) -> bool {
@@ -962,7 +1680,7 @@ fn is_use_statement_successful(response: Option<Result<Response>>) -> bool {
     false
 }
 
-fn get_unused_stream_id(messages: &Messages) -> Result<i16> {
+pub fn get_unused_stream_id(messages: &Messages) -> Result<i16> {
     // start at an unusual number to hopefully avoid looping many times when we receive stream ids that look like [0, 1, 2, ..]
     // We can quite happily give up 358 stream ids as that still allows for shotover message batches containing 2 ** 16 - 358 = 65178 messages
     for i in 358..i16::MAX {
@@ -1053,21 +1771,44 @@ impl Transform for CassandraSinkCluster {
     }
 
     async fn transform_pushed<'a>(&'a mut self, mut message_wrapper: Wrapper<'a>) -> ChainResponse {
-        message_wrapper.messages.retain_mut(|message| {
-            if let Some(Frame::Cassandra(CassandraFrame {
-                operation: CassandraOperation::Event(event),
-                ..
-            })) = message.frame()
-            {
-                match event {
-                    ServerEvent::TopologyChange(_) => false,
-                    ServerEvent::StatusChange(_) => false,
-                    ServerEvent::SchemaChange(_) => true,
-                }
-            } else {
-                true
+        if self.nodes_rx.has_changed()? {
+            self.pool.update_nodes(&mut self.nodes_rx);
+        }
+        if self.remote_nodes_rx.has_changed()? {
+            self.pool.update_remote_nodes(&mut self.remote_nodes_rx);
+        }
+
+        let mut messages = Vec::with_capacity(message_wrapper.messages.len());
+        for mut message in message_wrapper.messages.drain(..) {
+            let translated = match message.frame() {
+                Some(Frame::Cassandra(frame)) => match &frame.operation {
+                    // The shotover nodes are configured statically so cassandra nodes joining or leaving never change shotover's topology
+                    CassandraOperation::Event(ServerEvent::TopologyChange(_)) => Some(vec![]),
+                    CassandraOperation::Event(ServerEvent::StatusChange(status)) => Some(
+                        self.translate_status_change(status)
+                            .into_iter()
+                            .map(|event| {
+                                Message::from_frame(Frame::Cassandra(CassandraFrame {
+                                    version: frame.version,
+                                    stream_id: frame.stream_id,
+                                    tracing_id: None,
+                                    warnings: vec![],
+                                    operation: CassandraOperation::Event(event),
+                                }))
+                            })
+                            .collect(),
+                    ),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match translated {
+                Some(translated) => messages.extend(translated),
+                None => messages.push(message),
             }
-        });
+        }
+        message_wrapper.messages = messages;
+
         message_wrapper.call_next_transform_pushed().await
     }
 
@@ -1080,3 +1821,394 @@ impl Transform for CassandraSinkCluster {
             .set_pushed_messages_tx(pushed_messages_tx);
     }
 }
+
+#[cfg(test)]
+mod test_cassandra_sink_cluster {
+    use super::*;
+    use crate::codec::cassandra::CassandraCodec;
+    use cassandra_protocol::frame::events::{StatusChangeType, TopologyChange, TopologyChangeType};
+    use cassandra_protocol::frame::message_result::{
+        ColSpec, ColType, ColTypeOption, RowsMetadata, RowsMetadataFlags, TableSpec,
+    };
+    use cassandra_protocol::token::Murmur3Token;
+    use futures::SinkExt;
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    fn shotover_node(address: &str, rack: &str) -> ShotoverNode {
+        ShotoverNode {
+            address: address.parse().unwrap(),
+            data_center: "dc1".into(),
+            rack: rack.into(),
+            host_id: Uuid::new_v4(),
+        }
+    }
+
+    fn cassandra_node(address: &str, rack: &str) -> CassandraNode {
+        CassandraNode::new(
+            address.parse().unwrap(),
+            rack.into(),
+            vec![Murmur3Token::new(0)],
+            Uuid::new_v4(),
+        )
+    }
+
+    /// rack1 is presented by two shotover nodes and contains two cassandra nodes while rack2 has one of each
+    fn sink_cluster() -> CassandraSinkCluster {
+        let mut sink_cluster = CassandraSinkCluster::new(
+            vec![],
+            vec![
+                shotover_node("127.0.0.2:9042", "rack1"),
+                shotover_node("127.0.0.3:9042", "rack2"),
+            ],
+            "test".into(),
+            shotover_node("127.0.0.1:9042", "rack1"),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        sink_cluster.pool = NodePool::new(
+            vec![
+                cassandra_node("172.16.1.2:9042", "rack1"),
+                cassandra_node("172.16.1.3:9042", "rack1"),
+                cassandra_node("172.16.1.4:9042", "rack2"),
+            ],
+            None,
+            None,
+        );
+        sink_cluster
+    }
+
+    fn status_change(change_type: StatusChangeType, address: &str) -> StatusChange {
+        StatusChange {
+            change_type,
+            addr: address.parse().unwrap(),
+        }
+    }
+
+    fn status_events(change_type: StatusChangeType, addresses: &[&str]) -> Vec<ServerEvent> {
+        addresses
+            .iter()
+            .map(|address| ServerEvent::StatusChange(status_change(change_type, address)))
+            .collect()
+    }
+
+    /// Marks the node as the topology task does before the event is passed on to the transform
+    fn set_is_up(sink_cluster: &mut CassandraSinkCluster, address: &str, is_up: bool) {
+        let address: SocketAddr = address.parse().unwrap();
+        for node in sink_cluster.pool.nodes() {
+            if node.address == address {
+                node.is_up = is_up;
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_translate_status_change_single_node_rack() {
+        let mut sink_cluster = sink_cluster();
+
+        set_is_up(&mut sink_cluster, "172.16.1.4:9042", false);
+        assert_eq!(
+            sink_cluster
+                .translate_status_change(&status_change(StatusChangeType::Down, "172.16.1.4:9042")),
+            status_events(StatusChangeType::Down, &["127.0.0.3:9042"])
+        );
+
+        set_is_up(&mut sink_cluster, "172.16.1.4:9042", true);
+        assert_eq!(
+            sink_cluster
+                .translate_status_change(&status_change(StatusChangeType::Up, "172.16.1.4:9042")),
+            status_events(StatusChangeType::Up, &["127.0.0.3:9042"])
+        );
+
+        // a node that shotover does not know about has no shotover nodes to report
+        assert_eq!(
+            sink_cluster
+                .translate_status_change(&status_change(StatusChangeType::Down, "172.16.1.5:9042")),
+            vec![]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_translate_status_change_whole_rack() {
+        let mut sink_cluster = sink_cluster();
+
+        // the rack stays up while one of its nodes is up
+        set_is_up(&mut sink_cluster, "172.16.1.2:9042", false);
+        assert_eq!(
+            sink_cluster
+                .translate_status_change(&status_change(StatusChangeType::Down, "172.16.1.2:9042")),
+            vec![]
+        );
+
+        // once every node of the rack is down, every shotover node of the rack is reported down
+        set_is_up(&mut sink_cluster, "172.16.1.3:9042", false);
+        assert_eq!(
+            sink_cluster
+                .translate_status_change(&status_change(StatusChangeType::Down, "172.16.1.3:9042")),
+            status_events(
+                StatusChangeType::Down,
+                &["127.0.0.1:9042", "127.0.0.2:9042"]
+            )
+        );
+
+        // the rack is up again as soon as any of its nodes is up
+        set_is_up(&mut sink_cluster, "172.16.1.2:9042", true);
+        assert_eq!(
+            sink_cluster
+                .translate_status_change(&status_change(StatusChangeType::Up, "172.16.1.2:9042")),
+            status_events(StatusChangeType::Up, &["127.0.0.1:9042", "127.0.0.2:9042"])
+        );
+
+        set_is_up(&mut sink_cluster, "172.16.1.3:9042", true);
+        assert_eq!(
+            sink_cluster
+                .translate_status_change(&status_change(StatusChangeType::Up, "172.16.1.3:9042")),
+            vec![]
+        );
+    }
+
+    fn event(stream_id: i16, event: ServerEvent) -> Message {
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id,
+            tracing_id: None,
+            warnings: vec![],
+            operation: CassandraOperation::Event(event),
+        }))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transform_pushed() {
+        let mut sink_cluster = sink_cluster();
+        set_is_up(&mut sink_cluster, "172.16.1.4:9042", false);
+
+        let pushed = vec![
+            event(
+                -1,
+                ServerEvent::TopologyChange(TopologyChange {
+                    change_type: TopologyChangeType::NewNode,
+                    addr: "172.16.1.5:9042".parse().unwrap(),
+                }),
+            ),
+            event(
+                -1,
+                ServerEvent::StatusChange(status_change(StatusChangeType::Down, "172.16.1.4:9042")),
+            ),
+            event(
+                -1,
+                ServerEvent::StatusChange(status_change(StatusChangeType::Down, "172.16.1.2:9042")),
+            ),
+        ];
+
+        let mut messages = sink_cluster
+            .transform_pushed(Wrapper::new(pushed))
+            .await
+            .unwrap();
+        for message in &mut messages {
+            message.frame();
+        }
+
+        // the topology change is suppressed, as is the status change of a node in a rack that is still up
+        let mut expected = vec![event(
+            -1,
+            ServerEvent::StatusChange(status_change(StatusChangeType::Down, "127.0.0.3:9042")),
+        )];
+        for message in &mut expected {
+            message.frame();
+        }
+        assert_eq!(messages, expected);
+    }
+
+    fn rows(
+        table_name: &str,
+        columns: &[&str],
+        rows: Vec<Vec<MessageValue>>,
+    ) -> CassandraOperation {
+        CassandraOperation::Result(CassandraResult::Rows {
+            rows,
+            metadata: Box::new(RowsMetadata {
+                flags: RowsMetadataFlags::GLOBAL_TABLE_SPACE,
+                columns_count: columns.len() as i32,
+                paging_state: None,
+                new_metadata_id: None,
+                global_table_spec: Some(TableSpec {
+                    ks_name: "system".into(),
+                    table_name: table_name.into(),
+                }),
+                col_specs: columns
+                    .iter()
+                    .map(|name| ColSpec {
+                        table_spec: None,
+                        name: (*name).into(),
+                        col_type: ColTypeOption {
+                            id: ColType::Uuid,
+                            value: None,
+                        },
+                    })
+                    .collect(),
+            }),
+        })
+    }
+
+    /// A cassandra 3 node that only has system.peers, reporting the schema version of itself and its peers.
+    /// Returns its address and a count of the connections made to it.
+    async fn fake_control_node(
+        local: Uuid,
+        peers: Vec<(Uuid, Option<Uuid>)>,
+    ) -> (SocketAddr, Arc<std::sync::atomic::AtomicUsize>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let connections_count = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                connections_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let peers = peers.clone();
+                tokio::spawn(async move {
+                    let mut framed = Framed::new(socket, CassandraCodec::new());
+                    while let Some(Ok(requests)) = framed.next().await {
+                        let mut responses = vec![];
+                        for mut request in requests {
+                            let (stream_id, query) = match request.frame() {
+                                Some(Frame::Cassandra(CassandraFrame {
+                                    stream_id,
+                                    operation: CassandraOperation::Query { query, .. },
+                                    ..
+                                })) => (*stream_id, query.to_string()),
+                                other => panic!("unexpected request {other:?}"),
+                            };
+                            let operation = if query.contains("system.local") {
+                                rows(
+                                    "local",
+                                    &["schema_version"],
+                                    vec![vec![MessageValue::Uuid(local)]],
+                                )
+                            } else if query.contains("system.peers_v2") {
+                                CassandraOperation::Error(ErrorBody {
+                                    message: "unconfigured table peers_v2".into(),
+                                    ty: ErrorType::Invalid,
+                                })
+                            } else if query.contains("system.peers") {
+                                rows(
+                                    "peers",
+                                    &["host_id", "schema_version"],
+                                    peers
+                                        .iter()
+                                        .map(|(host_id, schema_version)| {
+                                            vec![
+                                                MessageValue::Uuid(*host_id),
+                                                schema_version
+                                                    .map(MessageValue::Uuid)
+                                                    .unwrap_or(MessageValue::Null),
+                                            ]
+                                        })
+                                        .collect(),
+                                )
+                            } else {
+                                panic!("unexpected query {query}")
+                            };
+                            responses.push(Message::from_frame(Frame::Cassandra(CassandraFrame {
+                                version: Version::V4,
+                                stream_id,
+                                tracing_id: None,
+                                warnings: vec![],
+                                operation,
+                            })));
+                        }
+                        framed.send(responses).await.unwrap();
+                    }
+                });
+            }
+        });
+        (address, connections)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fetch_schema_versions() {
+        let schema_version = Uuid::new_v4();
+        let stale_schema_version = Uuid::new_v4();
+
+        let mut sink_cluster = sink_cluster();
+        let up_host_id = sink_cluster.pool.nodes()[1].host_id;
+        let down_host_id = sink_cluster.pool.nodes()[2].host_id;
+        sink_cluster.pool.nodes()[2].is_up = false;
+        let remote_host_id = Uuid::new_v4();
+
+        let (address, connections) = fake_control_node(
+            schema_version,
+            vec![
+                (up_host_id, Some(schema_version)),
+                // a node that is down is not waited on
+                (down_host_id, Some(stale_schema_version)),
+                // a node shotover has not discovered, such as one in another data center, is waited on
+                (remote_host_id, Some(schema_version)),
+                // a node that has not reported its schema version yet is skipped
+                (Uuid::new_v4(), None),
+            ],
+        )
+        .await;
+        sink_cluster.init_handshake_connection = Some(
+            sink_cluster
+                .connection_factory
+                .new_connection(address)
+                .await
+                .unwrap(),
+        );
+
+        let schema_versions = sink_cluster
+            .fetch_schema_versions(Version::V4)
+            .await
+            .unwrap();
+        assert_eq!(
+            schema_versions,
+            vec![schema_version, schema_version, schema_version]
+        );
+        // every schema version is read through the control connection
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(sink_cluster
+            .pool
+            .nodes()
+            .iter()
+            .all(|node| node.outbound.is_none()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fetch_schema_versions_disagreement() {
+        let mut sink_cluster = sink_cluster();
+        let up_host_id = sink_cluster.pool.nodes()[1].host_id;
+
+        let (address, _) =
+            fake_control_node(Uuid::new_v4(), vec![(up_host_id, Some(Uuid::new_v4()))]).await;
+        sink_cluster.init_handshake_connection = Some(
+            sink_cluster
+                .connection_factory
+                .new_connection(address)
+                .await
+                .unwrap(),
+        );
+
+        let schema_versions = sink_cluster
+            .fetch_schema_versions(Version::V4)
+            .await
+            .unwrap();
+        assert_eq!(schema_versions.len(), 2);
+        assert!(!schema_versions.iter().all_equal());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fetch_schema_versions_without_control_connection() {
+        let mut sink_cluster = sink_cluster();
+        assert!(sink_cluster
+            .fetch_schema_versions(Version::V4)
+            .await
+            .is_err());
+    }
+}
@@ -1,16 +1,28 @@
-use crate::codec::cassandra::CassandraCodec;
-use crate::frame::Frame;
+use crate::frame::{CassandraFrame, CassandraOperation, Frame};
 use crate::message::{Message, Messages};
 use crate::tls::TlsConnector;
 use crate::transforms::cassandra::connection::CassandraConnection;
+use crate::transforms::cassandra::{create_codec, CassandraCompression};
+use crate::transforms::util::Response;
 use anyhow::{anyhow, Result};
 use cassandra_protocol::frame::Version;
 use cassandra_protocol::token::Murmur3Token;
+use cassandra_protocol::types::CBytesShort;
+use futures::future::join_all;
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tokio::net::ToSocketAddrs;
 use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
 use uuid::Uuid;
 
+/// Statements prepared after this many are not prepared on new connections, leaving the client to re-prepare them when needed
+const MAX_PREPARE_MESSAGES: usize = 10_000;
+/// How long a new connection waits for the nodes responses to the PREPARE requests replayed on it
+const PREPARE_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Clone)]
 pub struct CassandraNode {
     pub address: SocketAddr,
@@ -54,7 +66,11 @@ impl CassandraNode {
 pub struct ConnectionFactory {
     init_handshake: Vec<Message>,
     use_message: Option<Message>,
+    /// The PREPARE request of every statement prepared by a client, keyed by prepared id.
+    /// Shared by every clone as a statement has the same id on every connection.
+    prepare_messages: Arc<RwLock<HashMap<CBytesShort, Message>>>,
     tls: Option<TlsConnector>,
+    compression: Option<CassandraCompression>,
     pushed_messages_tx: Option<mpsc::UnboundedSender<Messages>>,
 }
 
@@ -63,18 +79,22 @@ impl Clone for ConnectionFactory {
         Self {
             init_handshake: self.init_handshake.clone(),
             use_message: None,
+            prepare_messages: self.prepare_messages.clone(),
             tls: self.tls.clone(),
+            compression: self.compression,
             pushed_messages_tx: None,
         }
     }
 }
 
 impl ConnectionFactory {
-    pub fn new(tls: Option<TlsConnector>) -> Self {
+    pub fn new(tls: Option<TlsConnector>, compression: Option<CassandraCompression>) -> Self {
         Self {
             init_handshake: vec![],
             use_message: None,
+            prepare_messages: Arc::new(RwLock::new(HashMap::new())),
             tls,
+            compression,
             pushed_messages_tx: None,
         }
     }
@@ -82,24 +102,36 @@ impl ConnectionFactory {
     /// For when you want to clone the config options for creating new connections but none of the state.
     /// When the transform chain is cloned for a new incoming connection, this method should be used so the state doesn't also get cloned to
     /// the new connection as aswell.
+    /// The prepared statements are still shared as they are not specific to a connection.
     pub fn new_with_same_config(&self) -> Self {
         Self {
             init_handshake: vec![],
             use_message: None,
+            prepare_messages: self.prepare_messages.clone(),
             tls: self.tls.clone(),
+            compression: self.compression,
             pushed_messages_tx: None,
         }
     }
 
+    /// For creating connections outside of the transform, e.g. from a spawned task, that must be in the same state as the transform's own connections.
+    pub fn clone_with_state(&self) -> Self {
+        Self {
+            use_message: self.use_message.clone(),
+            ..self.clone()
+        }
+    }
+
     pub async fn new_connection<A: ToSocketAddrs + std::fmt::Debug>(
         &self,
         address: A,
     ) -> Result<CassandraConnection> {
         let outbound = CassandraConnection::new(
             address,
-            CassandraCodec::new(),
+            create_codec(self.compression),
             self.tls.clone(),
             self.pushed_messages_tx.clone(),
+            None,
         )
         .await
         .map_err(|e| e.context("Failed to create new connection"))?;
@@ -131,6 +163,60 @@ impl ConnectionFactory {
             })?;
         }
 
+        // Prepare every statement the clients have prepared so that requests executing them can be routed to this connection
+        let prepare_messages: Vec<Message> = self
+            .prepare_messages
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        let mut prepare_responses = Vec::with_capacity(prepare_messages.len());
+        for prepare_message in prepare_messages {
+            let (return_chan_tx, return_chan_rx) = oneshot::channel();
+            outbound
+                .send(prepare_message, return_chan_tx)
+                .map_err(|e| {
+                    anyhow!(e).context(
+                        "Failed to initialize new connection with prepared statements, tx failed",
+                    )
+                })?;
+            prepare_responses.push(return_chan_rx);
+        }
+        let prepare_responses = timeout(PREPARE_TIMEOUT, join_all(prepare_responses))
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "Failed to initialize new connection with prepared statements, timed out after {:?}",
+                    PREPARE_TIMEOUT
+                )
+            })?;
+        for prepare_response in prepare_responses {
+            let Response {
+                mut original,
+                response,
+            } = prepare_response.map_err(|e| {
+                anyhow!(e).context(
+                    "Failed to initialize new connection with prepared statements, rx failed",
+                )
+            })?;
+            let mut response = response.map_err(|e| {
+                e.context("Failed to initialize new connection with prepared statements")
+            })?;
+            // A statement can stop being valid, e.g. when its table is dropped, which is reported to the client when it next executes the statement
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Error(error),
+                ..
+            })) = response.frame()
+            {
+                tracing::warn!(
+                    "Failed to prepare {:?} on new connection: {:?}",
+                    original.frame(),
+                    error
+                );
+            }
+        }
+
         Ok(outbound)
     }
 
@@ -145,6 +231,18 @@ impl ConnectionFactory {
         self.use_message = Some(message);
     }
 
+    /// Add a PREPARE statement that any new connection created will prepare.
+    pub fn add_prepare_message(&self, id: CBytesShort, message: Message) {
+        let mut prepare_messages = self.prepare_messages.write().unwrap();
+        if prepare_messages.len() < MAX_PREPARE_MESSAGES || prepare_messages.contains_key(&id) {
+            prepare_messages.insert(id, message);
+        }
+    }
+
+    pub fn get_prepare_message(&self, id: &CBytesShort) -> Option<Message> {
+        self.prepare_messages.read().unwrap().get(id).cloned()
+    }
+
     pub fn set_pushed_messages_tx(&mut self, pushed_messages_tx: mpsc::UnboundedSender<Messages>) {
         self.pushed_messages_tx = Some(pushed_messages_tx);
     }
@@ -161,3 +259,148 @@ impl ConnectionFactory {
         ))
     }
 }
+
+#[cfg(test)]
+mod test_connection_factory {
+    use super::*;
+    use crate::codec::cassandra::CassandraCodec;
+    use crate::frame::cassandra::{prepare_query, set_prepare_query};
+    use crate::frame::CassandraResult;
+    use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+    use cassandra_protocol::frame::message_result::{
+        BodyResResultPrepared, PreparedMetadata, RowsMetadata, RowsMetadataFlags,
+    };
+    use futures::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+    use tokio_util::codec::Framed;
+
+    fn frame(stream_id: i16, operation: CassandraOperation) -> Message {
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id,
+            tracing_id: None,
+            warnings: vec![],
+            operation,
+        }))
+    }
+
+    fn prepare(query: &str) -> Message {
+        let mut body = vec![];
+        set_prepare_query(&mut body, query);
+        frame(0, CassandraOperation::Prepare(body))
+    }
+
+    fn prepared(stream_id: i16, id: CBytesShort) -> Message {
+        frame(
+            stream_id,
+            CassandraOperation::Result(CassandraResult::Prepared(Box::new(
+                BodyResResultPrepared {
+                    id,
+                    result_metadata_id: None,
+                    metadata: PreparedMetadata {
+                        pk_indexes: vec![],
+                        global_table_spec: None,
+                        col_specs: vec![],
+                    },
+                    result_metadata: RowsMetadata {
+                        flags: RowsMetadataFlags::empty(),
+                        columns_count: 0,
+                        paging_state: None,
+                        new_metadata_id: None,
+                        global_table_spec: None,
+                        col_specs: vec![],
+                    },
+                },
+            ))),
+        )
+    }
+
+    /// A node that responds to `expected_prepares` PREPARE requests, rejecting statements on `ks.missing` as that table does not exist.
+    /// Returns the prepared statements once they have all been received.
+    async fn fake_node(expected_prepares: usize) -> (SocketAddr, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(socket, CassandraCodec::new());
+            let mut queries = vec![];
+            while queries.len() < expected_prepares {
+                let requests = framed.next().await.unwrap().unwrap();
+                let mut responses = vec![];
+                for mut request in requests {
+                    let stream_id = request.stream_id().unwrap();
+                    let query = match request.frame() {
+                        Some(Frame::Cassandra(CassandraFrame {
+                            operation: CassandraOperation::Prepare(body),
+                            ..
+                        })) => prepare_query(body).unwrap().to_owned(),
+                        other => panic!("expected a PREPARE but received {:?}", other),
+                    };
+                    responses.push(if query.contains("ks.missing") {
+                        frame(
+                            stream_id,
+                            CassandraOperation::Error(ErrorBody {
+                                message: "unconfigured table missing".into(),
+                                ty: ErrorType::Invalid,
+                            }),
+                        )
+                    } else {
+                        prepared(stream_id, CBytesShort::new(query.as_bytes().to_vec()))
+                    });
+                    queries.push(query);
+                }
+                framed.send(responses).await.unwrap();
+            }
+            queries
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn test_prepare_messages_shared_and_bounded() {
+        let factory = ConnectionFactory::new(None, None);
+        let clone = factory.new_with_same_config();
+
+        let id = CBytesShort::new(vec![0]);
+        factory.add_prepare_message(id.clone(), prepare("SELECT * FROM ks.table"));
+        assert!(clone.get_prepare_message(&id).is_some());
+        assert!(clone.clone().get_prepare_message(&id).is_some());
+
+        for i in 1..MAX_PREPARE_MESSAGES as u32 {
+            clone.add_prepare_message(
+                CBytesShort::new(i.to_be_bytes().to_vec()),
+                prepare("SELECT * FROM ks.table"),
+            );
+        }
+        let over_limit = CBytesShort::new(vec![1, 2, 3, 4, 5]);
+        factory.add_prepare_message(over_limit.clone(), prepare("SELECT * FROM ks.table"));
+        assert!(factory.get_prepare_message(&over_limit).is_none());
+        assert_eq!(
+            factory.prepare_messages.read().unwrap().len(),
+            MAX_PREPARE_MESSAGES
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_connection_prepares_statements() {
+        let (address, node) = fake_node(2).await;
+
+        let factory = ConnectionFactory::new(None, None);
+        factory.add_prepare_message(CBytesShort::new(vec![1]), prepare("SELECT * FROM ks.table"));
+        factory.add_prepare_message(
+            CBytesShort::new(vec![2]),
+            prepare("SELECT * FROM ks.missing"),
+        );
+
+        // A statement that the node fails to prepare does not prevent the connection from being used
+        let _connection = factory.new_connection(address).await.unwrap();
+
+        let mut queries = node.await.unwrap();
+        queries.sort();
+        assert_eq!(
+            queries,
+            vec!["SELECT * FROM ks.missing", "SELECT * FROM ks.table"]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use super::node::{CassandraNode, ConnectionFactory};
+use crate::frame::{CassandraFrame, CassandraOperation, Frame};
+use crate::message::Message;
+use crate::transforms::cassandra::connection::{CassandraConnection, Observer};
+use anyhow::{anyhow, Result};
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use cassandra_protocol::frame::Version;
+use futures::future::join_all;
+use metrics::{counter, gauge};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, watch};
+use tokio::time::timeout;
+use uuid::Uuid;
+
+/// Consecutive ejections of a node double the ejection duration up to this many times.
+const MAX_EJECTION_BACKOFF_EXPONENT: u32 = 5;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HealthCheckConfig {
+    /// How often every node is probed with an OPTIONS request, in milliseconds.
+    pub probe_interval_ms: u64,
+    /// A probe or request that has not received a response after this many milliseconds counts as a failure of its node.
+    pub timeout_ms: u64,
+    /// The number of consecutive failures after which a node is ejected.
+    pub max_consecutive_failures: u32,
+    /// How long a node is ejected for in milliseconds, doubled for each consecutive ejection of the node.
+    pub ejection_duration_ms: u64,
+}
+
+#[derive(Debug, Default)]
+struct HostHealth {
+    consecutive_failures: u32,
+    /// The number of times the node has been ejected without a success in between.
+    ejections: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl HostHealth {
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.map_or(false, |until| now < until)
+    }
+}
+
+/// Tracks the health of every node, shared by all connections to the cluster.
+/// Nodes are ejected when too many consecutive probes or requests sent to them fail.
+#[derive(Debug)]
+pub struct NodeHealth {
+    hosts: Mutex<HashMap<Uuid, HostHealth>>,
+    timeout: Duration,
+    max_consecutive_failures: u32,
+    ejection_duration: Duration,
+    chain_name: String,
+}
+
+impl NodeHealth {
+    pub fn new(config: &HealthCheckConfig, chain_name: String) -> Self {
+        NodeHealth {
+            hosts: Mutex::new(HashMap::new()),
+            timeout: Duration::from_millis(config.timeout_ms),
+            max_consecutive_failures: config.max_consecutive_failures,
+            ejection_duration: Duration::from_millis(config.ejection_duration_ms),
+            chain_name,
+        }
+    }
+
+    pub fn is_ejected(&self, host_id: Uuid) -> bool {
+        self.hosts
+            .lock()
+            .unwrap()
+            .get(&host_id)
+            .map_or(false, |host| host.is_ejected(Instant::now()))
+    }
+
+    pub fn record_success(&self, host_id: Uuid) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.entry(host_id).or_default();
+        host.consecutive_failures = 0;
+        if !host.is_ejected(Instant::now()) {
+            host.ejections = 0;
+        }
+    }
+
+    pub fn record_failure(&self, host_id: Uuid) {
+        let now = Instant::now();
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.entry(host_id).or_default();
+        if host.is_ejected(now) {
+            return;
+        }
+
+        host.consecutive_failures += 1;
+        if host.consecutive_failures >= self.max_consecutive_failures {
+            let duration = self.ejection_duration
+                * 2u32.pow(host.ejections.min(MAX_EJECTION_BACKOFF_EXPONENT));
+            tracing::warn!(
+                "Ejecting cassandra node {host_id} for {duration:?} after {} consecutive failures",
+                host.consecutive_failures
+            );
+            host.consecutive_failures = 0;
+            host.ejections += 1;
+            host.ejected_until = Some(now + duration);
+            counter!("node_ejections", 1, "chain" => self.chain_name.clone(), "transform" => "CassandraSinkCluster", "host_id" => host_id.to_string());
+        }
+    }
+
+    /// Returns an observer to send along with a request to the node, see [`CassandraConnection::send_observed`].
+    /// It records the request as a success or failure of the node once its response arrives.
+    /// A response arriving after the timeout counts as a failure, as the node is too slow to be considered healthy.
+    pub fn observer(self: &Arc<Self>, host_id: Uuid) -> Observer {
+        let node_health = self.clone();
+        let sent = Instant::now();
+        Box::new(move |response| match response {
+            Some(response)
+                if sent.elapsed() <= node_health.timeout && !is_node_failure(response) =>
+            {
+                node_health.record_success(host_id)
+            }
+            _ => node_health.record_failure(host_id),
+        })
+    }
+
+    fn report_metrics(&self, nodes: &[CassandraNode]) {
+        let now = Instant::now();
+        let hosts = self.hosts.lock().unwrap();
+        for node in nodes {
+            let healthy = node.is_up
+                && !hosts
+                    .get(&node.host_id)
+                    .map_or(false, |host| host.is_ejected(now));
+            gauge!("node_healthy", if healthy { 1.0 } else { 0.0 }, "chain" => self.chain_name.clone(), "transform" => "CassandraSinkCluster", "host_id" => node.host_id.to_string());
+        }
+    }
+}
+
+/// Errors that indicate a problem with the node itself rather than with the request.
+fn is_node_failure(response: &mut Message) -> bool {
+    matches!(
+        response.frame(),
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Error(ErrorBody {
+                ty: ErrorType::Server | ErrorType::Overloaded | ErrorType::IsBootstrapping,
+                ..
+            }),
+            ..
+        }))
+    )
+}
+
+/// Periodically probes every node that the topology reports as up, recording the results in `node_health`.
+/// Probes use a connection to each node without a handshake, as an OPTIONS request is valid before authentication.
+pub fn create_health_check_task(
+    node_health: Arc<NodeHealth>,
+    nodes_rx: watch::Receiver<Vec<CassandraNode>>,
+    connection_factory: ConnectionFactory,
+    probe_interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut connections: HashMap<Uuid, CassandraConnection> = HashMap::new();
+        let mut interval = tokio::time::interval(probe_interval);
+        loop {
+            interval.tick().await;
+
+            let nodes = nodes_rx.borrow().clone();
+            connections.retain(|host_id, _| nodes.iter().any(|node| node.host_id == *host_id));
+
+            let results = join_all(nodes.iter().filter(|node| node.is_up).map(|node| {
+                let connection = connections.get(&node.host_id).cloned();
+                let connection_factory = &connection_factory;
+                let probe_timeout = node_health.timeout;
+                async move {
+                    let result = timeout(
+                        probe_timeout,
+                        probe(node.address, connection, connection_factory),
+                    )
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow!("probe timed out")));
+                    (node.host_id, result)
+                }
+            }))
+            .await;
+
+            for (host_id, result) in results {
+                match result {
+                    Ok(connection) => {
+                        node_health.record_success(host_id);
+                        connections.insert(host_id, connection);
+                    }
+                    Err(err) => {
+                        tracing::debug!(
+                            "health check probe of cassandra node {host_id} failed: {err:?}"
+                        );
+                        node_health.record_failure(host_id);
+                        connections.remove(&host_id);
+                    }
+                }
+            }
+
+            node_health.report_metrics(&nodes);
+        }
+    });
+}
+
+async fn probe(
+    address: SocketAddr,
+    connection: Option<CassandraConnection>,
+    connection_factory: &ConnectionFactory,
+) -> Result<CassandraConnection> {
+    let connection = match connection {
+        Some(connection) => connection,
+        None => connection_factory.new_connection(address).await?,
+    };
+
+    let (return_chan_tx, return_chan_rx) = oneshot::channel();
+    connection.send(
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: 0,
+            tracing_id: None,
+            warnings: vec![],
+            operation: CassandraOperation::Options(vec![]),
+        })),
+        return_chan_tx,
+    )?;
+    let mut response = return_chan_rx.await?.response?;
+
+    match response.frame() {
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Supported(_),
+            ..
+        })) => Ok(connection),
+        _ => Err(anyhow!("unexpected response to OPTIONS {response:?}")),
+    }
+}
+
+#[cfg(test)]
+mod test_node_health {
+    use super::*;
+
+    fn node_health(ejection_duration_ms: u64) -> NodeHealth {
+        NodeHealth::new(
+            &HealthCheckConfig {
+                probe_interval_ms: 1000,
+                timeout_ms: 1000,
+                max_consecutive_failures: 3,
+                ejection_duration_ms,
+            },
+            "chain".to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_ejected_after_consecutive_failures() {
+        let node_health = node_health(60_000);
+        let host_id = Uuid::new_v4();
+
+        node_health.record_failure(host_id);
+        node_health.record_failure(host_id);
+        node_health.record_success(host_id);
+        node_health.record_failure(host_id);
+        node_health.record_failure(host_id);
+        assert!(!node_health.is_ejected(host_id));
+
+        node_health.record_failure(host_id);
+        assert!(node_health.is_ejected(host_id));
+        assert!(!node_health.is_ejected(Uuid::new_v4()));
+
+        // a success does not end the ejection early
+        node_health.record_success(host_id);
+        assert!(node_health.is_ejected(host_id));
+    }
+
+    #[test]
+    fn test_ejection_expires() {
+        let node_health = node_health(0);
+        let host_id = Uuid::new_v4();
+
+        for _ in 0..3 {
+            node_health.record_failure(host_id);
+        }
+        assert!(!node_health.is_ejected(host_id));
+        assert_eq!(
+            node_health
+                .hosts
+                .lock()
+                .unwrap()
+                .get(&host_id)
+                .unwrap()
+                .ejections,
+            1
+        );
+
+        node_health.record_success(host_id);
+        assert_eq!(
+            node_health
+                .hosts
+                .lock()
+                .unwrap()
+                .get(&host_id)
+                .unwrap()
+                .ejections,
+            0
+        );
+    }
+}
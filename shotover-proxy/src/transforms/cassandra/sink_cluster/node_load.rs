@@ -0,0 +1,148 @@
+use crate::transforms::cassandra::connection::Observer;
+use rand::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use uuid::Uuid;
+
+/// How much a new latency sample contributes to a node's moving average latency.
+const EWMA_WEIGHT: f64 = 0.25;
+
+/// How CassandraSinkCluster chooses between the nodes that a request could be routed to.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LoadBalancingPolicy {
+    /// Choose a node at random.
+    Random,
+    /// Pick two nodes at random and choose the one with the lowest moving average latency weighted by its outstanding requests.
+    PowerOfTwoChoices,
+    /// Choose the node with the fewest outstanding requests.
+    LeastOutstandingRequests,
+}
+
+#[derive(Debug, Default)]
+struct HostLoad {
+    in_flight: usize,
+    /// Exponentially weighted moving average of the latency in seconds, `None` until the first response.
+    ewma_latency: Option<f64>,
+}
+
+impl HostLoad {
+    fn score(&self) -> f64 {
+        self.ewma_latency.unwrap_or(0.0) * (self.in_flight + 1) as f64
+    }
+}
+
+/// Tracks the latency and outstanding requests of every node, shared by all connections to the cluster.
+#[derive(Debug)]
+pub struct NodeLoad {
+    policy: LoadBalancingPolicy,
+    hosts: Mutex<HashMap<Uuid, HostLoad>>,
+}
+
+impl NodeLoad {
+    pub fn new(policy: LoadBalancingPolicy) -> Self {
+        NodeLoad {
+            policy,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Choose one of the `candidates` according to the load balancing policy.
+    pub fn choose(&self, candidates: Vec<Uuid>, rng: &mut SmallRng) -> Option<Uuid> {
+        let hosts = self.hosts.lock().unwrap();
+        let default = HostLoad::default();
+        let load = |host_id: &Uuid| hosts.get(host_id).unwrap_or(&default);
+
+        match self.policy {
+            LoadBalancingPolicy::Random => candidates.choose(rng).copied(),
+            LoadBalancingPolicy::PowerOfTwoChoices => candidates
+                .choose_multiple(rng, 2)
+                .min_by(|a, b| load(*a).score().total_cmp(&load(*b).score()))
+                .copied(),
+            LoadBalancingPolicy::LeastOutstandingRequests => {
+                let least = candidates.iter().map(|x| load(x).in_flight).min()?;
+                candidates
+                    .iter()
+                    .filter(|x| load(*x).in_flight == least)
+                    .choose(rng)
+                    .copied()
+            }
+        }
+    }
+
+    /// Returns an observer to send along with a request to the node, see [`CassandraConnection::send_observed`](crate::transforms::cassandra::connection::CassandraConnection::send_observed).
+    /// The request counts as outstanding on the node until the observer is called, which also updates the node's latency.
+    pub fn observer(self: &Arc<Self>, host_id: Uuid) -> Observer {
+        let node_load = self.clone();
+        let sent = Instant::now();
+        node_load
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host_id)
+            .or_default()
+            .in_flight += 1;
+
+        Box::new(move |response| {
+            node_load.record_response(host_id, response.map(|_| sent.elapsed().as_secs_f64()))
+        })
+    }
+
+    /// `latency` is `None` when the request failed without a response.
+    fn record_response(&self, host_id: Uuid, latency: Option<f64>) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.entry(host_id).or_default();
+        host.in_flight = host.in_flight.saturating_sub(1);
+        if let Some(latency) = latency {
+            host.ewma_latency = Some(match host.ewma_latency {
+                Some(ewma) => ewma * (1.0 - EWMA_WEIGHT) + latency * EWMA_WEIGHT,
+                None => latency,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_node_load {
+    use super::*;
+
+    #[test]
+    fn test_power_of_two_choices() {
+        let node_load = NodeLoad::new(LoadBalancingPolicy::PowerOfTwoChoices);
+        let slow = Uuid::new_v4();
+        let fast = Uuid::new_v4();
+        node_load.record_response(slow, Some(1.0));
+        node_load.record_response(fast, Some(0.01));
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..10 {
+            assert_eq!(node_load.choose(vec![slow, fast], &mut rng), Some(fast));
+        }
+        assert_eq!(node_load.choose(vec![slow], &mut rng), Some(slow));
+        assert_eq!(node_load.choose(vec![], &mut rng), None);
+    }
+
+    #[test]
+    fn test_least_outstanding_requests() {
+        let node_load = NodeLoad::new(LoadBalancingPolicy::LeastOutstandingRequests);
+        let busy = Uuid::new_v4();
+        let idle = Uuid::new_v4();
+        node_load.hosts.lock().unwrap().insert(
+            busy,
+            HostLoad {
+                in_flight: 3,
+                ewma_latency: None,
+            },
+        );
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..10 {
+            assert_eq!(node_load.choose(vec![busy, idle], &mut rng), Some(idle));
+        }
+
+        // a response of the busy node reduces its outstanding requests
+        node_load.record_response(busy, Some(0.01));
+        assert_eq!(node_load.hosts.lock().unwrap()[&busy].in_flight, 2);
+    }
+}
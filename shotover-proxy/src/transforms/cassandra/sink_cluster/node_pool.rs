@@ -1,27 +1,50 @@
+use super::node_health::NodeHealth;
+use super::node_load::NodeLoad;
+use super::partition_key::{statement_keyspace, PartitionKeys};
 use super::routing_key::calculate_routing_key;
 use super::token_map::TokenMap;
+use super::topology::RemoteNodes;
+use crate::frame::cassandra::{BatchStatementType, CassandraBatch};
+use crate::frame::CassandraOperation;
 use crate::transforms::cassandra::sink_cluster::node::CassandraNode;
-use anyhow::{anyhow, Error, Result};
+use anyhow::Result;
 use cassandra_protocol::frame::message_execute::BodyReqExecuteOwned;
 use cassandra_protocol::frame::message_result::PreparedMetadata;
 use cassandra_protocol::frame::Version;
 use cassandra_protocol::token::Murmur3Token;
 use cassandra_protocol::types::CBytesShort;
+use itertools::Itertools;
 use rand::prelude::*;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{watch, RwLock};
+use uuid::Uuid;
 
 pub enum GetReplicaErr {
     NoMetadata,
-    Other(Error),
+    /// Every replica in the local data center is down or ejected
+    NoReplicaUp,
+}
+
+/// The routing key of a request along with the keyspace whose replication determines the request's replicas
+#[derive(Debug)]
+pub struct RoutingKey {
+    key: Vec<u8>,
+    /// `None` when the keyspace is not known, in which case only the first replica is used
+    keyspace: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct NodePool {
     prepared_metadata: Arc<RwLock<HashMap<CBytesShort, PreparedMetadata>>>,
     token_map: TokenMap,
+    partition_keys: Arc<PartitionKeys>,
+    node_health: Option<Arc<NodeHealth>>,
+    /// `None` when nodes are chosen at random
+    node_load: Option<Arc<NodeLoad>>,
     nodes: Vec<CassandraNode>,
+    /// Each data center's nodes are sorted by host_id so that every shotover instance fails over to the same nodes
+    remote_nodes: RemoteNodes,
 }
 
 impl Clone for NodePool {
@@ -29,16 +52,28 @@ impl Clone for NodePool {
         Self {
             prepared_metadata: self.prepared_metadata.clone(),
             token_map: TokenMap::new(&[]),
+            partition_keys: Arc::new(PartitionKeys::default()),
+            node_health: self.node_health.clone(),
+            node_load: self.node_load.clone(),
             nodes: vec![],
+            remote_nodes: RemoteNodes::new(),
         }
     }
 }
 
 impl NodePool {
-    pub fn new(nodes: Vec<CassandraNode>) -> Self {
+    pub fn new(
+        nodes: Vec<CassandraNode>,
+        node_health: Option<Arc<NodeHealth>>,
+        node_load: Option<Arc<NodeLoad>>,
+    ) -> Self {
         Self {
             token_map: TokenMap::new(nodes.as_slice()),
+            partition_keys: Arc::new(PartitionKeys::default()),
+            node_health,
+            node_load,
             nodes,
+            remote_nodes: RemoteNodes::new(),
             prepared_metadata: Arc::new(RwLock::new(HashMap::new())),
         }
     }
@@ -65,30 +100,155 @@ impl NodePool {
         self.token_map = TokenMap::new(self.nodes.as_slice());
     }
 
+    /// if the remote node lists have been updated use the new lists, copying over any existing connections
+    pub fn update_remote_nodes(&mut self, remote_nodes_rx: &mut watch::Receiver<RemoteNodes>) {
+        let mut new_remote_nodes = remote_nodes_rx.borrow_and_update().clone();
+
+        for node in self.remote_nodes.drain().flat_map(|(_, nodes)| nodes) {
+            if let Some(outbound) = node.outbound {
+                if let Some(new_node) = new_remote_nodes
+                    .values_mut()
+                    .flat_map(|nodes| nodes.iter_mut())
+                    .find(|new_node| new_node.host_id == node.host_id)
+                {
+                    new_node.outbound = Some(outbound);
+                }
+            }
+        }
+        for nodes in new_remote_nodes.values_mut() {
+            nodes.sort_by_key(|node| node.host_id);
+        }
+        self.remote_nodes = new_remote_nodes;
+    }
+
+    pub fn update_partition_keys(
+        &mut self,
+        partition_keys_rx: &mut watch::Receiver<Arc<PartitionKeys>>,
+    ) {
+        self.partition_keys = partition_keys_rx.borrow_and_update().clone();
+    }
+
     pub async fn add_prepared_result(&mut self, id: CBytesShort, metadata: PreparedMetadata) {
         let mut write_lock = self.prepared_metadata.write().await;
         write_lock.insert(id, metadata);
     }
 
-    pub fn get_random_node_in_dc_rack(
-        &mut self,
-        rack: &String,
-        rng: &mut SmallRng,
-    ) -> &mut CassandraNode {
+    /// Choose one of the `candidates` according to the load balancing policy.
+    fn choose(&self, candidates: Vec<Uuid>, rng: &mut SmallRng) -> Option<Uuid> {
+        match &self.node_load {
+            Some(node_load) => node_load.choose(candidates, rng),
+            None => candidates.choose(rng).copied(),
+        }
+    }
+
+    fn find_node(&mut self, host_id: Uuid) -> Option<&mut CassandraNode> {
+        self.nodes.iter_mut().find(|node| node.host_id == host_id)
+    }
+
+    /// Iterate over the nodes of every data center along with the name of their data center, which is `None` for the local data center
+    pub fn iter_all_nodes(&self) -> impl Iterator<Item = (Option<&str>, &CassandraNode)> {
+        self.nodes
+            .iter()
+            .map(|node| (None, node))
+            .chain(self.remote_nodes.iter().flat_map(|(data_center, nodes)| {
+                nodes
+                    .iter()
+                    .map(move |node| (Some(data_center.as_str()), node))
+            }))
+    }
+
+    /// Find a node in any data center
+    pub fn node_mut(&mut self, host_id: Uuid) -> Option<&mut CassandraNode> {
         self.nodes
             .iter_mut()
-            .filter(|x| x.rack == *rack && x.is_up)
-            .choose(rng)
-            .unwrap()
+            .chain(
+                self.remote_nodes
+                    .values_mut()
+                    .flat_map(|nodes| nodes.iter_mut()),
+            )
+            .find(|node| node.host_id == host_id)
+    }
+
+    /// Select a node in the rack according to the load balancing policy.
+    /// Ejected nodes are avoided, unless every node in the rack is ejected.
+    pub fn select_node_in_dc_rack(&mut self, rack: &str, rng: &mut SmallRng) -> &mut CassandraNode {
+        let node_health = &self.node_health;
+        let any_healthy = self
+            .nodes
+            .iter()
+            .any(|x| x.rack == rack && x.is_up && !is_ejected(node_health, x.host_id));
+        let candidates = self
+            .nodes
+            .iter()
+            .filter(|x| {
+                x.rack == rack && x.is_up && !(any_healthy && is_ejected(node_health, x.host_id))
+            })
+            .map(|x| x.host_id)
+            .collect();
+        let host_id = self.choose(candidates, rng).unwrap();
+        self.find_node(host_id).unwrap()
+    }
+
+    /// Returns true when `select_node_in_dc_rack` has a node to select from.
+    pub fn has_up_node_in_dc_rack(&self, rack: &str) -> bool {
+        self.nodes.iter().any(|x| x.rack == rack && x.is_up)
     }
 
-    /// Get a token routed replica node for the supplied execute message (if exists)
-    pub async fn replica_node(
+    /// Select a node in the rack that is neither ejected nor one of the `excluded` nodes, e.g. to send a speculative execution or retry to.
+    pub fn select_node_in_dc_rack_excluding(
         &mut self,
-        execute: &BodyReqExecuteOwned,
-        version: &Version,
+        rack: &str,
+        excluded: &[Uuid],
         rng: &mut SmallRng,
-    ) -> Result<Option<&mut CassandraNode>, GetReplicaErr> {
+    ) -> Option<&mut CassandraNode> {
+        let node_health = &self.node_health;
+        let candidates = self
+            .nodes
+            .iter()
+            .filter(|x| {
+                x.rack == rack
+                    && x.is_up
+                    && !excluded.contains(&x.host_id)
+                    && !is_ejected(node_health, x.host_id)
+            })
+            .map(|x| x.host_id)
+            .collect();
+        let host_id = self.choose(candidates, rng)?;
+        self.find_node(host_id)
+    }
+
+    /// Select a node of a remote data center to fail over to, according to the load balancing policy.
+    /// Only the first `max_hosts_per_dc` up and non-ejected nodes of each remote data center are considered.
+    pub fn select_remote_node(
+        &mut self,
+        max_hosts_per_dc: usize,
+        rng: &mut SmallRng,
+    ) -> Option<&mut CassandraNode> {
+        let node_health = &self.node_health;
+        let candidates = self
+            .remote_nodes
+            .values()
+            .flat_map(|nodes| {
+                nodes
+                    .iter()
+                    .filter(|x| x.is_up && !is_ejected(node_health, x.host_id))
+                    .take(max_hosts_per_dc)
+            })
+            .map(|x| x.host_id)
+            .collect();
+        let host_id = self.choose(candidates, rng)?;
+        self.remote_nodes
+            .values_mut()
+            .flat_map(|nodes| nodes.iter_mut())
+            .find(|node| node.host_id == host_id)
+    }
+
+    /// Get the routing key of the supplied execute message (if exists)
+    pub async fn execute_routing_key(
+        &self,
+        execute: &BodyReqExecuteOwned,
+        version: Version,
+    ) -> Result<Option<RoutingKey>, GetReplicaErr> {
         let metadata = {
             let read_lock = self.prepared_metadata.read().await;
             read_lock
@@ -97,27 +257,267 @@ impl NodePool {
                 .clone()
         };
 
-        let routing_key = calculate_routing_key(
-            &metadata.pk_indexes,
-            execute.query_parameters.values.as_ref().ok_or_else(|| {
-                GetReplicaErr::Other(anyhow!("Execute body does not have query paramters"))
-            })?,
-            *version,
+        // Fallback to a random node when the routing key cannot be calculated, e.g. a named value is missing
+        Ok(execute
+            .query_parameters
+            .values
+            .as_ref()
+            .and_then(|values| calculate_routing_key(&metadata, values, version))
+            .map(|key| RoutingKey {
+                key,
+                keyspace: metadata
+                    .global_table_spec
+                    .as_ref()
+                    .map(|table_spec| table_spec.ks_name.clone()),
+            }))
+    }
+
+    /// Get the routing key of the supplied QUERY or BATCH message (if exists)
+    /// The partition key values are taken from the literals in the CQL so a BATCH is only routed when all of its statements target the same partition.
+    pub async fn query_routing_key(
+        &self,
+        operation: &CassandraOperation,
+        version: Version,
+        keyspace: Option<&str>,
+    ) -> Option<RoutingKey> {
+        let keyspace = operation.keyspace_flag().or(keyspace);
+        match operation {
+            CassandraOperation::Query { query, .. } => Some(RoutingKey {
+                key: self.partition_keys.routing_key(query, keyspace, version)?,
+                keyspace: statement_keyspace(query, keyspace),
+            }),
+            CassandraOperation::Batch(batch) => Some(RoutingKey {
+                key: self.batch_routing_key(batch, keyspace, version).await?,
+                keyspace: keyspace.map(|keyspace| keyspace.to_owned()),
+            }),
+            _ => None,
+        }
+    }
+
+    async fn batch_routing_key(
+        &self,
+        batch: &CassandraBatch,
+        keyspace: Option<&str>,
+        version: Version,
+    ) -> Option<Vec<u8>> {
+        let mut batch_routing_key = None;
+        for query in batch.queries() {
+            let routing_key = match query.ty() {
+                BatchStatementType::Statement(statement) => self
+                    .partition_keys
+                    .routing_key(statement, keyspace, version)?,
+                BatchStatementType::PreparedId(id) => {
+                    let prepared_metadata = self.prepared_metadata.read().await;
+                    calculate_routing_key(prepared_metadata.get(id)?, query.values(), version)?
+                }
+            };
+
+            match &batch_routing_key {
+                None => batch_routing_key = Some(routing_key),
+                Some(batch_routing_key) if *batch_routing_key != routing_key => return None,
+                Some(_) => {}
+            }
+        }
+        batch_routing_key
+    }
+
+    /// The replicas of `routing_key` in the local data center, in token ring order.
+    /// The replicas are the distinct nodes that follow the token on the ring,
+    /// ignoring the spreading of replicas across racks done by NetworkTopologyStrategy.
+    fn replicas(&self, routing_key: &RoutingKey) -> Vec<Uuid> {
+        let replication_factor = routing_key
+            .keyspace
+            .as_deref()
+            .and_then(|keyspace| self.partition_keys.replication_factor(keyspace))
+            .unwrap_or(1);
+        self.token_map
+            .iter_replica_nodes(Murmur3Token::generate(&routing_key.key), usize::MAX)
+            .unique()
+            .take(replication_factor)
+            .collect()
+    }
+
+    /// Choose one of the `replicas` that is up and not ejected, according to the load balancing policy.
+    /// Replicas in `rack` are preferred over replicas in other racks.
+    fn choose_replica(&self, replicas: &[Uuid], rack: &str, rng: &mut SmallRng) -> Option<Uuid> {
+        let replicas: Vec<&CassandraNode> = replicas
+            .iter()
+            .filter_map(|&host_id| {
+                self.nodes.iter().find(|node| {
+                    node.host_id == host_id && node.is_up && !is_ejected(&self.node_health, host_id)
+                })
+            })
+            .collect();
+
+        let local_replicas: Vec<Uuid> = replicas
+            .iter()
+            .filter(|node| node.rack == rack)
+            .map(|node| node.host_id)
+            .collect();
+        let candidates = if local_replicas.is_empty() {
+            replicas.iter().map(|node| node.host_id).collect()
+        } else {
+            local_replicas
+        };
+
+        self.choose(candidates, rng)
+    }
+
+    /// Get a token routed replica node for `routing_key`.
+    /// Ejected replicas are not used, `GetReplicaErr::NoReplicaUp` is returned when no replica remains
+    /// so that the caller can fall back to a node that is not a replica instead.
+    pub fn replica_node_for_routing_key(
+        &mut self,
+        routing_key: &RoutingKey,
+        rack: &str,
+        rng: &mut SmallRng,
+    ) -> Result<Option<&mut CassandraNode>, GetReplicaErr> {
+        let replicas = self.replicas(routing_key);
+        match self.choose_replica(&replicas, rack, rng) {
+            Some(host_id) => Ok(self.find_node(host_id)),
+            None if replicas.is_empty() => Ok(None),
+            None => Err(GetReplicaErr::NoReplicaUp),
+        }
+    }
+
+    /// Select a replica of `routing_key` other than `excluded`, e.g. to send a speculative execution to.
+    pub fn select_other_replica(
+        &mut self,
+        routing_key: &RoutingKey,
+        excluded: Uuid,
+        rack: &str,
+        rng: &mut SmallRng,
+    ) -> Option<&mut CassandraNode> {
+        let replicas: Vec<Uuid> = self
+            .replicas(routing_key)
+            .into_iter()
+            .filter(|host_id| *host_id != excluded)
+            .collect();
+        let host_id = self.choose_replica(&replicas, rack, rng)?;
+        self.find_node(host_id)
+    }
+}
+
+fn is_ejected(node_health: &Option<Arc<NodeHealth>>, host_id: Uuid) -> bool {
+    node_health
+        .as_ref()
+        .map_or(false, |node_health| node_health.is_ejected(host_id))
+}
+
+#[cfg(test)]
+mod test_node_pool {
+    use super::*;
+
+    fn node(port: u16, token: i64) -> CassandraNode {
+        CassandraNode::new(
+            format!("127.0.0.1:{port}").parse().unwrap(),
+            "rack1".into(),
+            vec![Murmur3Token::new(token)],
+            Uuid::new_v4(),
         )
-        .unwrap();
-
-        // TODO this should use the keyspace info to properly select the replica count
-        let replica_host_ids = self
-            .token_map
-            .iter_replica_nodes(Murmur3Token::generate(&routing_key), 1);
-
-        if let Some(host_id) = replica_host_ids.choose(rng) {
-            return Ok(self
-                .nodes
-                .iter_mut()
-                .find(|node| host_id == node.host_id && node.is_up));
+    }
+
+    #[test]
+    fn test_select_remote_node() {
+        let mut pool = NodePool::new(vec![], None, None);
+        let mut remote_nodes = RemoteNodes::new();
+        remote_nodes.insert(
+            "dc2".to_owned(),
+            vec![node(9042, 0), node(9043, 1), node(9044, 2)],
+        );
+        let (_remote_nodes_tx, mut remote_nodes_rx) = watch::channel(remote_nodes);
+        pool.update_remote_nodes(&mut remote_nodes_rx);
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let first_host_id = pool.remote_nodes["dc2"][0].host_id;
+        for _ in 0..10 {
+            assert_eq!(
+                pool.select_remote_node(1, &mut rng).unwrap().host_id,
+                first_host_id
+            );
         }
 
-        Ok(None)
+        // down nodes do not count towards the maximum
+        pool.remote_nodes.get_mut("dc2").unwrap()[0].is_up = false;
+        let second_host_id = pool.remote_nodes["dc2"][1].host_id;
+        assert_eq!(
+            pool.select_remote_node(1, &mut rng).unwrap().host_id,
+            second_host_id
+        );
+
+        for node in pool.remote_nodes.get_mut("dc2").unwrap() {
+            node.is_up = false;
+        }
+        assert!(pool.select_remote_node(1, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_no_replica_up() {
+        let mut pool = NodePool::new(vec![node(9042, 0)], None, None);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let routing_key = RoutingKey {
+            key: vec![0],
+            keyspace: None,
+        };
+        assert!(matches!(
+            pool.replica_node_for_routing_key(&routing_key, "rack1", &mut rng),
+            Ok(Some(_))
+        ));
+
+        pool.nodes()[0].is_up = false;
+        assert!(matches!(
+            pool.replica_node_for_routing_key(&routing_key, "rack1", &mut rng),
+            Err(GetReplicaErr::NoReplicaUp)
+        ));
+
+        let mut pool = NodePool::new(vec![], None, None);
+        assert!(matches!(
+            pool.replica_node_for_routing_key(&routing_key, "rack1", &mut rng),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn test_select_other_replica() {
+        let mut pool = NodePool::new(
+            vec![node(9042, 0), node(9043, 100), node(9044, 200)],
+            None,
+            None,
+        );
+        let mut replication_factors = HashMap::new();
+        replication_factors.insert("ks".to_owned(), 2);
+        pool.partition_keys = Arc::new(PartitionKeys::new(HashMap::new(), replication_factors));
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        // the token of the key is owned by one of the nodes, its replica is the next distinct node on the ring
+        let routing_key = RoutingKey {
+            key: vec![0],
+            keyspace: Some("ks".to_owned()),
+        };
+        let replicas = pool.replicas(&routing_key);
+        assert_eq!(replicas.len(), 2);
+        for _ in 0..10 {
+            assert_eq!(
+                pool.select_other_replica(&routing_key, replicas[0], "rack1", &mut rng)
+                    .unwrap()
+                    .host_id,
+                replicas[1]
+            );
+        }
+
+        pool.find_node(replicas[1]).unwrap().is_up = false;
+        assert!(pool
+            .select_other_replica(&routing_key, replicas[0], "rack1", &mut rng)
+            .is_none());
+
+        // without the replication factor of the keyspace only the first replica is known
+        let routing_key = RoutingKey {
+            key: vec![0],
+            keyspace: Some("other".to_owned()),
+        };
+        assert_eq!(pool.replicas(&routing_key), vec![replicas[0]]);
+        assert!(pool
+            .select_other_replica(&routing_key, replicas[0], "rack1", &mut rng)
+            .is_none());
     }
 }
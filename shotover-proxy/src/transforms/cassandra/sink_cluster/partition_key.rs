@@ -0,0 +1,378 @@
+use super::routing_key::calculate_routing_key_from_values;
+use cassandra_protocol::frame::Version;
+use cassandra_protocol::types::value::Value;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use cql3_parser::common::{Identifier, Operand, RelationElement, RelationOperator};
+use cql3_parser::insert::InsertValues;
+use num::BigInt;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionKeyColumn {
+    pub name: String,
+    /// The type of the column as stored in `system_schema.columns` e.g. `int` or `text`
+    pub ty: String,
+}
+
+/// The partition key columns of every table in the cluster, used to route unprepared statements to a replica,
+/// along with the replication factor of every keyspace, used to find all of a statement's replicas.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartitionKeys {
+    /// Keyed by keyspace name and table name, the columns are in partition key order.
+    tables: HashMap<(String, String), Vec<PartitionKeyColumn>>,
+    /// Keyed by keyspace name, the number of replicas the keyspace has in the local data center.
+    replication_factors: HashMap<String, usize>,
+}
+
+impl PartitionKeys {
+    pub fn new(
+        tables: HashMap<(String, String), Vec<PartitionKeyColumn>>,
+        replication_factors: HashMap<String, usize>,
+    ) -> Self {
+        PartitionKeys {
+            tables,
+            replication_factors,
+        }
+    }
+
+    /// The number of replicas `keyspace` has in the local data center, `None` when it is not known.
+    pub fn replication_factor(&self, keyspace: &str) -> Option<usize> {
+        self.replication_factors.get(keyspace).copied()
+    }
+
+    /// Calculate the routing key of a statement from the literal values it provides for its table's partition key.
+    /// `keyspace` is used when the statement does not specify the keyspace of its table.
+    ///
+    /// Returns `None` when the statement does not restrict every partition key column to a single literal value,
+    /// e.g. when bind markers, functions or `IN` are used.
+    pub fn routing_key(
+        &self,
+        statement: &CassandraStatement,
+        keyspace: Option<&str>,
+        version: Version,
+    ) -> Option<Vec<u8>> {
+        let (table_name, operands) = match statement {
+            CassandraStatement::Select(select) => (
+                &select.table_name,
+                where_clause_operands(&select.where_clause),
+            ),
+            CassandraStatement::Update(update) => (
+                &update.table_name,
+                where_clause_operands(&update.where_clause),
+            ),
+            CassandraStatement::Delete(delete) => (
+                &delete.table_name,
+                where_clause_operands(&delete.where_clause),
+            ),
+            CassandraStatement::Insert(insert) => match &insert.values {
+                InsertValues::Values(values) => (
+                    &insert.table_name,
+                    insert
+                        .columns
+                        .iter()
+                        .map(identifier_name)
+                        .zip(values.iter())
+                        .collect(),
+                ),
+                InsertValues::Json(_) => return None,
+            },
+            _ => return None,
+        };
+
+        let keyspace = match &table_name.keyspace {
+            Some(keyspace) => identifier_name(keyspace),
+            None => keyspace?.to_owned(),
+        };
+        let columns = self
+            .tables
+            .get(&(keyspace, identifier_name(&table_name.name)))?;
+
+        let values = columns
+            .iter()
+            .map(|column| match operands.get(&column.name)? {
+                Operand::Const(literal) => serialize_literal(literal, &column.ty).map(Value::Some),
+                _ => None,
+            })
+            .collect::<Option<Vec<Value>>>()?;
+
+        calculate_routing_key_from_values(&values, version)
+    }
+}
+
+/// The keyspace of the table accessed by `statement`.
+/// `keyspace` is used when the statement does not specify the keyspace of its table.
+pub fn statement_keyspace(
+    statement: &CassandraStatement,
+    keyspace: Option<&str>,
+) -> Option<String> {
+    let table_name = match statement {
+        CassandraStatement::Select(select) => &select.table_name,
+        CassandraStatement::Update(update) => &update.table_name,
+        CassandraStatement::Delete(delete) => &delete.table_name,
+        CassandraStatement::Insert(insert) => &insert.table_name,
+        _ => return None,
+    };
+    match &table_name.keyspace {
+        Some(keyspace) => Some(identifier_name(keyspace)),
+        None => keyspace.map(|keyspace| keyspace.to_owned()),
+    }
+}
+
+/// Unquoted identifiers are case insensitive while `system_schema` stores the case sensitive name.
+pub fn identifier_name(identifier: &Identifier) -> String {
+    match identifier {
+        Identifier::Unquoted(name) => name.to_lowercase(),
+        Identifier::Quoted(name) => name.clone(),
+    }
+}
+
+fn where_clause_operands(where_clause: &[RelationElement]) -> HashMap<String, &Operand> {
+    where_clause
+        .iter()
+        .filter_map(|relation| match (&relation.obj, &relation.oper) {
+            (Operand::Column(column), RelationOperator::Equal) => {
+                Some((identifier_name(column), &relation.value))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Serialize a CQL literal into the bytes cassandra uses for a value of type `ty`.
+/// Returns `None` for types and literal formats that are not supported, such as timestamps given as a date string.
+pub fn serialize_literal(literal: &str, ty: &str) -> Option<Vec<u8>> {
+    let is_string = literal.starts_with('\'') || literal.starts_with("$$");
+    match ty {
+        "ascii" | "text" | "varchar" if is_string => Some(Operand::unescape(literal).into_bytes()),
+        "inet" if is_string => match IpAddr::from_str(&Operand::unescape(literal)).ok()? {
+            IpAddr::V4(ip) => Some(ip.octets().to_vec()),
+            IpAddr::V6(ip) => Some(ip.octets().to_vec()),
+        },
+        "blob" => literal
+            .strip_prefix("0x")
+            .or_else(|| literal.strip_prefix("0X"))
+            .and_then(|value| hex::decode(value).ok()),
+        "boolean" => match literal.to_lowercase().as_str() {
+            "true" => Some(vec![1]),
+            "false" => Some(vec![0]),
+            _ => None,
+        },
+        "tinyint" => i8::from_str(literal).ok().map(|x| x.to_be_bytes().to_vec()),
+        "smallint" => i16::from_str(literal)
+            .ok()
+            .map(|x| x.to_be_bytes().to_vec()),
+        "int" => i32::from_str(literal)
+            .ok()
+            .map(|x| x.to_be_bytes().to_vec()),
+        "bigint" | "timestamp" => i64::from_str(literal)
+            .ok()
+            .map(|x| x.to_be_bytes().to_vec()),
+        "varint" => BigInt::from_str(literal)
+            .ok()
+            .map(|x| x.to_signed_bytes_be()),
+        "float" => f32::from_str(literal)
+            .ok()
+            .map(|x| x.to_be_bytes().to_vec()),
+        "double" => f64::from_str(literal)
+            .ok()
+            .map(|x| x.to_be_bytes().to_vec()),
+        "uuid" | "timeuuid" => Uuid::parse_str(literal).ok().map(|x| x.as_bytes().to_vec()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test_partition_key {
+    use super::*;
+    use crate::frame::cassandra::parse_statement_single;
+    use crate::transforms::cassandra::sink_cluster::routing_key::calculate_routing_key;
+    use cassandra_protocol::frame::message_result::PreparedMetadata;
+    use cassandra_protocol::query::QueryValues;
+
+    fn partition_keys() -> PartitionKeys {
+        let mut tables = HashMap::new();
+        tables.insert(
+            ("ks".to_owned(), "single".to_owned()),
+            vec![PartitionKeyColumn {
+                name: "id".to_owned(),
+                ty: "int".to_owned(),
+            }],
+        );
+        tables.insert(
+            ("ks".to_owned(), "composite".to_owned()),
+            vec![
+                PartitionKeyColumn {
+                    name: "name".to_owned(),
+                    ty: "text".to_owned(),
+                },
+                PartitionKeyColumn {
+                    name: "Id".to_owned(),
+                    ty: "uuid".to_owned(),
+                },
+            ],
+        );
+        let mut replication_factors = HashMap::new();
+        replication_factors.insert("ks".to_owned(), 3);
+        PartitionKeys::new(tables, replication_factors)
+    }
+
+    fn routing_key(query: &str, keyspace: Option<&str>) -> Option<Vec<u8>> {
+        partition_keys().routing_key(&parse_statement_single(query), keyspace, Version::V4)
+    }
+
+    fn execute_routing_key(values: Vec<Value>) -> Option<Vec<u8>> {
+        let metadata = PreparedMetadata {
+            pk_indexes: (0..values.len() as i16).collect(),
+            global_table_spec: None,
+            col_specs: vec![],
+        };
+        calculate_routing_key(&metadata, &QueryValues::SimpleValues(values), Version::V4)
+    }
+
+    #[test]
+    fn test_single_column_partition_key() {
+        // The same partition is routed the same way as an EXECUTE with a bound value would be
+        let expected = execute_routing_key(vec![Value::Some(1i32.to_be_bytes().to_vec())]);
+        assert!(expected.is_some());
+
+        assert_eq!(
+            routing_key("SELECT * FROM ks.single WHERE id = 1", None),
+            expected
+        );
+        assert_eq!(
+            routing_key("SELECT * FROM single WHERE id = 1 AND x = 2", Some("ks")),
+            expected
+        );
+        assert_eq!(
+            routing_key("INSERT INTO ks.single (x, id) VALUES (5, 1)", None),
+            expected
+        );
+        assert_eq!(
+            routing_key("UPDATE ks.single SET x = 5 WHERE id = 1", None),
+            expected
+        );
+        assert_eq!(
+            routing_key("DELETE FROM ks.single WHERE id = 1", None),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_composite_partition_key() {
+        let uuid = "123e4567-e89b-12d3-a456-426655440000";
+        let expected = execute_routing_key(vec![
+            Value::Some(b"foo".to_vec()),
+            Value::Some(Uuid::parse_str(uuid).unwrap().as_bytes().to_vec()),
+        ]);
+        assert!(expected.is_some());
+
+        assert_eq!(
+            routing_key(
+                &format!(r#"SELECT * FROM ks.composite WHERE "Id" = {uuid} AND name = 'foo'"#),
+                None
+            ),
+            expected
+        );
+        assert_eq!(
+            routing_key(
+                &format!(r#"INSERT INTO ks.composite (name, "Id") VALUES ('foo', {uuid})"#),
+                None
+            ),
+            expected
+        );
+
+        // the quoted column name is case sensitive
+        assert_eq!(
+            routing_key(
+                &format!("SELECT * FROM ks.composite WHERE id = {uuid} AND name = 'foo'"),
+                None
+            ),
+            None
+        );
+        // not every partition key column is restricted
+        assert_eq!(
+            routing_key("SELECT * FROM ks.composite WHERE name = 'foo'", None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unroutable_statements() {
+        // unknown keyspace
+        assert_eq!(routing_key("SELECT * FROM single WHERE id = 1", None), None);
+        // unknown table
+        assert_eq!(
+            routing_key("SELECT * FROM ks.other WHERE id = 1", None),
+            None
+        );
+        // multiple partitions
+        assert_eq!(
+            routing_key("SELECT * FROM ks.single WHERE id IN (1, 2)", None),
+            None
+        );
+        // bind marker
+        assert_eq!(
+            routing_key("SELECT * FROM ks.single WHERE id = ?", None),
+            None
+        );
+        // literal does not match the column type
+        assert_eq!(
+            routing_key("SELECT * FROM ks.single WHERE id = 'foo'", None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_serialize_literal() {
+        assert_eq!(
+            serialize_literal("'foo''s'", "text"),
+            Some(b"foo's".to_vec())
+        );
+        assert_eq!(
+            serialize_literal("$$foo$$", "varchar"),
+            Some(b"foo".to_vec())
+        );
+        assert_eq!(serialize_literal("foo", "text"), None);
+        assert_eq!(serialize_literal("0xFFEA", "blob"), Some(vec![0xFF, 0xEA]));
+        assert_eq!(serialize_literal("TRUE", "boolean"), Some(vec![1]));
+        assert_eq!(serialize_literal("-1", "tinyint"), Some(vec![0xFF]));
+        assert_eq!(serialize_literal("1", "smallint"), Some(vec![0, 1]));
+        assert_eq!(
+            serialize_literal("1", "bigint"),
+            Some(vec![0, 0, 0, 0, 0, 0, 0, 1])
+        );
+        assert_eq!(serialize_literal("256", "varint"), Some(vec![1, 0]));
+        assert_eq!(
+            serialize_literal("1.5", "double"),
+            Some(1.5f64.to_be_bytes().to_vec())
+        );
+        assert_eq!(
+            serialize_literal("'127.0.0.1'", "inet"),
+            Some(vec![127, 0, 0, 1])
+        );
+        assert_eq!(serialize_literal("'2022-01-01'", "date"), None);
+    }
+
+    #[test]
+    fn test_statement_keyspace() {
+        let keyspace = |query: &str, keyspace: Option<&str>| {
+            statement_keyspace(&parse_statement_single(query), keyspace)
+        };
+        assert_eq!(
+            keyspace("SELECT * FROM Ks.single", Some("other")),
+            Some("ks".to_owned())
+        );
+        assert_eq!(
+            keyspace("INSERT INTO single (id) VALUES (1)", Some("ks")),
+            Some("ks".to_owned())
+        );
+        assert_eq!(keyspace("DELETE FROM single WHERE id = 1", None), None);
+        assert_eq!(keyspace("USE ks", Some("ks")), None);
+
+        assert_eq!(partition_keys().replication_factor("ks"), Some(3));
+        assert_eq!(partition_keys().replication_factor("other"), None);
+    }
+}
@@ -1,3 +1,4 @@
+use cassandra_protocol::frame::message_result::PreparedMetadata;
 use cassandra_protocol::frame::{Serialize, Version};
 use cassandra_protocol::query::QueryValues;
 use cassandra_protocol::types::value::Value;
@@ -8,17 +9,36 @@ use std::io::{Cursor, Write};
 
 // functions taken from https://github.com/krojew/cdrs-tokio/blob/9246dcf4227c1d4b1ff1eafaf0abfae2d831eec4/cdrs-tokio/src/cluster/session.rs#L126
 
+/// Calculate the routing key of a prepared statement from its bound values.
+/// Returns `None` if a value is missing for any of the partition key columns.
 pub fn calculate_routing_key(
-    pk_indexes: &[i16],
+    metadata: &PreparedMetadata,
     query_values: &QueryValues,
     version: Version,
 ) -> Option<Vec<u8>> {
-    let values = match query_values {
-        QueryValues::SimpleValues(values) => values,
-        _ => panic!("handle named"),
-    };
+    match query_values {
+        QueryValues::SimpleValues(values) => {
+            serialize_routing_key_with_indexes(values, &metadata.pk_indexes, version)
+        }
+        QueryValues::NamedValues(values) => {
+            // Named values are matched to the partition key by the names of the prepared statement's bind markers
+            let values = metadata
+                .pk_indexes
+                .iter()
+                .map(|index| {
+                    let col_spec = metadata.col_specs.get(*index as usize)?;
+                    values.get(&col_spec.name).cloned()
+                })
+                .collect::<Option<Vec<Value>>>()?;
+            calculate_routing_key_from_values(&values, version)
+        }
+    }
+}
 
-    serialize_routing_key_with_indexes(values, pk_indexes, version)
+/// Calculate the routing key from the values of each partition key column, given in partition key order.
+pub fn calculate_routing_key_from_values(values: &[Value], version: Version) -> Option<Vec<u8>> {
+    let pk_indexes: Vec<i16> = (0..values.len() as i16).collect();
+    serialize_routing_key_with_indexes(values, &pk_indexes, version)
 }
 
 fn serialize_routing_key_with_indexes(
@@ -67,3 +87,97 @@ fn serialize_routing_value(cursor: &mut Cursor<&mut Vec<u8>>, value: &Value, ver
     cursor.set_position(after_value_pos);
     let _ = cursor.write(&[0]);
 }
+
+#[cfg(test)]
+mod test_routing_key {
+    use super::*;
+    use cassandra_protocol::frame::message_result::{ColSpec, ColType, ColTypeOption};
+    use std::collections::HashMap;
+
+    fn prepared_metadata(pk_indexes: Vec<i16>, names: &[&str]) -> PreparedMetadata {
+        PreparedMetadata {
+            pk_indexes,
+            global_table_spec: None,
+            col_specs: names
+                .iter()
+                .map(|name| ColSpec {
+                    table_spec: None,
+                    name: name.to_string(),
+                    col_type: ColTypeOption {
+                        id: ColType::Int,
+                        value: None,
+                    },
+                })
+                .collect(),
+        }
+    }
+
+    fn value(x: i32) -> Value {
+        Value::Some(x.to_be_bytes().to_vec())
+    }
+
+    fn named_values(values: &[(&str, i32)]) -> QueryValues {
+        QueryValues::NamedValues(
+            values
+                .iter()
+                .map(|(name, x)| (name.to_string(), value(*x)))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn test_named_values_single_column() {
+        let metadata = prepared_metadata(vec![1], &["x", "id"]);
+
+        let simple = calculate_routing_key(
+            &metadata,
+            &QueryValues::SimpleValues(vec![value(5), value(420)]),
+            Version::V4,
+        );
+        assert_eq!(simple, Some(vec![0, 0, 0, 4, 0, 0, 1, 164]));
+
+        let named = calculate_routing_key(
+            &metadata,
+            &named_values(&[("id", 420), ("x", 5)]),
+            Version::V4,
+        );
+        assert_eq!(named, simple);
+    }
+
+    #[test]
+    fn test_named_values_composite() {
+        let metadata = prepared_metadata(vec![2, 0], &["b", "x", "a"]);
+
+        let simple = calculate_routing_key(
+            &metadata,
+            &QueryValues::SimpleValues(vec![value(2), value(5), value(1)]),
+            Version::V4,
+        );
+        assert!(simple.is_some());
+
+        let named = calculate_routing_key(
+            &metadata,
+            &named_values(&[("x", 5), ("a", 1), ("b", 2)]),
+            Version::V4,
+        );
+        assert_eq!(named, simple);
+    }
+
+    #[test]
+    fn test_named_values_missing() {
+        let metadata = prepared_metadata(vec![1], &["x", "id"]);
+
+        assert_eq!(
+            calculate_routing_key(&metadata, &named_values(&[("x", 5)]), Version::V4),
+            None
+        );
+        assert_eq!(
+            calculate_routing_key(
+                &prepared_metadata(vec![2], &["x", "id"]),
+                &named_values(&[("id", 420)]),
+                Version::V4
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use super::node::ConnectionFactory;
+use super::NodeObservers;
+use crate::message::Message;
+use crate::transforms::cassandra::connection::CassandraConnection;
+use crate::transforms::util::Response;
+use anyhow::Result;
+use metrics::{register_counter, Counter};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use uuid::Uuid;
+
+const DEFAULT_MAX_IN_FLIGHT: usize = 100;
+
+/// When a read has not received a response within `delay_ms` milliseconds,
+/// the read is also sent to another replica and whichever response arrives first is used.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SpeculativeExecutionConfig {
+    pub delay_ms: u64,
+    /// The maximum number of speculative executions in flight at once across all client connections, defaults to 100.
+    /// Reads that are slow to respond while the limit is reached are not speculatively executed, so that a cluster that is slow overall is not sent even more requests.
+    pub max_in_flight: Option<usize>,
+}
+
+/// Shared by every connection so that the limit on speculative executions in flight applies across all of them.
+#[derive(Clone)]
+pub struct SpeculativeExecution {
+    delay: Duration,
+    in_flight: Arc<Semaphore>,
+    speculative_executions: Counter,
+    speculative_execution_wins: Counter,
+}
+
+/// The connection to the node that a speculative execution is sent to.
+pub enum SpeculativeConnection {
+    Open(CassandraConnection),
+    /// The connection is only opened once a request is actually speculatively executed.
+    /// It is then sent through `opened_tx` so that the transform can reuse it.
+    Closed {
+        address: SocketAddr,
+        connection_factory: ConnectionFactory,
+        opened_tx: mpsc::UnboundedSender<(Uuid, CassandraConnection)>,
+    },
+}
+
+impl SpeculativeConnection {
+    /// Failing to connect to the speculative node should not fail a request that the first node can still handle, so errors are only logged.
+    async fn open(self, host_id: Uuid) -> Option<CassandraConnection> {
+        match self {
+            SpeculativeConnection::Open(connection) => Some(connection),
+            SpeculativeConnection::Closed {
+                address,
+                connection_factory,
+                opened_tx,
+            } => match connection_factory.new_connection(address).await {
+                Ok(connection) => {
+                    opened_tx.send((host_id, connection.clone())).ok();
+                    Some(connection)
+                }
+                Err(err) => {
+                    tracing::debug!(
+                        "Failed to connect to node {host_id} for speculative execution: {err:?}"
+                    );
+                    None
+                }
+            },
+        }
+    }
+}
+
+impl SpeculativeExecution {
+    pub fn new(config: &SpeculativeExecutionConfig, chain_name: &str) -> Self {
+        SpeculativeExecution {
+            delay: Duration::from_millis(config.delay_ms),
+            in_flight: Arc::new(Semaphore::new(
+                config.max_in_flight.unwrap_or(DEFAULT_MAX_IN_FLIGHT),
+            )),
+            speculative_executions: register_counter!("speculative_executions", "chain" => chain_name.to_owned(), "transform" => "CassandraSinkCluster"),
+            speculative_execution_wins: register_counter!("speculative_execution_wins", "chain" => chain_name.to_owned(), "transform" => "CassandraSinkCluster"),
+        }
+    }
+
+    /// Send `message` to `connection` and, if no response has arrived after the delay, also send it to `speculative_connection`.
+    /// The first response to arrive is returned through `return_chan` and the other one is discarded.
+    /// Each request is observed against the node it was actually sent to, including the one whose response is discarded.
+    pub fn send(
+        &self,
+        message: Message,
+        (host_id, connection): (Uuid, CassandraConnection),
+        (speculative_host_id, speculative_connection): (Uuid, SpeculativeConnection),
+        return_chan: oneshot::Sender<Response>,
+        node_observers: NodeObservers,
+    ) -> Result<()> {
+        let (primary_tx, mut primary_rx) = oneshot::channel();
+        connection.send_observed(
+            message.clone(),
+            primary_tx,
+            node_observers.for_node(host_id),
+        )?;
+
+        let speculative_execution = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                response = &mut primary_rx => {
+                    if let Ok(response) = response {
+                        return_chan.send(response).ok();
+                    }
+                    return;
+                }
+                _ = tokio::time::sleep(speculative_execution.delay) => {}
+            }
+
+            // Held until the speculative execution is decided
+            let permit = speculative_execution
+                .in_flight
+                .clone()
+                .try_acquire_owned()
+                .ok();
+
+            // Without a permit or a speculative connection we keep waiting on the first node
+            let speculative_connection = tokio::select! {
+                response = &mut primary_rx => {
+                    if let Ok(response) = response {
+                        return_chan.send(response).ok();
+                    }
+                    return;
+                }
+                connection = speculative_connection.open(speculative_host_id), if permit.is_some() => connection,
+            };
+            let (speculative_tx, mut speculative_rx) = oneshot::channel();
+            let sent = speculative_connection.map_or(false, |connection| {
+                connection
+                    .send_observed(
+                        message,
+                        speculative_tx,
+                        node_observers.for_node(speculative_host_id),
+                    )
+                    .is_ok()
+            });
+            if !sent {
+                if let Ok(response) = primary_rx.await {
+                    return_chan.send(response).ok();
+                }
+                return;
+            }
+            speculative_execution.speculative_executions.increment(1);
+
+            // A connection that fails drops its sender, in which case we keep waiting on the other connection.
+            // The losing request is abandoned by dropping its receiver, its connection discards the response when it arrives.
+            let response = tokio::select! {
+                Ok(response) = &mut primary_rx => response,
+                Ok(response) = &mut speculative_rx => {
+                    speculative_execution.speculative_execution_wins.increment(1);
+                    response
+                }
+                else => return,
+            };
+            return_chan.send(response).ok();
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_speculative_execution {
+    use super::*;
+    use crate::codec::cassandra::CassandraCodec;
+    use crate::frame::cassandra::parse_statement_single;
+    use crate::frame::{CassandraFrame, CassandraOperation, Frame};
+    use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+    use cassandra_protocol::frame::Version;
+    use cassandra_protocol::query::QueryParams;
+    use futures::{SinkExt, StreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    struct FakeNode {
+        address: SocketAddr,
+        connections: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    /// A node that responds to every request after `response_delay` with an error containing `name`, so that tests can tell which node responded.
+    async fn fake_node(name: &'static str, response_delay: Duration) -> FakeNode {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let node = FakeNode {
+            address: listener.local_addr().unwrap(),
+            connections: Arc::new(AtomicUsize::new(0)),
+            requests: Arc::new(AtomicUsize::new(0)),
+        };
+        let connections = node.connections.clone();
+        let requests = node.requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                connections.fetch_add(1, Ordering::SeqCst);
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let mut framed = Framed::new(socket, CassandraCodec::new());
+                    while let Some(Ok(messages)) = framed.next().await {
+                        for request in messages {
+                            requests.fetch_add(1, Ordering::SeqCst);
+                            let stream_id = request.stream_id().unwrap();
+                            tokio::time::sleep(response_delay).await;
+                            let response = Message::from_frame(Frame::Cassandra(CassandraFrame {
+                                version: Version::V4,
+                                stream_id,
+                                tracing_id: None,
+                                warnings: vec![],
+                                operation: CassandraOperation::Error(ErrorBody {
+                                    message: name.into(),
+                                    ty: ErrorType::Server,
+                                }),
+                            }));
+                            if framed.send(vec![response]).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        node
+    }
+
+    async fn connect(node: &FakeNode) -> CassandraConnection {
+        CassandraConnection::new(node.address, CassandraCodec::new(), None, None, None)
+            .await
+            .unwrap()
+    }
+
+    fn speculative_execution(delay_ms: u64, max_in_flight: usize) -> SpeculativeExecution {
+        SpeculativeExecution::new(
+            &SpeculativeExecutionConfig {
+                delay_ms,
+                max_in_flight: Some(max_in_flight),
+            },
+            "chain",
+        )
+    }
+
+    /// Send a read through `speculative_execution` and return the name of the node that responded.
+    async fn send(
+        speculative_execution: &SpeculativeExecution,
+        connection: CassandraConnection,
+        speculative_connection: SpeculativeConnection,
+    ) -> String {
+        let message = Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: 1,
+            tracing_id: None,
+            warnings: vec![],
+            operation: CassandraOperation::Query {
+                query: Box::new(parse_statement_single("SELECT * FROM ks.table")),
+                params: Box::new(QueryParams::default()),
+            },
+        }));
+        let (return_chan_tx, return_chan_rx) = oneshot::channel();
+        speculative_execution
+            .send(
+                message,
+                (Uuid::new_v4(), connection),
+                (Uuid::new_v4(), speculative_connection),
+                return_chan_tx,
+                NodeObservers {
+                    node_health: None,
+                    node_load: None,
+                },
+            )
+            .unwrap();
+
+        let mut response = return_chan_rx.await.unwrap().response.unwrap();
+        match response.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Error(ErrorBody { message, .. }),
+                ..
+            })) => message.to_string(),
+            frame => panic!("unexpected response {:?}", frame),
+        }
+    }
+
+    fn closed(
+        node: &FakeNode,
+    ) -> (
+        SpeculativeConnection,
+        mpsc::UnboundedReceiver<(Uuid, CassandraConnection)>,
+    ) {
+        let (opened_tx, opened_rx) = mpsc::unbounded_channel();
+        (
+            SpeculativeConnection::Closed {
+                address: node.address,
+                connection_factory: ConnectionFactory::new(None, None),
+                opened_tx,
+            },
+            opened_rx,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_no_speculative_execution_before_delay() {
+        let primary = fake_node("primary", Duration::from_millis(50)).await;
+        let speculative = fake_node("speculative", Duration::ZERO).await;
+        let speculative_execution = speculative_execution(1000, 10);
+
+        let (speculative_connection, mut opened_rx) = closed(&speculative);
+        assert_eq!(
+            send(
+                &speculative_execution,
+                connect(&primary).await,
+                speculative_connection
+            )
+            .await,
+            "primary"
+        );
+
+        // the speculative connection is never opened when the first node responds within the delay
+        assert_eq!(speculative.connections.load(Ordering::SeqCst), 0);
+        assert!(opened_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_speculative_execution_wins() {
+        let primary = fake_node("primary", Duration::from_secs(1)).await;
+        let speculative = fake_node("speculative", Duration::ZERO).await;
+        let speculative_execution = speculative_execution(50, 10);
+
+        let (speculative_connection, mut opened_rx) = closed(&speculative);
+        assert_eq!(
+            send(
+                &speculative_execution,
+                connect(&primary).await,
+                speculative_connection
+            )
+            .await,
+            "speculative"
+        );
+
+        // the connection opened for the speculative execution is handed back for reuse
+        assert_eq!(speculative.connections.load(Ordering::SeqCst), 1);
+        assert!(opened_rx.try_recv().is_ok());
+
+        // the first node still wins when it responds before the speculative node does
+        let slow_speculative = fake_node("speculative", Duration::from_secs(1)).await;
+        let primary = fake_node("primary", Duration::from_millis(200)).await;
+        assert_eq!(
+            send(
+                &speculative_execution,
+                connect(&primary).await,
+                SpeculativeConnection::Open(connect(&slow_speculative).await)
+            )
+            .await,
+            "primary"
+        );
+        assert_eq!(slow_speculative.requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight() {
+        let primary = fake_node("primary", Duration::from_millis(500)).await;
+        let speculative = fake_node("speculative", Duration::from_millis(500)).await;
+        let speculative_execution = speculative_execution(50, 1);
+        let speculative_connection = connect(&speculative).await;
+
+        let (first, second) = tokio::join!(
+            send(
+                &speculative_execution,
+                connect(&primary).await,
+                SpeculativeConnection::Open(speculative_connection.clone())
+            ),
+            send(
+                &speculative_execution,
+                connect(&primary).await,
+                SpeculativeConnection::Open(speculative_connection.clone())
+            ),
+        );
+        assert_eq!(first, "primary");
+        assert_eq!(second, "primary");
+
+        // only one of the reads was speculatively executed as the other one was over the limit
+        assert_eq!(speculative.requests.load(Ordering::SeqCst), 1);
+    }
+}
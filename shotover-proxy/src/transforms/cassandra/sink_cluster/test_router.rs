@@ -19,7 +19,7 @@ mod test_token_aware_router {
 
     #[test]
     fn test_router() {
-        let mut router = NodePool::new(prepare_nodes());
+        let mut router = NodePool::new(prepare_nodes(), None, None);
 
         let prepared_metadata = PreparedMetadata {
             pk_indexes: vec![0],
@@ -64,7 +64,7 @@ mod test_token_aware_router {
         router.add_prepared_result(id, prepared_metadata.clone());
 
         let routing_key = calculate_routing_key(
-            &prepared_metadata.pk_indexes,
+            &prepared_metadata,
             query_parameters.values.as_ref().unwrap(),
             Version::V4,
         )
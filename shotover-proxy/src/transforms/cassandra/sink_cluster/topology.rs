@@ -1,4 +1,5 @@
 use super::node::{CassandraNode, ConnectionFactory};
+use super::partition_key::{PartitionKeyColumn, PartitionKeys};
 use crate::frame::cassandra::parse_statement_single;
 use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
 use crate::message::{Message, MessageValue};
@@ -9,10 +10,15 @@ use cassandra_protocol::frame::events::{StatusChangeType, TopologyChangeType};
 use cassandra_protocol::frame::message_register::BodyReqRegister;
 use cassandra_protocol::token::Murmur3Token;
 use cassandra_protocol::{frame::Version, query::QueryParams};
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::{mpsc, oneshot, watch};
 
+/// The nodes of every data center other than the local data center, keyed by data center name.
+pub type RemoteNodes = HashMap<String, Vec<CassandraNode>>;
+
 #[derive(Debug)]
 pub struct TaskConnectionInfo {
     pub connection_factory: ConnectionFactory,
@@ -21,14 +27,22 @@ pub struct TaskConnectionInfo {
 
 pub fn create_topology_task(
     nodes_tx: watch::Sender<Vec<CassandraNode>>,
+    remote_nodes_tx: watch::Sender<RemoteNodes>,
+    partition_keys_tx: watch::Sender<Arc<PartitionKeys>>,
     mut connection_info_rx: mpsc::Receiver<TaskConnectionInfo>,
     data_center: String,
 ) {
     tokio::spawn(async move {
         while let Some(mut connection_info) = connection_info_rx.recv().await {
             let mut attempts = 0;
-            while let Err(err) =
-                topology_task_process(&nodes_tx, &mut connection_info, &data_center).await
+            while let Err(err) = topology_task_process(
+                &nodes_tx,
+                &remote_nodes_tx,
+                &partition_keys_tx,
+                &mut connection_info,
+                &data_center,
+            )
+            .await
             {
                 tracing::error!("topology task failed, retrying, error was: {err:?}");
                 attempts += 1;
@@ -43,6 +57,8 @@ pub fn create_topology_task(
 
 async fn topology_task_process(
     nodes_tx: &watch::Sender<Vec<CassandraNode>>,
+    remote_nodes_tx: &watch::Sender<RemoteNodes>,
+    partition_keys_tx: &watch::Sender<Arc<PartitionKeys>>,
     connection_info: &mut TaskConnectionInfo,
     data_center: &str,
 ) -> Result<()> {
@@ -58,10 +74,17 @@ async fn topology_task_process(
 
     let version = connection_info.connection_factory.get_version()?;
 
-    let mut nodes = fetch_current_nodes(&connection, connection_info, data_center).await?;
+    // Partition keys are sent first so that they are available as soon as routing to the nodes begins
+    partition_keys_tx.send(Arc::new(
+        fetch_partition_keys(&connection, data_center, version).await?,
+    ))?;
+
+    let (mut nodes, mut remote_nodes) =
+        fetch_current_nodes(&connection, connection_info, data_center, version).await?;
     nodes_tx.send(nodes.clone())?;
+    remote_nodes_tx.send(remote_nodes.clone())?;
 
-    register_for_topology_and_status_events(&connection, version).await?;
+    register_for_topology_status_and_schema_events(&connection, version).await?;
 
     loop {
         match pushed_messages_rx.recv().await {
@@ -75,17 +98,21 @@ async fn topology_task_process(
                         match event {
                             ServerEvent::TopologyChange(topology) => match topology.change_type {
                                 TopologyChangeType::NewNode => {
-                                    let mut new_nodes = fetch_current_nodes(
-                                        &connection,
-                                        connection_info,
-                                        data_center,
-                                    )
-                                    .await?;
+                                    let (mut new_nodes, mut new_remote_nodes) =
+                                        fetch_current_nodes(
+                                            &connection,
+                                            connection_info,
+                                            data_center,
+                                            version,
+                                        )
+                                        .await?;
 
                                     // is_up state gets carried over to new list
-                                    for node in &nodes {
+                                    for node in all_nodes(&mut nodes, &mut remote_nodes) {
                                         if !node.is_up {
-                                            for new_node in &mut new_nodes {
+                                            for new_node in
+                                                all_nodes(&mut new_nodes, &mut new_remote_nodes)
+                                            {
                                                 if new_node.address == node.address {
                                                     new_node.is_up = false;
                                                 }
@@ -94,13 +121,17 @@ async fn topology_task_process(
                                     }
 
                                     nodes = new_nodes;
+                                    remote_nodes = new_remote_nodes;
                                 }
                                 TopologyChangeType::RemovedNode => {
-                                    nodes.retain(|node| node.address != topology.addr)
+                                    nodes.retain(|node| node.address != topology.addr);
+                                    for dc_nodes in remote_nodes.values_mut() {
+                                        dc_nodes.retain(|node| node.address != topology.addr);
+                                    }
                                 }
                             },
                             ServerEvent::StatusChange(status) => {
-                                for node in &mut nodes {
+                                for node in all_nodes(&mut nodes, &mut remote_nodes) {
                                     if node.address == status.addr {
                                         node.is_up = match status.change_type {
                                             StatusChangeType::Up => true,
@@ -109,6 +140,11 @@ async fn topology_task_process(
                                     }
                                 }
                             }
+                            ServerEvent::SchemaChange(_) => {
+                                partition_keys_tx.send(Arc::new(
+                                    fetch_partition_keys(&connection, data_center, version).await?,
+                                ))?;
+                            }
                             event => tracing::error!("Unexpected event: {:?}", event),
                         }
                     }
@@ -119,10 +155,31 @@ async fn topology_task_process(
             }
         }
         nodes_tx.send(nodes.clone())?;
+        remote_nodes_tx.send(remote_nodes.clone())?;
     }
 }
 
-async fn register_for_topology_and_status_events(
+fn all_nodes<'a>(
+    nodes: &'a mut [CassandraNode],
+    remote_nodes: &'a mut RemoteNodes,
+) -> impl Iterator<Item = &'a mut CassandraNode> {
+    nodes
+        .iter_mut()
+        .chain(remote_nodes.values_mut().flat_map(|nodes| nodes.iter_mut()))
+}
+
+async fn fetch_partition_keys(
+    connection: &CassandraConnection,
+    data_center: &str,
+    version: Version,
+) -> Result<PartitionKeys> {
+    Ok(PartitionKeys::new(
+        system_schema_columns::query(connection, version).await?,
+        system_schema_keyspaces::query(connection, data_center, version).await?,
+    ))
+}
+
+async fn register_for_topology_status_and_schema_events(
     connection: &CassandraConnection,
     version: Version,
 ) -> Result<()> {
@@ -138,6 +195,7 @@ async fn register_for_topology_and_status_events(
                     events: vec![
                         SimpleServerEvent::TopologyChange,
                         SimpleServerEvent::StatusChange,
+                        SimpleServerEvent::SchemaChange,
                     ],
                 }),
             })),
@@ -159,16 +217,24 @@ async fn fetch_current_nodes(
     connection: &CassandraConnection,
     connection_info: &TaskConnectionInfo,
     data_center: &str,
-) -> Result<Vec<CassandraNode>> {
+    version: Version,
+) -> Result<(Vec<CassandraNode>, RemoteNodes)> {
     let (new_nodes, more_nodes) = tokio::join!(
-        system_local::query(connection, data_center, connection_info.address),
-        system_peers::query(connection, data_center)
+        system_local::query(connection, connection_info.address, version),
+        system_peers::query(connection, version)
     );
 
-    let mut new_nodes = new_nodes?;
-    new_nodes.extend(more_nodes?);
+    let mut nodes = vec![];
+    let mut remote_nodes = RemoteNodes::new();
+    for (node_data_center, node) in new_nodes?.into_iter().chain(more_nodes?) {
+        if node_data_center == data_center {
+            nodes.push(node);
+        } else {
+            remote_nodes.entry(node_data_center).or_default().push(node);
+        }
+    }
 
-    Ok(new_nodes)
+    Ok((nodes, remote_nodes))
 }
 
 mod system_local {
@@ -176,13 +242,13 @@ mod system_local {
 
     pub async fn query(
         connection: &CassandraConnection,
-        data_center: &str,
         address: SocketAddr,
-    ) -> Result<Vec<CassandraNode>> {
+        version: Version,
+    ) -> Result<Vec<(String, CassandraNode)>> {
         let (tx, rx) = oneshot::channel();
         connection.send(
             Message::from_frame(Frame::Cassandra(CassandraFrame {
-                version: Version::V4,
+                version,
                 stream_id: 1,
                 tracing_id: None,
                 warnings: vec![],
@@ -196,27 +262,23 @@ mod system_local {
             tx,
         )?;
 
-        into_nodes(rx.await?.response?, data_center, address)
+        into_nodes(rx.await?.response?, address)
     }
 
     fn into_nodes(
         mut response: Message,
-        config_data_center: &str,
         address: SocketAddr,
-    ) -> Result<Vec<CassandraNode>> {
+    ) -> Result<Vec<(String, CassandraNode)>> {
         if let Some(Frame::Cassandra(frame)) = response.frame() {
             match &mut frame.operation {
                 CassandraOperation::Result(CassandraResult::Rows { rows, .. }) => rows
                     .iter_mut()
-                    .filter(|row| {
-                        if let Some(MessageValue::Varchar(data_center)) = row.last() {
-                            data_center == config_data_center
-                        } else {
-                            false
-                        }
-                    })
                     .map(|row| {
-                        let _data_center = row.pop();
+                        let data_center = if let Some(MessageValue::Varchar(value)) = row.pop() {
+                            value
+                        } else {
+                            return Err(anyhow!("system.local.data_center not a varchar"));
+                        };
 
                         let host_id = if let Some(MessageValue::Uuid(host_id)) = row.pop() {
                             host_id
@@ -241,7 +303,10 @@ mod system_local {
                             return Err(anyhow!("system.local.rack not a varchar"));
                         };
 
-                        Ok(CassandraNode::new(address, rack, tokens, host_id))
+                        Ok((
+                            data_center,
+                            CassandraNode::new(address, rack, tokens, host_id),
+                        ))
                     })
                     .collect(),
                 operation => Err(anyhow!(
@@ -258,17 +323,30 @@ mod system_local {
     }
 }
 
+/// system.peers_v2 only exists from cassandra 4.0, older versions only have system.peers
+pub fn is_peers_v2_does_not_exist_error(message: &mut Message) -> bool {
+    if let Some(Frame::Cassandra(CassandraFrame {
+        operation: CassandraOperation::Error(error),
+        ..
+    })) = message.frame()
+    {
+        return error.message == "unconfigured table peers_v2";
+    }
+
+    false
+}
+
 mod system_peers {
     use super::*;
 
     pub async fn query(
         connection: &CassandraConnection,
-        data_center: &str,
-    ) -> Result<Vec<CassandraNode>> {
+        version: Version,
+    ) -> Result<Vec<(String, CassandraNode)>> {
         let (tx, rx) = oneshot::channel();
         connection.send(
             Message::from_frame(Frame::Cassandra(CassandraFrame {
-                version: Version::V4,
+                version,
                 stream_id: 0,
                 tracing_id: None,
                 warnings: vec![],
@@ -288,7 +366,7 @@ mod system_peers {
             let (tx, rx) = oneshot::channel();
             connection.send(
                 Message::from_frame(Frame::Cassandra(CassandraFrame {
-                    version: Version::V4,
+                    version,
                     stream_id: 0,
                     tracing_id: None,
                     warnings: vec![],
@@ -304,39 +382,24 @@ mod system_peers {
             response = rx.await?.response?;
         }
 
-        into_nodes(response, data_center)
-    }
-
-    fn is_peers_v2_does_not_exist_error(message: &mut Message) -> bool {
-        if let Some(Frame::Cassandra(CassandraFrame {
-            operation: CassandraOperation::Error(error),
-            ..
-        })) = message.frame()
-        {
-            return error.message == "unconfigured table peers_v2";
-        }
-
-        false
+        into_nodes(response)
     }
 
-    fn into_nodes(mut response: Message, config_data_center: &str) -> Result<Vec<CassandraNode>> {
+    fn into_nodes(mut response: Message) -> Result<Vec<(String, CassandraNode)>> {
         if let Some(Frame::Cassandra(frame)) = response.frame() {
             match &mut frame.operation {
                 CassandraOperation::Result(CassandraResult::Rows { rows, .. }) => rows
                     .iter_mut()
-                    .filter(|row| {
-                        if let Some(MessageValue::Varchar(data_center)) = row.last() {
-                            data_center == config_data_center
-                        } else {
-                            false
-                        }
-                    })
                     .map(|row| {
                         if row.len() != 5 && row.len() != 6 {
                             return Err(anyhow!("expected 5 or 6 columns but was {}", row.len()));
                         }
 
-                        let _data_center = row.pop();
+                        let data_center = if let Some(MessageValue::Varchar(value)) = row.pop() {
+                            value
+                        } else {
+                            return Err(anyhow!("system.peers(v2).data_center not a varchar"));
+                        };
 
                         let host_id = if let Some(MessageValue::Uuid(host_id)) = row.pop() {
                             host_id
@@ -380,11 +443,14 @@ mod system_peers {
                             9042
                         };
 
-                        Ok(CassandraNode::new(
-                            SocketAddr::new(ip, port.try_into()?),
-                            rack,
-                            tokens,
-                            host_id,
+                        Ok((
+                            data_center,
+                            CassandraNode::new(
+                                SocketAddr::new(ip, port.try_into()?),
+                                rack,
+                                tokens,
+                                host_id,
+                            ),
                         ))
                     })
                     .collect(),
@@ -401,3 +467,218 @@ mod system_peers {
         }
     }
 }
+
+mod system_schema_columns {
+    use super::*;
+
+    pub async fn query(
+        connection: &CassandraConnection,
+        version: Version,
+    ) -> Result<HashMap<(String, String), Vec<PartitionKeyColumn>>> {
+        let (tx, rx) = oneshot::channel();
+        connection.send(
+            Message::from_frame(Frame::Cassandra(CassandraFrame {
+                version,
+                stream_id: 0,
+                tracing_id: None,
+                warnings: vec![],
+                operation: CassandraOperation::Query {
+                    query: Box::new(parse_statement_single(
+                        "SELECT keyspace_name, table_name, column_name, kind, position, type FROM system_schema.columns",
+                    )),
+                    params: Box::new(QueryParams::default()),
+                },
+            })),
+            tx,
+        )?;
+
+        into_tables(rx.await?.response?)
+    }
+
+    fn into_tables(
+        mut response: Message,
+    ) -> Result<HashMap<(String, String), Vec<PartitionKeyColumn>>> {
+        if let Some(Frame::Cassandra(frame)) = response.frame() {
+            match &mut frame.operation {
+                CassandraOperation::Result(CassandraResult::Rows { rows, .. }) => {
+                    let mut tables: HashMap<(String, String), Vec<(i64, PartitionKeyColumn)>> =
+                        HashMap::new();
+                    for row in rows.drain(..) {
+                        let row: [MessageValue; 6] = row.try_into().map_err(|row: Vec<_>| {
+                            anyhow!("expected 6 columns but was {}", row.len())
+                        })?;
+                        match row {
+                            [MessageValue::Varchar(keyspace), MessageValue::Varchar(table), MessageValue::Varchar(name), MessageValue::Varchar(kind), MessageValue::Integer(position, _), MessageValue::Varchar(ty)] => {
+                                if kind == "partition_key" {
+                                    tables
+                                        .entry((keyspace, table))
+                                        .or_default()
+                                        .push((position, PartitionKeyColumn { name, ty }));
+                                }
+                            }
+                            row => {
+                                return Err(anyhow!(
+                                    "system_schema.columns returned unexpected values: {:?}",
+                                    row
+                                ))
+                            }
+                        }
+                    }
+
+                    Ok(tables
+                        .into_iter()
+                        .map(|(table, mut columns)| {
+                            columns.sort_by_key(|(position, _)| *position);
+                            (
+                                table,
+                                columns.into_iter().map(|(_, column)| column).collect(),
+                            )
+                        })
+                        .collect())
+                }
+                operation => Err(anyhow!(
+                    "system_schema.columns returned unexpected cassandra operation: {:?}",
+                    operation
+                )),
+            }
+        } else {
+            Err(anyhow!(
+                "Failed to parse system_schema.columns response {:?}",
+                response
+            ))
+        }
+    }
+}
+
+mod system_schema_keyspaces {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    pub async fn query(
+        connection: &CassandraConnection,
+        data_center: &str,
+        version: Version,
+    ) -> Result<HashMap<String, usize>> {
+        let (tx, rx) = oneshot::channel();
+        connection.send(
+            Message::from_frame(Frame::Cassandra(CassandraFrame {
+                version,
+                stream_id: 0,
+                tracing_id: None,
+                warnings: vec![],
+                operation: CassandraOperation::Query {
+                    query: Box::new(parse_statement_single(
+                        "SELECT keyspace_name, replication FROM system_schema.keyspaces",
+                    )),
+                    params: Box::new(QueryParams::default()),
+                },
+            })),
+            tx,
+        )?;
+
+        into_replication_factors(rx.await?.response?, data_center)
+    }
+
+    fn into_replication_factors(
+        mut response: Message,
+        data_center: &str,
+    ) -> Result<HashMap<String, usize>> {
+        if let Some(Frame::Cassandra(frame)) = response.frame() {
+            match &mut frame.operation {
+                CassandraOperation::Result(CassandraResult::Rows { rows, .. }) => {
+                    let mut replication_factors = HashMap::new();
+                    for row in rows.drain(..) {
+                        match row.as_slice() {
+                            [MessageValue::Varchar(keyspace), MessageValue::Map(replication)] => {
+                                if let Some(replication_factor) =
+                                    local_replication_factor(replication, data_center)
+                                {
+                                    replication_factors
+                                        .insert(keyspace.clone(), replication_factor);
+                                }
+                            }
+                            row => {
+                                return Err(anyhow!(
+                                    "system_schema.keyspaces returned unexpected values: {:?}",
+                                    row
+                                ))
+                            }
+                        }
+                    }
+                    Ok(replication_factors)
+                }
+                operation => Err(anyhow!(
+                    "system_schema.keyspaces returned unexpected cassandra operation: {:?}",
+                    operation
+                )),
+            }
+        } else {
+            Err(anyhow!(
+                "Failed to parse system_schema.keyspaces response {:?}",
+                response
+            ))
+        }
+    }
+
+    /// The number of replicas in `data_center` of a keyspace with the `replication` options.
+    /// Returns `None` for replication strategies that do not place replicas on the token ring, e.g. the `LocalStrategy` of the system keyspace.
+    pub(super) fn local_replication_factor(
+        replication: &BTreeMap<MessageValue, MessageValue>,
+        data_center: &str,
+    ) -> Option<usize> {
+        let option = |name: &str| match replication.get(&MessageValue::Varchar(name.to_owned())) {
+            Some(MessageValue::Varchar(value)) => Some(value.as_str()),
+            _ => None,
+        };
+        let replication_factor = match option("class")? {
+            class if class.ends_with("SimpleStrategy") => option("replication_factor")?,
+            class if class.ends_with("NetworkTopologyStrategy") => option(data_center)?,
+            _ => return None,
+        };
+        // With transient replication the replication factor is written as <replicas>/<transient replicas>
+        replication_factor.split('/').next()?.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod test_topology {
+    use super::system_schema_keyspaces::local_replication_factor;
+    use crate::message::MessageValue;
+    use std::collections::BTreeMap;
+
+    fn replication(options: &[(&str, &str)]) -> BTreeMap<MessageValue, MessageValue> {
+        options
+            .iter()
+            .map(|(name, value)| {
+                (
+                    MessageValue::Varchar(name.to_string()),
+                    MessageValue::Varchar(value.to_string()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_local_replication_factor() {
+        let simple = replication(&[
+            ("class", "org.apache.cassandra.locator.SimpleStrategy"),
+            ("replication_factor", "2"),
+        ]);
+        assert_eq!(local_replication_factor(&simple, "dc1"), Some(2));
+
+        let network_topology = replication(&[
+            (
+                "class",
+                "org.apache.cassandra.locator.NetworkTopologyStrategy",
+            ),
+            ("dc1", "3/1"),
+            ("dc2", "1"),
+        ]);
+        assert_eq!(local_replication_factor(&network_topology, "dc1"), Some(3));
+        assert_eq!(local_replication_factor(&network_topology, "dc2"), Some(1));
+        assert_eq!(local_replication_factor(&network_topology, "dc3"), None);
+
+        let local = replication(&[("class", "org.apache.cassandra.locator.LocalStrategy")]);
+        assert_eq!(local_replication_factor(&local, "dc1"), None);
+    }
+}
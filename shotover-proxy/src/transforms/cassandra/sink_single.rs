@@ -1,11 +1,15 @@
 use super::connection::CassandraConnection;
-use crate::codec::cassandra::CassandraCodec;
+use super::retry::{PreparedReads, RetryPolicy, RetryPolicyConfig, RetryReason, RetryState};
+use super::{create_codec, CassandraCompression};
 use crate::error::ChainResponse;
-use crate::message::Messages;
+use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
+use crate::message::{Message, Messages};
+use crate::proxy_protocol::{ProxyHeader, ProxyProtocolVersion};
+use crate::socket;
 use crate::tls::{TlsConnector, TlsConnectorConfig};
 use crate::transforms::util::Response;
 use crate::transforms::{Transform, Transforms, Wrapper};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use futures::stream::FuturesOrdered;
 use metrics::{register_counter, Counter};
@@ -20,6 +24,9 @@ pub struct CassandraSinkSingleConfig {
     pub address: String,
     pub tls: Option<TlsConnectorConfig>,
     pub read_timeout: Option<u64>,
+    pub compression: Option<CassandraCompression>,
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+    pub retry_policy: Option<RetryPolicyConfig>,
 }
 
 impl CassandraSinkSingleConfig {
@@ -30,6 +37,9 @@ impl CassandraSinkSingleConfig {
             chain_name,
             tls,
             self.read_timeout,
+            self.compression,
+            self.proxy_protocol,
+            self.retry_policy.as_ref(),
         )))
     }
 }
@@ -42,6 +52,16 @@ pub struct CassandraSinkSingle {
     tls: Option<TlsConnector>,
     pushed_messages_tx: Option<mpsc::UnboundedSender<Messages>>,
     read_timeout: Option<Duration>,
+    compression: Option<CassandraCompression>,
+    proxy_protocol: Option<ProxyProtocolVersion>,
+    retry_policy: Option<RetryPolicy>,
+    prepared_reads: PreparedReads,
+    /// The STARTUP, AUTH_RESPONSE and REGISTER requests sent by the client, replayed on a new connection when retrying
+    handshake: Vec<Message>,
+    /// The last successful USE request sent by the client, replayed on a new connection when retrying
+    use_message: Option<Message>,
+    /// Incremented for every new connection so that the requests of a batch that failed on the same connection only reconnect once
+    connection_generation: u64,
 }
 
 impl Clone for CassandraSinkSingle {
@@ -54,6 +74,13 @@ impl Clone for CassandraSinkSingle {
             failed_requests: self.failed_requests.clone(),
             pushed_messages_tx: None,
             read_timeout: self.read_timeout,
+            compression: self.compression,
+            proxy_protocol: self.proxy_protocol,
+            retry_policy: self.retry_policy.clone(),
+            prepared_reads: self.prepared_reads.clone(),
+            handshake: vec![],
+            use_message: None,
+            connection_generation: 0,
         }
     }
 }
@@ -64,9 +91,14 @@ impl CassandraSinkSingle {
         chain_name: String,
         tls: Option<TlsConnector>,
         timeout: Option<u64>,
+        compression: Option<CassandraCompression>,
+        proxy_protocol: Option<ProxyProtocolVersion>,
+        retry_policy: Option<&RetryPolicyConfig>,
     ) -> CassandraSinkSingle {
         let failed_requests = register_counter!("failed_requests", "chain" => chain_name.clone(), "transform" => "CassandraSinkSingle");
         let receive_timeout = timeout.map(Duration::from_secs);
+        let retry_policy = retry_policy
+            .map(|config| RetryPolicy::new(config, chain_name.clone(), "CassandraSinkSingle"));
 
         CassandraSinkSingle {
             address,
@@ -76,46 +108,231 @@ impl CassandraSinkSingle {
             tls,
             pushed_messages_tx: None,
             read_timeout: receive_timeout,
+            compression,
+            proxy_protocol,
+            retry_policy,
+            prepared_reads: PreparedReads::default(),
+            handshake: vec![],
+            use_message: None,
+            connection_generation: 0,
         }
     }
 }
 
 impl CassandraSinkSingle {
-    async fn send_message(&mut self, messages: Messages) -> ChainResponse {
+    async fn send_message(&mut self, message_wrapper: Wrapper<'_>) -> ChainResponse {
+        let proxy_header = self
+            .proxy_protocol
+            .map(|version| ProxyHeader::encode(message_wrapper.proxy_header, version));
         if self.outbound.is_none() {
-            trace!("creating outbound connection {:?}", self.address);
-            self.outbound = Some(
-                CassandraConnection::new(
-                    self.address.clone(),
-                    CassandraCodec::new(),
-                    self.tls.clone(),
-                    self.pushed_messages_tx.clone(),
-                )
-                .await?,
-            );
+            self.connect(proxy_header.clone()).await?;
         }
         trace!("sending frame upstream");
 
+        // The requests are kept so that they can be sent again if the retry policy decides to retry them
+        let requests = self
+            .retry_policy
+            .as_ref()
+            .map(|_| message_wrapper.messages.clone());
+
         let outbound = self.outbound.as_mut().unwrap();
-        let responses_future: Result<FuturesOrdered<oneshot::Receiver<Response>>> = messages
+        let responses_future: Result<FuturesOrdered<oneshot::Receiver<Response>>> = message_wrapper
+            .messages
             .into_iter()
             .map(|m| {
                 let (return_chan_tx, return_chan_rx) = oneshot::channel();
-                outbound.send(m, return_chan_tx)?;
+                if let Err(err) = outbound.send(m, return_chan_tx) {
+                    // When retrying, the dropped return_chan_tx results in the request being retried as a connection failure
+                    if requests.is_none() {
+                        return Err(err);
+                    }
+                }
 
                 Ok(return_chan_rx)
             })
             .collect();
 
-        super::connection::receive(self.read_timeout, &self.failed_requests, responses_future?)
-            .await
+        match requests {
+            None => {
+                super::connection::receive(
+                    self.read_timeout,
+                    &self.failed_requests,
+                    responses_future?,
+                )
+                .await
+            }
+            Some(requests) => {
+                let retry_policy = self.retry_policy.clone().unwrap();
+                // Every request of the batch was sent on this connection
+                let batch_generation = self.connection_generation;
+                let responses = super::connection::receive_each(
+                    self.read_timeout,
+                    &self.failed_requests,
+                    responses_future?,
+                )
+                .await?;
+
+                let mut results = Vec::with_capacity(responses.len());
+                for (mut request, response) in requests.into_iter().zip(responses) {
+                    let mut generation = batch_generation;
+                    let mut response = self
+                        .retry(
+                            &retry_policy,
+                            &mut request,
+                            response,
+                            &proxy_header,
+                            &mut generation,
+                        )
+                        .await?;
+                    self.prepared_reads.record(&mut request, &mut response);
+                    self.record_connection_state(&mut request, &mut response);
+                    results.push(response);
+                }
+                Ok(results)
+            }
+        }
+    }
+
+    /// Connect to the node, replaying the recorded handshake and USE request so that the new connection is in the same state as the one it replaces.
+    async fn connect(&mut self, proxy_header: Option<Vec<u8>>) -> Result<()> {
+        trace!("creating outbound connection {:?}", self.address);
+        let outbound = match socket::unix_socket_path(&self.address) {
+            Some(path) => {
+                CassandraConnection::new_with_stream(
+                    socket::connect_unix(path).await?,
+                    create_codec(self.compression),
+                    self.tls.clone(),
+                    self.pushed_messages_tx.clone(),
+                    proxy_header,
+                )
+                .await?
+            }
+            None => {
+                CassandraConnection::new(
+                    self.address.clone(),
+                    create_codec(self.compression),
+                    self.tls.clone(),
+                    self.pushed_messages_tx.clone(),
+                    proxy_header,
+                )
+                .await?
+            }
+        };
+
+        for message in self.handshake.iter().chain(&self.use_message) {
+            let mut response = super::connection::send_and_receive(
+                &outbound,
+                message.clone(),
+                self.read_timeout,
+                &self.failed_requests,
+            )
+            .await??;
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Error(error),
+                ..
+            })) = response.frame()
+            {
+                return Err(anyhow!(
+                    "Failed to initialize new connection with handshake: {:?}",
+                    error
+                ));
+            }
+        }
+
+        self.outbound = Some(outbound);
+        self.connection_generation += 1;
+        Ok(())
+    }
+
+    /// Record the requests that set up the state of the connection so that [`CassandraSinkSingle::connect`] can replay them.
+    fn record_connection_state(&mut self, request: &mut Message, response: &mut Message) {
+        match response.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(CassandraResult::SetKeyspace(_)),
+                ..
+            })) => {
+                // Only a USE request results in SetKeyspace, it replaces any previous USE as the keyspace has changed
+                self.use_message = Some(request.clone());
+                return;
+            }
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Error(_),
+                ..
+            })) => return,
+            _ => {}
+        }
+
+        if let Some(Frame::Cassandra(CassandraFrame {
+            operation:
+                CassandraOperation::Startup(_)
+                | CassandraOperation::AuthResponse(_)
+                | CassandraOperation::Register(_),
+            ..
+        })) = request.frame()
+        {
+            self.handshake.push(request.clone());
+        }
+    }
+
+    /// Send `request` again for as long as `retry_policy` decides that its `response` should be retried.
+    /// A connection failure is retried on a new connection, unless the connection `generation` that the response came from has already been replaced.
+    /// `generation` is updated to the connection that the returned response came from.
+    async fn retry(
+        &mut self,
+        retry_policy: &RetryPolicy,
+        request: &mut Message,
+        mut response: Result<Message>,
+        proxy_header: &Option<Vec<u8>>,
+        generation: &mut u64,
+    ) -> Result<Message> {
+        let mut state = RetryState::default();
+        while let Some(reason) = RetryReason::from_response(&mut response) {
+            let delay = match retry_policy.retry_delay(
+                self.prepared_reads.is_read(request),
+                reason,
+                &mut state,
+            ) {
+                Some(delay) => delay,
+                None => break,
+            };
+            tokio::time::sleep(delay).await;
+
+            if reason == RetryReason::ConnectionFailure && *generation == self.connection_generation
+            {
+                self.outbound = None;
+            }
+            response = self.send_single(request.clone(), proxy_header).await?;
+            *generation = self.connection_generation;
+        }
+        response
+    }
+
+    /// Send a single request, the outer `Result` fails when the response times out while the inner `Result` fails when the connection fails.
+    async fn send_single(
+        &mut self,
+        request: Message,
+        proxy_header: &Option<Vec<u8>>,
+    ) -> Result<Result<Message>> {
+        if self.outbound.is_none() {
+            if let Err(err) = self.connect(proxy_header.clone()).await {
+                return Ok(Err(err));
+            }
+        }
+
+        super::connection::send_and_receive(
+            self.outbound.as_ref().unwrap(),
+            request,
+            self.read_timeout,
+            &self.failed_requests,
+        )
+        .await
     }
 }
 
 #[async_trait]
 impl Transform for CassandraSinkSingle {
     async fn transform<'a>(&'a mut self, message_wrapper: Wrapper<'a>) -> ChainResponse {
-        self.send_message(message_wrapper.messages).await
+        self.send_message(message_wrapper).await
     }
 
     fn is_terminating(&self) -> bool {
@@ -150,7 +150,7 @@ impl Transform for ConsistentScatter {
         Ok(if results.len() < max_required_successes as usize {
             let mut messages = message_wrapper.messages;
             for message in &mut messages {
-                message.set_error("Not enough responses".into());
+                message.set_error("Not enough responses".into())?;
             }
             messages
         } else {
@@ -40,8 +40,8 @@ impl Transform for QueryTypeFilter {
                     None
                 }
             })
-            .map(|(i, m)| (i, m.to_filtered_reply()))
-            .collect();
+            .map(|(i, m)| Ok((i, m.to_filtered_reply()?)))
+            .collect::<Result<_>>()?;
 
         for (i, _) in removed_indexes.iter().rev() {
             message_wrapper.messages.remove(*i);
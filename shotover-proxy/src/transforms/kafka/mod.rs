@@ -0,0 +1 @@
+pub mod sink_single;
@@ -0,0 +1,204 @@
+use crate::codec::kafka::KafkaCodec;
+use crate::error::ChainResponse;
+use crate::frame::kafka::{KafkaFrame, KafkaResponse, KafkaResponseBody};
+use crate::frame::Frame;
+use crate::message::Message;
+use crate::tls::{AsyncStream, TlsConnector, TlsConnectorConfig};
+use crate::transforms::{Transform, Transforms, Wrapper};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use metrics::{register_counter, Counter};
+use serde::Deserialize;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_util::codec::Framed;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct KafkaSinkSingleConfig {
+    #[serde(rename = "remote_address")]
+    pub address: String,
+    /// The address clients should use to reach this shotover instance, advertised in place of the broker's own address
+    pub shotover_address: String,
+    pub tls: Option<TlsConnectorConfig>,
+}
+
+impl KafkaSinkSingleConfig {
+    pub async fn get_transform(&self, chain_name: String) -> Result<Transforms> {
+        let (host, port) = self
+            .shotover_address
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("shotover_address must be of the form host:port"))?;
+        let port = port
+            .parse()
+            .with_context(|| format!("invalid port in shotover_address {port:?}"))?;
+        let tls = self.tls.clone().map(TlsConnector::new).transpose()?;
+        Ok(Transforms::KafkaSinkSingle(KafkaSinkSingle::new(
+            self.address.clone(),
+            Bytes::from(host.to_owned()),
+            port,
+            tls,
+            chain_name,
+        )))
+    }
+}
+
+type KafkaFramed = Framed<Pin<Box<dyn AsyncStream + Send + Sync>>, KafkaCodec>;
+
+pub struct KafkaSinkSingle {
+    address: String,
+    shotover_host: Bytes,
+    shotover_port: i32,
+    tls: Option<TlsConnector>,
+    connection: Option<KafkaFramed>,
+    failed_requests: Counter,
+}
+
+impl Clone for KafkaSinkSingle {
+    fn clone(&self) -> Self {
+        KafkaSinkSingle {
+            address: self.address.clone(),
+            shotover_host: self.shotover_host.clone(),
+            shotover_port: self.shotover_port,
+            tls: self.tls.clone(),
+            connection: None,
+            failed_requests: self.failed_requests.clone(),
+        }
+    }
+}
+
+impl KafkaSinkSingle {
+    pub fn new(
+        address: String,
+        shotover_host: Bytes,
+        shotover_port: i32,
+        tls: Option<TlsConnector>,
+        chain_name: String,
+    ) -> KafkaSinkSingle {
+        let failed_requests = register_counter!("failed_requests", "chain" => chain_name, "transform" => "KafkaSinkSingle");
+
+        KafkaSinkSingle {
+            address,
+            shotover_host,
+            shotover_port,
+            tls,
+            connection: None,
+            failed_requests,
+        }
+    }
+
+    async fn connect(&mut self) -> Result<KafkaFramed> {
+        let tcp_stream = timeout(
+            Duration::from_secs(3),
+            TcpStream::connect(self.address.clone()),
+        )
+        .await?
+        .map_err(|e| anyhow::Error::new(e).context("Failed to connect to upstream"))?;
+
+        let generic_stream = if let Some(tls) = self.tls.as_mut() {
+            let tls_stream = tls.connect_unverified_hostname(tcp_stream).await?;
+            Box::pin(tls_stream) as Pin<Box<dyn AsyncStream + Send + Sync>>
+        } else {
+            Box::pin(tcp_stream) as Pin<Box<dyn AsyncStream + Send + Sync>>
+        };
+
+        Ok(Framed::new(generic_stream, KafkaCodec::new()))
+    }
+
+    /// Points the client at shotover instead of the broker, otherwise the client would bypass shotover for all subsequent requests.
+    fn rewrite_response(&self, response: &mut Message) {
+        if let Some(Frame::Kafka(KafkaFrame::Response(response_frame))) = response.frame() {
+            if let KafkaResponseBody::Metadata(_) | KafkaResponseBody::FindCoordinator(_) =
+                response_frame.body
+            {
+                response_frame.rewrite_broker_addresses(|host, port| {
+                    *host = self.shotover_host.clone();
+                    *port = self.shotover_port;
+                });
+                response.invalidate_cache();
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for KafkaSinkSingle {
+    fn is_terminating(&self) -> bool {
+        true
+    }
+
+    async fn transform<'a>(&'a mut self, mut message_wrapper: Wrapper<'a>) -> ChainResponse {
+        // Return immediately if we have no messages.
+        // If we tried to send no messages we would block forever waiting for a reply that will never come.
+        if message_wrapper.messages.is_empty() {
+            return Ok(message_wrapper.messages);
+        }
+
+        // Produce requests with acks=0 never receive a response, so a placeholder is returned in their place.
+        let mut placeholders = Vec::with_capacity(message_wrapper.messages.len());
+        for message in &mut message_wrapper.messages {
+            placeholders.push(match message.frame() {
+                Some(Frame::Kafka(KafkaFrame::Request(request))) if !request.expects_response() => {
+                    Some(KafkaResponse {
+                        api_key: request.header.api_key,
+                        api_version: request.header.api_version,
+                        correlation_id: request.header.correlation_id,
+                        header_tagged_fields: Bytes::new(),
+                        body: KafkaResponseBody::NoResponse,
+                    })
+                }
+                Some(Frame::Kafka(KafkaFrame::Request(_))) => None,
+                None => return Err(anyhow!("Failed to parse kafka request")),
+                frame => return Err(anyhow!("KafkaSinkSingle cannot send frame {frame:?}")),
+            });
+        }
+        let responses_len = placeholders.iter().filter(|x| x.is_none()).count();
+
+        if self.connection.is_none() {
+            self.connection = Some(self.connect().await?);
+        }
+        let connection = self.connection.as_mut().unwrap();
+
+        if let Err(err) = connection.send(message_wrapper.messages).await {
+            self.connection = None;
+            self.failed_requests.increment(1);
+            return Err(err.context("Failed to send messages to kafka destination"));
+        }
+
+        // kafka responds to requests in the order they were sent, so the responses can be collected directly off the connection
+        let mut responses = Vec::with_capacity(responses_len);
+        while responses.len() < responses_len {
+            match connection.next().await {
+                Some(Ok(messages)) => responses.extend(messages),
+                Some(Err(err)) => {
+                    self.connection = None;
+                    self.failed_requests.increment(1);
+                    return Err(anyhow!("{err:?}")
+                        .context("Failed to receive messages from kafka destination"));
+                }
+                None => {
+                    self.connection = None;
+                    self.failed_requests.increment(1);
+                    return Err(anyhow!("kafka destination closed the connection"));
+                }
+            }
+        }
+
+        let mut responses = responses.into_iter();
+        let mut result = Vec::with_capacity(placeholders.len());
+        for placeholder in placeholders {
+            let mut response = match placeholder {
+                Some(placeholder) => {
+                    Message::from_frame(Frame::Kafka(KafkaFrame::Response(placeholder)))
+                }
+                None => responses.next().unwrap(),
+            };
+            self.rewrite_response(&mut response);
+            result.push(response);
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,356 @@
+use crate::codec::memcached::MemcachedCodec;
+use crate::error::ChainResponse;
+use crate::frame::memcached::{
+    MemcachedCommand, MemcachedErrorKind, MemcachedRequest, MemcachedStatus,
+};
+use crate::frame::{Frame, MemcachedFrame};
+use crate::message::{Message, Metadata};
+use crate::tls::TlsConnectorConfig;
+use crate::transforms::memcached::sink_cluster::continuum::Continuum;
+use crate::transforms::util::cluster_connection_pool::{ConnectionPool, NoopAuthenticator};
+use crate::transforms::util::{Request, Response};
+use crate::transforms::{ResponseFuture, Transform, Transforms, Wrapper};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::FuturesOrdered;
+use futures::StreamExt;
+use metrics::{register_counter, Counter};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MemcachedSinkClusterConfig {
+    pub servers: Vec<String>,
+    pub connection_count: Option<usize>,
+    pub tls: Option<TlsConnectorConfig>,
+}
+
+impl MemcachedSinkClusterConfig {
+    pub async fn get_transform(&self, chain_name: String) -> Result<Transforms> {
+        if self.servers.is_empty() {
+            bail!("MemcachedSinkCluster requires at least one server");
+        }
+        Ok(Transforms::MemcachedSinkCluster(MemcachedSinkCluster::new(
+            self.servers.clone(),
+            self.connection_count.unwrap_or(1),
+            self.tls.clone(),
+            chain_name,
+        )?))
+    }
+}
+
+/// Distributes requests across independent memcached servers by consistent hashing the key of each request.
+/// memcached servers are unaware of each other, so unlike redis cluster there is no topology to discover and no redirections to follow.
+#[derive(Clone)]
+pub struct MemcachedSinkCluster {
+    servers: Vec<String>,
+    continuum: Continuum,
+    connections: HashMap<String, Vec<UnboundedSender<Request>>>,
+    connection_pool: ConnectionPool<MemcachedCodec, NoopAuthenticator, ()>,
+    connection_count: usize,
+    rng: SmallRng,
+    failed_requests: Counter,
+}
+
+impl MemcachedSinkCluster {
+    pub fn new(
+        servers: Vec<String>,
+        connection_count: usize,
+        tls: Option<TlsConnectorConfig>,
+        chain_name: String,
+    ) -> Result<Self> {
+        let failed_requests = register_counter!("failed_requests", "chain" => chain_name, "transform" => "MemcachedSinkCluster");
+
+        Ok(MemcachedSinkCluster {
+            continuum: Continuum::new(&servers),
+            servers,
+            connections: HashMap::new(),
+            connection_pool: ConnectionPool::new(MemcachedCodec::new(), tls)?,
+            connection_count,
+            rng: SmallRng::from_rng(rand::thread_rng()).unwrap(),
+            failed_requests,
+        })
+    }
+
+    async fn get_connection(&mut self, server: &str) -> Result<UnboundedSender<Request>> {
+        let is_open = self.connections.get(server).map_or(false, |connections| {
+            connections.iter().all(|connection| !connection.is_closed())
+        });
+        if !is_open {
+            let connections = self
+                .connection_pool
+                .get_connections(server, &None, self.connection_count)
+                .await?;
+            self.connections.insert(server.to_owned(), connections);
+        }
+
+        self.connections[server]
+            .choose(&mut self.rng)
+            .cloned()
+            .ok_or_else(|| anyhow!("no connections to {server} are available"))
+    }
+
+    async fn send_to_server(
+        &mut self,
+        server: &str,
+        message: Message,
+    ) -> Result<oneshot::Receiver<Response>> {
+        let (return_chan_tx, return_chan_rx) = oneshot::channel();
+        self.get_connection(server)
+            .await?
+            .send(Request {
+                message,
+                return_chan: Some(return_chan_tx),
+            })
+            .map_err(|_| anyhow!("connection to {server} was closed"))?;
+        Ok(return_chan_rx)
+    }
+
+    async fn dispatch_message(&mut self, mut message: Message) -> Result<ResponseFuture> {
+        let mut request = match message.frame() {
+            Some(Frame::Memcached(MemcachedFrame::Request(request))) => request.clone(),
+            None => bail!("Failed to parse memcached frame"),
+            frame => bail!("MemcachedSinkCluster cannot route frame {frame:?}"),
+        };
+
+        match request.command {
+            // Clears every server, the responses are combined into a single response
+            MemcachedCommand::FlushAll => {
+                let mut receivers = vec![];
+                for server in self.servers.clone() {
+                    receivers.push(self.send_to_server(&server, message.clone()).await?);
+                }
+                Ok(Box::pin(combine_responses(message, receivers)))
+            }
+            // The text protocol allows retrieving many keys at once, which may be spread across many servers
+            MemcachedCommand::Get | MemcachedCommand::Gets if request.keys.len() > 1 => {
+                let mut keys_by_server: Vec<(String, Vec<Bytes>)> = vec![];
+                for key in std::mem::take(&mut request.keys) {
+                    let server = self.continuum.server_for_key(&key);
+                    match keys_by_server.iter_mut().find(|(x, _)| *x == server) {
+                        Some((_, keys)) => keys.push(key),
+                        None => keys_by_server.push((server.to_owned(), vec![key])),
+                    }
+                }
+
+                if let [(server, _)] = keys_by_server.as_slice() {
+                    let server = server.clone();
+                    let receiver = self.send_to_server(&server, message).await?;
+                    return Ok(Box::pin(receive_response(receiver)));
+                }
+
+                let mut receivers = vec![];
+                for (server, keys) in keys_by_server {
+                    let sub_request = MemcachedRequest {
+                        keys,
+                        ..request.clone()
+                    };
+                    receivers.push(
+                        self.send_to_server(
+                            &server,
+                            Message::from_frame(Frame::Memcached(MemcachedFrame::Request(
+                                sub_request,
+                            ))),
+                        )
+                        .await?,
+                    );
+                }
+                Ok(Box::pin(combine_responses(message, receivers)))
+            }
+            _ => {
+                let server = match request.keys.first() {
+                    Some(key) => self.continuum.server_for_key(key).to_owned(),
+                    // Requests without a key such as version can be answered by any server
+                    None => self.servers[0].clone(),
+                };
+                let receiver = self.send_to_server(&server, message).await?;
+                Ok(Box::pin(receive_response(receiver)))
+            }
+        }
+    }
+}
+
+async fn receive_response(receiver: oneshot::Receiver<Response>) -> Result<Response> {
+    receiver
+        .await
+        .map_err(|_| anyhow!("memcached connection was closed before a response was received"))
+}
+
+/// Combines the responses to a request that was split across multiple servers.
+/// Retrieved values are concatenated, if any server returned an error then the first error is returned instead.
+async fn combine_responses(
+    original: Message,
+    receivers: Vec<oneshot::Receiver<Response>>,
+) -> Result<Response> {
+    let mut combined: Option<Message> = None;
+    for receiver in receivers {
+        let mut response = receive_response(receiver).await?.response?;
+        let combined_message = match combined.as_mut() {
+            Some(combined_message) => combined_message,
+            None => {
+                combined = Some(response);
+                continue;
+            }
+        };
+
+        let mut use_response = false;
+        match (combined_message.frame(), response.frame()) {
+            (
+                Some(Frame::Memcached(MemcachedFrame::Response(combined_frame))),
+                Some(Frame::Memcached(MemcachedFrame::Response(frame))),
+            ) => match (&mut combined_frame.status, &mut frame.status) {
+                (MemcachedStatus::Values(combined_values), MemcachedStatus::Values(values)) => {
+                    combined_values.append(values);
+                    combined_message.invalidate_cache();
+                }
+                (MemcachedStatus::Error(_), _) => {}
+                (_, MemcachedStatus::Error(_)) => use_response = true,
+                _ => {}
+            },
+            _ => bail!("memcached server returned a response that could not be parsed"),
+        }
+        if use_response {
+            combined = Some(response);
+        }
+    }
+
+    Ok(Response {
+        original,
+        response: combined.ok_or_else(|| anyhow!("request was not sent to any server")),
+    })
+}
+
+fn short_circuit(frame: MemcachedFrame) -> ResponseFuture {
+    let response = Response {
+        original: Message::from_frame(Frame::None),
+        response: Ok(Message::from_frame(Frame::Memcached(frame))),
+    };
+    Box::pin(async move { Ok(response) })
+}
+
+#[async_trait]
+impl Transform for MemcachedSinkCluster {
+    fn is_terminating(&self) -> bool {
+        true
+    }
+
+    async fn transform<'a>(&'a mut self, message_wrapper: Wrapper<'a>) -> ChainResponse {
+        let mut responses = FuturesOrdered::new();
+
+        for message in message_wrapper.messages {
+            let metadata = match message.metadata()? {
+                Metadata::Memcached(metadata) => metadata,
+                _ => bail!("MemcachedSinkCluster can only handle memcached messages"),
+            };
+            responses.push_back(match self.dispatch_message(message).await {
+                Ok(response) => response,
+                Err(e) => short_circuit(
+                    metadata.error_response(MemcachedErrorKind::ServerError, &format!("{e}")),
+                ),
+            });
+        }
+
+        let mut response_buffer = vec![];
+        while let Some(response) = responses.next().await {
+            let Response { original, response } = response?;
+            let mut response = match (response, original.metadata()?) {
+                (Ok(response), _) => response,
+                (Err(e), Metadata::Memcached(metadata)) => Message::from_frame(Frame::Memcached(
+                    metadata.error_response(MemcachedErrorKind::ServerError, &format!("{e}")),
+                )),
+                (Err(e), _) => return Err(e),
+            };
+            if let Some(Frame::Memcached(frame)) = response.frame() {
+                if frame.is_error() {
+                    self.failed_requests.increment(1);
+                }
+            }
+            response_buffer.push(response);
+        }
+        Ok(response_buffer)
+    }
+}
+
+mod continuum {
+    use bytes::Bytes;
+    use md5::{Digest, Md5};
+
+    /// Number of md5 digests generated per server, each digest provides 4 points on the continuum
+    const DIGESTS_PER_SERVER: usize = 40;
+
+    /// The ketama continuum used by libmemcached and most other memcached clients,
+    /// so that shotover distributes keys across servers in the same way as those clients do.
+    #[derive(Clone, Debug)]
+    pub struct Continuum {
+        /// Sorted by point
+        points: Vec<(u32, usize)>,
+        servers: Vec<String>,
+    }
+
+    impl Continuum {
+        pub fn new(servers: &[String]) -> Self {
+            let mut points = Vec::with_capacity(servers.len() * DIGESTS_PER_SERVER * 4);
+            for (server_index, server) in servers.iter().enumerate() {
+                for i in 0..DIGESTS_PER_SERVER {
+                    let digest = Md5::digest(format!("{server}-{i}").as_bytes());
+                    for chunk in digest.chunks_exact(4) {
+                        let point = u32::from_le_bytes(chunk.try_into().unwrap());
+                        points.push((point, server_index));
+                    }
+                }
+            }
+            points.sort_unstable();
+
+            Continuum {
+                points,
+                servers: servers.to_vec(),
+            }
+        }
+
+        pub fn server_for_key(&self, key: &Bytes) -> &str {
+            let digest = Md5::digest(key);
+            let hash = u32::from_le_bytes(digest[..4].try_into().unwrap());
+
+            // The first point at or after the hash, wrapping around to the start of the continuum
+            let index = self.points.partition_point(|(point, _)| *point < hash);
+            let (_, server_index) = self.points[index % self.points.len()];
+            &self.servers[server_index]
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::Continuum;
+        use bytes::Bytes;
+
+        #[test]
+        fn test_keys_are_distributed_consistently() {
+            let servers: Vec<String> = (0..3).map(|i| format!("127.0.0.1:1121{i}")).collect();
+            let continuum = Continuum::new(&servers);
+
+            let keys: Vec<Bytes> = (0..1000).map(|i| Bytes::from(format!("key{i}"))).collect();
+            for server in &servers {
+                let count = keys
+                    .iter()
+                    .filter(|key| continuum.server_for_key(key) == server)
+                    .count();
+                assert!(count > 150, "{server} was only assigned {count} keys");
+            }
+
+            // Removing a server only moves the keys that were assigned to it
+            let smaller = Continuum::new(&servers[..2]);
+            for key in &keys {
+                let server = continuum.server_for_key(key);
+                if server != servers[2] {
+                    assert_eq!(server, smaller.server_for_key(key));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::codec::memcached::MemcachedCodec;
+use crate::error::ChainResponse;
+use crate::frame::Frame;
+use crate::tls::{AsyncStream, TlsConnector, TlsConnectorConfig};
+use crate::transforms::{Transform, Transforms, Wrapper};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use metrics::{register_counter, Counter};
+use serde::Deserialize;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_util::codec::Framed;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MemcachedSinkSingleConfig {
+    #[serde(rename = "remote_address")]
+    pub address: String,
+    pub tls: Option<TlsConnectorConfig>,
+}
+
+impl MemcachedSinkSingleConfig {
+    pub async fn get_transform(&self, chain_name: String) -> Result<Transforms> {
+        let tls = self.tls.clone().map(TlsConnector::new).transpose()?;
+        Ok(Transforms::MemcachedSinkSingle(MemcachedSinkSingle::new(
+            self.address.clone(),
+            tls,
+            chain_name,
+        )))
+    }
+}
+
+type MemcachedFramed = Framed<Pin<Box<dyn AsyncStream + Send + Sync>>, MemcachedCodec>;
+
+pub struct MemcachedSinkSingle {
+    address: String,
+    tls: Option<TlsConnector>,
+    connection: Option<MemcachedFramed>,
+    failed_requests: Counter,
+}
+
+impl Clone for MemcachedSinkSingle {
+    fn clone(&self) -> Self {
+        MemcachedSinkSingle {
+            address: self.address.clone(),
+            tls: self.tls.clone(),
+            connection: None,
+            failed_requests: self.failed_requests.clone(),
+        }
+    }
+}
+
+impl MemcachedSinkSingle {
+    pub fn new(
+        address: String,
+        tls: Option<TlsConnector>,
+        chain_name: String,
+    ) -> MemcachedSinkSingle {
+        let failed_requests = register_counter!("failed_requests", "chain" => chain_name, "transform" => "MemcachedSinkSingle");
+
+        MemcachedSinkSingle {
+            address,
+            tls,
+            connection: None,
+            failed_requests,
+        }
+    }
+
+    async fn connect(&mut self) -> Result<MemcachedFramed> {
+        let tcp_stream = timeout(
+            Duration::from_secs(3),
+            TcpStream::connect(self.address.clone()),
+        )
+        .await?
+        .map_err(|e| anyhow::Error::new(e).context("Failed to connect to upstream"))?;
+
+        let generic_stream = if let Some(tls) = self.tls.as_mut() {
+            let tls_stream = tls.connect_unverified_hostname(tcp_stream).await?;
+            Box::pin(tls_stream) as Pin<Box<dyn AsyncStream + Send + Sync>>
+        } else {
+            Box::pin(tcp_stream) as Pin<Box<dyn AsyncStream + Send + Sync>>
+        };
+
+        Ok(Framed::new(generic_stream, MemcachedCodec::new()))
+    }
+}
+
+#[async_trait]
+impl Transform for MemcachedSinkSingle {
+    fn is_terminating(&self) -> bool {
+        true
+    }
+
+    async fn transform<'a>(&'a mut self, message_wrapper: Wrapper<'a>) -> ChainResponse {
+        // Return immediately if we have no messages.
+        // If we tried to send no messages we would block forever waiting for a reply that will never come.
+        if message_wrapper.messages.is_empty() {
+            return Ok(message_wrapper.messages);
+        }
+
+        if self.connection.is_none() {
+            self.connection = Some(self.connect().await?);
+        }
+        let connection = self.connection.as_mut().unwrap();
+
+        let messages_len = message_wrapper.messages.len();
+        if let Err(err) = connection.send(message_wrapper.messages).await {
+            self.connection = None;
+            return Err(err.context("Failed to send messages to memcached destination"));
+        }
+
+        // memcached responds to requests in the order they were sent, so the responses can be collected directly off the connection
+        let mut result = Vec::with_capacity(messages_len);
+        while result.len() < messages_len {
+            let messages = match connection.next().await {
+                Some(Ok(messages)) => messages,
+                Some(Err(err)) => {
+                    self.connection = None;
+                    return Err(anyhow!("{err:?}")
+                        .context("Failed to receive messages from memcached destination"));
+                }
+                None => {
+                    self.connection = None;
+                    return Err(anyhow!("memcached destination closed the connection"));
+                }
+            };
+            for mut message in messages {
+                if let Some(Frame::Memcached(frame)) = message.frame() {
+                    if frame.is_error() {
+                        self.failed_requests.increment(1);
+                    }
+                }
+                result.push(message);
+            }
+        }
+        Ok(result)
+    }
+}
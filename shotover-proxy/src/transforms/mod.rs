@@ -1,5 +1,15 @@
 use crate::error::ChainResponse;
 use crate::message::Messages;
+use crate::proxy_protocol::ProxyHeader;
+use crate::transforms::cassandra::authenticate::{
+    CassandraAuthenticate, CassandraAuthenticateConfig,
+};
+use crate::transforms::cassandra::authorize::{CassandraAuthorize, CassandraAuthorizeConfig};
+use crate::transforms::cassandra::auto_prepare::{
+    CassandraAutoPrepare, CassandraAutoPrepareConfig,
+};
+use crate::transforms::cassandra::consistency::{CassandraConsistency, CassandraConsistencyConfig};
+use crate::transforms::cassandra::firewall::{CassandraFirewall, CassandraFirewallConfig};
 use crate::transforms::cassandra::peers_rewrite::CassandraPeersRewrite;
 use crate::transforms::cassandra::peers_rewrite::CassandraPeersRewriteConfig;
 use crate::transforms::cassandra::sink_cluster::CassandraSinkCluster;
@@ -17,11 +27,17 @@ use crate::transforms::distributed::consistent_scatter::{
     ConsistentScatter, ConsistentScatterConfig,
 };
 use crate::transforms::filter::{QueryTypeFilter, QueryTypeFilterConfig};
+use crate::transforms::kafka::sink_single::{KafkaSinkSingle, KafkaSinkSingleConfig};
 use crate::transforms::load_balance::ConnectionBalanceAndPool;
 #[cfg(test)]
 use crate::transforms::loopback::Loopback;
+use crate::transforms::memcached::sink_cluster::{
+    MemcachedSinkCluster, MemcachedSinkClusterConfig,
+};
+use crate::transforms::memcached::sink_single::{MemcachedSinkSingle, MemcachedSinkSingleConfig};
 use crate::transforms::null::Null;
 use crate::transforms::parallel_map::{ParallelMap, ParallelMapConfig};
+use crate::transforms::postgres::sink_single::{PostgresSinkSingle, PostgresSinkSingleConfig};
 use crate::transforms::protect::Protect;
 #[cfg(feature = "alpha-transforms")]
 use crate::transforms::protect::ProtectConfig;
@@ -55,11 +71,14 @@ pub mod coalesce;
 pub mod debug;
 pub mod distributed;
 pub mod filter;
+pub mod kafka;
 pub mod load_balance;
 pub mod loopback;
+pub mod memcached;
 pub mod noop;
 pub mod null;
 pub mod parallel_map;
+pub mod postgres;
 pub mod protect;
 pub mod query_counter;
 pub mod redis;
@@ -78,6 +97,11 @@ pub enum Transforms {
     CassandraSinkSingle(CassandraSinkSingle),
     CassandraSinkCluster(Box<CassandraSinkCluster>),
     RedisSinkSingle(RedisSinkSingle),
+    CassandraAuthenticate(CassandraAuthenticate),
+    CassandraAutoPrepare(CassandraAutoPrepare),
+    CassandraAuthorize(CassandraAuthorize),
+    CassandraConsistency(CassandraConsistency),
+    CassandraFirewall(CassandraFirewall),
     CassandraPeersRewrite(CassandraPeersRewrite),
     RedisCache(SimpleRedisCache),
     Tee(Tee),
@@ -89,6 +113,10 @@ pub enum Transforms {
     RedisTimestampTagger(RedisTimestampTagger),
     RedisSinkCluster(RedisSinkCluster),
     RedisClusterPortsRewrite(RedisClusterPortsRewrite),
+    MemcachedSinkSingle(MemcachedSinkSingle),
+    MemcachedSinkCluster(MemcachedSinkCluster),
+    KafkaSinkSingle(KafkaSinkSingle),
+    PostgresSinkSingle(PostgresSinkSingle),
     DebugReturner(DebugReturner),
     DebugRandomDelay(DebugRandomDelay),
     DebugPrinter(DebugPrinter),
@@ -112,6 +140,11 @@ impl Transforms {
         match self {
             Transforms::CassandraSinkSingle(c) => c.transform(message_wrapper).await,
             Transforms::CassandraSinkCluster(c) => c.transform(message_wrapper).await,
+            Transforms::CassandraAuthenticate(c) => c.transform(message_wrapper).await,
+            Transforms::CassandraAutoPrepare(c) => c.transform(message_wrapper).await,
+            Transforms::CassandraAuthorize(c) => c.transform(message_wrapper).await,
+            Transforms::CassandraConsistency(c) => c.transform(message_wrapper).await,
+            Transforms::CassandraFirewall(c) => c.transform(message_wrapper).await,
             Transforms::CassandraPeersRewrite(c) => c.transform(message_wrapper).await,
             Transforms::RedisCache(r) => r.transform(message_wrapper).await,
             Transforms::Tee(m) => m.transform(message_wrapper).await,
@@ -128,6 +161,10 @@ impl Transforms {
             Transforms::RedisTimestampTagger(r) => r.transform(message_wrapper).await,
             Transforms::RedisClusterPortsRewrite(r) => r.transform(message_wrapper).await,
             Transforms::RedisSinkCluster(r) => r.transform(message_wrapper).await,
+            Transforms::MemcachedSinkSingle(m) => m.transform(message_wrapper).await,
+            Transforms::MemcachedSinkCluster(m) => m.transform(message_wrapper).await,
+            Transforms::KafkaSinkSingle(k) => k.transform(message_wrapper).await,
+            Transforms::PostgresSinkSingle(p) => p.transform(message_wrapper).await,
             Transforms::ParallelMap(s) => s.transform(message_wrapper).await,
             Transforms::PoolConnections(s) => s.transform(message_wrapper).await,
             Transforms::Coalesce(s) => s.transform(message_wrapper).await,
@@ -141,6 +178,11 @@ impl Transforms {
         match self {
             Transforms::CassandraSinkSingle(c) => c.transform_pushed(message_wrapper).await,
             Transforms::CassandraSinkCluster(c) => c.transform_pushed(message_wrapper).await,
+            Transforms::CassandraAuthenticate(c) => c.transform_pushed(message_wrapper).await,
+            Transforms::CassandraAutoPrepare(c) => c.transform_pushed(message_wrapper).await,
+            Transforms::CassandraAuthorize(c) => c.transform_pushed(message_wrapper).await,
+            Transforms::CassandraConsistency(c) => c.transform_pushed(message_wrapper).await,
+            Transforms::CassandraFirewall(c) => c.transform_pushed(message_wrapper).await,
             Transforms::CassandraPeersRewrite(c) => c.transform_pushed(message_wrapper).await,
             Transforms::RedisCache(r) => r.transform_pushed(message_wrapper).await,
             Transforms::Tee(m) => m.transform_pushed(message_wrapper).await,
@@ -157,6 +199,10 @@ impl Transforms {
             Transforms::RedisTimestampTagger(r) => r.transform_pushed(message_wrapper).await,
             Transforms::RedisClusterPortsRewrite(r) => r.transform_pushed(message_wrapper).await,
             Transforms::RedisSinkCluster(r) => r.transform_pushed(message_wrapper).await,
+            Transforms::MemcachedSinkSingle(m) => m.transform_pushed(message_wrapper).await,
+            Transforms::MemcachedSinkCluster(m) => m.transform_pushed(message_wrapper).await,
+            Transforms::KafkaSinkSingle(k) => k.transform_pushed(message_wrapper).await,
+            Transforms::PostgresSinkSingle(p) => p.transform_pushed(message_wrapper).await,
             Transforms::ParallelMap(s) => s.transform_pushed(message_wrapper).await,
             Transforms::PoolConnections(s) => s.transform_pushed(message_wrapper).await,
             Transforms::Coalesce(s) => s.transform_pushed(message_wrapper).await,
@@ -174,6 +220,11 @@ impl Transforms {
         match self {
             Transforms::CassandraSinkSingle(a) => a.prep_transform_chain(t).await,
             Transforms::CassandraSinkCluster(a) => a.prep_transform_chain(t).await,
+            Transforms::CassandraAuthenticate(c) => c.prep_transform_chain(t).await,
+            Transforms::CassandraAutoPrepare(c) => c.prep_transform_chain(t).await,
+            Transforms::CassandraAuthorize(c) => c.prep_transform_chain(t).await,
+            Transforms::CassandraConsistency(c) => c.prep_transform_chain(t).await,
+            Transforms::CassandraFirewall(c) => c.prep_transform_chain(t).await,
             Transforms::CassandraPeersRewrite(c) => c.prep_transform_chain(t).await,
             Transforms::RedisSinkSingle(a) => a.prep_transform_chain(t).await,
             Transforms::RedisCache(a) => a.prep_transform_chain(t).await,
@@ -190,6 +241,10 @@ impl Transforms {
             Transforms::RedisTimestampTagger(a) => a.prep_transform_chain(t).await,
             Transforms::RedisSinkCluster(r) => r.prep_transform_chain(t).await,
             Transforms::RedisClusterPortsRewrite(r) => r.prep_transform_chain(t).await,
+            Transforms::MemcachedSinkSingle(m) => m.prep_transform_chain(t).await,
+            Transforms::MemcachedSinkCluster(m) => m.prep_transform_chain(t).await,
+            Transforms::KafkaSinkSingle(k) => k.prep_transform_chain(t).await,
+            Transforms::PostgresSinkSingle(p) => p.prep_transform_chain(t).await,
             Transforms::ParallelMap(s) => s.prep_transform_chain(t).await,
             Transforms::PoolConnections(s) => s.prep_transform_chain(t).await,
             Transforms::Coalesce(s) => s.prep_transform_chain(t).await,
@@ -203,6 +258,11 @@ impl Transforms {
         match self {
             Transforms::CassandraSinkSingle(c) => c.validate(),
             Transforms::CassandraSinkCluster(c) => c.validate(),
+            Transforms::CassandraAuthenticate(c) => c.validate(),
+            Transforms::CassandraAutoPrepare(c) => c.validate(),
+            Transforms::CassandraAuthorize(c) => c.validate(),
+            Transforms::CassandraConsistency(c) => c.validate(),
+            Transforms::CassandraFirewall(c) => c.validate(),
             Transforms::CassandraPeersRewrite(c) => c.validate(),
             Transforms::RedisCache(r) => r.validate(),
             Transforms::Tee(t) => t.validate(),
@@ -214,6 +274,10 @@ impl Transforms {
             Transforms::DebugForceParse(p) => p.validate(),
             Transforms::Null(n) => n.validate(),
             Transforms::RedisSinkCluster(r) => r.validate(),
+            Transforms::MemcachedSinkSingle(m) => m.validate(),
+            Transforms::MemcachedSinkCluster(m) => m.validate(),
+            Transforms::KafkaSinkSingle(k) => k.validate(),
+            Transforms::PostgresSinkSingle(p) => p.validate(),
             Transforms::ParallelMap(s) => s.validate(),
             Transforms::PoolConnections(s) => s.validate(),
             Transforms::Coalesce(s) => s.validate(),
@@ -232,6 +296,11 @@ impl Transforms {
         match self {
             Transforms::CassandraSinkSingle(c) => c.is_terminating(),
             Transforms::CassandraSinkCluster(c) => c.is_terminating(),
+            Transforms::CassandraAuthenticate(c) => c.is_terminating(),
+            Transforms::CassandraAutoPrepare(c) => c.is_terminating(),
+            Transforms::CassandraAuthorize(c) => c.is_terminating(),
+            Transforms::CassandraConsistency(c) => c.is_terminating(),
+            Transforms::CassandraFirewall(c) => c.is_terminating(),
             Transforms::CassandraPeersRewrite(c) => c.is_terminating(),
             Transforms::RedisCache(r) => r.is_terminating(),
             Transforms::Tee(t) => t.is_terminating(),
@@ -243,6 +312,10 @@ impl Transforms {
             Transforms::DebugForceParse(p) => p.is_terminating(),
             Transforms::Null(n) => n.is_terminating(),
             Transforms::RedisSinkCluster(r) => r.is_terminating(),
+            Transforms::MemcachedSinkSingle(m) => m.is_terminating(),
+            Transforms::MemcachedSinkCluster(m) => m.is_terminating(),
+            Transforms::KafkaSinkSingle(k) => k.is_terminating(),
+            Transforms::PostgresSinkSingle(p) => p.is_terminating(),
             Transforms::ParallelMap(s) => s.is_terminating(),
             Transforms::PoolConnections(s) => s.is_terminating(),
             Transforms::Coalesce(s) => s.is_terminating(),
@@ -261,6 +334,11 @@ impl Transforms {
         match self {
             Transforms::CassandraSinkSingle(c) => c.set_pushed_messages_tx(pushed_messages_tx),
             Transforms::CassandraSinkCluster(c) => c.set_pushed_messages_tx(pushed_messages_tx),
+            Transforms::CassandraAuthenticate(c) => c.set_pushed_messages_tx(pushed_messages_tx),
+            Transforms::CassandraAutoPrepare(c) => c.set_pushed_messages_tx(pushed_messages_tx),
+            Transforms::CassandraAuthorize(c) => c.set_pushed_messages_tx(pushed_messages_tx),
+            Transforms::CassandraConsistency(c) => c.set_pushed_messages_tx(pushed_messages_tx),
+            Transforms::CassandraFirewall(c) => c.set_pushed_messages_tx(pushed_messages_tx),
             Transforms::CassandraPeersRewrite(c) => c.set_pushed_messages_tx(pushed_messages_tx),
             Transforms::RedisCache(r) => r.set_pushed_messages_tx(pushed_messages_tx),
             Transforms::Tee(t) => t.set_pushed_messages_tx(pushed_messages_tx),
@@ -272,6 +350,10 @@ impl Transforms {
             Transforms::DebugForceParse(p) => p.set_pushed_messages_tx(pushed_messages_tx),
             Transforms::Null(n) => n.set_pushed_messages_tx(pushed_messages_tx),
             Transforms::RedisSinkCluster(r) => r.set_pushed_messages_tx(pushed_messages_tx),
+            Transforms::MemcachedSinkSingle(m) => m.set_pushed_messages_tx(pushed_messages_tx),
+            Transforms::MemcachedSinkCluster(m) => m.set_pushed_messages_tx(pushed_messages_tx),
+            Transforms::KafkaSinkSingle(k) => k.set_pushed_messages_tx(pushed_messages_tx),
+            Transforms::PostgresSinkSingle(p) => p.set_pushed_messages_tx(pushed_messages_tx),
             Transforms::ParallelMap(s) => s.set_pushed_messages_tx(pushed_messages_tx),
             Transforms::PoolConnections(s) => s.set_pushed_messages_tx(pushed_messages_tx),
             Transforms::Coalesce(s) => s.set_pushed_messages_tx(pushed_messages_tx),
@@ -294,6 +376,11 @@ pub enum TransformsConfig {
     CassandraSinkSingle(CassandraSinkSingleConfig),
     CassandraSinkCluster(CassandraSinkClusterConfig),
     RedisSinkSingle(RedisSinkSingleConfig),
+    CassandraAuthenticate(CassandraAuthenticateConfig),
+    CassandraAutoPrepare(CassandraAutoPrepareConfig),
+    CassandraAuthorize(CassandraAuthorizeConfig),
+    CassandraConsistency(CassandraConsistencyConfig),
+    CassandraFirewall(CassandraFirewallConfig),
     CassandraPeersRewrite(CassandraPeersRewriteConfig),
     RedisCache(RedisConfig),
     Tee(TeeConfig),
@@ -301,6 +388,10 @@ pub enum TransformsConfig {
     RedisSinkCluster(RedisSinkClusterConfig),
     RedisClusterPortsRewrite(RedisClusterPortsRewriteConfig),
     RedisTimestampTagger,
+    MemcachedSinkSingle(MemcachedSinkSingleConfig),
+    MemcachedSinkCluster(MemcachedSinkClusterConfig),
+    KafkaSinkSingle(KafkaSinkSingleConfig),
+    PostgresSinkSingle(PostgresSinkSingleConfig),
     DebugPrinter,
     DebugReturner(DebugReturnerConfig),
     Null,
@@ -327,6 +418,11 @@ impl TransformsConfig {
         match self {
             TransformsConfig::CassandraSinkSingle(c) => c.get_transform(chain_name).await,
             TransformsConfig::CassandraSinkCluster(c) => c.get_transform(chain_name).await,
+            TransformsConfig::CassandraAuthenticate(c) => c.get_transform().await,
+            TransformsConfig::CassandraAutoPrepare(c) => c.get_transform().await,
+            TransformsConfig::CassandraAuthorize(c) => c.get_transform().await,
+            TransformsConfig::CassandraConsistency(c) => c.get_transform().await,
+            TransformsConfig::CassandraFirewall(c) => c.get_transform().await,
             TransformsConfig::CassandraPeersRewrite(c) => c.get_transform().await,
             TransformsConfig::RedisCache(r) => r.get_transform().await,
             TransformsConfig::Tee(t) => t.get_transform().await,
@@ -348,6 +444,10 @@ impl TransformsConfig {
             #[cfg(feature = "alpha-transforms")]
             TransformsConfig::DebugForceEncode(d) => d.get_transform().await,
             TransformsConfig::RedisSinkCluster(r) => r.get_transform(chain_name).await,
+            TransformsConfig::MemcachedSinkSingle(m) => m.get_transform(chain_name).await,
+            TransformsConfig::MemcachedSinkCluster(m) => m.get_transform(chain_name).await,
+            TransformsConfig::KafkaSinkSingle(k) => k.get_transform(chain_name).await,
+            TransformsConfig::PostgresSinkSingle(p) => p.get_transform(chain_name).await,
             TransformsConfig::ParallelMap(s) => s.get_transform().await,
             //TransformsConfig::PoolConnections(s) => s.get_transform().await,
             TransformsConfig::Coalesce(s) => s.get_transform().await,
@@ -386,6 +486,11 @@ pub struct Wrapper<'a> {
     /// This can occur at any time but will always occur before the transform is destroyed due to either
     /// shotover or the transform's chain shutting down.
     pub flush: bool,
+    /// The user the client authenticated as, set by an authenticating transform such as CassandraAuthenticate.
+    pub authenticated_user: Option<String>,
+    /// The addresses of the client connection that the messages were received on, used by sinks that send a PROXY protocol header.
+    /// When the source received a PROXY protocol header this is that header unchanged.
+    pub proxy_header: Option<ProxyHeader>,
 }
 
 /// [`Wrapper`] will not (cannot) bring the current list of transforms that it needs to traverse with it
@@ -400,6 +505,8 @@ impl<'a> Clone for Wrapper<'a> {
             chain_name: self.chain_name.clone(),
             local_addr: self.local_addr,
             flush: false,
+            authenticated_user: self.authenticated_user.clone(),
+            proxy_header: self.proxy_header,
         }
     }
 }
@@ -439,6 +546,25 @@ impl<'a> Wrapper<'a> {
         result
     }
 
+    /// Calls the next transform with `messages` like [`Wrapper::call_next_transform`], but without consuming the [`Wrapper`].
+    /// This allows a transform to call the remaining transforms more than once, e.g. to retry a request that failed.
+    pub async fn call_next_transform_with(&mut self, messages: Messages) -> ChainResponse {
+        let transforms = std::mem::replace(&mut self.transforms, [].iter_mut()).into_slice();
+        let wrapper = Wrapper {
+            messages,
+            transforms: transforms.iter_mut(),
+            client_details: self.client_details.clone(),
+            local_addr: self.local_addr,
+            chain_name: self.chain_name.clone(),
+            flush: self.flush,
+            authenticated_user: self.authenticated_user.clone(),
+            proxy_header: self.proxy_header,
+        };
+        let result = wrapper.call_next_transform().await;
+        self.transforms = transforms.iter_mut();
+        result
+    }
+
     pub async fn call_next_transform_pushed(mut self) -> ChainResponse {
         let transform = match self.transforms.next() {
             Some(transform) => transform,
@@ -469,6 +595,8 @@ impl<'a> Wrapper<'a> {
             local_addr: "127.0.0.1:8000".parse().unwrap(),
             chain_name: "".to_string(),
             flush: false,
+            authenticated_user: None,
+            proxy_header: None,
         }
     }
 
@@ -480,6 +608,8 @@ impl<'a> Wrapper<'a> {
             local_addr,
             chain_name,
             flush: false,
+            authenticated_user: None,
+            proxy_header: None,
         }
     }
 
@@ -492,12 +622,15 @@ impl<'a> Wrapper<'a> {
             local_addr: "127.0.0.1:10000".parse().unwrap(),
             chain_name,
             flush: true,
+            authenticated_user: None,
+            proxy_header: None,
         }
     }
 
     pub fn new_with_client_details(
         m: Messages,
         client_details: String,
+        proxy_header: Option<ProxyHeader>,
         chain_name: String,
         local_addr: SocketAddr,
     ) -> Self {
@@ -508,6 +641,8 @@ impl<'a> Wrapper<'a> {
             local_addr,
             chain_name,
             flush: false,
+            authenticated_user: None,
+            proxy_header,
         }
     }
 
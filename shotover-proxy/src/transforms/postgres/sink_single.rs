@@ -0,0 +1,218 @@
+use crate::codec::postgres::PostgresCodec;
+use crate::error::ChainResponse;
+use crate::frame::postgres::{BackendMessage, FrontendMessage, SSL_REQUEST_CODE};
+use crate::frame::{Frame, PostgresFrame};
+use crate::message::Message;
+use crate::tls::{AsyncStream, TlsConnector, TlsConnectorConfig};
+use crate::transforms::{Transform, Transforms, Wrapper};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use metrics::{register_counter, Counter};
+use serde::Deserialize;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_util::codec::Framed;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PostgresSinkSingleConfig {
+    #[serde(rename = "remote_address")]
+    pub address: String,
+    pub tls: Option<TlsConnectorConfig>,
+}
+
+impl PostgresSinkSingleConfig {
+    pub async fn get_transform(&self, chain_name: String) -> Result<Transforms> {
+        let tls = self.tls.clone().map(TlsConnector::new).transpose()?;
+        Ok(Transforms::PostgresSinkSingle(PostgresSinkSingle::new(
+            self.address.clone(),
+            tls,
+            chain_name,
+        )))
+    }
+}
+
+type PostgresFramed = Framed<Pin<Box<dyn AsyncStream + Send + Sync>>, PostgresCodec>;
+
+/// How a request from the client is handled
+enum Route {
+    /// Sent to postgres, which will respond to it
+    Send,
+    /// Sent to postgres, which will not respond to it
+    SendWithoutResponse,
+    /// A cancel request must be sent on a new connection, which postgres closes without responding
+    Cancel,
+    /// Answered by shotover without involving postgres
+    Respond(Vec<BackendMessage>),
+}
+
+pub struct PostgresSinkSingle {
+    address: String,
+    tls: Option<TlsConnector>,
+    connection: Option<PostgresFramed>,
+    failed_requests: Counter,
+}
+
+impl Clone for PostgresSinkSingle {
+    fn clone(&self) -> Self {
+        PostgresSinkSingle {
+            address: self.address.clone(),
+            tls: self.tls.clone(),
+            connection: None,
+            failed_requests: self.failed_requests.clone(),
+        }
+    }
+}
+
+impl PostgresSinkSingle {
+    pub fn new(
+        address: String,
+        tls: Option<TlsConnector>,
+        chain_name: String,
+    ) -> PostgresSinkSingle {
+        let failed_requests = register_counter!("failed_requests", "chain" => chain_name, "transform" => "PostgresSinkSingle");
+
+        PostgresSinkSingle {
+            address,
+            tls,
+            connection: None,
+            failed_requests,
+        }
+    }
+
+    async fn connect(&mut self) -> Result<PostgresFramed> {
+        let mut tcp_stream = timeout(
+            Duration::from_secs(3),
+            TcpStream::connect(self.address.clone()),
+        )
+        .await?
+        .map_err(|e| anyhow::Error::new(e).context("Failed to connect to upstream"))?;
+
+        let generic_stream = if let Some(tls) = self.tls.as_mut() {
+            // Postgres only begins a TLS handshake after the client asks for one
+            tcp_stream.write_i32(8).await?;
+            tcp_stream.write_i32(SSL_REQUEST_CODE).await?;
+            if tcp_stream.read_u8().await? != b'S' {
+                bail!("postgres destination does not support TLS");
+            }
+            let tls_stream = tls.connect_unverified_hostname(tcp_stream).await?;
+            Box::pin(tls_stream) as Pin<Box<dyn AsyncStream + Send + Sync>>
+        } else {
+            Box::pin(tcp_stream) as Pin<Box<dyn AsyncStream + Send + Sync>>
+        };
+
+        Ok(Framed::new(generic_stream, PostgresCodec::new()))
+    }
+
+    async fn send_cancel_request(&mut self, message: Message) -> Result<()> {
+        let mut connection = self.connect().await?;
+        connection
+            .send(vec![message])
+            .await
+            .map_err(|err| err.context("Failed to send cancel request to postgres destination"))
+    }
+}
+
+fn route(message: &mut Message) -> Result<Route> {
+    let frame: &PostgresFrame = match message.frame() {
+        Some(Frame::Postgres(frame)) => frame,
+        None => bail!("Failed to parse postgres frame"),
+        frame => bail!("PostgresSinkSingle cannot send frame {frame:?}"),
+    };
+    match frame {
+        PostgresFrame::Request(messages) => Ok(match messages.as_slice() {
+            // Shotover handles TLS itself so encryption is never negotiated with postgres on behalf of the client
+            [FrontendMessage::SslRequest | FrontendMessage::GssEncRequest] => {
+                Route::Respond(vec![BackendMessage::EncryptionNotSupported])
+            }
+            [FrontendMessage::CancelRequest { .. }] => Route::Cancel,
+            _ if frame.expects_response() => Route::Send,
+            _ => Route::SendWithoutResponse,
+        }),
+        PostgresFrame::Response(_) => bail!("PostgresSinkSingle cannot send a postgres response"),
+    }
+}
+
+#[async_trait]
+impl Transform for PostgresSinkSingle {
+    fn is_terminating(&self) -> bool {
+        true
+    }
+
+    async fn transform<'a>(&'a mut self, message_wrapper: Wrapper<'a>) -> ChainResponse {
+        // Return immediately if we have no messages.
+        // If we tried to send no messages we would block forever waiting for a reply that will never come.
+        if message_wrapper.messages.is_empty() {
+            return Ok(message_wrapper.messages);
+        }
+
+        let mut routes = Vec::with_capacity(message_wrapper.messages.len());
+        let mut requests = vec![];
+        for mut message in message_wrapper.messages {
+            let route = route(&mut message)?;
+            match route {
+                Route::Send | Route::SendWithoutResponse => requests.push(message),
+                Route::Cancel => self.send_cancel_request(message).await?,
+                Route::Respond(_) => {}
+            }
+            routes.push(route);
+        }
+
+        let mut responses = vec![];
+        if !requests.is_empty() {
+            if self.connection.is_none() {
+                self.connection = Some(self.connect().await?);
+            }
+            let connection = self.connection.as_mut().unwrap();
+
+            if let Err(err) = connection.send(requests).await {
+                self.connection = None;
+                return Err(err.context("Failed to send messages to postgres destination"));
+            }
+
+            // postgres responds to requests in the order they were sent, so the responses can be collected directly off the connection
+            let responses_len = routes
+                .iter()
+                .filter(|route| matches!(route, Route::Send))
+                .count();
+            while responses.len() < responses_len {
+                match connection.next().await {
+                    Some(Ok(messages)) => responses.extend(messages),
+                    Some(Err(err)) => {
+                        self.connection = None;
+                        return Err(anyhow!("{err:?}")
+                            .context("Failed to receive messages from postgres destination"));
+                    }
+                    None => {
+                        self.connection = None;
+                        return Err(anyhow!("postgres destination closed the connection"));
+                    }
+                }
+            }
+        }
+
+        let mut responses = responses.into_iter();
+        let mut result = Vec::with_capacity(routes.len());
+        for route in routes {
+            let mut response = match route {
+                Route::Send => responses.next().unwrap(),
+                Route::SendWithoutResponse | Route::Cancel => {
+                    Message::from_frame(Frame::Postgres(PostgresFrame::Response(vec![])))
+                }
+                Route::Respond(messages) => {
+                    Message::from_frame(Frame::Postgres(PostgresFrame::Response(messages)))
+                }
+            };
+            if let Some(Frame::Postgres(frame)) = response.frame() {
+                if frame.is_error() {
+                    self.failed_requests.increment(1);
+                }
+            }
+            result.push(response);
+        }
+        Ok(result)
+    }
+}
@@ -1,5 +1,9 @@
 use crate::error::ChainResponse;
+use crate::frame::kafka::api_key;
 use crate::frame::Frame;
+use crate::frame::KafkaFrame;
+use crate::frame::MemcachedFrame;
+use crate::frame::PostgresFrame;
 use crate::frame::RedisFrame;
 use crate::transforms::{Transform, Transforms, Wrapper};
 use anyhow::Result;
@@ -42,6 +46,26 @@ impl Transform for QueryCounter {
                         counter!("query_count", 1, "name" => self.counter_name.clone(), "query" => "unknown", "type" => "redis");
                     }
                 }
+                Some(Frame::Memcached(MemcachedFrame::Request(request))) => {
+                    counter!("query_count", 1, "name" => self.counter_name.clone(), "query" => request.command.name(), "type" => "memcached");
+                }
+                Some(Frame::Memcached(MemcachedFrame::Response(_))) => {
+                    counter!("query_count", 1, "name" => self.counter_name.clone(), "query" => "unknown", "type" => "memcached");
+                }
+                Some(Frame::Kafka(KafkaFrame::Request(request))) => {
+                    counter!("query_count", 1, "name" => self.counter_name.clone(), "query" => api_key::name(request.header.api_key), "type" => "kafka");
+                }
+                Some(Frame::Kafka(KafkaFrame::Response(_))) => {
+                    counter!("query_count", 1, "name" => self.counter_name.clone(), "query" => "unknown", "type" => "kafka");
+                }
+                Some(Frame::Postgres(PostgresFrame::Request(messages))) => {
+                    for message in messages {
+                        counter!("query_count", 1, "name" => self.counter_name.clone(), "query" => message.name(), "type" => "postgres");
+                    }
+                }
+                Some(Frame::Postgres(PostgresFrame::Response(_))) => {
+                    counter!("query_count", 1, "name" => self.counter_name.clone(), "query" => "unknown", "type" => "postgres");
+                }
                 Some(Frame::None) | None => {
                     counter!("query_count", 1, "name" => self.counter_name.clone(), "query" => "unknown", "type" => "none")
                 }
@@ -464,6 +464,7 @@ impl RedisSinkCluster {
                 self.choose_and_send(&lookup, message).await
             }
             RoutingInfo::Auth => self.on_auth(message).await,
+            RoutingInfo::Hello => self.on_hello(message).await,
             RoutingInfo::Unsupported => {
                 short_circuit(RedisFrame::Error(
                     Str::from_inner(Bytes::from_static(b"ERR unknown command - Shotover RedisSinkCluster does not not support this command")).unwrap(),
@@ -486,6 +487,7 @@ impl RedisSinkCluster {
             RoutingInfo::AllNodes(_)
             | RoutingInfo::AllMasters(_)
             | RoutingInfo::Random
+            | RoutingInfo::Hello
             | RoutingInfo::Unsupported
             | RoutingInfo::ShortCircuitNil
             | RoutingInfo::ShortCircuitOk => {
@@ -526,14 +528,76 @@ impl RedisSinkCluster {
 
         match self.build_connections(Some(token)).await {
             Ok(()) => short_circuit(RedisFrame::SimpleString("OK".into())),
-            Err(TransformError::Upstream(RedisError::BadCredentials)) => {
+            Err(err) => self.auth_error_response(err),
+        }
+    }
+
+    async fn on_hello(&mut self, mut message: Message) -> Result<ResponseFuture> {
+        let command = match message.frame() {
+            Some(Frame::Redis(RedisFrame::Array(ref mut command))) => command,
+            None => bail!("Failed to parse redis frame"),
+            message => bail!("syntax error: bad command: {message:?}"),
+        };
+
+        // HELLO [protover [AUTH username password] [SETNAME clientname]]
+        let mut token = None;
+        let mut args = command.iter().skip(2);
+        while let Some(arg) = args.next() {
+            match arg {
+                RedisFrame::BulkString(arg) if arg.eq_ignore_ascii_case(b"AUTH") => {
+                    match (args.next(), args.next()) {
+                        (
+                            Some(RedisFrame::BulkString(username)),
+                            Some(RedisFrame::BulkString(password)),
+                        ) => {
+                            token = Some(UsernamePasswordToken {
+                                username: Some(username.clone()),
+                                password: password.clone(),
+                            });
+                        }
+                        _ => bail!("syntax error: expected username and password"),
+                    }
+                }
+                // Connection names are ignored for the same reasons as CLIENT SETNAME
+                RedisFrame::BulkString(arg) if arg.eq_ignore_ascii_case(b"SETNAME") => {
+                    args.next();
+                }
+                _ => bail!("syntax error: unexpected HELLO argument"),
+            }
+        }
+
+        // Authentication is handled by shotover so only the protocol negotiation is sent on to a node
+        if command.len() > 2 {
+            command.truncate(2);
+            message.invalidate_cache();
+        }
+
+        if let Some(token) = token {
+            if let Err(err) = self.build_connections(Some(token)).await {
+                return self.auth_error_response(err);
+            }
+        }
+
+        let lookup = self
+            .slots
+            .masters
+            .values()
+            .choose(&mut self.rng)
+            .cloned()
+            .unwrap_or_default();
+        self.choose_and_send(&lookup, message).await
+    }
+
+    fn auth_error_response(&self, err: TransformError) -> Result<ResponseFuture> {
+        match err {
+            TransformError::Upstream(RedisError::BadCredentials) => {
                 self.send_error_response("WRONGPASS invalid username-password")
             }
-            Err(TransformError::Upstream(RedisError::NotAuthorized)) => {
+            TransformError::Upstream(RedisError::NotAuthorized) => {
                 self.send_error_response("NOPERM upstream user lacks required permission")
             }
-            Err(TransformError::Upstream(e)) => self.send_error_response(e.to_string().as_str()),
-            Err(e) => Err(anyhow!(e).context("authentication failed")),
+            TransformError::Upstream(e) => self.send_error_response(e.to_string().as_str()),
+            e => Err(anyhow!(e).context("authentication failed")),
         }
     }
 
@@ -607,6 +671,8 @@ impl SlotMap {
 pub enum RoutingInfo {
     Slot(u16),
     Auth,
+    /// Any AUTH arguments are handled by shotover and the protocol negotiation is sent to a random master
+    Hello,
     /// In handling mode falls back to sending to the destination address
     AllNodes(ResponseJoin),
     /// In handling mode falls back to sending to the destination address
@@ -718,7 +784,7 @@ impl RoutingInfo {
             // We just need a single redis node to handle this for us so shotover can pretend to be a single node.
             // So we just pick a node at random.
             b"ECHO" | b"PING" => RoutingInfo::Random,
-            b"HELLO" => RoutingInfo::Unsupported,
+            b"HELLO" => RoutingInfo::Hello,
             _ => match args.get(1) {
                 Some(key) => RoutingInfo::for_key(key).unwrap_or(RoutingInfo::Unsupported),
                 None => RoutingInfo::Random,
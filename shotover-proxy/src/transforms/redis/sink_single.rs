@@ -1,20 +1,26 @@
-use crate::codec::redis::RedisCodec;
+use crate::codec::redis::{
+    is_monitor_output, is_subscription_confirmation, resp2_to_resp3_value, RedisCodec,
+};
 use crate::error::ChainResponse;
-use crate::frame::Frame;
-use crate::frame::RedisFrame;
+use crate::frame::{Frame, RedisFrame, Resp3Frame};
 use crate::message::{Message, Messages};
+use crate::proxy_protocol::{ProxyHeader, ProxyProtocolVersion};
 use crate::server::CodecReadError;
+use crate::socket;
 use crate::tls::{AsyncStream, TlsConnector, TlsConnectorConfig};
 use crate::transforms::{Transform, Transforms, Wrapper};
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
+use bytes::Bytes;
 use futures::stream::{SplitSink, SplitStream};
 use futures::{FutureExt, SinkExt, StreamExt};
 use metrics::{register_counter, Counter};
 use serde::Deserialize;
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::pin::Pin;
 use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio::time::timeout;
@@ -26,6 +32,7 @@ pub struct RedisSinkSingleConfig {
     #[serde(rename = "remote_address")]
     pub address: String,
     pub tls: Option<TlsConnectorConfig>,
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
 }
 
 impl RedisSinkSingleConfig {
@@ -34,6 +41,7 @@ impl RedisSinkSingleConfig {
         Ok(Transforms::RedisSinkSingle(RedisSinkSingle::new(
             self.address.clone(),
             tls,
+            self.proxy_protocol,
             chain_name,
         )))
     }
@@ -50,10 +58,13 @@ struct Connection {
 pub struct RedisSinkSingle {
     address: String,
     tls: Option<TlsConnector>,
+    proxy_protocol: Option<ProxyProtocolVersion>,
     connection: Option<Connection>,
     chain_name: String,
     failed_requests: Counter,
     pushed_messages_tx: Option<mpsc::UnboundedSender<Messages>>,
+    /// The client id of the connection that client side caching invalidations are redirected to
+    invalidation_client_id: Option<i64>,
 }
 
 impl Clone for RedisSinkSingle {
@@ -61,27 +72,125 @@ impl Clone for RedisSinkSingle {
         RedisSinkSingle {
             address: self.address.clone(),
             tls: self.tls.clone(),
+            proxy_protocol: self.proxy_protocol,
             connection: None,
             chain_name: self.chain_name.clone(),
             failed_requests: self.failed_requests.clone(),
             pushed_messages_tx: None,
+            invalidation_client_id: None,
         }
     }
 }
 
 impl RedisSinkSingle {
-    pub fn new(address: String, tls: Option<TlsConnector>, chain_name: String) -> RedisSinkSingle {
+    pub fn new(
+        address: String,
+        tls: Option<TlsConnector>,
+        proxy_protocol: Option<ProxyProtocolVersion>,
+        chain_name: String,
+    ) -> RedisSinkSingle {
         let failed_requests = register_counter!("failed_requests", "chain" => chain_name.clone(), "transform" => "RedisSinkSingle");
 
         RedisSinkSingle {
             address,
             tls,
+            proxy_protocol,
             connection: None,
             chain_name,
             failed_requests,
             pushed_messages_tx: None,
+            invalidation_client_id: None,
         }
     }
+
+    async fn connect(&mut self, proxy_header: Option<ProxyHeader>) -> Result<RedisFramed> {
+        let mut stream = match socket::unix_socket_path(&self.address) {
+            Some(path) => Box::pin(socket::connect_unix(path).await?)
+                as Pin<Box<dyn AsyncStream + Send + Sync>>,
+            None => Box::pin(
+                timeout(
+                    Duration::from_secs(3),
+                    TcpStream::connect(self.address.clone()),
+                )
+                .await?
+                .map_err(|e| anyhow::Error::new(e).context("Failed to connect to upstream"))?,
+            ) as Pin<Box<dyn AsyncStream + Send + Sync>>,
+        };
+
+        if let Some(version) = self.proxy_protocol {
+            stream
+                .write_all(&ProxyHeader::encode(proxy_header, version))
+                .await
+                .context("Failed to send PROXY protocol header to upstream")?;
+        }
+
+        let generic_stream = if let Some(tls) = self.tls.as_mut() {
+            let tls_stream = tls.connect_unverified_hostname(stream).await?;
+            Box::pin(tls_stream) as Pin<Box<dyn AsyncStream + Send + Sync>>
+        } else {
+            stream
+        };
+
+        Ok(Framed::new(generic_stream, RedisCodec::new()))
+    }
+
+    /// Redis only sends client side caching invalidations on the tracking connection itself in RESP3, but shotover always communicates with redis in RESP2.
+    /// So tracking is redirected to a separate connection subscribed to `__redis__:invalidate`, whose invalidations are delivered to the client as RESP3 pushes.
+    /// Returns the client id of the connection to redirect to.
+    async fn connect_invalidations(
+        &mut self,
+        proxy_header: Option<ProxyHeader>,
+        pushed_messages_tx: mpsc::UnboundedSender<Messages>,
+    ) -> Result<i64> {
+        let mut connection = self.connect(proxy_header).await?;
+        connection
+            .send(vec![
+                command(&["CLIENT", "ID"]),
+                command(&["SUBSCRIBE", "__redis__:invalidate"]),
+            ])
+            .await
+            .context("Failed to send messages to redis destination")?;
+
+        let mut responses = vec![];
+        while responses.len() < 2 {
+            match connection.next().await {
+                Some(Ok(messages)) => responses.extend(messages),
+                Some(Err(err)) => bail!("encountered error in redis stream: {err:?}"),
+                None => bail!("redis closed the connection for client side caching invalidations"),
+            }
+        }
+        let client_id = match responses[0].frame() {
+            Some(Frame::Redis(RedisFrame::Integer(client_id))) => *client_id,
+            frame => bail!("Unexpected response to CLIENT ID: {frame:?}"),
+        };
+        if let Some(Frame::Redis(RedisFrame::Error(err))) = responses[1].frame() {
+            bail!("Failed to subscribe to client side caching invalidations: {err}");
+        }
+
+        tokio::spawn(invalidation_task(connection, pushed_messages_tx).in_current_span());
+        Ok(client_id)
+    }
+}
+
+fn command(args: &[&'static str]) -> Message {
+    Message::from_frame(Frame::Redis(RedisFrame::Array(
+        args.iter()
+            .map(|arg| RedisFrame::BulkString(Bytes::from_static(arg.as_bytes())))
+            .collect(),
+    )))
+}
+
+/// Returns true if the request is a `CLIENT TRACKING ON` that does not redirect invalidations to another connection
+fn is_tracking_without_redirect(message: &mut Message) -> bool {
+    let args = match message.frame() {
+        Some(Frame::Redis(RedisFrame::Array(args))) => args,
+        _ => return false,
+    };
+    let is_arg = |index: usize, name: &[u8]| matches!(args.get(index), Some(RedisFrame::BulkString(arg)) if arg.eq_ignore_ascii_case(name));
+    is_arg(0, b"CLIENT")
+        && is_arg(1, b"TRACKING")
+        && is_arg(2, b"ON")
+        && !(3..args.len()).any(|index| is_arg(index, b"REDIRECT"))
 }
 
 #[async_trait]
@@ -98,21 +207,8 @@ impl Transform for RedisSinkSingle {
         }
 
         if self.connection.is_none() {
-            let tcp_stream = timeout(
-                Duration::from_secs(3),
-                TcpStream::connect(self.address.clone()),
-            )
-            .await?
-            .map_err(|e| anyhow::Error::new(e).context("Failed to connect to upstream"))?;
-
-            let generic_stream = if let Some(tls) = self.tls.as_mut() {
-                let tls_stream = tls.connect_unverified_hostname(tcp_stream).await?;
-                Box::pin(tls_stream) as Pin<Box<dyn AsyncStream + Send + Sync>>
-            } else {
-                Box::pin(tcp_stream) as Pin<Box<dyn AsyncStream + Send + Sync>>
-            };
-
-            let (outbound_tx, outbound_rx) = Framed::new(generic_stream, RedisCodec::new()).split();
+            let (outbound_tx, outbound_rx) =
+                self.connect(message_wrapper.proxy_header).await?.split();
             let (response_messages_tx, response_messages_rx) = mpsc::unbounded_channel();
             let (sent_message_type_tx, sent_message_type_rx) = mpsc::unbounded_channel();
 
@@ -132,16 +228,52 @@ impl Transform for RedisSinkSingle {
             })
         }
 
+        // Invalidations can only be delivered when there is a pushed messages chain, e.g. not in an alternate chain of a tee transform
+        if let Some(pushed_messages_tx) = self.pushed_messages_tx.clone() {
+            for message in &mut message_wrapper.messages {
+                if is_tracking_without_redirect(message) {
+                    let client_id = match self.invalidation_client_id {
+                        Some(client_id) => client_id,
+                        None => {
+                            let client_id = self
+                                .connect_invalidations(
+                                    message_wrapper.proxy_header,
+                                    pushed_messages_tx.clone(),
+                                )
+                                .await?;
+                            self.invalidation_client_id = Some(client_id);
+                            client_id
+                        }
+                    };
+                    if let Some(Frame::Redis(RedisFrame::Array(args))) = message.frame() {
+                        args.push(RedisFrame::BulkString(Bytes::from_static(b"REDIRECT")));
+                        args.push(RedisFrame::BulkString(Bytes::from(client_id.to_string())));
+                    }
+                    message.invalidate_cache();
+                }
+            }
+        }
+
         let connection = self.connection.as_mut().unwrap();
 
         for message in &mut message_wrapper.messages {
             let ty = if let Some(Frame::Redis(RedisFrame::Array(array))) = message.frame() {
                 if let Some(RedisFrame::BulkString(bytes)) = array.first() {
+                    let channels = array.len() - 1;
                     match bytes.to_ascii_uppercase().as_slice() {
-                        b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" => MessageType::Subscribe,
-                        b"UNSUBSCRIBE" | b"PUNSUBSCRIBE" | b"SUNSUBSCRIBE" => {
-                            MessageType::Unsubscribe
+                        b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" => {
+                            MessageType::Subscribe(channels)
+                        }
+                        b"UNSUBSCRIBE" => {
+                            MessageType::Unsubscribe(SubscriptionKind::Channel, channels)
+                        }
+                        b"PUNSUBSCRIBE" => {
+                            MessageType::Unsubscribe(SubscriptionKind::Pattern, channels)
                         }
+                        b"SUNSUBSCRIBE" => {
+                            MessageType::Unsubscribe(SubscriptionKind::ShardChannel, channels)
+                        }
+                        b"MONITOR" => MessageType::Monitor,
                         b"RESET" => MessageType::Reset,
                         _ => MessageType::Other,
                     }
@@ -186,7 +318,7 @@ impl Transform for RedisSinkSingle {
 
 /// Processes responses coming in from the server.
 /// Responses are then filtered into either the regular chain or pushed messages chain
-/// depending on if they are a subscription or response message.
+/// depending on if they are a response or a message that does not respond to any request.
 ///
 /// A separate task is needed to process the incoming messages so that subscription messages can be sent immediately
 /// without waiting for an incoming request to trigger the RedisSinkSingle transform again.
@@ -198,7 +330,7 @@ async fn server_response_processing_task(
     response_messages_tx: mpsc::UnboundedSender<Message>,
     mut sent_message_type: mpsc::UnboundedReceiver<MessageType>,
 ) {
-    let mut is_subscribed = true;
+    let mut state = ConnectionState::default();
     loop {
         tokio::select! {
             responses = outbound_rx.next().fuse() => {
@@ -206,7 +338,7 @@ async fn server_response_processing_task(
                     responses,
                     &subscribe_tx,
                     &response_messages_tx,
-                    &mut is_subscribed,
+                    &mut state,
                     &mut sent_message_type
                 ).await {
                     return;
@@ -225,92 +357,43 @@ async fn process_server_response(
     responses: Option<Result<Messages, CodecReadError>>,
     subscribe_tx: &Option<mpsc::UnboundedSender<Messages>>,
     response_messages_tx: &mpsc::UnboundedSender<Message>,
-    is_subscribed: &mut bool,
+    state: &mut ConnectionState,
     sent_message_type: &mut mpsc::UnboundedReceiver<MessageType>,
 ) -> bool {
     match responses {
         Some(Ok(messages)) => {
             for mut message in messages {
-                // Notes on subscription responses
-                //
-                // There are 3 types of pubsub responses and the type is determined by the first value in the array:
-                // * `subscribe` - a response to a SUBSCRIBE, PSUBSCRIBE or SSUBSCRIBE request
-                // * `unsubscribe` - a response to an UNSUBSCRIBE, PUNSUBSCRIBE or SUNSUBSCRIBE request
-                // * `message` - a subscription message
-                //
-                // Additionally redis will:
-                // * accept a few regular commands while in pubsub mode: PING, RESET and QUIT
-                // * return an error response when a nonexistent or non pubsub compatible command is used
-                //
-                // Note: PING has a custom response when in pubsub mode.
-                //       It returns an array ['pong', $pingMessage] instead of directly returning $pingMessage.
-                //       But this doesnt cause any problems for us.
-
-                // Determine if message is a `message` subscription message
-                //
-                // Because PING, RESET, QUIT and error responses never return a RedisFrame::Array starting with `message`,
-                // they have no way to collide with the `message` value of a subscription message.
-                // So while we are in subscription mode we can use that to determine if an
-                // incoming message is a subscription message.
-                let is_subscription_message = if *is_subscribed {
-                    if let Some(Frame::Redis(RedisFrame::Array(array))) = message.frame() {
-                        if let [RedisFrame::BulkString(ty), ..] = array.as_slice() {
-                            ty.as_ref() == b"message"
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
-                } else {
-                    false
+                let is_response = match message.frame() {
+                    Some(Frame::Redis(frame)) => state.is_response(frame, sent_message_type).await,
+                    _ => state.take_request(sent_message_type).await.map(|_| true),
                 };
 
-                // Update is_subscribed state
-                //
-                // In order to make sense of a response we need the main task to
-                // send us the type of its corresponding request.
-                //
-                // In order to keep the incoming request MessageTypes in sync with their corresponding responses
-                // we must only process a MessageType when the message is not a subscription message.
-                // This is fine because subscription messages cannot affect the is_subscribed state.
-                if !is_subscription_message {
-                    match sent_message_type.recv().await {
-                        Some(MessageType::Subscribe) | Some(MessageType::Unsubscribe) => {
-                            if let Some(Frame::Redis(RedisFrame::Array(array))) = message.frame() {
-                                if let Some(RedisFrame::Integer(number_of_subscribed_channels)) =
-                                    array.get(2)
-                                {
-                                    *is_subscribed = *number_of_subscribed_channels != 0;
-                                }
-                            }
-                        }
-                        Some(MessageType::Other) => {}
-                        Some(MessageType::Reset) => {
-                            *is_subscribed = false;
-                        }
-                        None => {
-                            tracing::debug!("RedisSinkSingle dropped after a message was received from server, RedisSinkSingle request processor task shutting down");
-                            return true;
-                        }
-                    }
-                }
-
                 // Route the message down the correct path:
-                // * `message` subscription messages:
+                // * messages that do not respond to any request:
                 //    needs to be routed down the pushed_messages chain
                 // * everything else:
                 //    needs to be routed down the regular chain
-                if is_subscription_message {
-                    // subscribe_tx may not exist if we are e.g. in an alternate chain of a tee transform
-                    if let Some(subscribe_tx) = subscribe_tx {
-                        if let Err(mpsc::error::SendError(_)) = subscribe_tx.send(vec![message]) {
-                            tracing::debug!("shotover chain is terminated, will continue running until Transform is dropped");
+                match is_response {
+                    Some(true) => {
+                        if let Err(mpsc::error::SendError(_)) = response_messages_tx.send(message) {
+                            tracing::debug!("RedisSinkSingle dropped after a message was received from server, RedisSinkSingle request processor task shutting down");
+                            return true;
                         }
                     }
-                } else if let Err(mpsc::error::SendError(_)) = response_messages_tx.send(message) {
-                    tracing::debug!("RedisSinkSingle dropped after a message was received from server, RedisSinkSingle request processor task shutting down");
-                    return true;
+                    Some(false) => {
+                        // subscribe_tx may not exist if we are e.g. in an alternate chain of a tee transform
+                        if let Some(subscribe_tx) = subscribe_tx {
+                            if let Err(mpsc::error::SendError(_)) =
+                                subscribe_tx.send(vec![into_pushed_message(message)])
+                            {
+                                tracing::debug!("shotover chain is terminated, will continue running until Transform is dropped");
+                            }
+                        }
+                    }
+                    None => {
+                        tracing::debug!("RedisSinkSingle dropped after a message was received from server, RedisSinkSingle request processor task shutting down");
+                        return true;
+                    }
                 }
             }
             false
@@ -326,10 +409,415 @@ async fn process_server_response(
     }
 }
 
-#[derive(Debug)]
+/// Pubsub messages are sent as RESP3 pushes, which the client codec sends to RESP2 clients as the original array.
+/// MONITOR output is a simple string in both versions of RESP so it is left as is.
+fn into_pushed_message(mut message: Message) -> Message {
+    match message.frame() {
+        Some(Frame::Redis(RedisFrame::Array(array))) => {
+            Message::from_frame(Frame::Resp3(Resp3Frame::Push {
+                data: std::mem::take(array)
+                    .into_iter()
+                    .map(resp2_to_resp3_value)
+                    .collect(),
+                attributes: None,
+            }))
+        }
+        _ => message,
+    }
+}
+
+/// Delivers the client side caching invalidations received by the connection that tracking is redirected to.
+/// The task ends when either the client connection or the redis connection is closed.
+async fn invalidation_task(
+    mut connection: RedisFramed,
+    pushed_messages_tx: mpsc::UnboundedSender<Messages>,
+) {
+    loop {
+        tokio::select! {
+            messages = connection.next() => match messages {
+                Some(Ok(messages)) => {
+                    let pushes: Messages = messages.into_iter().filter_map(into_invalidation).collect();
+                    if !pushes.is_empty() && pushed_messages_tx.send(pushes).is_err() {
+                        return;
+                    }
+                }
+                Some(Err(err)) => {
+                    tracing::error!("encountered error in redis stream: {err:?}");
+                    return;
+                }
+                None => {
+                    tracing::debug!("redis closed the connection for client side caching invalidations");
+                    return;
+                }
+            },
+            _ = pushed_messages_tx.closed() => return,
+        }
+    }
+}
+
+/// Translates a pubsub message on `__redis__:invalidate` into the RESP3 push that redis sends on the tracking connection.
+/// The keys are null when every key is invalidated, e.g. by FLUSHALL.
+fn into_invalidation(mut message: Message) -> Option<Message> {
+    match message.frame() {
+        Some(Frame::Redis(RedisFrame::Array(array))) => match array.as_mut_slice() {
+            [RedisFrame::BulkString(kind), _, keys] if kind.as_ref() == b"message" => {
+                Some(Message::from_frame(Frame::Resp3(Resp3Frame::Push {
+                    data: vec![
+                        Resp3Frame::BlobString {
+                            data: Bytes::from_static(b"invalidate"),
+                            attributes: None,
+                        },
+                        resp2_to_resp3_value(std::mem::replace(keys, RedisFrame::Null)),
+                    ],
+                    attributes: None,
+                })))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 enum MessageType {
     Other,
-    Subscribe,
-    Unsubscribe,
+    /// A (P|S)SUBSCRIBE to the contained number of channels, which is confirmed once for each channel
+    Subscribe(usize),
+    /// A (P|S)UNSUBSCRIBE from the contained number of channels, which is confirmed once for each channel.
+    /// Without any channels it unsubscribes from every channel of its kind.
+    Unsubscribe(SubscriptionKind, usize),
+    Monitor,
     Reset,
 }
+
+#[derive(Debug, Clone, Copy)]
+enum SubscriptionKind {
+    Channel,
+    Pattern,
+    ShardChannel,
+}
+
+/// The state needed to tell the responses from redis apart from the messages that do not respond to any request.
+///
+/// Redis sends exactly one message in response to each request with the exception of:
+/// * (P|S)(UN)SUBSCRIBE - confirms each channel separately, the first confirmation is used as the response and the rest are pushed
+/// * pubsub messages - pushed while subscribed to a channel
+/// * MONITOR output - pushed once a MONITOR request succeeds
+#[derive(Default)]
+struct ConnectionState {
+    /// The type of the oldest request that has not yet been responded to, once it has been received from the RedisSinkSingle
+    request: Option<MessageType>,
+    channels: HashSet<Bytes>,
+    patterns: HashSet<Bytes>,
+    shard_channels: HashSet<Bytes>,
+    /// The number of confirmations still to be received for the last subscription request
+    remaining_confirmations: usize,
+    monitoring: bool,
+}
+
+impl ConnectionState {
+    /// Returns the type of the oldest request that has not yet been responded to, or `None` if the RedisSinkSingle has been dropped
+    async fn peek_request(
+        &mut self,
+        sent_message_type: &mut mpsc::UnboundedReceiver<MessageType>,
+    ) -> Option<MessageType> {
+        if self.request.is_none() {
+            self.request = sent_message_type.recv().await;
+        }
+        self.request
+    }
+
+    /// Consumes the oldest request that has not yet been responded to, or returns `None` if the RedisSinkSingle has been dropped
+    async fn take_request(
+        &mut self,
+        sent_message_type: &mut mpsc::UnboundedReceiver<MessageType>,
+    ) -> Option<MessageType> {
+        let request = self.peek_request(sent_message_type).await;
+        self.request = None;
+        request
+    }
+
+    fn subscriptions(&mut self, kind: SubscriptionKind) -> &mut HashSet<Bytes> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    fn is_subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty() || !self.shard_channels.is_empty()
+    }
+
+    /// Returns true if the frame responds to the oldest request that has not yet been responded to,
+    /// or false if it does not respond to any request and should be pushed instead.
+    /// Returns `None` if the RedisSinkSingle has been dropped.
+    async fn is_response(
+        &mut self,
+        frame: &RedisFrame,
+        sent_message_type: &mut mpsc::UnboundedReceiver<MessageType>,
+    ) -> Option<bool> {
+        if let RedisFrame::Array(array) = frame {
+            if let [RedisFrame::BulkString(kind), channel, ..] = array.as_slice() {
+                // While subscribed redis will only respond to pubsub commands, PING, RESET and QUIT,
+                // none of which can respond with an array starting with these values.
+                if matches!(kind.as_ref(), b"message" | b"pmessage" | b"smessage")
+                    && self.is_subscribed()
+                {
+                    return Some(false);
+                }
+
+                if is_subscription_confirmation(kind) && array.len() == 3 {
+                    let is_response = if self.remaining_confirmations > 0 {
+                        self.remaining_confirmations -= 1;
+                        false
+                    } else {
+                        match self.peek_request(sent_message_type).await? {
+                            MessageType::Subscribe(channels) => {
+                                self.remaining_confirmations = channels.saturating_sub(1);
+                                true
+                            }
+                            MessageType::Unsubscribe(kind, channels) => {
+                                // Unsubscribing from every channel confirms each channel, or confirms once when there were none
+                                let channels = match channels {
+                                    0 => self.subscriptions(kind).len().max(1),
+                                    channels => channels,
+                                };
+                                self.remaining_confirmations = channels - 1;
+                                true
+                            }
+                            // Redis unsubscribes from shard channels without any request when their slot is migrated to another node
+                            _ if self.is_subscribed() => false,
+                            _ => return self.respond(frame, sent_message_type).await,
+                        }
+                    };
+                    if is_response {
+                        self.request = None;
+                    }
+                    if let RedisFrame::BulkString(channel) = channel {
+                        let channel = channel.clone();
+                        match kind.as_ref() {
+                            b"subscribe" => self.channels.insert(channel),
+                            b"psubscribe" => self.patterns.insert(channel),
+                            b"ssubscribe" => self.shard_channels.insert(channel),
+                            b"unsubscribe" => self.channels.remove(&channel),
+                            b"punsubscribe" => self.patterns.remove(&channel),
+                            _ => self.shard_channels.remove(&channel),
+                        };
+                    }
+                    return Some(is_response);
+                }
+            }
+        }
+
+        if self.monitoring && is_monitor_output(frame) {
+            return Some(false);
+        }
+
+        self.respond(frame, sent_message_type).await
+    }
+
+    /// Consumes the request that the frame responds to
+    async fn respond(
+        &mut self,
+        frame: &RedisFrame,
+        sent_message_type: &mut mpsc::UnboundedReceiver<MessageType>,
+    ) -> Option<bool> {
+        let is_error = matches!(frame, RedisFrame::Error(_));
+        match self.take_request(sent_message_type).await? {
+            MessageType::Reset if !is_error => {
+                self.channels.clear();
+                self.patterns.clear();
+                self.shard_channels.clear();
+                self.remaining_confirmations = 0;
+                self.monitoring = false;
+            }
+            MessageType::Monitor if !is_error => self.monitoring = true,
+            _ => {}
+        }
+        Some(true)
+    }
+}
+
+#[cfg(test)]
+mod test_redis_sink_single {
+    use super::*;
+
+    fn bulk_string(value: &'static str) -> RedisFrame {
+        RedisFrame::BulkString(Bytes::from_static(value.as_bytes()))
+    }
+
+    fn confirmation(kind: &'static str, channel: &'static str, count: i64) -> RedisFrame {
+        RedisFrame::Array(vec![
+            bulk_string(kind),
+            bulk_string(channel),
+            RedisFrame::Integer(count),
+        ])
+    }
+
+    fn simple_string(value: &'static str) -> RedisFrame {
+        RedisFrame::SimpleString(Bytes::from_static(value.as_bytes()))
+    }
+
+    /// Sends the types of the requests and returns whether each of the frames received from redis is a response
+    async fn classify(requests: Vec<MessageType>, frames: Vec<RedisFrame>) -> Vec<bool> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        for request in requests {
+            tx.send(request).unwrap();
+        }
+        let mut state = ConnectionState::default();
+        let mut result = vec![];
+        for frame in frames {
+            result.push(state.is_response(&frame, &mut rx).await.unwrap());
+        }
+        result
+    }
+
+    #[tokio::test]
+    async fn test_subscription_confirmations() {
+        assert_eq!(
+            classify(
+                vec![
+                    MessageType::Subscribe(2),
+                    MessageType::Subscribe(1),
+                    MessageType::Other,
+                ],
+                vec![
+                    confirmation("subscribe", "a", 1),
+                    confirmation("subscribe", "b", 2),
+                    confirmation("subscribe", "c", 3),
+                    RedisFrame::Array(vec![
+                        bulk_string("message"),
+                        bulk_string("a"),
+                        bulk_string("hi"),
+                    ]),
+                    RedisFrame::Array(vec![bulk_string("pong"), bulk_string("")]),
+                ],
+            )
+            .await,
+            vec![true, false, true, false, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_from_every_channel() {
+        assert_eq!(
+            classify(
+                vec![
+                    MessageType::Subscribe(2),
+                    MessageType::Unsubscribe(SubscriptionKind::Channel, 0),
+                    MessageType::Unsubscribe(SubscriptionKind::Channel, 0),
+                    MessageType::Other,
+                ],
+                vec![
+                    confirmation("subscribe", "a", 1),
+                    confirmation("subscribe", "b", 2),
+                    confirmation("unsubscribe", "a", 1),
+                    confirmation("unsubscribe", "b", 0),
+                    RedisFrame::Array(vec![
+                        bulk_string("unsubscribe"),
+                        RedisFrame::Null,
+                        RedisFrame::Integer(0),
+                    ]),
+                    // Not subscribed anymore so this is the response to a regular request
+                    RedisFrame::Array(vec![
+                        bulk_string("message"),
+                        bulk_string("a"),
+                        bulk_string("hi"),
+                    ]),
+                ],
+            )
+            .await,
+            vec![true, false, true, false, true, true]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_monitor() {
+        assert_eq!(
+            classify(
+                vec![MessageType::Monitor, MessageType::Other, MessageType::Reset],
+                vec![
+                    simple_string("OK"),
+                    simple_string("1339518083.107412 [0 127.0.0.1:60866] \"keys\" \"*\""),
+                    simple_string("PONG"),
+                    simple_string("1339518087.877697 [0 127.0.0.1:60866] \"dbsize\""),
+                    simple_string("RESET"),
+                ],
+            )
+            .await,
+            vec![true, false, true, false, true]
+        );
+    }
+
+    #[test]
+    fn test_into_invalidation() {
+        let mut push =
+            into_invalidation(Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                bulk_string("message"),
+                bulk_string("__redis__:invalidate"),
+                RedisFrame::Array(vec![bulk_string("foo")]),
+            ]))))
+            .unwrap();
+        assert_eq!(
+            push.frame(),
+            Some(&mut Frame::Resp3(Resp3Frame::Push {
+                data: vec![
+                    Resp3Frame::BlobString {
+                        data: Bytes::from_static(b"invalidate"),
+                        attributes: None,
+                    },
+                    Resp3Frame::Array {
+                        data: vec![Resp3Frame::BlobString {
+                            data: Bytes::from_static(b"foo"),
+                            attributes: None,
+                        }],
+                        attributes: None,
+                    },
+                ],
+                attributes: None,
+            }))
+        );
+
+        assert!(
+            into_invalidation(Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                bulk_string("subscribe"),
+                bulk_string("__redis__:invalidate"),
+                RedisFrame::Integer(1),
+            ]))))
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_is_tracking_without_redirect() {
+        let tracking = |args: Vec<RedisFrame>| {
+            is_tracking_without_redirect(&mut Message::from_frame(Frame::Redis(RedisFrame::Array(
+                args,
+            ))))
+        };
+        assert!(tracking(vec![
+            bulk_string("CLIENT"),
+            bulk_string("TRACKING"),
+            bulk_string("on"),
+        ]));
+        assert!(tracking(vec![
+            bulk_string("client"),
+            bulk_string("tracking"),
+            bulk_string("ON"),
+            bulk_string("BCAST"),
+        ]));
+        assert!(!tracking(vec![
+            bulk_string("CLIENT"),
+            bulk_string("TRACKING"),
+            bulk_string("ON"),
+            bulk_string("REDIRECT"),
+            bulk_string("5"),
+        ]));
+        assert!(!tracking(vec![
+            bulk_string("CLIENT"),
+            bulk_string("TRACKING"),
+            bulk_string("OFF"),
+        ]));
+    }
+}
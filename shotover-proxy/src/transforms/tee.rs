@@ -148,7 +148,7 @@ impl Transform for Tee {
                 if !chain_response.eq(&tee_response) {
                     for message in &mut chain_response {
                         message.set_error(
-                            "ERR The responses from the Tee subchain and down-chain did not match and behavior is set to fail on mismatch".into())
+                            "ERR The responses from the Tee subchain and down-chain did not match and behavior is set to fail on mismatch".into())?;
                     }
                 }
                 Ok(chain_response)
@@ -11,6 +11,8 @@ use tokio::sync::{mpsc, watch};
 pub async fn run_topology_task(ca_path: Option<&str>, port: Option<u32>) -> Vec<CassandraNode> {
     let port = port.unwrap_or(9042);
     let (nodes_tx, mut nodes_rx) = watch::channel(vec![]);
+    let (remote_nodes_tx, _remote_nodes_rx) = watch::channel(Default::default());
+    let (partition_keys_tx, _partition_keys_rx) = watch::channel(Default::default());
     let (task_handshake_tx, task_handshake_rx) = mpsc::channel(1);
     let tls = ca_path.map(|ca_path| {
         TlsConnector::new(TlsConnectorConfig {
@@ -21,12 +23,18 @@ pub async fn run_topology_task(ca_path: Option<&str>, port: Option<u32>) -> Vec<
         .unwrap()
     });
 
-    let mut connection_factory = ConnectionFactory::new(tls);
+    let mut connection_factory = ConnectionFactory::new(tls, None);
     for message in create_handshake() {
         connection_factory.push_handshake_message(message);
     }
 
-    create_topology_task(nodes_tx, task_handshake_rx, "dc1".to_string());
+    create_topology_task(
+        nodes_tx,
+        remote_nodes_tx,
+        partition_keys_tx,
+        task_handshake_rx,
+        "dc1".to_string(),
+    );
 
     // Give the handshake task a hardcoded handshake.
     // Normally the handshake is the handshake that the client gave shotover.
@@ -0,0 +1,56 @@
+use crate::helpers::ShotoverManager;
+use hex_literal::hex;
+use serial_test::serial;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Produce v0 request with acks=0 and no topics from client id "test", kafka does not respond to it
+const PRODUCE_NO_ACKS_REQUEST: [u8; 28] =
+    hex!("00000018 0000 0000 00000005 0004 74657374 0000 000003e8 00000000");
+
+/// Metadata v1 request for all topics from client id "test"
+const METADATA_REQUEST: [u8; 22] = hex!("00000012 0003 0001 00000007 0004 74657374 ffffffff");
+
+/// Metadata v1 response with a single broker 1 at localhost:9092 in rack null, controller 1 and no topics
+const METADATA_RESPONSE: [u8; 41] = hex!(
+    "00000025 00000007 00000001 00000001 0009 6c6f63616c686f7374 00002384 ffff 00000001 00000000"
+);
+
+/// The same metadata response but with the broker at 127.0.0.1:9192
+const METADATA_RESPONSE_REWRITTEN: [u8; 41] = hex!(
+    "00000025 00000007 00000001 00000001 0009 3132372e302e302e31 000023e8 ffff 00000001 00000000"
+);
+
+async fn read_message(stream: &mut TcpStream) -> Vec<u8> {
+    let size = stream.read_i32().await.unwrap();
+    let mut message = size.to_be_bytes().to_vec();
+    message.resize(4 + size as usize, 0);
+    stream.read_exact(&mut message[4..]).await.unwrap();
+    message
+}
+
+/// A broker that checks it receives the expected requests and only responds to the metadata request
+async fn fake_broker(listener: TcpListener) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    assert_eq!(read_message(&mut stream).await, PRODUCE_NO_ACKS_REQUEST);
+    assert_eq!(read_message(&mut stream).await, METADATA_REQUEST);
+    stream.write_all(&METADATA_RESPONSE).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_passthrough_rewrites_metadata() {
+    let listener = TcpListener::bind("127.0.0.1:9092").await.unwrap();
+    let broker = tokio::spawn(fake_broker(listener));
+
+    let _shotover_manager =
+        ShotoverManager::from_topology_file("tests/test-configs/kafka-passthrough/topology.yaml");
+
+    let mut client = TcpStream::connect("127.0.0.1:9192").await.unwrap();
+    client.write_all(&PRODUCE_NO_ACKS_REQUEST).await.unwrap();
+    client.write_all(&METADATA_REQUEST).await.unwrap();
+
+    // The produce request receives no response, so the first response received is for the metadata request
+    assert_eq!(read_message(&mut client).await, METADATA_RESPONSE_REWRITTEN);
+    broker.await.unwrap();
+}
@@ -2,6 +2,9 @@ mod cassandra_int_tests;
 pub mod codec;
 mod examples;
 mod helpers;
+mod kafka_int_tests;
+mod memcached_int_tests;
+mod postgres_int_tests;
 mod redis_int_tests;
 pub mod runner;
 pub mod transforms;
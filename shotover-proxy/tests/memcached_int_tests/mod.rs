@@ -0,0 +1,325 @@
+use crate::helpers::ShotoverManager;
+use bytes::{Bytes, BytesMut};
+use serial_test::serial;
+use shotover_proxy::frame::memcached::{
+    MemcachedCommand, MemcachedProtocol, MemcachedRequest, MemcachedStatus, MemcachedValue,
+};
+use shotover_proxy::frame::MemcachedFrame;
+use std::time::Duration;
+use test_helpers::docker_compose::DockerCompose;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+struct Connection {
+    stream: TcpStream,
+    buffer: BytesMut,
+}
+
+impl Connection {
+    async fn new(port: u16) -> Connection {
+        Connection {
+            stream: TcpStream::connect(("127.0.0.1", port)).await.unwrap(),
+            buffer: BytesMut::new(),
+        }
+    }
+
+    /// Send the raw request and wait for a single response
+    async fn request(&mut self, request: &[u8]) -> MemcachedStatus {
+        self.stream.write_all(request).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), self.response())
+            .await
+            .expect("timed out waiting for a memcached response")
+    }
+
+    async fn binary_request(&mut self, request: MemcachedRequest) -> MemcachedStatus {
+        let mut bytes = BytesMut::new();
+        MemcachedFrame::Request(request).encode(&mut bytes).unwrap();
+        self.request(&bytes).await
+    }
+
+    async fn response(&mut self) -> MemcachedStatus {
+        loop {
+            match MemcachedFrame::decode_response(&mut self.buffer).unwrap() {
+                Some((MemcachedFrame::Response(response), _)) => return response.status,
+                Some((frame, _)) => panic!("expected a response but was {frame:?}"),
+                None => {
+                    if self.stream.read_buf(&mut self.buffer).await.unwrap() == 0 {
+                        panic!("memcached connection was closed");
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn binary(command: MemcachedCommand, key: &str, value: &str) -> MemcachedRequest {
+    let mut request = MemcachedRequest::new(MemcachedProtocol::Binary, command);
+    if !key.is_empty() {
+        request.keys = vec![Bytes::copy_from_slice(key.as_bytes())];
+    }
+    request.value = Bytes::copy_from_slice(value.as_bytes());
+    request
+}
+
+fn text_value(key: &str, flags: u32, data: &str) -> MemcachedValue {
+    MemcachedValue {
+        key: Bytes::copy_from_slice(key.as_bytes()),
+        flags,
+        cas: None,
+        data: Bytes::copy_from_slice(data.as_bytes()),
+    }
+}
+
+fn cas_of(status: &MemcachedStatus) -> u64 {
+    match status {
+        MemcachedStatus::Values(values) => values[0].cas.unwrap(),
+        status => panic!("expected a value but was {status:?}"),
+    }
+}
+
+async fn test_text_protocol(connection: &mut Connection) {
+    assert_eq!(
+        connection.request(b"set foo 5 0 3\r\nbar\r\n").await,
+        MemcachedStatus::Stored
+    );
+    assert_eq!(
+        connection.request(b"get foo\r\n").await,
+        MemcachedStatus::Values(vec![text_value("foo", 5, "bar")])
+    );
+    assert_eq!(
+        connection.request(b"get missing\r\n").await,
+        MemcachedStatus::Values(vec![])
+    );
+
+    assert_eq!(
+        connection.request(b"add foo 0 0 3\r\nbaz\r\n").await,
+        MemcachedStatus::NotStored
+    );
+    assert_eq!(
+        connection
+            .request(b"replace missing 0 0 3\r\nbaz\r\n")
+            .await,
+        MemcachedStatus::NotStored
+    );
+    assert_eq!(
+        connection.request(b"append foo 0 0 1\r\n>\r\n").await,
+        MemcachedStatus::Stored
+    );
+    assert_eq!(
+        connection.request(b"prepend foo 0 0 1\r\n<\r\n").await,
+        MemcachedStatus::Stored
+    );
+    assert_eq!(
+        connection.request(b"get foo\r\n").await,
+        MemcachedStatus::Values(vec![text_value("foo", 5, "<bar>")])
+    );
+
+    let cas = cas_of(&connection.request(b"gets foo\r\n").await);
+    assert_eq!(
+        connection
+            .request(format!("cas foo 0 0 3 {}\r\nnew\r\n", cas + 1).as_bytes())
+            .await,
+        MemcachedStatus::Exists
+    );
+    assert_eq!(
+        connection
+            .request(format!("cas foo 0 0 3 {cas}\r\nnew\r\n").as_bytes())
+            .await,
+        MemcachedStatus::Stored
+    );
+
+    assert_eq!(
+        connection.request(b"set counter 0 0 2\r\n10\r\n").await,
+        MemcachedStatus::Stored
+    );
+    assert_eq!(
+        connection.request(b"incr counter 5\r\n").await,
+        MemcachedStatus::Number(15)
+    );
+    assert_eq!(
+        connection.request(b"decr counter 3\r\n").await,
+        MemcachedStatus::Number(12)
+    );
+    assert_eq!(
+        connection.request(b"incr missing 1\r\n").await,
+        MemcachedStatus::NotFound
+    );
+    assert_eq!(
+        connection.request(b"touch counter 100\r\n").await,
+        MemcachedStatus::Touched
+    );
+
+    // no response is received for a noreply request, so the next response is for the following get
+    assert_eq!(
+        connection
+            .request(b"set quiet 0 0 1 noreply\r\nq\r\nget quiet\r\n")
+            .await,
+        MemcachedStatus::Values(vec![text_value("quiet", 0, "q")])
+    );
+
+    assert_eq!(
+        connection.request(b"delete foo\r\n").await,
+        MemcachedStatus::Deleted
+    );
+    assert_eq!(
+        connection.request(b"delete foo\r\n").await,
+        MemcachedStatus::NotFound
+    );
+    assert!(matches!(
+        connection.request(b"version\r\n").await,
+        MemcachedStatus::Version(_)
+    ));
+}
+
+async fn test_binary_protocol(connection: &mut Connection) {
+    let mut set = binary(MemcachedCommand::Set, "binary", "value");
+    set.flags = 3;
+    assert_eq!(
+        connection.binary_request(set).await,
+        MemcachedStatus::Stored
+    );
+
+    match connection
+        .binary_request(binary(MemcachedCommand::GetK, "binary", ""))
+        .await
+    {
+        MemcachedStatus::Values(values) => {
+            assert_eq!(values.len(), 1);
+            assert_eq!(values[0].key, "binary");
+            assert_eq!(values[0].flags, 3);
+            assert_eq!(values[0].data, "value");
+        }
+        status => panic!("expected a value but was {status:?}"),
+    }
+    assert_eq!(
+        connection
+            .binary_request(binary(MemcachedCommand::Get, "missing", ""))
+            .await,
+        MemcachedStatus::NotFound
+    );
+
+    // a quiet get that misses has no response, so the next response is for the noop
+    let mut quiet_get = binary(MemcachedCommand::Get, "missing", "");
+    quiet_get.noreply = true;
+    let mut bytes = BytesMut::new();
+    MemcachedFrame::Request(quiet_get)
+        .encode(&mut bytes)
+        .unwrap();
+    MemcachedFrame::Request(binary(MemcachedCommand::Noop, "", ""))
+        .encode(&mut bytes)
+        .unwrap();
+    assert_eq!(connection.request(&bytes).await, MemcachedStatus::Ok);
+
+    let mut incr = binary(MemcachedCommand::Incr, "binary_counter", "");
+    incr.delta = 2;
+    incr.initial = 10;
+    assert_eq!(
+        connection.binary_request(incr.clone()).await,
+        MemcachedStatus::Number(10)
+    );
+    assert_eq!(
+        connection.binary_request(incr).await,
+        MemcachedStatus::Number(12)
+    );
+
+    assert_eq!(
+        connection
+            .binary_request(binary(MemcachedCommand::Delete, "binary", ""))
+            .await,
+        MemcachedStatus::Deleted
+    );
+    assert!(matches!(
+        connection
+            .binary_request(binary(MemcachedCommand::Version, "", ""))
+            .await,
+        MemcachedStatus::Version(_)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_passthrough() {
+    let _compose = DockerCompose::new("example-configs/memcached-passthrough/docker-compose.yml");
+    let _shotover_manager =
+        ShotoverManager::from_topology_file("example-configs/memcached-passthrough/topology.yaml");
+
+    let mut connection = Connection::new(11211).await;
+    test_text_protocol(&mut connection).await;
+    test_binary_protocol(&mut connection).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_cluster() {
+    let _compose = DockerCompose::new("example-configs/memcached-cluster/docker-compose.yml");
+    let _shotover_manager =
+        ShotoverManager::from_topology_file("example-configs/memcached-cluster/topology.yaml");
+
+    let mut connection = Connection::new(11211).await;
+    test_text_protocol(&mut connection).await;
+    test_binary_protocol(&mut connection).await;
+
+    let keys: Vec<String> = (0..30).map(|i| format!("key{i}")).collect();
+    for key in &keys {
+        assert_eq!(
+            connection
+                .request(format!("set {key} 0 0 {}\r\n{key}\r\n", key.len()).as_bytes())
+                .await,
+            MemcachedStatus::Stored
+        );
+    }
+
+    // a get of keys owned by different servers is combined into a single response
+    let mut get = b"get".to_vec();
+    for key in &keys {
+        get.extend(format!(" {key}").as_bytes());
+    }
+    get.extend(b"\r\n");
+    match connection.request(&get).await {
+        MemcachedStatus::Values(values) => {
+            let mut found: Vec<String> = values
+                .iter()
+                .map(|value| {
+                    assert_eq!(value.key, value.data);
+                    String::from_utf8(value.key.to_vec()).unwrap()
+                })
+                .collect();
+            found.sort();
+            let mut expected = keys.clone();
+            expected.sort();
+            assert_eq!(found, expected);
+        }
+        status => panic!("expected values but was {status:?}"),
+    }
+
+    // each key is stored on exactly one server and the keys are spread across the servers
+    let mut servers_used = 0;
+    let mut keys_stored = 0;
+    for port in [11212, 11213, 11214] {
+        let mut server = Connection::new(port).await;
+        let mut stored = 0;
+        for key in &keys {
+            if let MemcachedStatus::Values(values) =
+                server.request(format!("get {key}\r\n").as_bytes()).await
+            {
+                stored += values.len();
+            }
+        }
+        if stored > 0 {
+            servers_used += 1;
+        }
+        keys_stored += stored;
+    }
+    assert_eq!(keys_stored, keys.len());
+    assert!(servers_used > 1);
+
+    // flush_all is sent to every server
+    assert_eq!(
+        connection.request(b"flush_all\r\n").await,
+        MemcachedStatus::Ok
+    );
+    assert_eq!(
+        connection.request(&get).await,
+        MemcachedStatus::Values(vec![])
+    );
+}
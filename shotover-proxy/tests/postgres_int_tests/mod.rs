@@ -0,0 +1,93 @@
+use crate::helpers::ShotoverManager;
+use bytes::Bytes;
+use futures::SinkExt;
+use serial_test::serial;
+use test_helpers::docker_compose::DockerCompose;
+use tokio_postgres::{Client, NoTls};
+
+async fn connect(port: u16) -> Client {
+    let (client, connection) = tokio_postgres::connect(
+        &format!("host=127.0.0.1 port={port} user=postgres password=password"),
+        NoTls,
+    )
+    .await
+    .unwrap();
+    tokio::spawn(async move {
+        if let Err(err) = connection.await {
+            panic!("postgres connection failed: {err}");
+        }
+    });
+    client
+}
+
+async fn test_simple_query(client: &Client) {
+    client
+        .batch_execute(
+            "CREATE TABLE test (id int PRIMARY KEY, name text); INSERT INTO test VALUES (1, 'foo')",
+        )
+        .await
+        .unwrap();
+
+    let messages = client
+        .simple_query("SELECT name FROM test WHERE id = 1")
+        .await
+        .unwrap();
+    match &messages[0] {
+        tokio_postgres::SimpleQueryMessage::Row(row) => assert_eq!(row.get(0), Some("foo")),
+        _ => panic!("expected a row"),
+    }
+}
+
+async fn test_extended_query(client: &Client) {
+    let insert = client
+        .prepare("INSERT INTO test (id, name) VALUES ($1, $2)")
+        .await
+        .unwrap();
+    client.execute(&insert, &[&2i32, &"bar"]).await.unwrap();
+
+    let rows = client
+        .query("SELECT name FROM test WHERE id >= $1 ORDER BY id", &[&1i32])
+        .await
+        .unwrap();
+    let names: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+    assert_eq!(names, vec!["foo", "bar"]);
+
+    // postgres skips the rest of the request after an error, the connection must remain usable afterwards
+    client
+        .query("SELECT * FROM missing", &[])
+        .await
+        .unwrap_err();
+    let row = client.query_one("SELECT 1::int4", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), 1);
+}
+
+async fn test_copy(client: &Client) {
+    let sink = client
+        .copy_in::<_, Bytes>("COPY test (id, name) FROM STDIN")
+        .await
+        .unwrap();
+    futures::pin_mut!(sink);
+    sink.send(Bytes::from_static(b"3\tbaz\n4\tqux\n"))
+        .await
+        .unwrap();
+    assert_eq!(sink.finish().await.unwrap(), 2);
+
+    let row = client
+        .query_one("SELECT count(*) FROM test", &[])
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, i64>(0), 4);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_passthrough() {
+    let _compose = DockerCompose::new("example-configs/postgres-passthrough/docker-compose.yml");
+    let _shotover_manager =
+        ShotoverManager::from_topology_file("example-configs/postgres-passthrough/topology.yaml");
+
+    let client = connect(5432).await;
+    test_simple_query(&client).await;
+    test_extended_query(&client).await;
+    test_copy(&client).await;
+}
@@ -193,15 +193,18 @@ async fn test_time_cluster(connection: &mut Connection) {
 }
 
 async fn test_hello_cluster(connection: &mut Connection) {
-    // The Lettuce client relies on the error message here containing the string "unknown" to detect that shotover does not support RESP3
+    // The connection is still RESP2 so the HELLO response is a flat array of keys and values
+    let hello: HashMap<String, Value> = redis::cmd("HELLO").query_async(connection).await.unwrap();
+    assert_eq!(hello.get("proto"), Some(&Value::Int(2)));
+
     assert_eq!(
         redis::cmd("HELLO")
+            .arg(4)
             .query_async::<_, ()>(connection)
             .await
             .unwrap_err()
-            .detail()
-            .unwrap(),
-        "unknown command - Shotover RedisSinkCluster does not not support this command".to_string(),
+            .code(),
+        Some("NOPROTO"),
     );
 }
 
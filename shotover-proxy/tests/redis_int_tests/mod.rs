@@ -26,6 +26,61 @@ async fn test_passthrough() {
     test_invalid_frame().await;
 }
 
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_passthrough_unix_socket() {
+    let socket_path = Path::new("/tmp/shotover-redis-passthrough-unix.sock");
+    let _compose = DockerCompose::new("example-configs/redis-passthrough/docker-compose.yml");
+    {
+        let _shotover_manager = ShotoverManager::from_topology_file(
+            "tests/test-configs/redis-passthrough-unix/topology.yaml",
+        );
+        assert!(socket_path.exists());
+
+        let client =
+            redis::Client::open("redis+unix:///tmp/shotover-redis-passthrough-unix.sock").unwrap();
+        let mut connection = client.get_async_connection().await.unwrap();
+        redis::cmd("SET")
+            .arg("unix_key")
+            .arg("unix_value")
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .unwrap();
+        let value: String = redis::cmd("GET")
+            .arg("unix_key")
+            .query_async(&mut connection)
+            .await
+            .unwrap();
+        assert_eq!(value, "unix_value");
+    }
+
+    // The socket file is removed when shotover shuts down
+    assert!(!socket_path.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn test_chain_error_policy_respond_with_error() {
+    let shotover_manager = ShotoverManager::from_topology_file(
+        "tests/test-configs/redis-chain-error-policy/topology.yaml",
+    );
+    let mut connection = shotover_manager.redis_connection_async(6379).await;
+
+    // The connection stays open so every request receives the error
+    for _ in 0..3 {
+        let err = redis::cmd("GET")
+            .arg("key")
+            .query_async::<_, String>(&mut connection)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            "An error was signalled by the server: Internal shotover error",
+            err
+        );
+    }
+}
+
 #[tokio::test(flavor = "multi_thread")]
 #[serial]
 async fn test_passthrough_redis_down() {
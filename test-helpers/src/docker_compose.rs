@@ -98,6 +98,10 @@ impl DockerCompose {
             | "example-configs/redis-tls/docker-compose.yml" => {
                 self.wait_for_log("Ready to accept connections", 1, 110)
             }
+            // postgres restarts once after initializing the database
+            "example-configs/postgres-passthrough/docker-compose.yml" => {
+                self.wait_for_log("database system is ready to accept connections", 2, 110)
+            }
             "example-configs/redis-multi/docker-compose.yml" => {
                 self.wait_for_log("Ready to accept connections", 3, 110)
             }